@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE BookFragments ADD COLUMN ChapterNumber INTEGER;
+UPDATE BookFragments
+   SET ChapterNumber = Chapters.Number
+  FROM Chapters
+ WHERE Chapters.Id = BookFragments.Chapter;
+ALTER TABLE BookFragments DROP COLUMN Chapter;
+ALTER TABLE BookFragments RENAME COLUMN ChapterNumber TO Chapter;
+ALTER TABLE BookFragments ALTER COLUMN Chapter SET NOT NULL;
+DROP TABLE Chapters;
@@ -0,0 +1,33 @@
+-- Your SQL goes here
+CREATE TABLE Chapters (
+       Id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+       Book UUID
+            REFERENCES Books(Id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+            NOT NULL,
+       Number INTEGER NOT NULL,
+       Title VARCHAR(255),
+       Epigraph TEXT,
+       Cover VARCHAR(255),
+       BgSoundSource VARCHAR(255),
+       UNIQUE (Book, Number) DEFERRABLE INITIALLY IMMEDIATE
+);
+
+-- Create a chapter for each chapter number already used by a fragment
+INSERT INTO Chapters (Book, Number)
+SELECT DISTINCT Book, Chapter FROM BookFragments;
+
+ALTER TABLE BookFragments RENAME COLUMN Chapter TO ChapterNumber;
+ALTER TABLE BookFragments
+      ADD COLUMN Chapter UUID
+          REFERENCES Chapters(Id)
+          ON UPDATE CASCADE
+          ON DELETE CASCADE;
+UPDATE BookFragments
+   SET Chapter = Chapters.Id
+  FROM Chapters
+ WHERE Chapters.Book = BookFragments.Book
+   AND Chapters.Number = BookFragments.ChapterNumber;
+ALTER TABLE BookFragments ALTER COLUMN Chapter SET NOT NULL;
+ALTER TABLE BookFragments DROP COLUMN ChapterNumber;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE BookFragments
+      DROP CONSTRAINT BookFragments_Book_Chapter_Rank_Key;
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+-- Remove duplicate ranks and gaps left by earlier reorderings before
+-- enforcing unique ranks
+UPDATE BookFragments
+   SET Rank = Ranked.NewRank
+  FROM (SELECT Id,
+               ROW_NUMBER() OVER (PARTITION BY Chapter ORDER BY Rank, Id)
+                 AS NewRank
+          FROM BookFragments) AS Ranked
+ WHERE BookFragments.Id = Ranked.Id
+   AND BookFragments.Rank <> Ranked.NewRank;
+
+ALTER TABLE BookFragments
+      ADD CONSTRAINT BookFragments_Book_Chapter_Rank_Key
+          UNIQUE (Book, Chapter, Rank)
+          DEFERRABLE INITIALLY DEFERRED;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE ImageCache (
+       Query VARCHAR(255) PRIMARY KEY,
+       Url TEXT NOT NULL,
+       Author VARCHAR(255),
+       AuthorUrl TEXT,
+       Provider VARCHAR(255) NOT NULL,
+       FetchedAt TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE BookFragments
+      DROP COLUMN OneShotSoundAsset,
+      DROP COLUMN BgSoundAsset,
+      DROP COLUMN ImgAsset;
+ALTER TABLE Books DROP COLUMN CoverAsset;
+DROP TABLE MediaAssets;
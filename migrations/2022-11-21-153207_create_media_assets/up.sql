@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE MediaAssets (
+       Id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+       Filename VARCHAR(255) NOT NULL,
+       ContentType VARCHAR(255) NOT NULL,
+       Size BIGINT NOT NULL,
+       Uploaded TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+ALTER TABLE Books
+      ADD COLUMN CoverAsset UUID REFERENCES MediaAssets(Id);
+ALTER TABLE BookFragments
+      ADD COLUMN OneShotSoundAsset UUID REFERENCES MediaAssets(Id),
+      ADD COLUMN BgSoundAsset UUID REFERENCES MediaAssets(Id),
+      ADD COLUMN ImgAsset UUID REFERENCES MediaAssets(Id);
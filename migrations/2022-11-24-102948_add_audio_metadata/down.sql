@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE MediaAssets
+      DROP COLUMN Duration,
+      DROP COLUMN Codec,
+      DROP COLUMN SampleRate,
+      DROP COLUMN Channels,
+      DROP COLUMN Loudness;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE MediaAssets
+      ADD COLUMN Duration DOUBLE PRECISION,
+      ADD COLUMN Codec VARCHAR(255),
+      ADD COLUMN SampleRate INTEGER,
+      ADD COLUMN Channels INTEGER,
+      ADD COLUMN Loudness DOUBLE PRECISION;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE MediaVariants;
+ALTER TABLE MediaAssets
+      DROP COLUMN Width,
+      DROP COLUMN Height,
+      DROP COLUMN Blurhash;
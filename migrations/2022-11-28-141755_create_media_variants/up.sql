@@ -0,0 +1,19 @@
+-- Your SQL goes here
+ALTER TABLE MediaAssets
+      ADD COLUMN Width INTEGER,
+      ADD COLUMN Height INTEGER,
+      ADD COLUMN Blurhash VARCHAR(255);
+
+CREATE TABLE MediaVariants (
+       Id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+       Asset UUID
+             REFERENCES MediaAssets(Id)
+             ON UPDATE CASCADE
+             ON DELETE CASCADE
+             NOT NULL,
+       Width INTEGER NOT NULL,
+       Height INTEGER NOT NULL,
+       ContentType VARCHAR(255) NOT NULL,
+       Size BIGINT NOT NULL,
+       UNIQUE (Asset, Width, ContentType)
+);
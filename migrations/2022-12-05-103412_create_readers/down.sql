@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE Bookmarks;
+DROP TABLE ReadingProgress;
+DROP TABLE Readers;
@@ -0,0 +1,45 @@
+-- Your SQL goes here
+CREATE TABLE Readers (
+       Id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+       Name VARCHAR(255) NOT NULL,
+       TokenHash VARCHAR(64) NOT NULL UNIQUE,
+       Created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE ReadingProgress (
+       Id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+       Reader UUID
+              REFERENCES Readers(Id)
+              ON UPDATE CASCADE
+              ON DELETE CASCADE
+              NOT NULL,
+       Book UUID
+            REFERENCES Books(Id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+            NOT NULL,
+       Fragment UUID
+                REFERENCES BookFragments(Id)
+                ON UPDATE CASCADE
+                ON DELETE SET NULL,
+       ChapterNumber INTEGER NOT NULL,
+       Rank INTEGER NOT NULL,
+       Updated TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+       UNIQUE (Reader, Book)
+);
+
+CREATE TABLE Bookmarks (
+       Id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+       Reader UUID
+              REFERENCES Readers(Id)
+              ON UPDATE CASCADE
+              ON DELETE CASCADE
+              NOT NULL,
+       Fragment UUID
+                REFERENCES BookFragments(Id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+                NOT NULL,
+       Note TEXT,
+       Created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE ApiKeys;
+DROP TABLE Sessions;
+DROP TABLE Users;
+DROP TYPE UserRole;
@@ -0,0 +1,37 @@
+-- Your SQL goes here
+CREATE TYPE UserRole AS ENUM ('reader', 'editor', 'admin');
+
+CREATE TABLE Users (
+       Id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+       Username VARCHAR(255) NOT NULL UNIQUE,
+       PasswordHash VARCHAR(255) NOT NULL,
+       Role UserRole NOT NULL DEFAULT 'reader',
+       Created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE Sessions (
+       Id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+       Owner UUID
+             REFERENCES Users(Id)
+             ON UPDATE CASCADE
+             ON DELETE CASCADE
+             NOT NULL,
+       TokenHash VARCHAR(64) NOT NULL UNIQUE,
+       Created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+       Expires TIMESTAMP NOT NULL
+);
+
+CREATE TABLE ApiKeys (
+       Id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+       Owner UUID
+             REFERENCES Users(Id)
+             ON UPDATE CASCADE
+             ON DELETE CASCADE
+             NOT NULL,
+       Name VARCHAR(255) NOT NULL,
+       Prefix VARCHAR(16) NOT NULL,
+       KeyHash VARCHAR(64) NOT NULL UNIQUE,
+       Created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+       LastUsed TIMESTAMP,
+       Revoked TIMESTAMP
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE Collaborators;
+ALTER TABLE Books
+      DROP COLUMN Owner;
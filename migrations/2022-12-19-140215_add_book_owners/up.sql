@@ -0,0 +1,25 @@
+-- Your SQL goes here
+ALTER TABLE Books
+      ADD COLUMN Owner UUID
+                 REFERENCES Users(Id)
+                 ON UPDATE CASCADE
+                 ON DELETE SET NULL;
+
+CREATE TABLE Collaborators (
+       Id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+       Book UUID
+            REFERENCES Books(Id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+            NOT NULL,
+       Member UUID
+              REFERENCES Users(Id)
+              ON UPDATE CASCADE
+              ON DELETE CASCADE
+              NOT NULL,
+       ViewDraft BOOLEAN NOT NULL DEFAULT FALSE,
+       EditFragments BOOLEAN NOT NULL DEFAULT FALSE,
+       Publish BOOLEAN NOT NULL DEFAULT FALSE,
+       DeleteBook BOOLEAN NOT NULL DEFAULT FALSE,
+       UNIQUE (Book, Member)
+);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE PublishedFragments;
+ALTER TABLE Books
+      DROP COLUMN Status;
+DROP TYPE BookStatus;
@@ -0,0 +1,40 @@
+-- Your SQL goes here
+CREATE TYPE BookStatus AS ENUM ('draft', 'in_review', 'published', 'archived');
+
+-- Books created before the publishing workflow were visible to
+-- everyone, so they are published, while new books start as drafts
+ALTER TABLE Books
+      ADD COLUMN Status BookStatus NOT NULL DEFAULT 'published';
+ALTER TABLE Books ALTER COLUMN Status SET DEFAULT 'draft';
+
+-- Fragments of published books as they were when they were last
+-- published, while BookFragments holds their current draft
+CREATE TABLE PublishedFragments (
+       Id UUID PRIMARY KEY,
+       Content TEXT NOT NULL,
+       OneShotSoundSource VARCHAR(255),
+       BgSoundType SoundType NOT NULL DEFAULT 'none',
+       BgSoundSource VARCHAR(255),
+       ImgType ImageType NOT NULL DEFAULT 'none',
+       ImgSource VARCHAR(255),
+       Book UUID
+            REFERENCES Books(Id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+            NOT NULL,
+       Rank INTEGER NOT NULL,
+       Chapter UUID
+               REFERENCES Chapters(Id)
+               ON UPDATE CASCADE
+               ON DELETE CASCADE
+               NOT NULL,
+       OneShotSoundAsset UUID REFERENCES MediaAssets(Id),
+       BgSoundAsset UUID REFERENCES MediaAssets(Id),
+       ImgAsset UUID REFERENCES MediaAssets(Id)
+);
+
+INSERT INTO PublishedFragments
+SELECT Id, Content, OneShotSoundSource, BgSoundType, BgSoundSource,
+       ImgType, ImgSource, Book, Rank, Chapter, OneShotSoundAsset,
+       BgSoundAsset, ImgAsset
+  FROM BookFragments;
@@ -0,0 +1,46 @@
+-- Your SQL goes here
+-- Every state a fragment went through, numbered from 1 for each
+-- fragment
+CREATE TABLE FragmentRevisions (
+       Id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+       Fragment UUID
+                REFERENCES BookFragments(Id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+                NOT NULL,
+       Revision INTEGER NOT NULL,
+       Content TEXT NOT NULL,
+       OneShotSoundSource VARCHAR(255),
+       BgSoundType SoundType NOT NULL DEFAULT 'none',
+       BgSoundSource VARCHAR(255),
+       ImgType ImageType NOT NULL DEFAULT 'none',
+       ImgSource VARCHAR(255),
+       Chapter UUID NOT NULL,
+       Rank INTEGER NOT NULL,
+       OneShotSoundAsset UUID
+                         REFERENCES MediaAssets(Id)
+                         ON DELETE SET NULL,
+       BgSoundAsset UUID
+                    REFERENCES MediaAssets(Id)
+                    ON DELETE SET NULL,
+       ImgAsset UUID
+                REFERENCES MediaAssets(Id)
+                ON DELETE SET NULL,
+       Editor UUID
+              REFERENCES Users(Id)
+              ON UPDATE CASCADE
+              ON DELETE SET NULL,
+       Created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+       UNIQUE (Fragment, Revision)
+);
+
+-- Existing fragments start their history with their current state
+INSERT INTO FragmentRevisions (Fragment, Revision, Content,
+                               OneShotSoundSource, BgSoundType,
+                               BgSoundSource, ImgType, ImgSource,
+                               Chapter, Rank, OneShotSoundAsset,
+                               BgSoundAsset, ImgAsset)
+SELECT Id, 1, Content, OneShotSoundSource, BgSoundType, BgSoundSource,
+       ImgType, ImgSource, Chapter, Rank, OneShotSoundAsset,
+       BgSoundAsset, ImgAsset
+  FROM BookFragments;
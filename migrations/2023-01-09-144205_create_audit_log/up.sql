@@ -0,0 +1,20 @@
+-- Your SQL goes here
+CREATE TYPE AuditAction AS ENUM ('create', 'update', 'delete');
+
+-- Changes made through the API, with the state of the changed entity
+-- before and after the change
+CREATE TABLE AuditLog (
+       Id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+       Actor UUID
+             REFERENCES Users(Id)
+             ON UPDATE CASCADE
+             ON DELETE SET NULL,
+       ActorName VARCHAR(255) NOT NULL,
+       Route VARCHAR(255) NOT NULL,
+       Action AuditAction NOT NULL,
+       EntityType VARCHAR(255) NOT NULL,
+       EntityId UUID NOT NULL,
+       Before JSONB,
+       After JSONB,
+       Created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
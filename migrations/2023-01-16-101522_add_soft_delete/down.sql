@@ -0,0 +1,21 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM BookFragments
+      WHERE DeletedAt IS NOT NULL;
+DELETE FROM Books
+      WHERE DeletedAt IS NOT NULL;
+DELETE FROM Authors
+      WHERE DeletedAt IS NOT NULL;
+
+ALTER TABLE BookFragments
+      DROP CONSTRAINT BookFragments_Book_Chapter_Rank_Key;
+ALTER TABLE BookFragments
+      ADD CONSTRAINT BookFragments_Book_Chapter_Rank_Key
+          UNIQUE (Book, Chapter, Rank)
+          DEFERRABLE INITIALLY DEFERRED;
+
+ALTER TABLE BookFragments
+      DROP COLUMN DeletedAt;
+ALTER TABLE Books
+      DROP COLUMN DeletedAt;
+ALTER TABLE Authors
+      DROP COLUMN DeletedAt;
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+-- Authors, books and fragments are moved to the trash before being
+-- deleted for good
+ALTER TABLE Authors
+      ADD COLUMN DeletedAt TIMESTAMP;
+ALTER TABLE Books
+      ADD COLUMN DeletedAt TIMESTAMP;
+ALTER TABLE BookFragments
+      ADD COLUMN DeletedAt TIMESTAMP;
+
+-- Trashed fragments keep their rank, which the fragments left in
+-- their chapter may reuse
+ALTER TABLE BookFragments
+      DROP CONSTRAINT BookFragments_Book_Chapter_Rank_Key;
+ALTER TABLE BookFragments
+      ADD CONSTRAINT BookFragments_Book_Chapter_Rank_Key
+          EXCLUDE USING btree (Book WITH =, Chapter WITH =, Rank WITH =)
+          WHERE (DeletedAt IS NULL)
+          DEFERRABLE INITIALLY DEFERRED;
@@ -0,0 +1,17 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER BooksLanguage ON Books;
+DROP TRIGGER PublishedFragmentsSearchVector ON PublishedFragments;
+DROP TRIGGER BookFragmentsSearchVector ON BookFragments;
+DROP TRIGGER BooksSearchVector ON Books;
+DROP FUNCTION BookLanguageChanged();
+DROP FUNCTION FragmentSearchVector();
+DROP FUNCTION BookSearchVector();
+
+ALTER TABLE PublishedFragments
+      DROP COLUMN SearchVector;
+ALTER TABLE BookFragments
+      DROP COLUMN SearchVector;
+ALTER TABLE Books
+      DROP COLUMN SearchVector;
+ALTER TABLE Books
+      DROP COLUMN Language;
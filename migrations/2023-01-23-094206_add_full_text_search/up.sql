@@ -0,0 +1,74 @@
+-- Your SQL goes here
+-- Text search configuration used to index a book and its fragments,
+-- such as 'english' or 'french'. The 'simple' configuration indexes
+-- words as they are, without stemming nor stop words.
+ALTER TABLE Books
+      ADD COLUMN Language VARCHAR(63) NOT NULL DEFAULT 'simple';
+
+ALTER TABLE Books
+      ADD COLUMN SearchVector TSVECTOR;
+ALTER TABLE BookFragments
+      ADD COLUMN SearchVector TSVECTOR;
+ALTER TABLE PublishedFragments
+      ADD COLUMN SearchVector TSVECTOR;
+
+-- Titles weigh more than synopses when ranking books
+CREATE FUNCTION BookSearchVector() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.SearchVector :=
+        SETWEIGHT(TO_TSVECTOR(NEW.Language::REGCONFIG, NEW.Title), 'A') ||
+        SETWEIGHT(TO_TSVECTOR(NEW.Language::REGCONFIG,
+                              COALESCE(NEW.Synopsis, '')), 'B');
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Fragments are indexed in the language of their book
+CREATE FUNCTION FragmentSearchVector() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.SearchVector := TO_TSVECTOR(
+        (SELECT Language FROM Books WHERE Id = NEW.Book)::REGCONFIG,
+        NEW.Content);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Changing the language of a book indexes its fragments again
+CREATE FUNCTION BookLanguageChanged() RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE BookFragments
+       SET SearchVector = TO_TSVECTOR(NEW.Language::REGCONFIG, Content)
+     WHERE Book = NEW.Id;
+    UPDATE PublishedFragments
+       SET SearchVector = TO_TSVECTOR(NEW.Language::REGCONFIG, Content)
+     WHERE Book = NEW.Id;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER BooksSearchVector
+       BEFORE INSERT OR UPDATE OF Title, Synopsis, Language ON Books
+       FOR EACH ROW EXECUTE FUNCTION BookSearchVector();
+CREATE TRIGGER BookFragmentsSearchVector
+       BEFORE INSERT OR UPDATE OF Content, Book ON BookFragments
+       FOR EACH ROW EXECUTE FUNCTION FragmentSearchVector();
+CREATE TRIGGER PublishedFragmentsSearchVector
+       BEFORE INSERT OR UPDATE OF Content, Book ON PublishedFragments
+       FOR EACH ROW EXECUTE FUNCTION FragmentSearchVector();
+CREATE TRIGGER BooksLanguage
+       AFTER UPDATE OF Language ON Books
+       FOR EACH ROW
+       WHEN (OLD.Language IS DISTINCT FROM NEW.Language)
+       EXECUTE FUNCTION BookLanguageChanged();
+
+-- Index the existing books and fragments
+UPDATE Books SET Title = Title;
+UPDATE BookFragments SET Content = Content;
+UPDATE PublishedFragments SET Content = Content;
+
+CREATE INDEX Books_SearchVector_Idx
+       ON Books USING GIN (SearchVector);
+CREATE INDEX BookFragments_SearchVector_Idx
+       ON BookFragments USING GIN (SearchVector);
+CREATE INDEX PublishedFragments_SearchVector_Idx
+       ON PublishedFragments USING GIN (SearchVector);
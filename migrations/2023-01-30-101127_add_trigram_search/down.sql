@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX Books_Title_Trgm_Idx;
+DROP INDEX Authors_Names_Trgm_Idx;
+DROP EXTENSION pg_trgm;
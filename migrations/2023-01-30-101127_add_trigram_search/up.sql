@@ -0,0 +1,11 @@
+-- Your SQL goes here
+-- Authors and books are found by name despite typos by comparing the
+-- trigrams of the query with those of their names
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX Authors_Names_Trgm_Idx
+       ON Authors USING GIN ((COALESCE(Firstname, '') || ' ' ||
+                              COALESCE(Lastname, '') || ' ' ||
+                              COALESCE(Penname, '')) gin_trgm_ops);
+CREATE INDEX Books_Title_Trgm_Idx
+       ON Books USING GIN (Title gin_trgm_ops);
@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM Users WHERE Subject IS NOT NULL;
+DROP INDEX Users_Local_Username_Idx;
+ALTER TABLE Users ADD CONSTRAINT Users_Username_Key UNIQUE (Username);
+
+ALTER TABLE Users
+      DROP CONSTRAINT Users_Identity_Check,
+      DROP CONSTRAINT Users_Identity_Key,
+      DROP COLUMN Subject,
+      DROP COLUMN Issuer;
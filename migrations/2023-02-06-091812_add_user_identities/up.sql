@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- Users provisioned from an identity provider are identified by the
+-- issuer and the subject of their tokens, and their usernames are only
+-- unique among local accounts so they can never be mistaken for one
+ALTER TABLE Users
+      ADD COLUMN Issuer VARCHAR(255),
+      ADD COLUMN Subject VARCHAR(255),
+      ADD CONSTRAINT Users_Identity_Key UNIQUE (Issuer, Subject),
+      ADD CONSTRAINT Users_Identity_Check
+          CHECK ((Issuer IS NULL) = (Subject IS NULL));
+
+ALTER TABLE Users DROP CONSTRAINT Users_Username_Key;
+CREATE UNIQUE INDEX Users_Local_Username_Idx
+       ON Users (Username) WHERE Subject IS NULL;
@@ -0,0 +1,16 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM BookFragments
+      WHERE Chapter IN (SELECT Id FROM Chapters
+                         WHERE DeletedAt IS NOT NULL);
+DELETE FROM Chapters
+      WHERE DeletedAt IS NOT NULL;
+
+ALTER TABLE Chapters
+      DROP CONSTRAINT Chapters_Book_Number_Key;
+ALTER TABLE Chapters
+      ADD CONSTRAINT Chapters_Book_Number_Key
+          UNIQUE (Book, Number)
+          DEFERRABLE INITIALLY IMMEDIATE;
+
+ALTER TABLE Chapters
+      DROP COLUMN DeletedAt;
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- Chapters are moved to the trash along with their fragments before
+-- being deleted for good
+ALTER TABLE Chapters
+      ADD COLUMN DeletedAt TIMESTAMP;
+
+-- Trashed chapters keep their number, which the chapters left in
+-- their book may reuse
+ALTER TABLE Chapters
+      DROP CONSTRAINT Chapters_Book_Number_Key;
+ALTER TABLE Chapters
+      ADD CONSTRAINT Chapters_Book_Number_Key
+          EXCLUDE USING btree (Book WITH =, Number WITH =)
+          WHERE (DeletedAt IS NULL)
+          DEFERRABLE INITIALLY IMMEDIATE;
@@ -0,0 +1,29 @@
+-- This file should undo anything in `up.sql`
+DROP VIEW PublicBooks;
+
+ALTER TABLE PublishedFragments
+      DROP CONSTRAINT PublishedFragments_Chapter_Fkey;
+DELETE FROM PublishedFragments
+      WHERE Chapter NOT IN (SELECT Id FROM Chapters);
+ALTER TABLE PublishedFragments
+      ADD CONSTRAINT PublishedFragments_Chapter_Fkey
+          FOREIGN KEY (Chapter)
+          REFERENCES Chapters(Id)
+          ON UPDATE CASCADE
+          ON DELETE CASCADE;
+
+CREATE OR REPLACE FUNCTION BookLanguageChanged() RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE BookFragments
+       SET SearchVector = TO_TSVECTOR(NEW.Language::REGCONFIG, Content)
+     WHERE Book = NEW.Id;
+    UPDATE PublishedFragments
+       SET SearchVector = TO_TSVECTOR(NEW.Language::REGCONFIG, Content)
+     WHERE Book = NEW.Id;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TABLE PublishedChapters;
+DROP TABLE PublishedBooks;
+DROP FUNCTION PublishedBookSearchVector();
@@ -0,0 +1,122 @@
+-- Your SQL goes here
+-- Metadata of published books as they were when they were last
+-- published, while Books holds their current draft. The owner, the
+-- status and the language of a book are never part of its snapshot.
+CREATE TABLE PublishedBooks (
+       Id UUID PRIMARY KEY
+          REFERENCES Books(Id)
+          ON UPDATE CASCADE
+          ON DELETE CASCADE,
+       Title VARCHAR(255) NOT NULL,
+       Author UUID
+              REFERENCES Authors(Id)
+              ON UPDATE CASCADE
+              ON DELETE CASCADE
+              NOT NULL,
+       Isbn TEXT[],
+       Cover VARCHAR(255),
+       Publisher VARCHAR(255),
+       Published DATE,
+       Genre TEXT[],
+       Synopsis TEXT,
+       BookType BookType NOT NULL,
+       CoverAsset UUID REFERENCES MediaAssets(Id),
+       SearchVector TSVECTOR
+);
+
+-- Chapters of published books as they were when they were last
+-- published, while Chapters holds their current draft
+CREATE TABLE PublishedChapters (
+       Id UUID PRIMARY KEY,
+       Book UUID
+            REFERENCES Books(Id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+            NOT NULL,
+       Number INTEGER NOT NULL,
+       Title VARCHAR(255),
+       Epigraph TEXT,
+       Cover VARCHAR(255),
+       BgSoundSource VARCHAR(255)
+);
+
+-- Published books are indexed in the current language of their book
+CREATE FUNCTION PublishedBookSearchVector() RETURNS TRIGGER AS $$
+DECLARE
+    Config REGCONFIG;
+BEGIN
+    SELECT Language::REGCONFIG INTO Config FROM Books WHERE Id = NEW.Id;
+    NEW.SearchVector :=
+        SETWEIGHT(TO_TSVECTOR(Config, NEW.Title), 'A') ||
+        SETWEIGHT(TO_TSVECTOR(Config, COALESCE(NEW.Synopsis, '')), 'B');
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION BookLanguageChanged() RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE BookFragments
+       SET SearchVector = TO_TSVECTOR(NEW.Language::REGCONFIG, Content)
+     WHERE Book = NEW.Id;
+    UPDATE PublishedFragments
+       SET SearchVector = TO_TSVECTOR(NEW.Language::REGCONFIG, Content)
+     WHERE Book = NEW.Id;
+    UPDATE PublishedBooks SET Title = Title WHERE Id = NEW.Id;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER PublishedBooksSearchVector
+       BEFORE INSERT OR UPDATE OF Title, Synopsis ON PublishedBooks
+       FOR EACH ROW EXECUTE FUNCTION PublishedBookSearchVector();
+
+-- Books published so far were published as they are now
+INSERT INTO PublishedBooks
+SELECT Id, Title, Author, Isbn, Cover, Publisher, Published, Genre,
+       Synopsis, BookType, CoverAsset
+  FROM Books
+ WHERE Status = 'published';
+
+INSERT INTO PublishedChapters
+SELECT Id, Book, Number, Title, Epigraph, Cover, BgSoundSource
+  FROM Chapters
+ WHERE Id IN (SELECT Chapter FROM PublishedFragments)
+    OR (DeletedAt IS NULL
+        AND Book IN (SELECT Id FROM Books WHERE Status = 'published'));
+
+-- Published fragments belong to the published version of their
+-- chapter, which outlives its draft
+ALTER TABLE PublishedFragments
+      DROP CONSTRAINT PublishedFragments_Chapter_Fkey;
+ALTER TABLE PublishedFragments
+      ADD CONSTRAINT PublishedFragments_Chapter_Fkey
+          FOREIGN KEY (Chapter)
+          REFERENCES PublishedChapters(Id)
+          ON UPDATE CASCADE
+          ON DELETE CASCADE;
+
+CREATE INDEX PublishedBooks_SearchVector_Idx
+       ON PublishedBooks USING GIN (SearchVector);
+CREATE INDEX PublishedBooks_Title_Trgm_Idx
+       ON PublishedBooks USING GIN (Title gin_trgm_ops);
+CREATE INDEX PublishedChapters_Book_Idx
+       ON PublishedChapters (Book, Number);
+
+-- Books as everyone reads them: the snapshot of published books, and
+-- the draft of the others
+CREATE VIEW PublicBooks AS
+SELECT Books.Id, PublishedBooks.Title, PublishedBooks.Author,
+       PublishedBooks.Isbn, PublishedBooks.Cover, PublishedBooks.Publisher,
+       PublishedBooks.Published, PublishedBooks.Genre,
+       PublishedBooks.Synopsis, PublishedBooks.BookType,
+       PublishedBooks.CoverAsset, Books.Owner, Books.Status,
+       Books.DeletedAt, Books.Language, PublishedBooks.SearchVector
+  FROM Books
+  JOIN PublishedBooks ON PublishedBooks.Id = Books.Id
+ WHERE Books.Status = 'published'
+UNION ALL
+SELECT Id, Title, Author, Isbn, Cover, Publisher, Published, Genre,
+       Synopsis, BookType, CoverAsset, Owner, Status, DeletedAt,
+       Language, SearchVector
+  FROM Books
+ WHERE Status <> 'published';
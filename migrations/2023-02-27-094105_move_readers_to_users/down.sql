@@ -0,0 +1,38 @@
+-- This file should undo anything in `up.sql`
+-- Users migrated from readers become readers again, authenticated by
+-- their reader token, and the progress and bookmarks of other users
+-- are lost. The users themselves are kept.
+CREATE TABLE Readers (
+       Id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+       Name VARCHAR(255) NOT NULL,
+       TokenHash VARCHAR(64) NOT NULL UNIQUE,
+       Created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO Readers (Id, Name, TokenHash, Created)
+SELECT DISTINCT ON (Users.Id)
+       Users.Id, Users.Username, ApiKeys.KeyHash, Users.Created
+  FROM Users
+  JOIN ApiKeys ON ApiKeys.Owner = Users.Id
+ WHERE ApiKeys.Name = 'Reader token'
+   AND ApiKeys.Revoked IS NULL
+ ORDER BY Users.Id, ApiKeys.Created;
+
+DELETE FROM ReadingProgress WHERE Reader NOT IN (SELECT Id FROM Readers);
+DELETE FROM Bookmarks WHERE Reader NOT IN (SELECT Id FROM Readers);
+
+ALTER TABLE ReadingProgress
+      DROP CONSTRAINT ReadingProgress_Reader_Fkey,
+      ADD CONSTRAINT ReadingProgress_Reader_Fkey
+          FOREIGN KEY (Reader)
+          REFERENCES Readers(Id)
+          ON UPDATE CASCADE
+          ON DELETE CASCADE;
+
+ALTER TABLE Bookmarks
+      DROP CONSTRAINT Bookmarks_Reader_Fkey,
+      ADD CONSTRAINT Bookmarks_Reader_Fkey
+          FOREIGN KEY (Reader)
+          REFERENCES Readers(Id)
+          ON UPDATE CASCADE
+          ON DELETE CASCADE;
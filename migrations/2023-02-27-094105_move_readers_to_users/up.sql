@@ -0,0 +1,40 @@
+-- Your SQL goes here
+-- Readers become local users with the `reader` role, keeping their
+-- identifier so their progress and bookmarks follow them. They have
+-- no password, and their token becomes an API key so that reading
+-- apps keep working by sending it in the `X-Api-Key` header. Readers
+-- whose name is already taken get their identifier appended to it.
+INSERT INTO Users (Id, Username, PasswordHash, Role, Created)
+SELECT Id,
+       CASE WHEN COUNT(*) OVER (PARTITION BY Name) > 1
+                 OR Name IN (SELECT Username FROM Users
+                              WHERE Subject IS NULL)
+            THEN LEFT(Name, 218) || '-' || Id
+            ELSE Name
+       END,
+       '', 'reader', Created
+  FROM Readers
+ WHERE Id NOT IN (SELECT Id FROM Users);
+
+INSERT INTO ApiKeys (Owner, Name, Prefix, KeyHash, Created)
+SELECT Id, 'Reader token', '', TokenHash, Created
+  FROM Readers
+ WHERE TokenHash NOT IN (SELECT KeyHash FROM ApiKeys);
+
+ALTER TABLE ReadingProgress
+      DROP CONSTRAINT ReadingProgress_Reader_Fkey,
+      ADD CONSTRAINT ReadingProgress_Reader_Fkey
+          FOREIGN KEY (Reader)
+          REFERENCES Users(Id)
+          ON UPDATE CASCADE
+          ON DELETE CASCADE;
+
+ALTER TABLE Bookmarks
+      DROP CONSTRAINT Bookmarks_Reader_Fkey,
+      ADD CONSTRAINT Bookmarks_Reader_Fkey
+          FOREIGN KEY (Reader)
+          REFERENCES Users(Id)
+          ON UPDATE CASCADE
+          ON DELETE CASCADE;
+
+DROP TABLE Readers;
@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::{env, fmt, fs};
+
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use rocket::serde::json::{from_slice, Value};
+use rocket::serde::Deserialize;
+use tracing::info;
+
+use crate::models::Role;
+
+/// Error encountered while setting up or checking JWTs
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration or the key set is invalid
+    Config(String),
+    /// A token is invalid
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Config(message) => {
+                write!(f, "Invalid JWT configuration: {}", message)
+            }
+            Self::Invalid(message) => write!(f, "Invalid JWT: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<jsonwebtoken::errors::Error> for Error {
+    fn from(other: jsonwebtoken::errors::Error) -> Self {
+        Self::Invalid(other.to_string())
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(other: reqwest::Error) -> Self {
+        Self::Config(other.to_string())
+    }
+}
+
+/// Identity asserted by a valid token
+///
+/// Users are identified by the `issuer` and the `subject` of their
+/// tokens, their `username` is only displayed.
+pub struct Claims {
+    pub issuer: String,
+    pub subject: String,
+    pub username: String,
+    pub role: Role,
+}
+
+/// Part of the discovery document of an issuer, served at
+/// `<issuer>/.well-known/openid-configuration`
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Discovery {
+    jwks_uri: String,
+}
+
+/// Checks JWTs against the keys of an identity provider
+pub struct Verifier {
+    keys: JwkSet,
+    algorithm: Option<Algorithm>,
+    issuer: Option<String>,
+    audience: String,
+    username_claim: String,
+    role_claim: String,
+    roles: HashMap<String, Role>,
+}
+
+/// Value of the environment variable `name`, if it is set and not
+/// empty
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Download the document at `url`
+async fn fetch(client: &reqwest::Client, url: &str) -> Result<Vec<u8>, Error> {
+    info!("Fetching {}", url);
+    let body = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    Ok(body.to_vec())
+}
+
+/// Role named `name`, such as `editor`
+fn role_named(name: &str) -> Option<Role> {
+    match name {
+        "reader" => Some(Role::Reader),
+        "editor" => Some(Role::Editor),
+        "admin" => Some(Role::Admin),
+        _ => None,
+    }
+}
+
+/// Read a mapping of claim values to roles, such as
+/// `writers=editor,it=admin`
+fn parse_roles(mapping: &str) -> Result<HashMap<String, Role>, Error> {
+    mapping
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            pair.rsplit_once('=')
+                .and_then(|(value, role)| {
+                    Some((value.trim().to_owned(), role_named(role.trim())?))
+                })
+                .ok_or_else(|| {
+                    Error::Config(format!("invalid role mapping `{}`", pair))
+                })
+        })
+        .collect()
+}
+
+/// Value of the claim at `path`, whose segments are separated by
+/// dots such as `realm_access.roles`
+fn claim<'a>(claims: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(claims, |value, key| value.get(key))
+}
+
+/// Key checking the signatures made with `jwk`
+///
+/// Symmetric keys are written in base64url without padding in key
+/// sets, which `DecodingKey::from_jwk` does not read, so they are
+/// converted to standard base64 first.
+fn decoding_key(jwk: &Jwk) -> Result<DecodingKey, Error> {
+    match &jwk.algorithm {
+        AlgorithmParameters::OctetKey(params) => {
+            let mut value: String = params
+                .value
+                .chars()
+                .map(|c| match c {
+                    '-' => '+',
+                    '_' => '/',
+                    c => c,
+                })
+                .collect();
+            while value.len() % 4 != 0 {
+                value.push('=');
+            }
+            Ok(DecodingKey::from_base64_secret(&value)?)
+        }
+        _ => Ok(DecodingKey::from_jwk(jwk)?),
+    }
+}
+
+impl Verifier {
+    /// Build the verifier configured by the environment
+    ///
+    /// The keys of the identity provider are read once from the JWKS
+    /// file `ALEXANDRIA_JWKS_FILE`, downloaded from
+    /// `ALEXANDRIA_JWKS_URL`, or found through the discovery document
+    /// of the issuer `ALEXANDRIA_JWT_ISSUER`, in this order of
+    /// precedence. Restart alexandria to load rotated keys.
+    ///
+    /// Tokens must be signed by one of these keys with the algorithm
+    /// of the key, its `alg` parameter or `ALEXANDRIA_JWT_ALGORITHM`
+    /// for the keys without one, whatever algorithm the tokens claim
+    /// to use. They must not be expired, and their `aud` claim must
+    /// contain `ALEXANDRIA_JWT_AUDIENCE`. If `ALEXANDRIA_JWT_ISSUER`
+    /// is set, their `iss` claim must match it.
+    ///
+    /// Users are identified by the `iss` and `sub` claims of their
+    /// tokens, and named after the claim
+    /// `ALEXANDRIA_JWT_USERNAME_CLAIM`, `sub` by default. Their role
+    /// is read from the claim `ALEXANDRIA_JWT_ROLE_CLAIM`, `roles` by
+    /// default, which holds a string or an array of strings.
+    /// `ALEXANDRIA_JWT_ROLES` maps its values to roles, such as
+    /// `writers=editor,it=admin`. Without it, the values `reader`,
+    /// `editor` and `admin` are the roles of the same name. The
+    /// highest role found is used, and users with none are readers.
+    ///
+    /// # Errors
+    ///
+    /// If no key source or audience is set, if the keys cannot be read
+    /// or downloaded, if the algorithm of a key is unknown, or if the
+    /// role mapping is invalid, an `Error::Config` is returned.
+    pub async fn from_env(client: &reqwest::Client) -> Result<Self, Error> {
+        let issuer = var("ALEXANDRIA_JWT_ISSUER");
+        let audience = var("ALEXANDRIA_JWT_AUDIENCE").ok_or_else(|| {
+            Error::Config("ALEXANDRIA_JWT_AUDIENCE must be set".to_owned())
+        })?;
+        let algorithm = match var("ALEXANDRIA_JWT_ALGORITHM") {
+            Some(name) => Some(Algorithm::from_str(&name).map_err(|_| {
+                Error::Config(format!("unknown algorithm `{}`", name))
+            })?),
+            None => None,
+        };
+        let keys = match (
+            var("ALEXANDRIA_JWKS_FILE"),
+            var("ALEXANDRIA_JWKS_URL"),
+            &issuer,
+        ) {
+            (Some(path), _, _) => {
+                info!("Reading JWT keys from {}", path);
+                fs::read(&path)
+                    .map_err(|e| Error::Config(format!("{}: {}", path, e)))?
+            }
+            (None, Some(url), _) => fetch(client, &url).await?,
+            (None, None, Some(issuer)) => {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    issuer.trim_end_matches('/')
+                );
+                let discovery: Discovery =
+                    from_slice(&fetch(client, &url).await?).map_err(|e| {
+                        Error::Config(format!("invalid discovery: {}", e))
+                    })?;
+                fetch(client, &discovery.jwks_uri).await?
+            }
+            (None, None, None) => {
+                return Err(Error::Config(
+                    "ALEXANDRIA_JWKS_FILE, ALEXANDRIA_JWKS_URL or \
+                     ALEXANDRIA_JWT_ISSUER must be set"
+                        .to_owned(),
+                ))
+            }
+        };
+        let keys: JwkSet = from_slice(&keys)
+            .map_err(|e| Error::Config(format!("invalid key set: {}", e)))?;
+        if keys.keys.is_empty() {
+            return Err(Error::Config("the key set is empty".to_owned()));
+        }
+        if algorithm.is_none()
+            && keys.keys.iter().any(|key| key.common.algorithm.is_none())
+        {
+            return Err(Error::Config(
+                "ALEXANDRIA_JWT_ALGORITHM must be set for keys without an \
+                 algorithm"
+                    .to_owned(),
+            ));
+        }
+        let roles = match var("ALEXANDRIA_JWT_ROLES") {
+            Some(mapping) => parse_roles(&mapping)?,
+            None => ["reader", "editor", "admin"]
+                .into_iter()
+                .filter_map(|name| Some((name.to_owned(), role_named(name)?)))
+                .collect(),
+        };
+        Ok(Self {
+            keys,
+            algorithm,
+            issuer,
+            audience,
+            username_claim: var("ALEXANDRIA_JWT_USERNAME_CLAIM")
+                .unwrap_or_else(|| "sub".to_owned()),
+            role_claim: var("ALEXANDRIA_JWT_ROLE_CLAIM")
+                .unwrap_or_else(|| "roles".to_owned()),
+            roles,
+        })
+    }
+
+    /// Key which signed a token, from the key ID `kid` of its header
+    ///
+    /// A token without a key ID can only be checked against a key set
+    /// holding a single key.
+    fn key(&self, kid: Option<&str>) -> Result<&Jwk, Error> {
+        match (kid, self.keys.keys.as_slice()) {
+            (Some(kid), _) => self
+                .keys
+                .find(kid)
+                .ok_or_else(|| Error::Invalid(format!("unknown key {}", kid))),
+            (None, [key]) => Ok(key),
+            (None, _) => Err(Error::Invalid("missing key ID".to_owned())),
+        }
+    }
+
+    /// Highest role mapped from the values of the role claim
+    fn role(&self, value: Option<&Value>) -> Role {
+        let values: Vec<&str> = match value {
+            Some(Value::String(value)) => vec![value.as_str()],
+            Some(Value::Array(values)) => {
+                values.iter().filter_map(Value::as_str).collect()
+            }
+            _ => Vec::new(),
+        };
+        values
+            .into_iter()
+            .filter_map(|value| self.roles.get(value).copied())
+            .max()
+            .unwrap_or(Role::Reader)
+    }
+
+    /// Check a token and read the identity it asserts
+    ///
+    /// # Errors
+    ///
+    /// If the token is not signed by a known key with its algorithm,
+    /// is expired, was not issued by the configured issuer for the
+    /// configured audience, or does not hold an issuer, a subject and
+    /// a username, an `Error::Invalid` is returned.
+    pub fn verify(&self, token: &str) -> Result<Claims, Error> {
+        let header = decode_header(token)?;
+        let jwk = self.key(header.kid.as_deref())?;
+        let algorithm =
+            jwk.common.algorithm.or(self.algorithm).ok_or_else(|| {
+                Error::Invalid("unknown algorithm".to_owned())
+            })?;
+        let mut validation = Validation::new(algorithm);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "aud"]);
+        let data = decode::<Value>(token, &decoding_key(jwk)?, &validation)?;
+        let text = |name: &str| {
+            claim(&data.claims, name)
+                .and_then(Value::as_str)
+                .filter(|value| !value.is_empty())
+                .map(ToOwned::to_owned)
+                .ok_or_else(|| {
+                    Error::Invalid(format!("missing claim {}", name))
+                })
+        };
+        Ok(Claims {
+            issuer: text("iss")?,
+            subject: text("sub")?,
+            username: text(&self.username_claim)?,
+            role: self.role(claim(&data.claims, &self.role_claim)),
+        })
+    }
+}
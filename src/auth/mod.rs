@@ -0,0 +1,308 @@
+pub mod jwt;
+pub mod password;
+
+use diesel::result::Error::NotFound;
+use diesel::PgConnection;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Outcome, Request};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::db::book::Readable;
+use crate::db::{collaborator, user, ApiResult};
+use crate::models::{Book, Collaborator, Role, User};
+use crate::ServerState;
+
+/// How long a session lasts after its user logged in, in days
+pub const SESSION_DAYS: i64 = 30;
+
+/// Prefix of the session tokens
+pub const SESSION_PREFIX: &str = "as";
+
+/// Prefix of the API keys
+pub const KEY_PREFIX: &str = "ak";
+
+/// How bearer tokens sent in the `Authorization` header are checked
+pub enum Mode {
+    /// Bearer tokens are the tokens of the sessions opened by logging
+    /// in with a username and a password
+    Local,
+    /// Bearer tokens are JWTs issued by an identity provider, and
+    /// logging in with a password is disabled
+    Jwt(Box<jwt::Verifier>),
+}
+
+/// Read the authentication mode from the environment
+///
+/// `ALEXANDRIA_AUTH_MODE` is either `local`, the default, or `jwt`.
+/// The JWT mode is configured as described by
+/// [`jwt::Verifier::from_env`]. API keys are accepted in both modes.
+///
+/// # Errors
+///
+/// If the mode is unknown or the JWT mode cannot be set up, a
+/// `jwt::Error::Config` is returned.
+pub async fn from_env(client: &reqwest::Client) -> Result<Mode, jwt::Error> {
+    match std::env::var("ALEXANDRIA_AUTH_MODE").as_deref() {
+        Err(_) | Ok("" | "local") => Ok(Mode::Local),
+        Ok("jwt") => {
+            let verifier = jwt::Verifier::from_env(client).await?;
+            info!("Authenticating bearer tokens as JWTs");
+            Ok(Mode::Jwt(Box::new(verifier)))
+        }
+        Ok(other) => Err(jwt::Error::Config(format!(
+            "unknown authentication mode `{}`",
+            other
+        ))),
+    }
+}
+
+/// Credential a request was authenticated with
+#[derive(Debug, Clone, Copy)]
+pub enum Credential {
+    /// Token of a session opened by logging in, sent in the
+    /// `Authorization` header as a bearer token
+    Session(Uuid),
+    /// API key sent in the `X-Api-Key` header
+    ApiKey(Uuid),
+    /// JWT issued by an identity provider, sent in the `Authorization`
+    /// header as a bearer token
+    Jwt,
+}
+
+/// The user sending a request along with the credential they used
+#[derive(Clone)]
+pub struct Identity {
+    pub user: User,
+    pub credential: Credential,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AuthError {
+    Missing,
+    Invalid,
+    Forbidden,
+    Database,
+}
+
+/// Find the user asserted by a JWT, provisioning them if needed
+fn from_jwt(
+    connector: &mut PgConnection,
+    verifier: &jwt::Verifier,
+    token: &str,
+) -> Result<ApiResult<(User, Credential)>, (Status, AuthError)> {
+    match verifier.verify(token) {
+        Ok(claims) => {
+            Ok(user::provision(connector, &claims)
+                .map(|user| (user, Credential::Jwt)))
+        }
+        Err(e) => {
+            info!("Rejected bearer token: {}", e);
+            Err((Status::Unauthorized, AuthError::Invalid))
+        }
+    }
+}
+
+/// Find the user sending `request` from its credential
+fn lookup(request: &Request<'_>) -> Result<Identity, (Status, AuthError)> {
+    let headers = request.headers();
+    let bearer = headers
+        .get_one("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    let key = headers.get_one("x-api-key");
+    let state = request.rocket().state::<ServerState>().unwrap();
+    let mut connector = state
+        .pool
+        .get()
+        .map_err(|_| (Status::InternalServerError, AuthError::Database))?;
+    let found = match (bearer, key) {
+        (Some(token), _) => match &state.auth {
+            Mode::Local => {
+                user::from_session(&mut connector, &password::hash_token(token))
+                    .map(|(user, id)| (user, Credential::Session(id)))
+            }
+            Mode::Jwt(verifier) => from_jwt(&mut connector, verifier, token)?,
+        },
+        (None, Some(key)) => {
+            user::from_api_key(&mut connector, &password::hash_token(key))
+                .map(|(user, id)| (user, Credential::ApiKey(id)))
+        }
+        (None, None) => return Err((Status::Unauthorized, AuthError::Missing)),
+    };
+    match found {
+        Ok((user, credential)) => Ok(Identity { user, credential }),
+        Err(NotFound) => Err((Status::Unauthorized, AuthError::Invalid)),
+        Err(_) => Err((Status::InternalServerError, AuthError::Database)),
+    }
+}
+
+/// Authenticate the user sending `request` and check they have at
+/// least the role `role`
+///
+/// The user is looked up once per request, however many guards
+/// require it.
+async fn authenticate(
+    request: &Request<'_>,
+    role: Role,
+) -> request::Outcome<Identity, AuthError> {
+    let found = request
+        .local_cache_async(async { lookup(request) })
+        .await
+        .clone();
+    match found {
+        Ok(identity) if identity.user.role >= role => {
+            Outcome::Success(identity)
+        }
+        Ok(_) => Outcome::Failure((Status::Forbidden, AuthError::Forbidden)),
+        Err(e) => Outcome::Failure(e),
+    }
+}
+
+/// Declare a request guard authenticating users having at least a
+/// given role
+macro_rules! role_guard {
+    ($(#[$doc:meta])* $name:ident, $role:expr) => {
+        $(#[$doc])*
+        pub struct $name(pub Identity);
+
+        #[rocket::async_trait]
+        impl<'r> FromRequest<'r> for $name {
+            type Error = AuthError;
+
+            async fn from_request(
+                request: &'r Request<'_>,
+            ) -> request::Outcome<Self, Self::Error> {
+                authenticate(request, $role).await.map(Self)
+            }
+        }
+    };
+}
+
+role_guard!(
+    /// Any authenticated user
+    ///
+    /// Unauthenticated requests get a 401 error.
+    Reader,
+    Role::Reader
+);
+
+role_guard!(
+    /// A user allowed to edit the library, see [`Role::Editor`]
+    ///
+    /// Unauthenticated requests get a 401 error, and requests from
+    /// users with a lower role get a 403 error.
+    Editor,
+    Role::Editor
+);
+
+role_guard!(
+    /// A user allowed to manage users, see [`Role::Admin`]
+    ///
+    /// Unauthenticated requests get a 401 error, and requests from
+    /// users with a lower role get a 403 error.
+    Admin,
+    Role::Admin
+);
+
+/// Permission a user may have on a book
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// View the book while it is a draft
+    ViewDraft,
+    /// Edit the metadata, the chapters and the fragments of the book
+    EditFragments,
+    /// Publish the book
+    Publish,
+    /// Delete the book
+    Delete,
+    /// Manage the collaborators and the owner of the book, which only
+    /// its owner can do
+    Manage,
+}
+
+impl Permission {
+    /// Whether this permission was granted to `collaborator`
+    #[must_use]
+    pub fn granted(self, collaborator: &Collaborator) -> bool {
+        match self {
+            Self::ViewDraft => collaborator.viewdraft,
+            Self::EditFragments => collaborator.editfragments,
+            Self::Publish => collaborator.publish,
+            Self::Delete => collaborator.deletebook,
+            Self::Manage => false,
+        }
+    }
+}
+
+/// Whether `user` has `permission` on `book`
+///
+/// Administrators have all permissions on all books, and owners on
+/// their own books. Other users only have the permissions they were
+/// granted as collaborators. Books without an owner, such as the ones
+/// created before books had owners, can only be handled by
+/// administrators until they give them an owner.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `can`
+pub fn can(
+    connector: &mut PgConnection,
+    user: &User,
+    book: &Book,
+    permission: Permission,
+) -> ApiResult<bool> {
+    if user.role == Role::Admin || book.owner == Some(user.id) {
+        return Ok(true);
+    }
+    match collaborator::get(connector, book.id, user.id)? {
+        Some(collaborator) => Ok(permission.granted(&collaborator)),
+        None => Ok(false),
+    }
+}
+
+/// Find which books `user` can read
+///
+/// Published books can be read by everyone, including anonymous users
+/// for whom `user` is `None`. Other books can only be read by the
+/// users who can view their drafts, see [`Permission::ViewDraft`].
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `readable_by`
+pub fn readable_by(
+    connector: &mut PgConnection,
+    user: Option<&User>,
+) -> ApiResult<Readable> {
+    match user {
+        Some(user) if user.role == Role::Admin => Ok(Readable::All),
+        Some(user) => Ok(Readable::Published {
+            owner: Some(user.id),
+            drafts: collaborator::drafts(connector, user.id)?,
+        }),
+        None => Ok(Readable::Published {
+            owner: None,
+            drafts: Vec::new(),
+        }),
+    }
+}
+
+/// Keep the books `user` can read among `books`, see [`readable_by`]
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `readable`
+pub fn readable(
+    connector: &mut PgConnection,
+    user: Option<&User>,
+    books: Vec<Book>,
+) -> ApiResult<Vec<Book>> {
+    let readable = readable_by(connector, user)?;
+    Ok(books
+        .into_iter()
+        .filter(|book| readable.allows(book))
+        .collect())
+}
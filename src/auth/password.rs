@@ -0,0 +1,60 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{
+    self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+};
+use argon2::Argon2;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Minimal length of a password, in characters
+pub const MIN_LENGTH: usize = 8;
+
+/// Hash a password with argon2 and a random salt
+///
+/// The hash is returned in the PHC string format, which holds the
+/// parameters and the salt along with the hash itself.
+///
+/// # Errors
+///
+/// If the password cannot be hashed, an error is returned.
+pub fn hash(password: &str) -> Result<String, password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// Check `password` against a hash returned by [`hash`]
+#[must_use]
+pub fn verify(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Generate a new secret token starting with `prefix`
+///
+/// The token is made of `prefix` and an underscore followed by 244
+/// random bits written in hexadecimal, such as `ak_3f2a...`.
+#[must_use]
+pub fn new_token(prefix: &str) -> String {
+    format!(
+        "{}_{}{}",
+        prefix,
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    )
+}
+
+/// Hash a token returned by [`new_token`] to store it or look it up
+///
+/// Tokens are random enough for a fast hash to be safe, contrary to
+/// passwords. The SHA-256 hash of the token is returned in
+/// hexadecimal.
+#[must_use]
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
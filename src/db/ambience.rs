@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+
+use diesel::expression_methods::ExpressionMethods;
+use diesel::{PgConnection, QueryDsl, Queryable, RunQueryDsl};
+use rocket::serde::Serialize;
+use uuid::Uuid;
+
+use crate::db::fragment::{self, Simple};
+use crate::db::media::{self, Picture};
+use crate::db::page::{Fields, Item, Page, Paginated};
+use crate::db::published::{self, Version};
+use crate::db::{chapter, ApiResult};
+use crate::models::{Bookfragment, CachedImage, Chapter, ImageType, SoundType};
+use crate::schema::{
+    bookfragments, chapters, publishedchapters, publishedfragments,
+};
+
+/// Background image actually displayed for a fragment
+///
+/// `imgtype` is either `Url` or `Auto`, and `imgsource` holds
+/// respectively the URL of the image or the directions to find it.
+/// Once the directions of an `Auto` image are turned into a concrete
+/// image, see `images::resolve`, the latter is held by `resolved`.
+/// An image uploaded as a media asset is described by `picture`,
+/// along with its variants.
+#[derive(Serialize, Clone, PartialEq, Eq)]
+#[serde(crate = "rocket::serde")]
+pub struct Image {
+    pub imgtype: ImageType,
+    pub imgsource: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<CachedImage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub picture: Option<Picture>,
+    #[serde(skip)]
+    asset: Option<Uuid>,
+}
+
+/// Background image and sound actually in use for a fragment
+///
+/// Contrary to the image and sound of the fragment itself, these are
+/// never `Same`: they are resolved from the preceding fragments.
+#[derive(Serialize, Clone, Default)]
+#[serde(crate = "rocket::serde")]
+pub struct Effective {
+    pub effective_image: Option<Image>,
+    pub effective_sound: Option<String>,
+}
+
+/// A fragment, optionally along with its effective image and sound
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Resolved<T: Serialize> {
+    #[serde(flatten)]
+    pub fragment: T,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub effective: Option<Effective>,
+}
+
+/// A range of consecutive fragments over which an asset is active
+///
+/// `from` and `to` are respectively the first and the last fragments
+/// of the range, both included.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Span<T: Serialize> {
+    pub asset: T,
+    pub from: Simple,
+    pub to: Simple,
+    pub fragments: usize,
+}
+
+/// Background images and sounds active over the course of a book
+#[derive(Serialize, Default)]
+#[serde(crate = "rocket::serde")]
+pub struct Timeline {
+    pub images: Vec<Span<Image>>,
+    pub sounds: Vec<Span<String>>,
+}
+
+/// Background assets of a fragment as stored in the database
+#[derive(Queryable)]
+struct Assets {
+    id: Uuid,
+    chapter: Uuid,
+    rank: i32,
+    bgsoundtype: SoundType,
+    bgsoundsource: Option<String>,
+    imgtype: ImageType,
+    imgsource: Option<String>,
+    bgsoundasset: Option<Uuid>,
+    imgasset: Option<Uuid>,
+}
+
+/// Resolve the effective image and sound of fragments
+///
+/// `fragments` must be sorted in reading order. A fragment whose
+/// image or sound is `Same` keeps the one of the previous fragment,
+/// even across chapters. However, the first fragment of a chapter
+/// uses the default background image and sound of its chapter, if
+/// any, instead of the ones of the previous chapter. Sounds and
+/// images stored as media assets are replaced by their URL.
+fn resolve(
+    chapters: &[Chapter],
+    fragments: Vec<Assets>,
+) -> Vec<(Simple, Effective)> {
+    let chapters: HashMap<Uuid, &Chapter> = chapters
+        .iter()
+        .map(|chapter| (chapter.id, chapter))
+        .collect();
+    let mut current = Effective::default();
+    let mut previous_chapter = None;
+    let mut resolved = Vec::with_capacity(fragments.len());
+    for fragment in fragments {
+        let defaults = chapters
+            .get(&fragment.chapter)
+            .filter(|_| previous_chapter != Some(fragment.chapter));
+        let default_sound =
+            defaults.and_then(|chapter| chapter.bgsoundsource.clone());
+        let default_image = defaults.and_then(|chapter| {
+            chapter.cover.clone().map(|cover| Image {
+                imgtype: ImageType::Url,
+                imgsource: Some(cover),
+                resolved: None,
+                picture: None,
+                asset: None,
+            })
+        });
+        current.effective_sound = match fragment.bgsoundtype {
+            SoundType::None => None,
+            SoundType::Url => crate::media::source(
+                fragment.bgsoundasset,
+                fragment.bgsoundsource,
+            ),
+            SoundType::Same => default_sound.or(current.effective_sound),
+        };
+        current.effective_image = match fragment.imgtype {
+            ImageType::None => None,
+            ImageType::Same => default_image.or(current.effective_image),
+            ImageType::Url => Some(Image {
+                imgtype: ImageType::Url,
+                imgsource: crate::media::source(
+                    fragment.imgasset,
+                    fragment.imgsource,
+                ),
+                resolved: None,
+                picture: None,
+                asset: fragment.imgasset,
+            }),
+            ImageType::Auto => Some(Image {
+                imgtype: ImageType::Auto,
+                imgsource: fragment.imgsource,
+                resolved: None,
+                picture: None,
+                asset: None,
+            }),
+        };
+        previous_chapter = Some(fragment.chapter);
+        let simple = Simple {
+            uuid: fragment.id,
+            chapter: fragment.chapter,
+            rank: fragment.rank,
+        };
+        resolved.push((simple, current.clone()));
+    }
+    resolved
+}
+
+/// Describe the effective images of `resolved` which are media
+/// assets, see `media::pictures`
+fn add_pictures(
+    connector: &mut PgConnection,
+    resolved: &mut [(Simple, Effective)],
+) -> ApiResult<()> {
+    let mut ids: Vec<Uuid> = resolved
+        .iter()
+        .filter_map(|(_, effective)| effective.effective_image.as_ref())
+        .filter_map(|image| image.asset)
+        .collect();
+    ids.sort_unstable();
+    ids.dedup();
+    let pictures = media::pictures(connector, &ids)?;
+    for (_, effective) in resolved {
+        if let Some(image) = effective.effective_image.as_mut() {
+            image.picture =
+                image.asset.and_then(|id| pictures.get(&id).cloned());
+        }
+    }
+    Ok(())
+}
+
+/// Load the background assets of all fragments of a book in reading
+/// order
+fn assets(
+    connector: &mut PgConnection,
+    book_id: Uuid,
+    version: Version,
+) -> ApiResult<Vec<Assets>> {
+    match version {
+        Version::Draft => {
+            use bookfragments::dsl;
+            dsl::bookfragments
+                .inner_join(chapters::table)
+                .filter(dsl::book.eq(book_id))
+                .filter(dsl::deletedat.is_null())
+                .order((chapters::number.asc(), dsl::rank.asc()))
+                .select((
+                    dsl::id,
+                    dsl::chapter,
+                    dsl::rank,
+                    dsl::bgsoundtype,
+                    dsl::bgsoundsource,
+                    dsl::imgtype,
+                    dsl::imgsource,
+                    dsl::bgsoundasset,
+                    dsl::imgasset,
+                ))
+                .load::<Assets>(connector)
+        }
+        Version::Published => {
+            use publishedfragments::dsl;
+            dsl::publishedfragments
+                .inner_join(publishedchapters::table)
+                .filter(dsl::book.eq(book_id))
+                .order((publishedchapters::number.asc(), dsl::rank.asc()))
+                .select((
+                    dsl::id,
+                    dsl::chapter,
+                    dsl::rank,
+                    dsl::bgsoundtype,
+                    dsl::bgsoundsource,
+                    dsl::imgtype,
+                    dsl::imgsource,
+                    dsl::bgsoundasset,
+                    dsl::imgasset,
+                ))
+                .load::<Assets>(connector)
+        }
+    }
+}
+
+/// Resolve the effective image and sound of all fragments of a book
+///
+/// Fragments of the `version` of the book are returned in reading
+/// order, that is sorted by chapter then by rank within their
+/// chapter. Effective images uploaded as media assets are described
+/// along with their variants.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `effective`
+pub fn effective(
+    connector: &mut PgConnection,
+    book_id: Uuid,
+    version: Version,
+) -> ApiResult<Vec<(Simple, Effective)>> {
+    let chapters = match version {
+        Version::Draft => chapter::list(connector, book_id)?,
+        Version::Published => published::list_chapters(connector, book_id)?,
+    };
+    let fragments = assets(connector, book_id, version)?;
+    let mut resolved = resolve(&chapters, fragments);
+    add_pictures(connector, &mut resolved)?;
+    Ok(resolved)
+}
+
+/// List a page of the fragments of the `version` of a book, see
+/// `fragment::list`
+///
+/// If `resolve` is true, the effective image and sound of each
+/// fragment are returned along with it. They are resolved over the
+/// whole book, since they depend on the fragments preceding the page.
+///
+/// # Errors
+///
+/// If the cursor of `page` is not a fragment of the book, a `NotFound`
+/// error is returned. If an error is returned by diesel, forward it to
+/// the function calling `list`
+pub fn list(
+    connector: &mut PgConnection,
+    book_id: Uuid,
+    version: Version,
+    resolve: bool,
+    page: &Page,
+    fields: Option<&Fields>,
+) -> ApiResult<Paginated<Resolved<Item<Simple>>>> {
+    let fragments = match version {
+        Version::Draft => fragment::list(connector, book_id, page, fields)?,
+        Version::Published => {
+            published::list(connector, book_id, page, fields)?
+        }
+    };
+    let mut effective: HashMap<Uuid, Effective> = if resolve {
+        effective(connector, book_id, version)?
+            .into_iter()
+            .map(|(fragment, effective)| (fragment.uuid, effective))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+    Ok(fragments.map(|(id, fragment)| Resolved {
+        fragment,
+        effective: effective.remove(&id),
+    }))
+}
+
+/// List all fragments of the `version` of a chapter, see
+/// `fragment::list_chapter`
+///
+/// If `resolve` is true, the effective image and sound of each
+/// fragment are returned along with it.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list_chapter`
+pub fn list_chapter(
+    connector: &mut PgConnection,
+    chapter: &Chapter,
+    version: Version,
+    resolve: bool,
+) -> ApiResult<Vec<Resolved<Simple>>> {
+    if resolve {
+        Ok(effective(connector, chapter.book, version)?
+            .into_iter()
+            .filter(|(fragment, _)| fragment.chapter == chapter.id)
+            .map(|(fragment, effective)| Resolved {
+                fragment,
+                effective: Some(effective),
+            })
+            .collect())
+    } else {
+        let fragments = match version {
+            Version::Draft => fragment::list_chapter(connector, chapter.id)?,
+            Version::Published => {
+                published::list_chapter(connector, chapter.id)?
+            }
+        };
+        Ok(fragments
+            .into_iter()
+            .map(|fragment| Resolved {
+                fragment,
+                effective: None,
+            })
+            .collect())
+    }
+}
+
+/// Get the `version` of a full fragment along with its effective
+/// image and sound
+///
+/// # Errors
+///
+/// If the fragment does not exist in this version, diesel returns a
+/// `NotFound` error. If an error is returned by diesel, forward it to
+/// the function calling `get`
+pub fn get(
+    connector: &mut PgConnection,
+    id: Uuid,
+    version: Version,
+    resolve: bool,
+) -> ApiResult<Resolved<Bookfragment>> {
+    let fragment = match version {
+        Version::Draft => fragment::get(connector, id)?,
+        Version::Published => published::get(connector, id)?,
+    };
+    let effective = if resolve {
+        Some(
+            effective(connector, fragment.book, version)?
+                .into_iter()
+                .find(|(simple, _)| simple.uuid == id)
+                .map(|(_, effective)| effective)
+                .unwrap_or_default(),
+        )
+    } else {
+        None
+    };
+    Ok(Resolved {
+        fragment,
+        effective,
+    })
+}
+
+/// Extend the last span of `spans` with `fragment` if it uses the
+/// same asset, or start a new span otherwise
+fn extend<T: Serialize + PartialEq>(
+    spans: &mut Vec<Span<T>>,
+    asset: T,
+    fragment: Simple,
+    contiguous: bool,
+) {
+    match spans.last_mut() {
+        Some(span) if contiguous && span.asset == asset => {
+            span.to = fragment;
+            span.fragments += 1;
+        }
+        _ => spans.push(Span {
+            asset,
+            from: fragment,
+            to: fragment,
+            fragments: 1,
+        }),
+    }
+}
+
+/// Compute the ranges of fragments over which each background image
+/// and sound of a book is active
+///
+/// `resolved` must be sorted in reading order, as returned by
+/// [`effective`]. Consecutive fragments using the same asset are
+/// merged in a single span, and fragments without any background
+/// image or sound do not appear in any span.
+#[must_use]
+pub fn timeline(resolved: Vec<(Simple, Effective)>) -> Timeline {
+    let mut timeline = Timeline::default();
+    let mut previous = Effective::default();
+    for (fragment, effective) in resolved {
+        if let Some(image) = &effective.effective_image {
+            extend(
+                &mut timeline.images,
+                image.clone(),
+                fragment,
+                previous.effective_image.is_some(),
+            );
+        }
+        if let Some(sound) = &effective.effective_sound {
+            extend(
+                &mut timeline.sounds,
+                sound.clone(),
+                fragment,
+                previous.effective_sound.is_some(),
+            );
+        }
+        previous = effective;
+    }
+    timeline
+}
@@ -0,0 +1,76 @@
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use uuid::Uuid;
+
+use crate::db::ApiResult;
+use crate::models::{AuditAction, AuditEntry};
+use crate::schema::auditlog::dsl;
+
+/// Criteria the entries of the audit log must match, see [`list`]
+///
+/// Unset criteria match all entries.
+pub struct Filter {
+    pub actor: Option<Uuid>,
+    pub action: Option<AuditAction>,
+    pub entity: Option<String>,
+    pub id: Option<Uuid>,
+    pub since: Option<chrono::NaiveDateTime>,
+    pub until: Option<chrono::NaiveDateTime>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// Add entries to the audit log
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `record`
+pub fn record(
+    connector: &mut PgConnection,
+    entries: &[AuditEntry],
+) -> ApiResult<usize> {
+    diesel::insert_into(dsl::auditlog)
+        .values(entries)
+        .execute(connector)
+}
+
+/// List the entries of the audit log matching `filter`, from the
+/// newest to the oldest
+///
+/// `since` is included while `until` is excluded. At most
+/// `filter.limit` entries are returned, after skipping
+/// `filter.offset` of them.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list`
+pub fn list(
+    connector: &mut PgConnection,
+    filter: &Filter,
+) -> ApiResult<Vec<AuditEntry>> {
+    let mut query = dsl::auditlog.into_boxed();
+    if let Some(actor) = filter.actor {
+        query = query.filter(dsl::actor.eq(actor));
+    }
+    if let Some(action) = filter.action {
+        query = query.filter(dsl::action.eq(action));
+    }
+    if let Some(entity) = &filter.entity {
+        query = query.filter(dsl::entitytype.eq(entity));
+    }
+    if let Some(id) = filter.id {
+        query = query.filter(dsl::entityid.eq(id));
+    }
+    if let Some(since) = filter.since {
+        query = query.filter(dsl::created.ge(since));
+    }
+    if let Some(until) = filter.until {
+        query = query.filter(dsl::created.lt(until));
+    }
+    query
+        .order((dsl::created.desc(), dsl::id.desc()))
+        .limit(filter.limit)
+        .offset(filter.offset)
+        .load(connector)
+}
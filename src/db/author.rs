@@ -1,18 +1,121 @@
-use diesel::BoolExpressionMethods;
-use diesel::{PgConnection, PgTextExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::dsl::sql;
+use diesel::sql_types::{Bool, Float, Json, Text};
+use diesel::{BoolExpressionMethods, ExpressionMethods};
+use diesel::{NullableExpressionMethods, PgSortExpressionMethods};
+use diesel::{PgConnection, QueryDsl, RunQueryDsl};
+use rocket::serde::json::Value;
 use uuid::Uuid;
 
-use crate::schema::authors::dsl;
-use crate::{models::Author, db::ApiResult};
+use crate::db::page::{keyset, Fields, Item, Page, Paginated, Sort};
+use crate::db::search::{fuzzy, Scored};
+use crate::db::{published, serializable};
+use crate::db::ApiResult;
+use crate::models::{Author, Book, Bookfragment};
+use crate::schema::authors::{self, dsl};
+use crate::schema::{bookfragments, books};
 
-/// List authors in the database.
+/// Fields of the authors the user can select, see `page::Fields`
+pub const COLUMNS: &[(&str, &str)] = &[
+    ("id", "Authors.Id"),
+    ("firstname", "Authors.FirstName"),
+    ("lastname", "Authors.LastName"),
+    ("penname", "Authors.PenName"),
+];
+
+/// Full name of an author, as indexed for fuzzy searches, see
+/// [`find`]
+const NAMES: &str = "(COALESCE(Authors.Firstname, '') || ' ' || \
+                      COALESCE(Authors.Lastname, '') || ' ' || \
+                      COALESCE(Authors.Penname, ''))";
+
+/// Keys authors can be sorted by, see [`list`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Lastname,
+    Firstname,
+    Penname,
+}
+
+impl SortKey {
+    /// Find a sort key by its name, which is the name of its field
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lastname" => Some(Self::Lastname),
+            "firstname" => Some(Self::Firstname),
+            "penname" => Some(Self::Penname),
+            _ => None,
+        }
+    }
+}
+
+/// List a page of the authors in the database.
+///
+/// Authors are sorted according to `sort`, and those in the trash are
+/// left out. If `fields` is set, only these fields of the authors are
+/// read and returned.
 ///
 /// # Errors
 ///
-/// If an error is returned by diesel, forward it to the function
-/// calling `list`
-pub fn list(connector: &mut PgConnection) -> ApiResult<Vec<Author>> {
-    dsl::authors.load::<Author>(connector)
+/// If the cursor of `page` is not an author, a `NotFound` error is
+/// returned. If an error is returned by diesel, forward it to the
+/// function calling `list`
+pub fn list(
+    connector: &mut PgConnection,
+    page: &Page,
+    sort: Sort<SortKey>,
+    fields: Option<&Fields>,
+) -> ApiResult<Paginated<Item<Author>>> {
+    let query = dsl::authors.filter(dsl::deletedat.is_null()).into_boxed();
+    let query = match sort.key {
+        SortKey::Lastname => keyset!(
+            connector,
+            query,
+            dsl::authors,
+            dsl::lastname,
+            String,
+            dsl::id,
+            page,
+            sort
+        ),
+        SortKey::Firstname => keyset!(
+            connector,
+            query,
+            dsl::authors,
+            dsl::firstname,
+            String,
+            dsl::id,
+            page,
+            sort
+        ),
+        SortKey::Penname => keyset!(
+            connector,
+            query,
+            dsl::authors,
+            dsl::penname,
+            String,
+            dsl::id,
+            page,
+            sort
+        ),
+    }
+    .limit(page.limit + 1);
+    match fields {
+        None => {
+            Ok(
+                Paginated::new(query.load(connector)?, page, |a: &Author| a.id)
+                    .map(Item::Whole),
+            )
+        }
+        Some(fields) => Ok(Paginated::new(
+            query
+                .select((dsl::id, sql::<Json>(fields.sql())))
+                .load::<(Uuid, Value)>(connector)?,
+            page,
+            |(id, _)| *id,
+        )
+        .map(|(_, value)| Item::Partial(value))),
+    }
 }
 
 /// Add a new author in the database
@@ -29,6 +132,8 @@ pub fn new(connector: &mut PgConnection, author: Author) -> ApiResult<usize> {
 
 /// Update an author in the database
 ///
+/// Authors in the trash cannot be updated.
+///
 /// # Errors
 ///
 /// If an error is returned by diesel, forward it to the function
@@ -38,26 +143,37 @@ pub fn update(
     author: Author,
 ) -> ApiResult<usize> {
     diesel::update(dsl::authors.find(author.id))
-        .set(author)
+        .filter(dsl::deletedat.is_null())
+        .set(Author {
+            deletedat: None,
+            ..author
+        })
         .execute(connector)
 }
 
 /// Get a specific author from the database
 ///
-/// Find an author with holding the specific identifier `id`.
+/// Find an author with holding the specific identifier `id`, unless
+/// they are in the trash.
 ///
 /// # Errors
 ///
 /// If an error is returned by diesel, forward it to the function
 /// calling `get`
 pub fn get(connector: &mut PgConnection, id: Uuid) -> ApiResult<Author> {
-    dsl::authors.find(id).first(connector)
+    dsl::authors
+        .find(id)
+        .filter(dsl::deletedat.is_null())
+        .first(connector)
 }
 
 /// Find authors by name
 ///
-/// Find any author whose name contains `name`. May not work with full
-/// names of if the query contains a typo.
+/// Find any author whose first name, last name and pen name contain
+/// `name`, or are similar enough to it, so that full names such as
+/// "Victor Hugo" and queries with typos find their author. Authors
+/// are returned along with the word similarity of their name with
+/// `name`, from the most to the least similar, see `search::fuzzy`.
 ///
 /// # Errors
 ///
@@ -65,29 +181,93 @@ pub fn get(connector: &mut PgConnection, id: Uuid) -> ApiResult<Author> {
 /// calling `find`
 pub fn find(
     connector: &mut PgConnection,
-    name: &String,
-) -> ApiResult<Vec<Author>> {
-    let str_query = format!("%{}%", name);
-    dsl::authors
-        .filter(
-            dsl::firstname.ilike(str_query.to_string()).or(dsl::lastname
-                .ilike(str_query.to_string())
-                .or(dsl::penname.ilike(str_query))),
-        )
-        .load::<Author>(connector)
+    name: &str,
+) -> ApiResult<Vec<Scored<Author>>> {
+    let score = || {
+        sql::<Float>("WORD_SIMILARITY(")
+            .bind::<Text, _>(name.to_owned())
+            .sql(&format!(", {})", NAMES))
+    };
+    let matches = sql::<Bool>("(")
+        .bind::<Text, _>(name.to_owned())
+        .sql(&format!(" <% {0} OR {0} ILIKE '%' || ", NAMES))
+        .bind::<Text, _>(name.to_owned())
+        .sql(" || '%')");
+    let found = fuzzy(connector, |connector| {
+        dsl::authors
+            .filter(dsl::deletedat.is_null())
+            .filter(matches)
+            .select((authors::all_columns, score()))
+            .order((score().desc(), dsl::id))
+            .load::<(Author, f32)>(connector)
+    })?;
+    Ok(found
+        .into_iter()
+        .map(|(item, score)| Scored { item, score })
+        .collect())
 }
 
-/// Delete a specific author from the database
+/// Move a specific author to the trash
+///
+/// The author holding the identifier `id` is moved to the trash along
+/// with their books, including the ones last published under their
+/// name, and the fragments of these books, which all share the same
+/// deletion date so they can be restored together, see
+/// `trash::restore`. They are deleted for good once the retention
+/// period of the trash is over, see `trash::purge`.
 ///
-/// Delete the author holding the identifier `id`.
+/// The whole operation runs in a single serializable transaction.
+/// Returns the books and fragments moved to the trash along with the
+/// author, as they were before.
 ///
 /// # Errors
 ///
-/// If an error is returned by diesel, forward it to the function
-/// calling `delete`
-pub fn delete(connector: &mut PgConnection, id: Uuid) -> ApiResult<()> {
-    match diesel::delete(dsl::authors.find(id)).execute(connector) {
-        Ok(_) => Ok(()),
-        Err(e) => Err(e),
-    }
+/// If the author does not exist or is already in the trash, a
+/// `NotFound` error is returned. If an error is returned by diesel,
+/// forward it to the function calling `delete`
+pub fn delete(
+    connector: &mut PgConnection,
+    id: Uuid,
+) -> ApiResult<(Vec<Book>, Vec<Bookfragment>)> {
+    serializable(connector, |connector| {
+        let now = chrono::Utc::now().naive_utc();
+        let trashed = diesel::update(dsl::authors.find(id))
+            .filter(dsl::deletedat.is_null())
+            .set(dsl::deletedat.eq(now))
+            .execute(connector)?;
+        if trashed == 0 {
+            return Err(diesel::result::Error::NotFound);
+        }
+        let published = published::by_author(connector, id)?;
+        let trashed_books: Vec<Book> = diesel::update(books::table)
+            .filter(books::author.eq(id).or(books::id.eq_any(published)))
+            .filter(books::deletedat.is_null())
+            .set(books::deletedat.eq(now))
+            .get_results(connector)?;
+        let trashed_fragments: Vec<Bookfragment> =
+            diesel::update(bookfragments::table)
+                .filter(
+                    bookfragments::book
+                        .eq_any(trashed_books.iter().map(|book| book.id)),
+                )
+                .filter(bookfragments::deletedat.is_null())
+                .set(bookfragments::deletedat.eq(now))
+                .get_results(connector)?;
+        Ok((
+            trashed_books
+                .into_iter()
+                .map(|book| Book {
+                    deletedat: None,
+                    ..book
+                })
+                .collect(),
+            trashed_fragments
+                .into_iter()
+                .map(|fragment| Bookfragment {
+                    deletedat: None,
+                    ..fragment
+                })
+                .collect(),
+        ))
+    })
 }
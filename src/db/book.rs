@@ -1,11 +1,21 @@
+use diesel::dsl::sql;
+use diesel::sql_types::{Bool, Float, Json, Text};
 use diesel::{
-    insert_into, PgConnection, PgTextExpressionMethods, QueryDsl, RunQueryDsl,
+    insert_into, BoolExpressionMethods, ExpressionMethods,
+    NullableExpressionMethods, PgConnection, PgSortExpressionMethods,
+    PgTextExpressionMethods, QueryDsl, RunQueryDsl,
 };
-use rocket::serde::Deserialize;
+use rocket::serde::json::Value;
+use rocket::serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::models::BookType;
+use crate::db::media::{self, Picture};
+use crate::db::page::{keyset, Fields, Item, Page, Paginated, Sort};
+use crate::db::search::{fuzzy, Scored};
+use crate::db::serializable;
+use crate::models::{BookStatus, BookType, Bookfragment};
 use crate::schema::books::dsl;
+use crate::schema::{authors, bookfragments};
 use crate::{db::ApiResult, models::Book};
 
 /// Advanced search query for books
@@ -21,54 +31,370 @@ pub struct SearchQuery {
     pub booktype: Option<BookType>,
 }
 
-/// Add a new book in the database
+diesel::table! {
+    use diesel::sql_types::{
+        Array, Date, Nullable, Text, Timestamp, Uuid, Varchar,
+    };
+    use crate::schema::sql_types::{Booktype, Bookstatus};
+
+    /// Books as everyone reads them, see the `PublicBooks` view: the
+    /// metadata of published books are the ones of their last
+    /// publication, see `published::book`, and the ones of the other
+    /// books are their draft
+    publicbooks (id) {
+        id -> Uuid,
+        title -> Varchar,
+        author -> Uuid,
+        isbn -> Nullable<Array<Nullable<Text>>>,
+        cover -> Nullable<Varchar>,
+        publisher -> Nullable<Varchar>,
+        published -> Nullable<Date>,
+        genre -> Nullable<Array<Nullable<Text>>>,
+        synopsis -> Nullable<Text>,
+        booktype -> Booktype,
+        coverasset -> Nullable<Uuid>,
+        owner -> Nullable<Uuid>,
+        status -> Bookstatus,
+        deletedat -> Nullable<Timestamp>,
+        language -> Varchar,
+    }
+}
+
+/// Fields of the books the user can select, see `page::Fields`
+pub const COLUMNS: &[(&str, &str)] = &[
+    ("id", "PublicBooks.Id"),
+    ("title", "PublicBooks.Title"),
+    ("author", "PublicBooks.Author"),
+    ("isbn", "PublicBooks.Isbn"),
+    ("cover", "PublicBooks.Cover"),
+    ("publisher", "PublicBooks.Publisher"),
+    ("published", "PublicBooks.Published"),
+    ("genre", "PublicBooks.Genre"),
+    ("synopsis", "PublicBooks.Synopsis"),
+    (
+        "booktype",
+        "REPLACE(INITCAP(PublicBooks.BookType::TEXT), '-', '')",
+    ),
+    ("coverasset", "PublicBooks.CoverAsset"),
+    ("owner", "PublicBooks.Owner"),
+    ("status", "REPLACE(PublicBooks.Status::TEXT, '_', '-')"),
+    ("language", "PublicBooks.Language"),
+];
+
+/// Keys books can be sorted by, see [`list`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Title,
+    Published,
+}
+
+impl SortKey {
+    /// Find a sort key by its name, which is the name of its field
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "title" => Some(Self::Title),
+            "published" => Some(Self::Published),
+            _ => None,
+        }
+    }
+}
+
+/// Books a user can read, see `auth::readable_by`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Readable {
+    /// All books
+    All,
+    /// Published books, the books of `owner` if set, and the books
+    /// whose drafts can be viewed, listed in `drafts`
+    Published {
+        owner: Option<Uuid>,
+        drafts: Vec<Uuid>,
+    },
+}
+
+impl Readable {
+    /// Whether `book` can be read
+    #[must_use]
+    pub fn allows(&self, book: &Book) -> bool {
+        match self {
+            Self::All => true,
+            Self::Published { owner, drafts } => {
+                book.status == BookStatus::Published
+                    || (owner.is_some() && book.owner == *owner)
+                    || drafts.contains(&book.id)
+            }
+        }
+    }
+}
+
+/// A book, along with its cover if it is an image uploaded as a
+/// media asset
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct WithCover {
+    #[serde(flatten)]
+    pub book: Book,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover_picture: Option<Picture>,
+}
+
+/// Describe the covers of `books` which are media assets, see
+/// `media::pictures`
 ///
 /// # Errors
 ///
 /// If an error is returned by diesel, forward it to the function
-/// calling `new`
+/// calling `with_covers`
+pub fn with_covers(
+    connector: &mut PgConnection,
+    books: Vec<Book>,
+) -> ApiResult<Vec<WithCover>> {
+    let ids: Vec<Uuid> =
+        books.iter().filter_map(|book| book.coverasset).collect();
+    let pictures = media::pictures(connector, &ids)?;
+    Ok(books
+        .into_iter()
+        .map(|book| WithCover {
+            cover_picture: book
+                .coverasset
+                .and_then(|id| pictures.get(&id).cloned()),
+            book,
+        })
+        .collect())
+}
+
+/// Describe the cover of `book` if it is a media asset, see
+/// [`with_covers`]
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `with_cover`
+pub fn with_cover(
+    connector: &mut PgConnection,
+    book: Book,
+) -> ApiResult<WithCover> {
+    let ids: Vec<Uuid> = book.coverasset.into_iter().collect();
+    let mut pictures = media::pictures(connector, &ids)?;
+    Ok(WithCover {
+        cover_picture: book.coverasset.and_then(|id| pictures.remove(&id)),
+        book,
+    })
+}
+
+/// Check the author `author` exists and is not in the trash
+///
+/// Books cannot be attached to authors in the trash, which would
+/// delete them for good when purging the trash, see `trash::purge`.
+///
+/// # Errors
+///
+/// If the author does not exist or is in the trash, a `NotFound`
+/// error is returned. If an error is returned by diesel, forward it
+/// to the function calling `check_author`
+fn check_author(connector: &mut PgConnection, author: Uuid) -> ApiResult<()> {
+    authors::table
+        .find(author)
+        .filter(authors::deletedat.is_null())
+        .select(authors::id)
+        .first::<Uuid>(connector)
+        .map(|_| ())
+}
+
+/// Add a new book in the database
+///
+/// # Errors
+///
+/// If the author of the book does not exist or is in the trash, a
+/// `NotFound` error is returned. If an error is returned by diesel,
+/// forward it to the function calling `new`
 pub fn new(connector: &mut PgConnection, book: Book) -> ApiResult<usize> {
+    check_author(connector, book.author)?;
     insert_into(dsl::books).values(book).execute(connector)
 }
 
-/// List books in the database.
+/// List a page of the books in the database.
+///
+/// Only the `readable` books are listed, sorted according to `sort`,
+/// and those in the trash are left out. Published books are listed
+/// as they were last published, see [`publicbooks`]. If `fields` is
+/// set, only these fields of the books are read and returned.
+/// Otherwise, whole books are returned along with their cover, see
+/// [`with_covers`].
 ///
 /// # Errors
 ///
-/// If an error is returned by diesel, forward it to the function
-/// calling `list`
-pub fn list(connector: &mut PgConnection) -> ApiResult<Vec<Book>> {
-    dsl::books.load::<Book>(connector)
+/// If the cursor of `page` is not a book, a `NotFound` error is
+/// returned. If an error is returned by diesel, forward it to the
+/// function calling `list`
+pub fn list(
+    connector: &mut PgConnection,
+    page: &Page,
+    sort: Sort<SortKey>,
+    readable: &Readable,
+    fields: Option<&Fields>,
+) -> ApiResult<Paginated<Item<WithCover>>> {
+    use publicbooks::dsl;
+    let mut query =
+        dsl::publicbooks.filter(dsl::deletedat.is_null()).into_boxed();
+    if let Readable::Published { owner, drafts } = readable {
+        let published = dsl::status.eq(BookStatus::Published);
+        query = match owner {
+            Some(owner) => query.filter(
+                published
+                    .or(dsl::owner.eq(owner))
+                    .or(dsl::id.eq_any(drafts.clone())),
+            ),
+            None => query.filter(published.or(dsl::id.eq_any(drafts.clone()))),
+        };
+    }
+    let query = match sort.key {
+        SortKey::Title => keyset!(
+            connector,
+            query,
+            dsl::publicbooks,
+            dsl::title,
+            String,
+            dsl::id,
+            page,
+            sort
+        ),
+        SortKey::Published => keyset!(
+            connector,
+            query,
+            dsl::publicbooks,
+            dsl::published,
+            chrono::NaiveDate,
+            dsl::id,
+            page,
+            sort
+        ),
+    }
+    .limit(page.limit + 1);
+    match fields {
+        None => {
+            let books =
+                Paginated::new(query.load(connector)?, page, |b: &Book| b.id);
+            let next = books.next;
+            Ok(Paginated {
+                items: with_covers(connector, books.items)?
+                    .into_iter()
+                    .map(Item::Whole)
+                    .collect(),
+                next,
+            })
+        }
+        Some(fields) => Ok(Paginated::new(
+            query
+                .select((dsl::id, sql::<Json>(fields.sql())))
+                .load::<(Uuid, Value)>(connector)?,
+            page,
+            |(id, _)| *id,
+        )
+        .map(|(_, value)| Item::Partial(value))),
+    }
 }
 
 /// Get a specific book from the database
 ///
-/// Find a book with holding the specific identifier `id`.
+/// Find a book with holding the specific identifier `id`, unless it
+/// is in the trash.
 ///
 /// # Errors
 ///
 /// If an error is returned by diesel, forward it to the function
 /// calling `get`
 pub fn get(connector: &mut PgConnection, identifier: Uuid) -> ApiResult<Book> {
-    dsl::books.find(identifier).first(connector)
+    dsl::books
+        .find(identifier)
+        .filter(dsl::deletedat.is_null())
+        .first(connector)
 }
 
-/// Update a book
+/// List the books of the author `author`, except the ones in the
+/// trash
 ///
 /// # Errors
 ///
 /// If an error is returned by diesel, forward it to the function
-/// calling `get`
+/// calling `by_author`
+pub fn by_author(
+    connector: &mut PgConnection,
+    author: Uuid,
+) -> ApiResult<Vec<Book>> {
+    dsl::books
+        .filter(dsl::author.eq(author))
+        .filter(dsl::deletedat.is_null())
+        .load(connector)
+}
+
+/// Update a book
+///
+/// Books in the trash cannot be updated.
+///
+/// # Errors
+///
+/// If the author of the book does not exist or is in the trash, a
+/// `NotFound` error is returned. If an error is returned by diesel,
+/// forward it to the function calling `update`
 pub fn update(connector: &mut PgConnection, book: Book) -> ApiResult<usize> {
+    check_author(connector, book.author)?;
     diesel::update(dsl::books.find(book.id))
-        .set(book)
+        .filter(dsl::deletedat.is_null())
+        .set(Book {
+            deletedat: None,
+            ..book
+        })
         .execute(connector)
 }
 
+/// Give a book to the user `owner`
+///
+/// # Errors
+///
+/// If the user does not exist, diesel returns a `ForeignKeyViolation`
+/// error. If an error is returned by diesel, forward it to the
+/// function calling `set_owner`
+pub fn set_owner(
+    connector: &mut PgConnection,
+    identifier: Uuid,
+    owner: Uuid,
+) -> ApiResult<Book> {
+    diesel::update(dsl::books.find(identifier))
+        .filter(dsl::deletedat.is_null())
+        .set(dsl::owner.eq(owner))
+        .get_result(connector)
+}
+
+/// Move a book to another stage of the publishing workflow
+///
+/// To publish a book, see `published::publish` instead.
+///
+/// # Errors
+///
+/// If the book does not exist, diesel returns a `NotFound` error. If
+/// an error is returned by diesel, forward it to the function calling
+/// `set_status`
+pub fn set_status(
+    connector: &mut PgConnection,
+    identifier: Uuid,
+    status: BookStatus,
+) -> ApiResult<Book> {
+    diesel::update(dsl::books.find(identifier))
+        .filter(dsl::deletedat.is_null())
+        .set(dsl::status.eq(status))
+        .get_result(connector)
+}
+
 /// Find a book by title
 ///
-/// Find a book whose title contains `name`. May not work with typos
-/// in the query.
+/// Find a book whose title contains `name` or is similar enough to
+/// it, so that queries with typos find their book, leaving out the
+/// books in the trash. Published books are found by the title they
+/// were last published with, see [`publicbooks`]. Books are returned
+/// along with the word similarity of their title with `name`, from
+/// the most to the least similar, see `search::fuzzy`.
 ///
 /// # Errors
 ///
@@ -76,18 +402,38 @@ pub fn update(connector: &mut PgConnection, book: Book) -> ApiResult<usize> {
 /// calling `find`
 pub fn find(
     connector: &mut PgConnection,
-    name: &String,
-) -> ApiResult<Vec<Book>> {
-    let query = format!("%{}%", name);
-    dsl::books
-        .filter(dsl::title.ilike(query))
-        .load::<Book>(connector)
+    name: &str,
+) -> ApiResult<Vec<Scored<Book>>> {
+    use publicbooks::dsl;
+    let score = || {
+        sql::<Float>("WORD_SIMILARITY(")
+            .bind::<Text, _>(name.to_owned())
+            .sql(", PublicBooks.Title)")
+    };
+    let matches = sql::<Bool>("(")
+        .bind::<Text, _>(name.to_owned())
+        .sql(" <% PublicBooks.Title OR PublicBooks.Title ILIKE '%' || ")
+        .bind::<Text, _>(name.to_owned())
+        .sql(" || '%')");
+    let found = fuzzy(connector, |connector| {
+        dsl::publicbooks
+            .filter(dsl::deletedat.is_null())
+            .filter(matches)
+            .select((publicbooks::all_columns, score()))
+            .order((score().desc(), dsl::id))
+            .load::<(Book, f32)>(connector)
+    })?;
+    Ok(found
+        .into_iter()
+        .map(|(item, score)| Scored { item, score })
+        .collect())
 }
 
 /// Do an advanced search for books
 ///
-/// Search a book by its title, type, and genres. Similar to [`find`].
-/// See [`SearchQuery`] for more details.
+/// Search a book by its title, type, and genres. Similar to [`find`],
+/// books in the trash are left out and published books are searched
+/// as they were last published. See [`SearchQuery`] for more details.
 ///
 /// # Errors
 ///
@@ -100,7 +446,9 @@ pub fn advanced_find(
     connector: &mut PgConnection,
     search: SearchQuery,
 ) -> ApiResult<Vec<Book>> {
-    let books: Vec<Book> = dsl::books
+    use publicbooks::dsl;
+    let books: Vec<Book> = dsl::publicbooks
+        .filter(dsl::deletedat.is_null())
         .filter(
             dsl::title.ilike(format!("%{}%", search.name.unwrap_or_default())),
         )
@@ -125,17 +473,47 @@ pub fn advanced_find(
     Ok(books)
 }
 
-/// Delete a specific book from the database
+/// Move a specific book to the trash
+///
+/// The book holding the identifier `id` is moved to the trash along
+/// with its fragments, which share the same deletion date so they
+/// can be restored together, see `trash::restore`. They are deleted
+/// for good once the retention period of the trash is over, see
+/// `trash::purge`.
 ///
-/// Delete the book holding the identifier `id`.
+/// The whole operation runs in a single serializable transaction.
+/// Returns the fragments moved to the trash along with the book, as
+/// they were before.
 ///
 /// # Errors
 ///
-/// If an error is returned by diesel, forward it to the function
-/// calling `delete`
-pub fn delete(connector: &mut PgConnection, identifier: Uuid) -> ApiResult<()> {
-    match diesel::delete(dsl::books.find(identifier)).execute(connector) {
-        Ok(_) => Ok(()),
-        Err(e) => Err(e),
-    }
+/// If the book does not exist or is already in the trash, a
+/// `NotFound` error is returned. If an error is returned by diesel,
+/// forward it to the function calling `delete`
+pub fn delete(
+    connector: &mut PgConnection,
+    identifier: Uuid,
+) -> ApiResult<Vec<Bookfragment>> {
+    serializable(connector, |connector| {
+        let now = chrono::Utc::now().naive_utc();
+        let trashed = diesel::update(dsl::books.find(identifier))
+            .filter(dsl::deletedat.is_null())
+            .set(dsl::deletedat.eq(now))
+            .execute(connector)?;
+        if trashed == 0 {
+            return Err(diesel::result::Error::NotFound);
+        }
+        let fragments: Vec<Bookfragment> = diesel::update(bookfragments::table)
+            .filter(bookfragments::book.eq(identifier))
+            .filter(bookfragments::deletedat.is_null())
+            .set(bookfragments::deletedat.eq(now))
+            .get_results(connector)?;
+        Ok(fragments
+            .into_iter()
+            .map(|fragment| Bookfragment {
+                deletedat: None,
+                ..fragment
+            })
+            .collect())
+    })
 }
@@ -0,0 +1,230 @@
+use diesel::expression_methods::ExpressionMethods;
+use diesel::{sql_query, Connection, PgConnection, QueryDsl, RunQueryDsl};
+use uuid::Uuid;
+
+use crate::db::{serializable, ApiResult};
+use crate::models::{Bookfragment, Chapter};
+use crate::schema::bookfragments;
+use crate::schema::chapters::dsl;
+
+/// List all chapters of a book
+///
+/// Chapters are sorted by their number, and the ones in the trash are
+/// left out.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list`
+pub fn list(
+    connector: &mut PgConnection,
+    book_id: Uuid,
+) -> ApiResult<Vec<Chapter>> {
+    dsl::chapters
+        .filter(dsl::book.eq(book_id))
+        .filter(dsl::deletedat.is_null())
+        .order(dsl::number.asc())
+        .load::<Chapter>(connector)
+}
+
+/// Get a specific chapter from the database
+///
+/// # Errors
+///
+/// If the chapter does not exist or is in the trash, a `NotFound`
+/// error is returned. If an error is returned by diesel, forward it
+/// to the function calling `get`
+pub fn get(connector: &mut PgConnection, id: Uuid) -> ApiResult<Chapter> {
+    dsl::chapters
+        .find(id)
+        .filter(dsl::deletedat.is_null())
+        .first(connector)
+}
+
+/// Count the chapters of a book, leaving out the ones in the trash
+fn count(connector: &mut PgConnection, book_id: Uuid) -> ApiResult<i32> {
+    let count: i64 = dsl::chapters
+        .filter(dsl::book.eq(book_id))
+        .filter(dsl::deletedat.is_null())
+        .count()
+        .get_result(connector)?;
+    Ok(i32::try_from(count).unwrap_or(i32::MAX))
+}
+
+/// Shift chapters in a book
+///
+/// Shift all chapters of a book numbered from `from` (included) to
+/// `to` (excluded) by `shift`. If `to` is `None`, shift all chapters
+/// until the end of the book. Chapters in the trash keep their
+/// number.
+///
+/// # Errors
+///
+/// Any error returned by diesel will be forwarded to the caller of
+/// `shift_chapters`
+fn shift_chapters(
+    connector: &mut PgConnection,
+    book_id: Uuid,
+    from: i32,
+    to: Option<i32>,
+    shift: i32,
+) -> ApiResult<usize> {
+    diesel::update(dsl::chapters)
+        .filter(dsl::book.eq(book_id))
+        .filter(dsl::deletedat.is_null())
+        .filter(dsl::number.ge(from))
+        .filter(dsl::number.lt(to.unwrap_or(i32::MAX)))
+        .set(dsl::number.eq(dsl::number + shift))
+        .execute(connector)
+}
+
+/// Create a new chapter
+///
+/// If a chapter already exists in the same book with the same number,
+/// treat this as an insert and shift the chapter and all following
+/// ones by one. If the number exceeds the amount of chapters in the
+/// book, the chapter is appended at the end of the book.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `new`
+pub fn new(
+    connector: &mut PgConnection,
+    mut chapter: Chapter,
+) -> ApiResult<Chapter> {
+    connector.transaction(|connector| {
+        let last = count(connector, chapter.book)? + 1;
+        chapter.number = chapter.number.clamp(1, last);
+        shift_chapters(connector, chapter.book, chapter.number, None, 1)?;
+        diesel::insert_into(dsl::chapters)
+            .values(chapter)
+            .get_result(connector)
+    })
+}
+
+/// Update a chapter
+///
+/// If the chapter’s number changed, shift the chapters between its
+/// former and its new number in order to keep the numbering of the
+/// book continuous.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `update`
+pub fn update(
+    connector: &mut PgConnection,
+    mut chapter: Chapter,
+) -> ApiResult<Chapter> {
+    connector.transaction(|connector| {
+        let original = get(connector, chapter.id)?;
+        chapter.book = original.book;
+        chapter.number =
+            chapter.number.clamp(1, count(connector, chapter.book)?);
+        if original.number != chapter.number {
+            sql_query("SET CONSTRAINTS ALL DEFERRED").execute(connector)?;
+            if original.number < chapter.number {
+                shift_chapters(
+                    connector,
+                    chapter.book,
+                    original.number + 1,
+                    Some(chapter.number + 1),
+                    -1,
+                )?;
+            } else {
+                shift_chapters(
+                    connector,
+                    chapter.book,
+                    chapter.number,
+                    Some(original.number),
+                    1,
+                )?;
+            }
+        }
+        diesel::update(dsl::chapters.find(chapter.id))
+            .set(chapter)
+            .get_result(connector)
+    })
+}
+
+/// Move a chapter to the trash along with its fragments
+///
+/// The fragments of the chapter share its deletion date so they can
+/// be restored together, see [`restore`]. The chapters following the
+/// deleted one are renumbered so the book does not end up with a gap
+/// in its chapters. They are deleted for good once the retention
+/// period of the trash is over, see `trash::purge`.
+///
+/// The whole operation runs in a single serializable transaction.
+/// Returns the fragments moved to the trash along with the chapter,
+/// as they were before.
+///
+/// # Errors
+///
+/// If the chapter does not exist or is already in the trash, a
+/// `NotFound` error is returned. If an error is returned by diesel,
+/// forward it to the function calling `delete`
+pub fn delete(
+    connector: &mut PgConnection,
+    id: Uuid,
+) -> ApiResult<Vec<Bookfragment>> {
+    serializable(connector, |connector| {
+        let now = chrono::Utc::now().naive_utc();
+        let chapter: Chapter = diesel::update(dsl::chapters.find(id))
+            .filter(dsl::deletedat.is_null())
+            .set(dsl::deletedat.eq(now))
+            .get_result(connector)?;
+        let fragments: Vec<Bookfragment> =
+            diesel::update(bookfragments::table)
+                .filter(bookfragments::chapter.eq(id))
+                .filter(bookfragments::deletedat.is_null())
+                .set(bookfragments::deletedat.eq(now))
+                .get_results(connector)?;
+        shift_chapters(connector, chapter.book, chapter.number + 1, None, -1)?;
+        Ok(fragments
+            .into_iter()
+            .map(|fragment| Bookfragment {
+                deletedat: None,
+                ..fragment
+            })
+            .collect())
+    })
+}
+
+/// Bring a chapter back from the trash
+///
+/// The chapter is inserted back at the number it had in its book when
+/// it was moved to the trash, or at the end of the book if the book
+/// got shorter since, and the chapters at and after this number are
+/// shifted by one. Its fragments moved to the trash along with it are
+/// restored too, while the ones deleted earlier stay in the trash.
+/// The whole operation runs in a single serializable transaction.
+///
+/// # Errors
+///
+/// If the chapter is not in the trash, a `NotFound` error is
+/// returned. If an error is returned by diesel, forward it to the
+/// function calling `restore`
+pub fn restore(connector: &mut PgConnection, id: Uuid) -> ApiResult<Chapter> {
+    serializable(connector, |connector| {
+        let trashed: Chapter = dsl::chapters
+            .find(id)
+            .filter(dsl::deletedat.is_not_null())
+            .first(connector)?;
+        let last = count(connector, trashed.book)? + 1;
+        let number = trashed.number.clamp(1, last);
+        shift_chapters(connector, trashed.book, number, None, 1)?;
+        diesel::update(bookfragments::table)
+            .filter(bookfragments::chapter.eq(id))
+            .filter(bookfragments::deletedat.eq(trashed.deletedat))
+            .set(bookfragments::deletedat.eq(None::<chrono::NaiveDateTime>))
+            .execute(connector)?;
+        diesel::update(dsl::chapters.find(id))
+            .set((
+                dsl::number.eq(number),
+                dsl::deletedat.eq(None::<chrono::NaiveDateTime>),
+            ))
+            .get_result(connector)
+    })
+}
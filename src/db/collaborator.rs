@@ -0,0 +1,104 @@
+use diesel::upsert::excluded;
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, OptionalExtension, PgConnection,
+    QueryDsl, RunQueryDsl,
+};
+use uuid::Uuid;
+
+use crate::db::ApiResult;
+use crate::models::Collaborator;
+use crate::schema::collaborators::dsl;
+
+/// List the collaborators of a book
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list`
+pub fn list(
+    connector: &mut PgConnection,
+    book: Uuid,
+) -> ApiResult<Vec<Collaborator>> {
+    dsl::collaborators
+        .filter(dsl::book.eq(book))
+        .order(dsl::id.asc())
+        .load::<Collaborator>(connector)
+}
+
+/// List the books whose drafts `member` can view as a collaborator
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `drafts`
+pub fn drafts(
+    connector: &mut PgConnection,
+    member: Uuid,
+) -> ApiResult<Vec<Uuid>> {
+    dsl::collaborators
+        .filter(dsl::member.eq(member).and(dsl::viewdraft.eq(true)))
+        .select(dsl::book)
+        .load::<Uuid>(connector)
+}
+
+/// Get the permissions of `member` on a book, if they collaborate on
+/// it
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `get`
+pub fn get(
+    connector: &mut PgConnection,
+    book: Uuid,
+    member: Uuid,
+) -> ApiResult<Option<Collaborator>> {
+    dsl::collaborators
+        .filter(dsl::book.eq(book).and(dsl::member.eq(member)))
+        .first(connector)
+        .optional()
+}
+
+/// Add a collaborator to a book, or replace their permissions if they
+/// already collaborate on it
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `set`
+pub fn set(
+    connector: &mut PgConnection,
+    collaborator: &Collaborator,
+) -> ApiResult<Collaborator> {
+    diesel::insert_into(dsl::collaborators)
+        .values(collaborator)
+        .on_conflict((dsl::book, dsl::member))
+        .do_update()
+        .set((
+            dsl::viewdraft.eq(excluded(dsl::viewdraft)),
+            dsl::editfragments.eq(excluded(dsl::editfragments)),
+            dsl::publish.eq(excluded(dsl::publish)),
+            dsl::deletebook.eq(excluded(dsl::deletebook)),
+        ))
+        .get_result(connector)
+}
+
+/// Remove a collaborator from a book
+///
+/// Returns the amount of collaborators removed.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `delete`
+pub fn delete(
+    connector: &mut PgConnection,
+    book: Uuid,
+    member: Uuid,
+) -> ApiResult<usize> {
+    diesel::delete(
+        dsl::collaborators
+            .filter(dsl::book.eq(book).and(dsl::member.eq(member))),
+    )
+    .execute(connector)
+}
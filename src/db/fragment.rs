@@ -1,18 +1,31 @@
 use std::cmp::Ordering;
 
+use diesel::dsl::sql;
 use diesel::expression_methods::ExpressionMethods;
-use diesel::{PgConnection, QueryDsl, RunQueryDsl};
+use diesel::sql_types::{self, Json};
+use diesel::{
+    sql_query, BoolExpressionMethods, PgConnection, QueryDsl, RunQueryDsl,
+};
 use rayon::prelude::*;
+use rocket::serde::json::Value;
 use rocket::serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::db::page::{Fields, Item, Page, Paginated};
+use crate::db::{revision, serializable, ApiResult};
+use crate::models::{Bookfragment, Chapter};
 use crate::schema::bookfragments::{self, dsl};
-use crate::{models::Bookfragment, db::ApiResult};
+use crate::schema::chapters;
 
+/// Lightweight representation of a fragment
+///
+/// Fragments are compared by their rank only, which is only
+/// meaningful for fragments belonging to the same chapter.
 #[derive(Serialize, Deserialize, Copy, Clone, Eq)]
 #[serde(crate = "rocket::serde")]
 pub struct Simple {
     pub uuid: Uuid,
+    pub chapter: Uuid,
     pub rank: i32,
 }
 
@@ -34,123 +47,430 @@ impl PartialEq for Simple {
     }
 }
 
-/// List all fragments of a book
+/// Fields of the fragments stored in `table` the user can select, see
+/// `page::Fields`
 ///
-/// In order to preserve bandwidth, only the UUID and the rank of all
-/// fragments are returned. If the user wishes to get a full fragment,
-/// they can instead use the function `get`.
+/// The identifier of a fragment is named `uuid`, as in [`Simple`].
+#[must_use]
+pub fn columns(table: &str) -> Vec<(&'static str, String)> {
+    let column = |name: &str| format!("{}.{}", table, name);
+    let label = |name: &str| format!("INITCAP({}.{}::TEXT)", table, name);
+    vec![
+        ("uuid", column("Id")),
+        ("book", column("Book")),
+        ("chapter", column("Chapter")),
+        ("rank", column("Rank")),
+        ("content", column("Content")),
+        ("oneshotsoundsource", column("OneShotSoundSource")),
+        ("bgsoundtype", label("BgSoundType")),
+        ("bgsoundsource", column("BgSoundSource")),
+        ("imgtype", label("ImgType")),
+        ("imgsource", column("ImgSource")),
+        ("oneshotsoundasset", column("OneShotSoundAsset")),
+        ("bgsoundasset", column("BgSoundAsset")),
+        ("imgasset", column("ImgAsset")),
+    ]
+}
+
+/// List a page of the fragments of a book
+///
+/// In order to preserve bandwidth, only the UUID, the chapter and the
+/// rank of the fragments are returned, or the `fields` asked for if
+/// any. If the user wishes to get a full fragment, they can instead
+/// use the function `get`. Fragments are sorted by chapter, then by
+/// rank within their chapter, and the ones in the trash are left out.
+/// Each fragment is paired with its UUID, even when it is not among
+/// the `fields`.
 ///
 /// # Errors
 ///
-/// If an error is returned by diesel, forward it to the function
-/// calling `list`
+/// If the cursor of `page` is not a fragment of the book, a `NotFound`
+/// error is returned. If an error is returned by diesel, forward it to
+/// the function calling `list`
 pub fn list(
     connector: &mut PgConnection,
     book_id: Uuid,
-) -> ApiResult<Vec<Simple>> {
-    let mut list = dsl::bookfragments
+    page: &Page,
+    fields: Option<&Fields>,
+) -> ApiResult<Paginated<(Uuid, Item<Simple>)>> {
+    let mut query = dsl::bookfragments
+        .inner_join(chapters::table)
         .filter(dsl::book.eq(book_id))
-        .load::<Bookfragment>(connector)?
+        .filter(dsl::deletedat.is_null())
+        .into_boxed();
+    if let Some(cursor) = page.cursor {
+        let (number, rank): (i32, i32) = dsl::bookfragments
+            .inner_join(chapters::table)
+            .filter(dsl::id.eq(cursor))
+            .filter(dsl::book.eq(book_id))
+            .filter(dsl::deletedat.is_null())
+            .select((chapters::number, dsl::rank))
+            .first(connector)?;
+        query = query.filter(
+            chapters::number
+                .gt(number)
+                .or(chapters::number.eq(number).and(dsl::rank.gt(rank))),
+        );
+    }
+    let query = query
+        .order((chapters::number.asc(), dsl::rank.asc()))
+        .limit(page.limit + 1);
+    match fields {
+        None => Ok(Paginated::new(
+            query
+                .select((dsl::id, dsl::chapter, dsl::rank))
+                .load::<(Uuid, Uuid, i32)>(connector)?,
+            page,
+            |(uuid, _, _)| *uuid,
+        )
+        .map(|(uuid, chapter, rank)| {
+            let fragment = Simple {
+                uuid,
+                chapter,
+                rank,
+            };
+            (uuid, Item::Whole(fragment))
+        })),
+        Some(fields) => Ok(Paginated::new(
+            query
+                .select((dsl::id, sql::<Json>(fields.sql())))
+                .load::<(Uuid, Value)>(connector)?,
+            page,
+            |(uuid, _)| *uuid,
+        )
+        .map(|(uuid, value)| (uuid, Item::Partial(value)))),
+    }
+}
+
+/// List all full fragments of a book
+///
+/// Contrary to [`list`], the full fragments are returned. Fragments
+/// are sorted by chapter, then by rank within their chapter.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list_full`
+///
+/// [`list`]: ./fn.list.html
+pub fn list_full(
+    connector: &mut PgConnection,
+    book_id: Uuid,
+) -> ApiResult<Vec<Bookfragment>> {
+    dsl::bookfragments
+        .inner_join(chapters::table)
+        .filter(dsl::book.eq(book_id))
+        .filter(dsl::deletedat.is_null())
+        .order((chapters::number.asc(), dsl::rank.asc()))
+        .select(bookfragments::all_columns)
+        .load::<Bookfragment>(connector)
+}
+
+/// List all fragments of a chapter
+///
+/// Same as [`list`], but restricted to a single chapter.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list_chapter`
+///
+/// [`list`]: ./fn.list.html
+pub fn list_chapter(
+    connector: &mut PgConnection,
+    chapter_id: Uuid,
+) -> ApiResult<Vec<Simple>> {
+    let list = dsl::bookfragments
+        .filter(dsl::chapter.eq(chapter_id))
+        .filter(dsl::deletedat.is_null())
+        .order(dsl::rank.asc())
+        .select((dsl::id, dsl::chapter, dsl::rank))
+        .load::<(Uuid, Uuid, i32)>(connector)?
         .par_iter()
-        .map(|f| Simple {
-            uuid: f.id,
-            rank: f.rank,
+        .map(|&(uuid, chapter, rank)| Simple {
+            uuid,
+            chapter,
+            rank,
         })
         .collect::<Vec<Simple>>();
-    list.sort();
     Ok(list)
 }
 
-/// Return a full fragment
+/// Return a full fragment, unless it is in the trash
 ///
 /// # Errors
 ///
 /// If an error is returned by diesel, forward it to the function
 /// calling `get`
 pub fn get(connector: &mut PgConnection, id: Uuid) -> ApiResult<Bookfragment> {
-    dsl::bookfragments.find(id).first(connector)
+    dsl::bookfragments
+        .find(id)
+        .filter(dsl::deletedat.is_null())
+        .first(connector)
 }
 
-/// Shift fragments in a book
+/// Shift fragments in a chapter
 ///
-/// Shift all fragments by a set amount in a book starting from and to
-/// a set rank. The rank specified by the value of `from` is included
-/// while `to` is excluded.
+/// Shift all fragments by a set amount in a chapter starting from and
+/// to a set rank. The rank specified by the value of `from` is included
+/// while `to` is excluded. Fragments in the trash keep their rank.
 ///
 /// Two values are optional:
 /// - If `None` is passed as the value of `to`, treat all fragments
-///   from `from` until the end of the book to be shifted.
+///   from `from` until the end of the chapter to be shifted.
 /// - If `None` is passed to `shift`, treat it as a positive shift of
 ///   one rank only.
 ///
+/// This function does not lock anything by itself and should only be
+/// called inside a transaction holding the lock of the chapter, see
+/// `lock_chapter`. Ranks are only required to be unique when the
+/// transaction commits.
+///
 /// # Errors
 ///
 /// Any error returned by diesel will be forwarded to the caller of
 /// `shift_fragments`
 pub fn shift_fragments(
     connector: &mut PgConnection,
-    book: Uuid,
+    chapter: Uuid,
     from: i32,
     to: Option<i32>,
     shift: Option<i32>,
 ) -> ApiResult<usize> {
     diesel::update(dsl::bookfragments)
-        .filter(dsl::book.eq(book))
+        .filter(dsl::chapter.eq(chapter))
+        .filter(dsl::deletedat.is_null())
         .filter(dsl::rank.ge(from))
         .filter(dsl::rank.lt(to.unwrap_or(i32::MAX)))
         .set(dsl::rank.eq(dsl::rank + shift.unwrap_or(1)))
         .execute(connector)
 }
 
-/// Move a fragment inside a book
+/// Lock a chapter and all of its fragments
+///
+/// The chapter row and the rows of its fragments are locked until the
+/// end of the current transaction so no concurrent transaction can
+/// reorder them. Returns the chapter and its amount of fragments,
+/// leaving out the ones in the trash.
+///
+/// # Errors
+///
+/// Any error returned by diesel will be forwarded to the caller of
+/// `lock_chapter`. If the chapter does not exist or is in the trash,
+/// a `NotFound` error is returned.
+fn lock_chapter(
+    connector: &mut PgConnection,
+    chapter: Uuid,
+) -> ApiResult<(Chapter, i32)> {
+    let locked = chapters::table
+        .find(chapter)
+        .filter(chapters::deletedat.is_null())
+        .for_update()
+        .first::<Chapter>(connector)?;
+    let fragments = dsl::bookfragments
+        .filter(dsl::chapter.eq(chapter))
+        .filter(dsl::deletedat.is_null())
+        .select(dsl::id)
+        .for_update()
+        .load::<Uuid>(connector)?
+        .len();
+    Ok((locked, i32::try_from(fragments).unwrap_or(i32::MAX)))
+}
+
+/// Lock two chapters in a deterministic order
+///
+/// Chapters are always locked in the same order to prevent deadlocks
+/// between two transactions moving fragments in opposite directions.
+fn lock_chapters(
+    connector: &mut PgConnection,
+    first: Uuid,
+    second: Uuid,
+) -> ApiResult<((Chapter, i32), (Chapter, i32))> {
+    if first <= second {
+        let first = lock_chapter(connector, first)?;
+        Ok((first, lock_chapter(connector, second)?))
+    } else {
+        let second = lock_chapter(connector, second)?;
+        Ok((lock_chapter(connector, first)?, second))
+    }
+}
+
+/// Insert a fragment in its chapter
+///
+/// The rank of the fragment is clamped between the first rank and the
+/// rank following the last fragment of the chapter, and the fragments
+/// at and after this rank are shifted by one. The new fragment is
+/// recorded as its first revision, made by `editor`. Must be called
+/// inside a transaction.
+fn insert(
+    connector: &mut PgConnection,
+    mut fragment: Bookfragment,
+    editor: Uuid,
+) -> ApiResult<Bookfragment> {
+    let (chapter, count) = lock_chapter(connector, fragment.chapter)?;
+    fragment.book = chapter.book;
+    fragment.rank = fragment.rank.clamp(1, count + 1);
+    fragment.deletedat = None;
+    shift_fragments(connector, fragment.chapter, fragment.rank, None, None)?;
+    let inserted = diesel::insert_into(dsl::bookfragments)
+        .values(fragment)
+        .get_result(connector)?;
+    revision::record(connector, &inserted, Some(editor))?;
+    Ok(inserted)
+}
+
+/// Move a fragment to a rank, possibly in another chapter
+///
+/// Fragments between the former and the new position of the fragment
+/// are shifted in order to keep the ranks of both chapters gap-free.
+/// Must be called inside a transaction.
+fn relocate(
+    connector: &mut PgConnection,
+    id: Uuid,
+    chapter: Uuid,
+    to: i32,
+) -> ApiResult<Bookfragment> {
+    let original = get(connector, id)?;
+    let (book, to) = if original.chapter == chapter {
+        let (locked, count) = lock_chapter(connector, chapter)?;
+        let to = to.clamp(1, count);
+        if original.rank < to {
+            shift_fragments(
+                connector,
+                chapter,
+                original.rank + 1,
+                Some(to + 1),
+                Some(-1),
+            )?;
+        } else if to < original.rank {
+            shift_fragments(connector, chapter, to, Some(original.rank), None)?;
+        }
+        (locked.book, to)
+    } else {
+        let (_, (target, count)) =
+            lock_chapters(connector, original.chapter, chapter)?;
+        let to = to.clamp(1, count + 1);
+        shift_fragments(
+            connector,
+            original.chapter,
+            original.rank + 1,
+            None,
+            Some(-1),
+        )?;
+        shift_fragments(connector, chapter, to, None, None)?;
+        (target.book, to)
+    };
+    diesel::update(dsl::bookfragments.find(id))
+        .set((
+            dsl::book.eq(book),
+            dsl::chapter.eq(chapter),
+            dsl::rank.eq(to),
+        ))
+        .get_result(connector)
+}
+
+/// Remove a fragment from its chapter, moving it to the trash
+///
+/// The fragments following it are shifted back by one, while the
+/// fragment keeps its rank so it can be restored at the same place,
+/// see [`restore`]. Must be called inside a transaction.
+///
+/// [`restore`]: ./fn.restore.html
+fn remove(connector: &mut PgConnection, id: Uuid) -> ApiResult<Bookfragment> {
+    let fragment = get(connector, id)?;
+    lock_chapter(connector, fragment.chapter)?;
+    let fragment: Bookfragment =
+        diesel::update(dsl::bookfragments.find(id))
+            .set(dsl::deletedat.eq(chrono::Utc::now().naive_utc()))
+            .get_result(connector)?;
+    shift_fragments(
+        connector,
+        fragment.chapter,
+        fragment.rank + 1,
+        None,
+        Some(-1),
+    )?;
+    Ok(fragment)
+}
+
+/// Update a fragment, moving it if its chapter or rank changed
+///
+/// The updated fragment is recorded as a new revision made by
+/// `editor`. Must be called inside a transaction.
+fn modify(
+    connector: &mut PgConnection,
+    mut fragment: Bookfragment,
+    editor: Uuid,
+) -> ApiResult<Bookfragment> {
+    let original = get(connector, fragment.id)?;
+    fragment.deletedat = None;
+    // Move the fragment if the update moves it
+    if original.chapter != fragment.chapter || original.rank != fragment.rank {
+        let moved =
+            relocate(connector, fragment.id, fragment.chapter, fragment.rank)?;
+        fragment.rank = moved.rank;
+    }
+    fragment.book = chapters::table
+        .find(fragment.chapter)
+        .select(chapters::book)
+        .first(connector)?;
+    let updated = diesel::update(dsl::bookfragments.find(fragment.id))
+        .set(fragment)
+        .get_result(connector)?;
+    revision::record(connector, &updated, Some(editor))?;
+    Ok(updated)
+}
+
+/// Move a fragment inside its chapter
 ///
 /// Move an existing fragment to a new set rank, moving all the
 /// fragments between its current rank and its new rank if needed. If
-/// the new rank exceeds the amount of ranks existing in a book, the
+/// the new rank exceeds the amount of ranks existing in a chapter, the
 /// fragment’s new rank will simply be set to the last rank available
 /// --- i.e. if a fragment is moved to the rank 999 but the last
-/// existing rank is 41 after fragment shifts, it will be moved to
-/// rank 42.
+/// fragment of the chapter is at rank 42, it will be moved to rank
+/// 42. Likewise, ranks lower than 1 are treated as 1. The moved
+/// fragment is recorded as a revision made by `editor`, see
+/// `revision::record`.
+///
+/// The whole operation runs in a single serializable transaction.
 ///
 /// # Errors
 ///
-/// Any error returned by diesel will be forwarded to the callor of
-/// `move_fragment`
+/// Any error returned by diesel will be forwarded to the caller of
+/// `move_frag_id`
 ///
 /// # Returns
 ///
-/// Returns either a `usize` indicating how many fragments were
-/// shifted or a diesel [`Error`].
+/// Returns either the moved fragment with its new rank or a diesel
+/// [`Error`].
 ///
 /// [`Error`]: ../../diesel/result/enum.Error.html
 pub fn move_frag_id(
     connector: &mut PgConnection,
     fragment: Uuid,
     to: i32,
-) -> ApiResult<usize> {
-    let full_fragment: Bookfragment = bookfragments::dsl::bookfragments
-        .find(fragment)
-        .first(connector)?;
-    let shift_direction = if full_fragment.rank < to { -1 } else { 1 };
-    let moved_fragments = shift_fragments(
-        connector,
-        fragment,
-        full_fragment.rank,
-        Some(to),
-        Some(shift_direction),
-    )?;
-    diesel::update(bookfragments::dsl::bookfragments)
-        .filter(bookfragments::dsl::id.eq(fragment))
-        .set(bookfragments::dsl::rank.eq(to))
-        .execute(connector)?;
-    Ok(moved_fragments)
+    editor: Uuid,
+) -> ApiResult<Bookfragment> {
+    serializable(connector, |connector| {
+        let chapter = get(connector, fragment)?.chapter;
+        let moved = relocate(connector, fragment, chapter, to)?;
+        revision::record(connector, &moved, Some(editor))?;
+        Ok(moved)
+    })
 }
 
 /// Create a new fragment
 ///
-/// If a fragment already exists in the same book at the same rank as
-/// the new rank, treat this as an insert meant to move all the other
-/// fragments.
+/// If a fragment already exists in the same chapter at the same rank
+/// as the new rank, treat this as an insert meant to move all the
+/// other fragments. If the rank exceeds the amount of fragments in
+/// the chapter, the fragment is appended at the end of the chapter.
+/// The book of the fragment is always the book of its chapter.
+/// `editor` is the user creating the fragment, see
+/// `revision::record`.
+///
+/// The whole operation runs in a single serializable transaction.
 ///
 /// # Errors
 ///
@@ -159,50 +479,307 @@ pub fn move_frag_id(
 pub fn new(
     connector: &mut PgConnection,
     fragment: Bookfragment,
-) -> ApiResult<usize> {
-    let book_fragments = bookfragments::dsl::bookfragments
-        .filter(bookfragments::dsl::book.eq(fragment.book))
-        .load::<Bookfragment>(connector)?;
-    // Check if there is already a fragment with the same rank.
-    // If yes, shift the existing fragments in order to place the new
-    // fragment
-    if !book_fragments.is_empty() {
-        shift_fragments(connector, fragment.book, fragment.rank, None, None)?;
-    }
-    diesel::insert_into(dsl::bookfragments)
-        .values(fragment)
-        .execute(connector)
+    editor: Uuid,
+) -> ApiResult<Bookfragment> {
+    serializable(connector, |connector| {
+        insert(connector, fragment.clone(), editor)
+    })
 }
 
 /// Update a fragment
 ///
-/// As with new fragments, if the current fragment’s rank has changed,
-/// shift the necessary fragments.
+/// As with new fragments, if the current fragment’s chapter or rank
+/// has changed, shift the necessary fragments. The previous state of
+/// the fragment is kept in its revisions, and its new state is
+/// recorded as a revision made by `editor`.
+///
+/// The whole operation runs in a single serializable transaction.
 ///
 /// # Errors
 ///
 /// If an error is returned by diesel, forward it to the function
-/// calling `delete`
-pub fn update(connector: &mut PgConnection, fragment: Bookfragment) -> ApiResult<usize> {
-    let original_frag = dsl::bookfragments.find(fragment.id).first::<Bookfragment>(connector)?;
-    // Move the fragment if the update moves it
-    if original_frag.rank != fragment.rank {
-        move_frag_id(connector, fragment.id, fragment.rank)?;
-    }
-    diesel::update(dsl::bookfragments.find(fragment.id))
-        .set(fragment)
-        .execute(connector)
+/// calling `update`
+pub fn update(
+    connector: &mut PgConnection,
+    fragment: Bookfragment,
+    editor: Uuid,
+) -> ApiResult<Bookfragment> {
+    serializable(connector, |connector| {
+        modify(connector, fragment.clone(), editor)
+    })
 }
 
-/// Delete a book fragment
+/// Move a book fragment to the trash
+///
+/// The fragments following it in its chapter are shifted back by one
+/// rank. It is deleted for good once the retention period of the
+/// trash is over, see `trash::purge`. The whole operation runs in a
+/// single serializable transaction.
 ///
 /// # Errors
 ///
 /// If an error is returned by diesel, forward it to the function
 /// calling `delete`
 pub fn delete(connector: &mut PgConnection, id: Uuid) -> ApiResult<()> {
-    match diesel::delete(dsl::bookfragments.find(id)).execute(connector) {
-        Ok(_) => Ok(()),
-        Err(e) => Err(e),
+    serializable(connector, |connector| remove(connector, id).map(|_| ()))
+}
+
+/// Bring a fragment back from the trash
+///
+/// The fragment is inserted back at the rank it had in its chapter
+/// when it was moved to the trash, or at the end of the chapter if
+/// the chapter got shorter since, and the fragments at and after this
+/// rank are shifted by one. The whole operation runs in a single
+/// serializable transaction.
+///
+/// # Errors
+///
+/// If the fragment is not in the trash, a `NotFound` error is
+/// returned. If an error is returned by diesel, forward it to the
+/// function calling `restore`
+pub fn restore(
+    connector: &mut PgConnection,
+    id: Uuid,
+) -> ApiResult<Bookfragment> {
+    serializable(connector, |connector| {
+        let trashed: Bookfragment = dsl::bookfragments
+            .find(id)
+            .filter(dsl::deletedat.is_not_null())
+            .first(connector)?;
+        let (_, count) = lock_chapter(connector, trashed.chapter)?;
+        let rank = trashed.rank.clamp(1, count + 1);
+        shift_fragments(connector, trashed.chapter, rank, None, None)?;
+        diesel::update(dsl::bookfragments.find(id))
+            .set((
+                dsl::rank.eq(rank),
+                dsl::deletedat.eq(None::<chrono::NaiveDateTime>),
+            ))
+            .get_result(connector)
+    })
+}
+
+/// Renumber all fragments of a book
+///
+/// Repair the ranks of every chapter of a book so they start at 1 and
+/// have no gap, keeping the current order of the fragments. Returns
+/// how many fragments had their rank changed.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `renumber`
+pub fn renumber(connector: &mut PgConnection, book: Uuid) -> ApiResult<usize> {
+    serializable(connector, |connector| {
+        dsl::bookfragments
+            .filter(dsl::book.eq(book))
+            .filter(dsl::deletedat.is_null())
+            .select(dsl::id)
+            .for_update()
+            .load::<Uuid>(connector)?;
+        sql_query(
+            "UPDATE BookFragments
+                SET Rank = Ranked.NewRank
+               FROM (SELECT Id,
+                            ROW_NUMBER() OVER (PARTITION BY Chapter
+                                               ORDER BY Rank, Id)
+                              AS NewRank
+                       FROM BookFragments
+                      WHERE Book = $1
+                        AND DeletedAt IS NULL) AS Ranked
+              WHERE BookFragments.Id = Ranked.Id
+                AND BookFragments.Rank <> Ranked.NewRank",
+        )
+        .bind::<sql_types::Uuid, _>(book)
+        .execute(connector)
+    })
+}
+
+/// A single operation on a fragment, see [`batch`]
+///
+/// [`batch`]: ./fn.batch.html
+#[derive(Clone)]
+pub enum Operation {
+    /// Insert a new fragment, see [`new`]
+    ///
+    /// [`new`]: ./fn.new.html
+    Create(Bookfragment),
+    /// Update an existing fragment, see [`update`]
+    ///
+    /// [`update`]: ./fn.update.html
+    Update(Bookfragment),
+    /// Move a fragment to a rank, optionally in another chapter
+    Move {
+        id: Uuid,
+        chapter: Option<Uuid>,
+        to: i32,
+    },
+    /// Delete a fragment, see [`delete`]
+    ///
+    /// [`delete`]: ./fn.delete.html
+    Delete(Uuid),
+}
+
+impl Operation {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Create(_) => "create",
+            Self::Update(_) => "update",
+            Self::Move { .. } => "move",
+            Self::Delete(_) => "delete",
+        }
+    }
+}
+
+/// Result of a single operation of a batch
+///
+/// `chapter` and `rank` hold the final position of the fragment. They
+/// are empty for deleted fragments.
+#[derive(Serialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct OperationReport {
+    pub index: usize,
+    pub op: &'static str,
+    pub id: Uuid,
+    pub chapter: Option<Uuid>,
+    pub rank: Option<i32>,
+}
+
+/// Error returned by [`batch`]
+///
+/// `index` holds the index of the operation which failed, if the
+/// failure can be attributed to a single operation.
+///
+/// [`batch`]: ./fn.batch.html
+#[derive(Debug)]
+pub struct BatchError {
+    pub index: Option<usize>,
+    pub error: diesel::result::Error,
+}
+
+/// Ensure a chapter belongs to `book` and is not in the trash
+fn check_chapter(
+    connector: &mut PgConnection,
+    book: Uuid,
+    chapter: Uuid,
+) -> ApiResult<()> {
+    let chapter_book: Uuid = chapters::table
+        .find(chapter)
+        .filter(chapters::deletedat.is_null())
+        .select(chapters::book)
+        .first(connector)?;
+    if chapter_book == book {
+        Ok(())
+    } else {
+        Err(diesel::result::Error::NotFound)
     }
 }
+
+/// Ensure a fragment belongs to `book`
+fn check_fragment(
+    connector: &mut PgConnection,
+    book: Uuid,
+    id: Uuid,
+) -> ApiResult<()> {
+    if get(connector, id)?.book == book {
+        Ok(())
+    } else {
+        Err(diesel::result::Error::NotFound)
+    }
+}
+
+/// Apply a single operation of a batch
+fn apply(
+    connector: &mut PgConnection,
+    book: Uuid,
+    editor: Uuid,
+    index: usize,
+    operation: Operation,
+) -> ApiResult<OperationReport> {
+    let op = operation.name();
+    let report = |fragment: &Bookfragment| OperationReport {
+        index,
+        op,
+        id: fragment.id,
+        chapter: Some(fragment.chapter),
+        rank: Some(fragment.rank),
+    };
+    match operation {
+        Operation::Create(fragment) => {
+            check_chapter(connector, book, fragment.chapter)?;
+            insert(connector, fragment, editor).map(|f| report(&f))
+        }
+        Operation::Update(fragment) => {
+            check_fragment(connector, book, fragment.id)?;
+            check_chapter(connector, book, fragment.chapter)?;
+            modify(connector, fragment, editor).map(|f| report(&f))
+        }
+        Operation::Move { id, chapter, to } => {
+            check_fragment(connector, book, id)?;
+            let chapter = match chapter {
+                Some(chapter) => {
+                    check_chapter(connector, book, chapter)?;
+                    chapter
+                }
+                None => get(connector, id)?.chapter,
+            };
+            let moved = relocate(connector, id, chapter, to)?;
+            revision::record(connector, &moved, Some(editor))?;
+            Ok(report(&moved))
+        }
+        Operation::Delete(id) => {
+            check_fragment(connector, book, id)?;
+            remove(connector, id)?;
+            Ok(OperationReport {
+                index,
+                op,
+                id,
+                chapter: None,
+                rank: None,
+            })
+        }
+    }
+}
+
+/// Apply a list of operations on the fragments of a book atomically
+///
+/// Operations are applied in order inside a single serializable
+/// transaction, using the same rank shifting rules as their single
+/// fragment counterparts. Every operation must target fragments and
+/// chapters of `book`. If any operation fails, none of them is
+/// applied. Created and updated fragments are recorded as revisions
+/// made by `editor`.
+///
+/// # Errors
+///
+/// If an operation fails, return the index of the failing operation
+/// along with the error returned by diesel. An operation targeting a
+/// fragment or a chapter of another book fails with a `NotFound`
+/// error.
+pub fn batch(
+    connector: &mut PgConnection,
+    book: Uuid,
+    operations: Vec<Operation>,
+    editor: Uuid,
+) -> Result<Vec<OperationReport>, BatchError> {
+    let mut failed = None;
+    serializable(connector, |connector| {
+        failed = None;
+        let mut reports = Vec::with_capacity(operations.len());
+        for (index, operation) in operations.iter().enumerate() {
+            failed = Some(index);
+            reports.push(apply(
+                connector,
+                book,
+                editor,
+                index,
+                operation.clone(),
+            )?);
+        }
+        failed = None;
+        Ok(reports)
+    })
+    .map_err(|error| BatchError {
+        index: failed,
+        error,
+    })
+}
@@ -0,0 +1,49 @@
+use diesel::upsert::excluded;
+use diesel::{
+    ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+};
+
+use crate::db::ApiResult;
+use crate::models::CachedImage;
+use crate::schema::imagecache::dsl;
+
+/// Get the cached image found for `query`, if any
+///
+/// `query` must be normalized, see `images::normalize`.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `get`
+pub fn get(
+    connector: &mut PgConnection,
+    query: &str,
+) -> ApiResult<Option<CachedImage>> {
+    dsl::imagecache.find(query).first(connector).optional()
+}
+
+/// Cache an image found by a provider
+///
+/// If an image is already cached for the same query, it is replaced.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `insert`
+pub fn insert(
+    connector: &mut PgConnection,
+    image: &CachedImage,
+) -> ApiResult<CachedImage> {
+    diesel::insert_into(dsl::imagecache)
+        .values(image)
+        .on_conflict(dsl::query)
+        .do_update()
+        .set((
+            dsl::url.eq(excluded(dsl::url)),
+            dsl::author.eq(excluded(dsl::author)),
+            dsl::authorurl.eq(excluded(dsl::authorurl)),
+            dsl::provider.eq(excluded(dsl::provider)),
+            dsl::fetchedat.eq(excluded(dsl::fetchedat)),
+        ))
+        .get_result(connector)
+}
@@ -0,0 +1,356 @@
+use std::collections::{HashMap, HashSet};
+
+use diesel::{
+    Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl,
+    TextExpressionMethods,
+};
+use rocket::serde::Serialize;
+use uuid::Uuid;
+
+use crate::db::ApiResult;
+use crate::media;
+use crate::media::audio::AudioInfo;
+use crate::models::{Bookfragment, MediaAsset, MediaVariant};
+use crate::schema::mediaassets::dsl;
+use crate::schema::mediavariants;
+
+/// Resized variant of an image, as sent to the user
+#[derive(Serialize, Clone, PartialEq, Eq)]
+#[serde(crate = "rocket::serde")]
+pub struct Variant {
+    pub url: String,
+    pub width: i32,
+    pub height: i32,
+    pub contenttype: String,
+}
+
+/// All variants of an image in a given format, written as the value
+/// of a `srcset` attribute such as `/media/<id>/320.webp 320w, ...`
+#[derive(Serialize, Clone, PartialEq, Eq)]
+#[serde(crate = "rocket::serde")]
+pub struct Source {
+    pub contenttype: String,
+    pub srcset: String,
+}
+
+/// An image uploaded as a media asset along with its variants
+///
+/// `sources` holds one `srcset` per format of the variants, so
+/// clients can pick the variant best suited to their screen, while
+/// `blurhash` can be displayed while the image is loading.
+#[derive(Serialize, Clone, PartialEq, Eq)]
+#[serde(crate = "rocket::serde")]
+pub struct Picture {
+    pub url: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub blurhash: Option<String>,
+    pub variants: Vec<Variant>,
+    pub sources: Vec<Source>,
+}
+
+/// List all media assets, most recently uploaded first
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list`
+pub fn list(connector: &mut PgConnection) -> ApiResult<Vec<MediaAsset>> {
+    dsl::mediaassets
+        .order(dsl::uploaded.desc())
+        .load::<MediaAsset>(connector)
+}
+
+/// Get a specific media asset from the database
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `get`
+pub fn get(connector: &mut PgConnection, id: Uuid) -> ApiResult<MediaAsset> {
+    dsl::mediaassets.find(id).first(connector)
+}
+
+/// Add a new media asset in the database
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `new`
+pub fn new(
+    connector: &mut PgConnection,
+    asset: &MediaAsset,
+) -> ApiResult<MediaAsset> {
+    diesel::insert_into(dsl::mediaassets)
+        .values(asset)
+        .get_result(connector)
+}
+
+/// Add the variants of an image in the database
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `new_variants`
+pub fn new_variants(
+    connector: &mut PgConnection,
+    variants: &[MediaVariant],
+) -> ApiResult<usize> {
+    diesel::insert_into(mediavariants::table)
+        .values(variants)
+        .execute(connector)
+}
+
+/// List the variants of an image, sorted by format then by width
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `variants`
+pub fn variants(
+    connector: &mut PgConnection,
+    asset: Uuid,
+) -> ApiResult<Vec<MediaVariant>> {
+    mediavariants::table
+        .filter(mediavariants::asset.eq(asset))
+        .order((mediavariants::contenttype.asc(), mediavariants::width.asc()))
+        .load::<MediaVariant>(connector)
+}
+
+/// Get the variant of an image of a given width and format
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `variant`
+pub fn variant(
+    connector: &mut PgConnection,
+    asset: Uuid,
+    width: i32,
+    content_type: &str,
+) -> ApiResult<MediaVariant> {
+    mediavariants::table
+        .filter(mediavariants::asset.eq(asset))
+        .filter(mediavariants::width.eq(width))
+        .filter(mediavariants::contenttype.eq(content_type))
+        .first(connector)
+}
+
+/// Delete a media asset from the database
+///
+/// Its variants are deleted along with it. Returns the amount of assets
+/// deleted.
+///
+/// # Errors
+///
+/// If the asset is still used by a book or a fragment, diesel
+/// returns a foreign key violation. If an error is returned by
+/// diesel, forward it to the function calling `delete`
+pub fn delete(connector: &mut PgConnection, id: Uuid) -> ApiResult<usize> {
+    diesel::delete(dsl::mediaassets.find(id)).execute(connector)
+}
+
+/// Keep the identifiers among `ids` which belong to existing assets
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `existing`
+pub fn existing(
+    connector: &mut PgConnection,
+    ids: &[Uuid],
+) -> ApiResult<HashSet<Uuid>> {
+    if ids.is_empty() {
+        return Ok(HashSet::new());
+    }
+    Ok(dsl::mediaassets
+        .filter(dsl::id.eq_any(ids))
+        .select(dsl::id)
+        .load::<Uuid>(connector)?
+        .into_iter()
+        .collect())
+}
+
+/// Store the properties of a sound found by probing it
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `set_audio`
+pub fn set_audio(
+    connector: &mut PgConnection,
+    id: Uuid,
+    info: &AudioInfo,
+) -> ApiResult<MediaAsset> {
+    diesel::update(dsl::mediaassets.find(id))
+        .set((
+            dsl::duration.eq(info.duration),
+            dsl::codec.eq(&info.codec),
+            dsl::samplerate.eq(i32::try_from(info.samplerate).ok()),
+            dsl::channels.eq(i32::try_from(info.channels).ok()),
+            dsl::loudness.eq(info.loudness),
+        ))
+        .get_result(connector)
+}
+
+/// Store the size in pixels, the blurhash and the variants of an
+/// image
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `set_image`
+pub fn set_image(
+    connector: &mut PgConnection,
+    id: Uuid,
+    (width, height): (u32, u32),
+    blurhash: &str,
+    variants: &[MediaVariant],
+) -> ApiResult<MediaAsset> {
+    connector.transaction(|connector| {
+        new_variants(connector, variants)?;
+        diesel::update(dsl::mediaassets.find(id))
+            .set((
+                dsl::width.eq(i32::try_from(width).ok()),
+                dsl::height.eq(i32::try_from(height).ok()),
+                dsl::blurhash.eq(blurhash),
+            ))
+            .get_result(connector)
+    })
+}
+
+/// List the images whose variants were never generated
+///
+/// These images were uploaded before alexandria generated variants,
+/// see `crate::media::variants::generate`.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `unprocessed`
+pub fn unprocessed(connector: &mut PgConnection) -> ApiResult<Vec<MediaAsset>> {
+    dsl::mediaassets
+        .filter(dsl::contenttype.like("image/%"))
+        .filter(dsl::width.is_null())
+        .load::<MediaAsset>(connector)
+}
+
+/// Describe the images among `ids` along with their variants
+///
+/// Identifiers which do not belong to an image are left out of the
+/// returned map.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `pictures`
+pub fn pictures(
+    connector: &mut PgConnection,
+    ids: &[Uuid],
+) -> ApiResult<HashMap<Uuid, Picture>> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let mut pictures: HashMap<Uuid, Picture> = dsl::mediaassets
+        .filter(dsl::id.eq_any(ids))
+        .filter(dsl::contenttype.like("image/%"))
+        .load::<MediaAsset>(connector)?
+        .into_iter()
+        .map(|asset| {
+            let picture = Picture {
+                url: media::url(asset.id),
+                width: asset.width,
+                height: asset.height,
+                blurhash: asset.blurhash,
+                variants: Vec::new(),
+                sources: Vec::new(),
+            };
+            (asset.id, picture)
+        })
+        .collect();
+    let variants = mediavariants::table
+        .filter(mediavariants::asset.eq_any(ids))
+        .order((mediavariants::contenttype.asc(), mediavariants::width.asc()))
+        .load::<MediaVariant>(connector)?;
+    for variant in variants {
+        let picture = match pictures.get_mut(&variant.asset) {
+            Some(picture) => picture,
+            None => continue,
+        };
+        let url = media::variant_url(
+            variant.asset,
+            variant.width,
+            &variant.contenttype,
+        );
+        let candidate = format!("{} {}w", url, variant.width);
+        match picture.sources.last_mut() {
+            Some(source) if source.contenttype == variant.contenttype => {
+                source.srcset.push_str(", ");
+                source.srcset.push_str(&candidate);
+            }
+            _ => picture.sources.push(Source {
+                contenttype: variant.contenttype.clone(),
+                srcset: candidate,
+            }),
+        }
+        picture.variants.push(Variant {
+            url,
+            width: variant.width,
+            height: variant.height,
+            contenttype: variant.contenttype,
+        });
+    }
+    Ok(pictures)
+}
+
+/// List the sounds whose properties are unknown
+///
+/// These sounds were uploaded before alexandria probed sounds, see
+/// `crate::media::audio::probe`.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `unprobed`
+pub fn unprobed(connector: &mut PgConnection) -> ApiResult<Vec<MediaAsset>> {
+    dsl::mediaassets
+        .filter(dsl::contenttype.like("audio/%"))
+        .filter(dsl::duration.is_null())
+        .load::<MediaAsset>(connector)
+}
+
+/// Check the sounds a fragment uses as media assets
+///
+/// Each sound asset of `fragment` must exist, be a sound, and have
+/// been successfully probed. Returns a message describing the first
+/// sound which does not, if any.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `check_sounds`
+pub fn check_sounds(
+    connector: &mut PgConnection,
+    fragment: &Bookfragment,
+) -> ApiResult<Option<String>> {
+    let ids = [fragment.oneshotsoundasset, fragment.bgsoundasset];
+    for id in ids.into_iter().flatten() {
+        let asset = match get(connector, id) {
+            Ok(asset) => asset,
+            Err(diesel::result::Error::NotFound) => {
+                return Ok(Some(format!("Media asset ID {} not found", id)))
+            }
+            Err(e) => return Err(e),
+        };
+        if !asset.contenttype.starts_with("audio/") {
+            return Ok(Some(format!("Media asset ID {} is not a sound", id)));
+        }
+        if asset.duration.is_none() {
+            return Ok(Some(format!(
+                "Media asset ID {} has not been probed",
+                id
+            )));
+        }
+    }
+    Ok(None)
+}
@@ -1,6 +1,19 @@
+pub mod ambience;
+pub mod audit;
 pub mod author;
 pub mod book;
+pub mod chapter;
+pub mod collaborator;
 pub mod fragment;
+pub mod image_cache;
+pub mod media;
+pub mod page;
+pub mod published;
+pub mod reader;
+pub mod revision;
+pub mod search;
+pub mod trash;
+pub mod user;
 
 #[macro_export]
 macro_rules! get_connector {
@@ -21,6 +34,7 @@ pub(crate) use get_connector;
 
 use diesel::pg::PgConnection;
 use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::migration::MigrationSource;
 use diesel_migrations::{
     embed_migrations, EmbeddedMigrations, MigrationHarness,
 };
@@ -29,24 +43,49 @@ use diesel_migrations::{
 /// alexandria is launching
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
-/// Run the list of migrations held by `MIGRATIONS`.
+/// Version of the latest migration held by `MIGRATIONS`
+///
+/// This is the version of the database schema this version of
+/// alexandria works with.
+#[must_use]
+pub fn schema_version() -> String {
+    MigrationSource::<diesel::pg::Pg>::migrations(&MIGRATIONS)
+        .ok()
+        .and_then(|migrations| {
+            migrations
+                .iter()
+                .map(|migration| migration.name().version().to_string())
+                .max()
+        })
+        .unwrap_or_default()
+}
+
+/// Run the migrations held by `MIGRATIONS` that are still pending
 ///
 /// # Errors
 ///
-/// If any error is encountered while running a migration, return
+/// If any error is encountered while running a migration, return it
+/// as a diesel error to the function calling `run_migrations`
 pub fn run_migrations(
     connection: &mut impl MigrationHarness<diesel::pg::Pg>,
 ) -> ApiResult<()> {
     use diesel::result::{DatabaseErrorKind, Error};
-    let result = connection.run_next_migration(MIGRATIONS);
-    info!("{:?}", result);
-    result.map(|_| ()).map_err(|e| {
-        info!("{:?}", e);
-        Error::DatabaseError(
+    match connection.run_pending_migrations(MIGRATIONS) {
+        Ok(versions) if versions.is_empty() => {
+            tracing::info!("Database schema is up to date");
+            Ok(())
+        }
+        Ok(versions) => {
+            for version in versions {
+                tracing::info!("Applied migration {}", version);
+            }
+            Ok(())
+        }
+        Err(e) => Err(Error::DatabaseError(
             DatabaseErrorKind::Unknown,
             Box::new(format!("Error running migrations: {}", e)),
-        )
-    })
+        )),
+    }
 }
 
 use dotenvy::dotenv;
@@ -54,6 +93,58 @@ use std::env;
 
 pub type ApiResult<T> = Result<T, diesel::result::Error>;
 
+/// How many times a serializable transaction is attempted before
+/// giving up because of concurrent transactions.
+const SERIALIZATION_ATTEMPTS: usize = 3;
+
+/// Run `transaction` inside a serializable transaction.
+///
+/// If the transaction fails because a concurrent transaction touched
+/// the same rows, it is retried up to `SERIALIZATION_ATTEMPTS` times.
+/// The closure must therefore be safe to run more than once. When
+/// called from inside another transaction, the closure runs in a
+/// savepoint of that transaction, which is then responsible for
+/// being serializable and for retrying it.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `serializable`
+pub fn serializable<T, F>(
+    connector: &mut PgConnection,
+    mut transaction: F,
+) -> ApiResult<T>
+where
+    F: FnMut(&mut PgConnection) -> ApiResult<T>,
+{
+    use diesel::connection::{AnsiTransactionManager, TransactionManager};
+    use diesel::result::{DatabaseErrorKind, Error};
+    use diesel::Connection;
+    if AnsiTransactionManager::transaction_manager_status_mut(connector)
+        .transaction_depth()?
+        .is_some()
+    {
+        return connector.transaction(transaction);
+    }
+    let mut attempt = 1;
+    loop {
+        match connector
+            .build_transaction()
+            .serializable()
+            .run(&mut transaction)
+        {
+            Err(Error::DatabaseError(
+                DatabaseErrorKind::SerializationFailure,
+                _,
+            )) if attempt < SERIALIZATION_ATTEMPTS => {
+                info!("Serialization failure, retrying transaction");
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
 /// Create a connection pool to the database.
 ///
 /// The pool and the connection manager are both handled by Diesel’s
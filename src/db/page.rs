@@ -0,0 +1,156 @@
+use rocket::serde::json::Value;
+use rocket::serde::Serialize;
+use uuid::Uuid;
+
+/// Sort a boxed `query` and keep the rows coming after the cursor of
+/// `page`
+///
+/// Rows are sorted by `key`, a column of `table` whose values are of
+/// type `value`, in the direction of `sort` with null keys last, then
+/// by `id` in the same direction so the order is stable. The key of
+/// the row of the cursor is read from `table`, which returns a
+/// `NotFound` error from the calling function if it does not exist.
+#[macro_export]
+macro_rules! keyset {
+    (
+        $connector:expr, $query:expr, $table:expr, $key:expr, $value:ty,
+        $id:expr, $page:expr, $sort:expr
+    ) => {{
+        let mut query = $query;
+        if let Some(cursor) = $page.cursor {
+            let value: Option<$value> = $table
+                .find(cursor)
+                .select($key.nullable())
+                .first($connector)?;
+            query = match (value, $sort.descending) {
+                (Some(value), false) => query.filter(
+                    $key.gt(value.clone())
+                        .or($key.is_null())
+                        .or($key.eq(value).and($id.gt(cursor))),
+                ),
+                (Some(value), true) => query.filter(
+                    $key.lt(value.clone())
+                        .or($key.is_null())
+                        .or($key.eq(value).and($id.lt(cursor))),
+                ),
+                (None, false) => {
+                    query.filter($key.is_null().and($id.gt(cursor)))
+                }
+                (None, true) => {
+                    query.filter($key.is_null().and($id.lt(cursor)))
+                }
+            };
+        }
+        if $sort.descending {
+            query.order(($key.desc().nulls_last(), $id.desc()))
+        } else {
+            query.order(($key.asc().nulls_last(), $id.asc()))
+        }
+    }};
+}
+
+pub(crate) use keyset;
+
+/// Position and size of a page of a list
+///
+/// `cursor` is the identifier of the last item of the previous page,
+/// or `None` for the first page.
+#[derive(Debug, Clone, Copy)]
+pub struct Page {
+    pub limit: i64,
+    pub cursor: Option<Uuid>,
+}
+
+/// Key a list is sorted by, along with its direction
+///
+/// Items with the same key are sorted by their identifier, so the
+/// order is stable across pages.
+#[derive(Debug, Clone, Copy)]
+pub struct Sort<K> {
+    pub key: K,
+    pub descending: bool,
+}
+
+/// A page of a list, along with the cursor of the next page
+///
+/// `next` is `None` on the last page.
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    pub next: Option<Uuid>,
+}
+
+impl<T> Paginated<T> {
+    /// Build a page from `rows`, loaded with a limit of one more item
+    /// than `page.limit` to know whether another page follows
+    ///
+    /// `id` returns the identifier of an item, used as the cursor of
+    /// the next page.
+    pub fn new(mut rows: Vec<T>, page: &Page, id: impl Fn(&T) -> Uuid) -> Self {
+        let limit = usize::try_from(page.limit).unwrap_or(0);
+        let more = rows.len() > limit;
+        rows.truncate(limit);
+        let next = if more { rows.last().map(id) } else { None };
+        Self { items: rows, next }
+    }
+
+    /// Transform each item of the page, keeping its cursor
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Paginated<U> {
+        Paginated {
+            items: self.items.into_iter().map(f).collect(),
+            next: self.next,
+        }
+    }
+}
+
+/// An item of a list, either whole or restricted to the fields the
+/// user asked for, see [`Fields`]
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde", untagged)]
+pub enum Item<T> {
+    Whole(T),
+    Partial(Value),
+}
+
+/// Fields of the items of a list the user asked for
+///
+/// The requested fields are selected in SQL as a single JSON object,
+/// so only these fields are read from the database.
+#[derive(Debug, Clone)]
+pub struct Fields(String);
+
+impl Fields {
+    /// Select the fields `requested` among `columns`
+    ///
+    /// `columns` maps the name of each field the user can ask for to
+    /// the SQL expression returning its value, written the same way it
+    /// is serialized when the whole item is returned.
+    ///
+    /// # Errors
+    ///
+    /// If no field is requested or if a requested field is unknown, an
+    /// error describing it is returned.
+    pub fn new<S: AsRef<str>>(
+        requested: &[&str],
+        columns: &[(&str, S)],
+    ) -> Result<Self, String> {
+        if requested.is_empty() {
+            return Err("No field requested".to_owned());
+        }
+        let mut pairs = Vec::with_capacity(requested.len());
+        for field in requested {
+            match columns.iter().find(|(name, _)| name == field) {
+                Some((name, column)) => {
+                    pairs.push(format!("'{}', {}", name, column.as_ref()));
+                }
+                None => return Err(format!("Unknown field {}", field)),
+            }
+        }
+        Ok(Self(format!("JSON_BUILD_OBJECT({})", pairs.join(", "))))
+    }
+
+    /// SQL expression building the JSON object of an item
+    #[must_use]
+    pub fn sql(&self) -> &str {
+        &self.0
+    }
+}
@@ -0,0 +1,321 @@
+use diesel::dsl::sql;
+use diesel::sql_types::Json;
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, PgConnection,
+    QueryDsl, RunQueryDsl,
+};
+use rocket::serde::json::Value;
+use uuid::Uuid;
+
+use crate::db::fragment::Simple;
+use crate::db::page::{Fields, Item, Page, Paginated};
+use crate::db::ApiResult;
+use crate::models::{
+    Book, BookStatus, Bookfragment, Chapter, PublishedBook, PublishedChapter,
+    PublishedFragment,
+};
+use crate::schema::publishedfragments::{self, dsl};
+use crate::schema::{
+    bookfragments, books, chapters, publishedbooks, publishedchapters,
+};
+
+/// Version of a book to read
+///
+/// The metadata, chapters and fragments of a book are edited in its
+/// draft, which readers only see once the book is published or
+/// republished, see [`publish`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    /// The book as it is currently edited
+    Draft,
+    /// The book as it was when it was last published
+    Published,
+}
+
+/// Publish a book
+///
+/// The published metadata, chapters and fragments of the book are
+/// replaced by a copy of their current draft, leaving out the
+/// chapters and fragments in the trash, and the book becomes
+/// `Published`. Returns the amount of fragments published.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `publish`
+pub fn publish(connector: &mut PgConnection, book: Uuid) -> ApiResult<usize> {
+    connector.transaction(|connector| {
+        diesel::delete(dsl::publishedfragments.filter(dsl::book.eq(book)))
+            .execute(connector)?;
+        diesel::delete(
+            publishedchapters::table
+                .filter(publishedchapters::book.eq(book)),
+        )
+        .execute(connector)?;
+        diesel::delete(publishedbooks::table.find(book)).execute(connector)?;
+        diesel::insert_into(publishedbooks::table)
+            .values(books::table.filter(books::id.eq(book)).select((
+                books::id,
+                books::title,
+                books::author,
+                books::isbn,
+                books::cover,
+                books::publisher,
+                books::published,
+                books::genre,
+                books::synopsis,
+                books::booktype,
+                books::coverasset,
+            )))
+            .into_columns(publishedbooks::all_columns)
+            .execute(connector)?;
+        diesel::insert_into(publishedchapters::table)
+            .values(
+                chapters::table
+                    .filter(chapters::book.eq(book))
+                    .filter(chapters::deletedat.is_null())
+                    .select((
+                        chapters::id,
+                        chapters::book,
+                        chapters::number,
+                        chapters::title,
+                        chapters::epigraph,
+                        chapters::cover,
+                        chapters::bgsoundsource,
+                    )),
+            )
+            .into_columns(publishedchapters::all_columns)
+            .execute(connector)?;
+        let published = diesel::insert_into(dsl::publishedfragments)
+            .values(
+                bookfragments::table
+                    .filter(bookfragments::book.eq(book))
+                    .filter(bookfragments::deletedat.is_null())
+                    .select((
+                        bookfragments::id,
+                        bookfragments::content,
+                        bookfragments::oneshotsoundsource,
+                        bookfragments::bgsoundtype,
+                        bookfragments::bgsoundsource,
+                        bookfragments::imgtype,
+                        bookfragments::imgsource,
+                        bookfragments::book,
+                        bookfragments::rank,
+                        bookfragments::chapter,
+                        bookfragments::oneshotsoundasset,
+                        bookfragments::bgsoundasset,
+                        bookfragments::imgasset,
+                    )),
+            )
+            .into_columns(publishedfragments::all_columns)
+            .execute(connector)?;
+        diesel::update(books::table.find(book))
+            .set(books::status.eq(BookStatus::Published))
+            .execute(connector)?;
+        Ok(published)
+    })
+}
+
+/// List a page of the published fragments of a book, see
+/// `fragment::list`
+///
+/// # Errors
+///
+/// If the cursor of `page` is not a published fragment of the book, a
+/// `NotFound` error is returned. If an error is returned by diesel,
+/// forward it to the function calling `list`
+pub fn list(
+    connector: &mut PgConnection,
+    book_id: Uuid,
+    page: &Page,
+    fields: Option<&Fields>,
+) -> ApiResult<Paginated<(Uuid, Item<Simple>)>> {
+    let mut query = dsl::publishedfragments
+        .inner_join(publishedchapters::table)
+        .filter(dsl::book.eq(book_id))
+        .into_boxed();
+    if let Some(cursor) = page.cursor {
+        let (number, rank): (i32, i32) = dsl::publishedfragments
+            .inner_join(publishedchapters::table)
+            .filter(dsl::id.eq(cursor))
+            .filter(dsl::book.eq(book_id))
+            .select((publishedchapters::number, dsl::rank))
+            .first(connector)?;
+        let chapter = publishedchapters::number;
+        query = query.filter(
+            chapter
+                .gt(number)
+                .or(chapter.eq(number).and(dsl::rank.gt(rank))),
+        );
+    }
+    let query = query
+        .order((publishedchapters::number.asc(), dsl::rank.asc()))
+        .limit(page.limit + 1);
+    match fields {
+        None => Ok(Paginated::new(
+            query
+                .select((dsl::id, dsl::chapter, dsl::rank))
+                .load::<(Uuid, Uuid, i32)>(connector)?,
+            page,
+            |(uuid, _, _)| *uuid,
+        )
+        .map(|(uuid, chapter, rank)| {
+            let fragment = Simple {
+                uuid,
+                chapter,
+                rank,
+            };
+            (uuid, Item::Whole(fragment))
+        })),
+        Some(fields) => Ok(Paginated::new(
+            query
+                .select((dsl::id, sql::<Json>(fields.sql())))
+                .load::<(Uuid, Value)>(connector)?,
+            page,
+            |(uuid, _)| *uuid,
+        )
+        .map(|(uuid, value)| (uuid, Item::Partial(value)))),
+    }
+}
+
+/// List all full published fragments of a book, see
+/// `fragment::list_full`
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list_full`
+pub fn list_full(
+    connector: &mut PgConnection,
+    book_id: Uuid,
+) -> ApiResult<Vec<Bookfragment>> {
+    let list = dsl::publishedfragments
+        .inner_join(publishedchapters::table)
+        .filter(dsl::book.eq(book_id))
+        .order((publishedchapters::number.asc(), dsl::rank.asc()))
+        .select(publishedfragments::all_columns)
+        .load::<PublishedFragment>(connector)?
+        .into_iter()
+        .map(Bookfragment::from)
+        .collect();
+    Ok(list)
+}
+
+/// Read `book` as it was when it was last published
+///
+/// The metadata of the book are replaced by their snapshot, while its
+/// owner, status and language are kept, see `PublishedBook`.
+///
+/// # Errors
+///
+/// If the book was never published, diesel returns a `NotFound`
+/// error. If an error is returned by diesel, forward it to the
+/// function calling `book`
+pub fn book(connector: &mut PgConnection, book: Book) -> ApiResult<Book> {
+    let published: PublishedBook =
+        publishedbooks::table.find(book.id).first(connector)?;
+    Ok(Book {
+        title: published.title,
+        author: published.author,
+        isbn: published.isbn,
+        cover: published.cover,
+        publisher: published.publisher,
+        published: published.published,
+        genre: published.genre,
+        synopsis: published.synopsis,
+        booktype: published.booktype,
+        coverasset: published.coverasset,
+        ..book
+    })
+}
+
+/// List the books whose last publication is by `author`, whether or
+/// not their draft still is
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `by_author`
+pub fn by_author(
+    connector: &mut PgConnection,
+    author: Uuid,
+) -> ApiResult<Vec<Uuid>> {
+    publishedbooks::table
+        .filter(publishedbooks::author.eq(author))
+        .select(publishedbooks::id)
+        .load(connector)
+}
+
+/// List all published chapters of a book, see `chapter::list`
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list_chapters`
+pub fn list_chapters(
+    connector: &mut PgConnection,
+    book_id: Uuid,
+) -> ApiResult<Vec<Chapter>> {
+    let list = publishedchapters::table
+        .filter(publishedchapters::book.eq(book_id))
+        .order(publishedchapters::number.asc())
+        .load::<PublishedChapter>(connector)?
+        .into_iter()
+        .map(Chapter::from)
+        .collect();
+    Ok(list)
+}
+
+/// Return a published chapter
+///
+/// # Errors
+///
+/// If the chapter was never published, diesel returns a `NotFound`
+/// error. If an error is returned by diesel, forward it to the
+/// function calling `chapter`
+pub fn chapter(connector: &mut PgConnection, id: Uuid) -> ApiResult<Chapter> {
+    publishedchapters::table
+        .find(id)
+        .first::<PublishedChapter>(connector)
+        .map(Chapter::from)
+}
+
+/// List all published fragments of a chapter, see
+/// `fragment::list_chapter`
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list_chapter`
+pub fn list_chapter(
+    connector: &mut PgConnection,
+    chapter_id: Uuid,
+) -> ApiResult<Vec<Simple>> {
+    let list = dsl::publishedfragments
+        .filter(dsl::chapter.eq(chapter_id))
+        .order(dsl::rank.asc())
+        .select((dsl::id, dsl::chapter, dsl::rank))
+        .load::<(Uuid, Uuid, i32)>(connector)?
+        .into_iter()
+        .map(|(uuid, chapter, rank)| Simple {
+            uuid,
+            chapter,
+            rank,
+        })
+        .collect();
+    Ok(list)
+}
+
+/// Return a full published fragment
+///
+/// # Errors
+///
+/// If the fragment was never published, diesel returns a `NotFound`
+/// error. If an error is returned by diesel, forward it to the
+/// function calling `get`
+pub fn get(connector: &mut PgConnection, id: Uuid) -> ApiResult<Bookfragment> {
+    dsl::publishedfragments
+        .find(id)
+        .first::<PublishedFragment>(connector)
+        .map(Bookfragment::from)
+}
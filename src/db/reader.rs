@@ -0,0 +1,170 @@
+use diesel::upsert::excluded;
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, PgConnection,
+    QueryDsl, RunQueryDsl,
+};
+use uuid::Uuid;
+
+use crate::db::{published, ApiResult};
+use crate::models::{Bookfragment, Bookmark, Progress};
+use crate::schema::{bookfragments, bookmarks, readingprogress};
+
+/// List the progress of a reader in all their books, most recently
+/// updated first
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list_progress`
+pub fn list_progress(
+    connector: &mut PgConnection,
+    reader: Uuid,
+) -> ApiResult<Vec<Progress>> {
+    readingprogress::table
+        .filter(readingprogress::reader.eq(reader))
+        .order(readingprogress::updated.desc())
+        .load::<Progress>(connector)
+}
+
+/// Get the progress of a reader in a book
+///
+/// # Errors
+///
+/// If the reader never read the book, diesel returns a `NotFound`
+/// error. If an error is returned by diesel, forward it to the
+/// function calling `get_progress`
+pub fn get_progress(
+    connector: &mut PgConnection,
+    reader: Uuid,
+    book: Uuid,
+) -> ApiResult<Progress> {
+    readingprogress::table
+        .filter(readingprogress::reader.eq(reader))
+        .filter(readingprogress::book.eq(book))
+        .first(connector)
+}
+
+/// Set `fragment` as the last fragment a reader read in its book
+///
+/// `fragment` is a published fragment, whose chapter number is read
+/// from the published version of its chapter. The previous progress
+/// of the reader in the book, if any, is replaced.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `set_progress`
+pub fn set_progress(
+    connector: &mut PgConnection,
+    reader: Uuid,
+    fragment: &Bookfragment,
+) -> ApiResult<Progress> {
+    connector.transaction(|connector| {
+        let chapter = published::chapter(connector, fragment.chapter)?;
+        let progress = Progress {
+            id: Uuid::new_v4(),
+            reader,
+            book: fragment.book,
+            fragment: Some(fragment.id),
+            chapternumber: chapter.number,
+            rank: fragment.rank,
+            updated: chrono::Utc::now().naive_utc(),
+        };
+        diesel::insert_into(readingprogress::table)
+            .values(&progress)
+            .on_conflict((readingprogress::reader, readingprogress::book))
+            .do_update()
+            .set((
+                readingprogress::fragment
+                    .eq(excluded(readingprogress::fragment)),
+                readingprogress::chapternumber
+                    .eq(excluded(readingprogress::chapternumber)),
+                readingprogress::rank.eq(excluded(readingprogress::rank)),
+                readingprogress::updated.eq(excluded(readingprogress::updated)),
+            ))
+            .get_result(connector)
+    })
+}
+
+/// List the bookmarks of a reader, oldest first
+///
+/// If `book` is set, only the bookmarks on fragments of this book are
+/// listed.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list_bookmarks`
+pub fn list_bookmarks(
+    connector: &mut PgConnection,
+    reader: Uuid,
+    book: Option<Uuid>,
+) -> ApiResult<Vec<Bookmark>> {
+    let mut query = bookmarks::table
+        .inner_join(bookfragments::table)
+        .filter(bookmarks::reader.eq(reader))
+        .filter(bookfragments::deletedat.is_null())
+        .select(bookmarks::all_columns)
+        .order(bookmarks::created.asc())
+        .into_boxed();
+    if let Some(book) = book {
+        query = query.filter(bookfragments::book.eq(book));
+    }
+    query.load::<Bookmark>(connector)
+}
+
+/// Add a new bookmark in the database
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `new_bookmark`
+pub fn new_bookmark(
+    connector: &mut PgConnection,
+    bookmark: &Bookmark,
+) -> ApiResult<Bookmark> {
+    diesel::insert_into(bookmarks::table)
+        .values(bookmark)
+        .get_result(connector)
+}
+
+/// Replace the note of a bookmark of a reader
+///
+/// # Errors
+///
+/// If the reader has no such bookmark, diesel returns a `NotFound`
+/// error. If an error is returned by diesel, forward it to the
+/// function calling `update_bookmark`
+pub fn update_bookmark(
+    connector: &mut PgConnection,
+    reader: Uuid,
+    id: Uuid,
+    note: Option<String>,
+) -> ApiResult<Bookmark> {
+    diesel::update(
+        bookmarks::table
+            .filter(bookmarks::id.eq(id).and(bookmarks::reader.eq(reader))),
+    )
+    .set(bookmarks::note.eq(note))
+    .get_result(connector)
+}
+
+/// Delete a bookmark of a reader
+///
+/// Returns the amount of bookmarks deleted.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `delete_bookmark`
+pub fn delete_bookmark(
+    connector: &mut PgConnection,
+    reader: Uuid,
+    id: Uuid,
+) -> ApiResult<usize> {
+    diesel::delete(
+        bookmarks::table
+            .filter(bookmarks::id.eq(id).and(bookmarks::reader.eq(reader))),
+    )
+    .execute(connector)
+}
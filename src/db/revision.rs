@@ -0,0 +1,197 @@
+use diesel::dsl::max;
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use rocket::serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+use uuid::Uuid;
+
+use crate::db::{serializable, ApiResult};
+use crate::models::{Bookfragment, FragmentRevision};
+use crate::schema::bookfragments;
+use crate::schema::fragmentrevisions::dsl;
+
+/// Record the current state of a fragment as its next revision
+///
+/// Must be called inside a transaction, right after the fragment was
+/// created or updated.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `record`
+pub fn record(
+    connector: &mut PgConnection,
+    fragment: &Bookfragment,
+    editor: Option<Uuid>,
+) -> ApiResult<FragmentRevision> {
+    let last: Option<i32> = dsl::fragmentrevisions
+        .filter(dsl::fragment.eq(fragment.id))
+        .select(max(dsl::revision))
+        .first(connector)?;
+    diesel::insert_into(dsl::fragmentrevisions)
+        .values(FragmentRevision {
+            id: Uuid::new_v4(),
+            fragment: fragment.id,
+            revision: last.unwrap_or(0) + 1,
+            content: fragment.content.clone(),
+            oneshotsoundsource: fragment.oneshotsoundsource.clone(),
+            bgsoundtype: fragment.bgsoundtype.clone(),
+            bgsoundsource: fragment.bgsoundsource.clone(),
+            imgtype: fragment.imgtype.clone(),
+            imgsource: fragment.imgsource.clone(),
+            chapter: fragment.chapter,
+            rank: fragment.rank,
+            oneshotsoundasset: fragment.oneshotsoundasset,
+            bgsoundasset: fragment.bgsoundasset,
+            imgasset: fragment.imgasset,
+            editor,
+            created: chrono::Utc::now().naive_utc(),
+        })
+        .get_result(connector)
+}
+
+/// List all revisions of a fragment, from the oldest to the newest
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list`
+pub fn list(
+    connector: &mut PgConnection,
+    fragment: Uuid,
+) -> ApiResult<Vec<FragmentRevision>> {
+    dsl::fragmentrevisions
+        .filter(dsl::fragment.eq(fragment))
+        .order(dsl::revision.asc())
+        .load(connector)
+}
+
+/// Get a revision of a fragment by its number
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `get`
+pub fn get(
+    connector: &mut PgConnection,
+    fragment: Uuid,
+    revision: i32,
+) -> ApiResult<FragmentRevision> {
+    dsl::fragmentrevisions
+        .filter(dsl::fragment.eq(fragment))
+        .filter(dsl::revision.eq(revision))
+        .first(connector)
+}
+
+/// Bring a fragment back to one of its revisions
+///
+/// The content, sounds and image of the revision are copied back to
+/// the fragment, which keeps its current chapter and rank so the
+/// fragments around it are left untouched. The restored state is
+/// recorded as a new revision made by `editor`, so the restoration
+/// can itself be undone. Media assets deleted since the revision was
+/// made are not restored.
+///
+/// The whole operation runs in a single serializable transaction.
+///
+/// # Errors
+///
+/// If the fragment or the revision does not exist, or if the fragment
+/// is in the trash, a `NotFound` error is returned. If an error is
+/// returned by diesel, forward it to the function calling `restore`
+pub fn restore(
+    connector: &mut PgConnection,
+    fragment: Uuid,
+    revision: i32,
+    editor: Uuid,
+) -> ApiResult<Bookfragment> {
+    serializable(connector, |connector| {
+        let old = get(connector, fragment, revision)?;
+        let mut restored: Bookfragment = bookfragments::table
+            .find(fragment)
+            .filter(bookfragments::deletedat.is_null())
+            .for_update()
+            .first(connector)?;
+        restored.content = old.content;
+        restored.oneshotsoundsource = old.oneshotsoundsource;
+        restored.bgsoundtype = old.bgsoundtype;
+        restored.bgsoundsource = old.bgsoundsource;
+        restored.imgtype = old.imgtype;
+        restored.imgsource = old.imgsource;
+        restored.oneshotsoundasset = old.oneshotsoundasset;
+        restored.bgsoundasset = old.bgsoundasset;
+        restored.imgasset = old.imgasset;
+        let restored: Bookfragment =
+            diesel::update(bookfragments::table.find(fragment))
+                .set(restored)
+                .get_result(connector)?;
+        record(connector, &restored, Some(editor))?;
+        Ok(restored)
+    })
+}
+
+/// Line of the content of a fragment in a [`Diff`]
+#[derive(Serialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct Line {
+    /// Either `equal`, `delete` or `insert`
+    pub op: &'static str,
+    pub text: String,
+}
+
+/// Differences between two revisions of a fragment, see [`diff`]
+#[derive(Serialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct Diff {
+    pub from: i32,
+    pub to: i32,
+    /// Lines of the content, as a line by line diff
+    pub content: Vec<Line>,
+    /// Names of the other elements of the fragment which changed
+    pub changed: Vec<&'static str>,
+}
+
+/// Compare two revisions of a fragment
+///
+/// The content is compared line by line, while the other elements of
+/// the revisions are only reported as changed or not.
+#[must_use]
+pub fn diff(from: &FragmentRevision, to: &FragmentRevision) -> Diff {
+    let content = TextDiff::from_lines(&from.content, &to.content)
+        .iter_all_changes()
+        .map(|change| Line {
+            op: match change.tag() {
+                ChangeTag::Equal => "equal",
+                ChangeTag::Delete => "delete",
+                ChangeTag::Insert => "insert",
+            },
+            text: change.to_string_lossy().trim_end_matches('\n').to_owned(),
+        })
+        .collect();
+    let changed = [
+        (
+            "oneshotsoundsource",
+            from.oneshotsoundsource != to.oneshotsoundsource,
+        ),
+        ("bgsoundtype", from.bgsoundtype != to.bgsoundtype),
+        ("bgsoundsource", from.bgsoundsource != to.bgsoundsource),
+        ("imgtype", from.imgtype != to.imgtype),
+        ("imgsource", from.imgsource != to.imgsource),
+        ("chapter", from.chapter != to.chapter),
+        ("rank", from.rank != to.rank),
+        (
+            "oneshotsoundasset",
+            from.oneshotsoundasset != to.oneshotsoundasset,
+        ),
+        ("bgsoundasset", from.bgsoundasset != to.bgsoundasset),
+        ("imgasset", from.imgasset != to.imgasset),
+    ]
+    .into_iter()
+    .filter_map(|(name, changed)| changed.then_some(name))
+    .collect();
+    Diff {
+        from: from.revision,
+        to: to.revision,
+        content,
+        changed,
+    }
+}
@@ -0,0 +1,239 @@
+use diesel::sql_types::{
+    Array, BigInt, Bool, Float, Integer, Nullable, Text, Uuid as SqlUuid,
+};
+use diesel::{
+    sql_query, Connection, PgConnection, QueryableByName, RunQueryDsl,
+};
+use rocket::serde::Serialize;
+use uuid::Uuid;
+
+use crate::db::book::Readable;
+use crate::db::ApiResult;
+
+/// Options of `TS_HEADLINE` building the snippets of the hits, with
+/// matching words surrounded by `<mark>` tags
+const HEADLINE: &str = "StartSel=<mark>, StopSel=</mark>, \
+                        MaxFragments=2, MaxWords=30, MinWords=10";
+
+/// Search query parsed with the text search configuration of each
+/// language books are written in
+///
+/// The query is read as a web search, so `"quoted words"` match a
+/// phrase, `or` matches either word and `-word` excludes a word.
+const QUERIES: &str = "WITH Queries AS (
+         SELECT Language, WEBSEARCH_TO_TSQUERY(Language::REGCONFIG, $1) AS Query
+           FROM (SELECT DISTINCT Language FROM Books) AS Languages
+     )";
+
+/// A book whose title or synopsis matches a search
+#[derive(QueryableByName, Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct BookHit {
+    #[diesel(sql_type = SqlUuid)]
+    pub id: Uuid,
+    #[diesel(sql_type = Text)]
+    pub title: String,
+    #[diesel(sql_type = SqlUuid)]
+    pub author: Uuid,
+    /// Relevance of the book, titles weighing more than synopses
+    #[diesel(sql_type = Float)]
+    pub score: f32,
+    /// Excerpt of the title and synopsis with the matching words
+    /// highlighted
+    #[diesel(sql_type = Text)]
+    pub snippet: String,
+}
+
+/// A fragment whose content matches a search
+#[derive(QueryableByName, Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct FragmentHit {
+    #[diesel(sql_type = SqlUuid)]
+    pub id: Uuid,
+    #[diesel(sql_type = SqlUuid)]
+    pub book: Uuid,
+    #[diesel(sql_type = Text)]
+    pub booktitle: String,
+    #[diesel(sql_type = SqlUuid)]
+    pub chapter: Uuid,
+    #[diesel(sql_type = Integer)]
+    pub chapternumber: i32,
+    /// Rank of the fragment within its chapter
+    #[diesel(sql_type = Integer)]
+    pub rank: i32,
+    /// Relevance of the fragment
+    #[diesel(sql_type = Float)]
+    pub score: f32,
+    /// Excerpt of the content with the matching words highlighted
+    #[diesel(sql_type = Text)]
+    pub snippet: String,
+}
+
+/// Word similarity from which fuzzy searches match, lower than the
+/// default of `pg_trgm` so that full names with a typo in each word
+/// are still found
+const SIMILARITY_THRESHOLD: f32 = 0.4;
+
+/// Run the fuzzy search `find` with the threshold of the `<%`
+/// operator of `pg_trgm` set to [`SIMILARITY_THRESHOLD`]
+///
+/// The threshold is only set within the transaction running `find`.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `fuzzy`
+pub fn fuzzy<T>(
+    connector: &mut PgConnection,
+    find: impl FnOnce(&mut PgConnection) -> ApiResult<T>,
+) -> ApiResult<T> {
+    connector.transaction(|connector| {
+        sql_query(format!(
+            "SET LOCAL pg_trgm.word_similarity_threshold = {}",
+            SIMILARITY_THRESHOLD
+        ))
+        .execute(connector)?;
+        find(connector)
+    })
+}
+
+/// An item found by a fuzzy search, along with its similarity with
+/// the query, from 0 to 1
+///
+/// See `author::find` and `book::find`.
+#[derive(Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Scored<T> {
+    #[serde(flatten)]
+    pub item: T,
+    pub score: f32,
+}
+
+/// Books and fragments matching a search, see [`search`]
+#[derive(Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Hits {
+    pub books: Vec<BookHit>,
+    pub fragments: Vec<FragmentHit>,
+}
+
+/// Check `language` is a text search configuration known to the
+/// database, such as `english` or `simple`
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `language_exists`
+pub fn language_exists(
+    connector: &mut PgConnection,
+    language: &str,
+) -> ApiResult<bool> {
+    #[derive(QueryableByName)]
+    struct Found {
+        #[diesel(sql_type = Bool)]
+        found: bool,
+    }
+    sql_query(
+        "SELECT EXISTS (SELECT 1 FROM pg_ts_config WHERE cfgname = $1) \
+         AS Found",
+    )
+    .bind::<Text, _>(language)
+    .get_result::<Found>(connector)
+    .map(|found| found.found)
+}
+
+/// Search the books and fragments `readable` by a user for `query`
+///
+/// Books are matched on their title and synopsis, and fragments on
+/// their content, each in the language of their book. Books and
+/// fragments are searched in the version a reader reads by default:
+/// the published version of published books, and the draft of the
+/// others, see `book::publicbooks`. Items in
+/// the trash are left out. At most `limit` books and `limit`
+/// fragments are returned, from the most to the least relevant.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `search`
+pub fn search(
+    connector: &mut PgConnection,
+    query: &str,
+    readable: &Readable,
+    limit: i64,
+) -> ApiResult<Hits> {
+    let (all, owner, drafts) = match readable {
+        Readable::All => (true, None, Vec::new()),
+        Readable::Published { owner, drafts } => {
+            (false, *owner, drafts.clone())
+        }
+    };
+    let books = sql_query(format!(
+        "{}
+         SELECT PublicBooks.Id, PublicBooks.Title, PublicBooks.Author,
+                TS_RANK(PublicBooks.SearchVector, Queries.Query) AS Score,
+                TS_HEADLINE(PublicBooks.Language::REGCONFIG,
+                            PublicBooks.Title || '. ' ||
+                                COALESCE(PublicBooks.Synopsis, ''),
+                            Queries.Query, '{}') AS Snippet
+           FROM PublicBooks
+           JOIN Queries ON Queries.Language = PublicBooks.Language
+          WHERE PublicBooks.SearchVector @@ Queries.Query
+            AND PublicBooks.DeletedAt IS NULL
+            AND ($2 OR PublicBooks.Status = 'published'
+                 OR PublicBooks.Owner = $3 OR PublicBooks.Id = ANY($4))
+          ORDER BY Score DESC, PublicBooks.Id
+          LIMIT $5",
+        QUERIES, HEADLINE
+    ))
+    .bind::<Text, _>(query)
+    .bind::<Bool, _>(all)
+    .bind::<Nullable<SqlUuid>, _>(owner)
+    .bind::<Array<SqlUuid>, _>(&drafts)
+    .bind::<BigInt, _>(limit)
+    .load::<BookHit>(connector)?;
+    let fragments = sql_query(format!(
+        "{},
+         Fragments AS (
+             SELECT PublishedFragments.Id, PublishedFragments.Book,
+                    Chapter, Number, Rank, Content, SearchVector
+               FROM PublishedFragments
+               JOIN PublishedChapters
+                 ON PublishedChapters.Id = PublishedFragments.Chapter
+              WHERE PublishedFragments.Book IN (SELECT Id FROM Books
+                                                 WHERE Status = 'published')
+             UNION ALL
+             SELECT BookFragments.Id, BookFragments.Book,
+                    Chapter, Number, Rank, Content, SearchVector
+               FROM BookFragments
+               JOIN Chapters ON Chapters.Id = BookFragments.Chapter
+              WHERE BookFragments.DeletedAt IS NULL
+                AND BookFragments.Book IN (
+                        SELECT Id FROM Books
+                         WHERE Status <> 'published'
+                           AND ($2 OR Owner = $3 OR Id = ANY($4)))
+         )
+         SELECT Fragments.Id, Fragments.Book,
+                PublicBooks.Title AS BookTitle, Fragments.Chapter,
+                Fragments.Number AS ChapterNumber, Fragments.Rank,
+                TS_RANK(Fragments.SearchVector, Queries.Query) AS Score,
+                TS_HEADLINE(PublicBooks.Language::REGCONFIG,
+                            Fragments.Content, Queries.Query, '{}')
+                    AS Snippet
+           FROM Fragments
+           JOIN PublicBooks ON PublicBooks.Id = Fragments.Book
+           JOIN Queries ON Queries.Language = PublicBooks.Language
+          WHERE Fragments.SearchVector @@ Queries.Query
+            AND PublicBooks.DeletedAt IS NULL
+          ORDER BY Score DESC, Fragments.Id
+          LIMIT $5",
+        QUERIES, HEADLINE
+    ))
+    .bind::<Text, _>(query)
+    .bind::<Bool, _>(all)
+    .bind::<Nullable<SqlUuid>, _>(owner)
+    .bind::<Array<SqlUuid>, _>(&drafts)
+    .bind::<BigInt, _>(limit)
+    .load::<FragmentHit>(connector)?;
+    Ok(Hits { books, fragments })
+}
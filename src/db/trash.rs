@@ -0,0 +1,558 @@
+use std::env;
+use std::time::Duration;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use diesel::dsl::sql;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::sql_types::Bool;
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, PgConnection, QueryDsl,
+    RunQueryDsl,
+};
+use hmac::{Hmac, Mac};
+use rocket::serde::Serialize;
+use sha2::Sha256;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::db::{chapter, fragment, published, serializable, ApiResult};
+use crate::models::{Author, Book, Bookfragment, Chapter};
+use crate::schema::{authors, bookfragments, books, chapters};
+
+/// Amount of days an item stays in the trash when
+/// `ALEXANDRIA_TRASH_RETENTION_DAYS` is not set
+const DEFAULT_RETENTION_DAYS: i64 = 30;
+
+/// Seconds between two purges of the trash, see
+/// [`purge_periodically`]
+const PURGE_INTERVAL: u64 = 60 * 60;
+
+/// Condition matching the authors who have no book left, including
+/// in the last publication of a book, see [`purge`]
+const WITHOUT_BOOKS: &str =
+    "NOT EXISTS (SELECT 1 FROM Books WHERE Books.Author = Authors.Id)
+     AND NOT EXISTS (SELECT 1 FROM PublishedBooks
+                      WHERE PublishedBooks.Author = Authors.Id)";
+
+/// Content of the trash, see [`list`]
+#[derive(Serialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct Trash {
+    pub authors: Vec<Author>,
+    pub books: Vec<Book>,
+    pub chapters: Vec<Chapter>,
+    pub fragments: Vec<Bookfragment>,
+}
+
+/// List the authors, books, chapters and fragments in the trash, from
+/// the most recently deleted to the oldest
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list`
+pub fn list(connector: &mut PgConnection) -> ApiResult<Trash> {
+    let authors = authors::table
+        .filter(authors::deletedat.is_not_null())
+        .order(authors::deletedat.desc())
+        .load(connector)?;
+    let books = books::table
+        .filter(books::deletedat.is_not_null())
+        .order(books::deletedat.desc())
+        .load(connector)?;
+    let chapters = chapters::table
+        .filter(chapters::deletedat.is_not_null())
+        .order(chapters::deletedat.desc())
+        .load(connector)?;
+    let fragments = bookfragments::table
+        .filter(bookfragments::deletedat.is_not_null())
+        .order(bookfragments::deletedat.desc())
+        .load(connector)?;
+    Ok(Trash {
+        authors,
+        books,
+        chapters,
+        fragments,
+    })
+}
+
+/// Get an author in the trash
+///
+/// # Errors
+///
+/// If the author does not exist or is not in the trash, a `NotFound`
+/// error is returned. If an error is returned by diesel, forward it
+/// to the function calling `author`
+pub fn author(connector: &mut PgConnection, id: Uuid) -> ApiResult<Author> {
+    authors::table
+        .find(id)
+        .filter(authors::deletedat.is_not_null())
+        .first(connector)
+}
+
+/// List the books moved to the trash along with the author `author`,
+/// which `restore_author` would bring back
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `author_books`
+pub fn author_books(
+    connector: &mut PgConnection,
+    author: &Author,
+) -> ApiResult<Vec<Book>> {
+    books::table
+        .filter(books::author.eq(author.id))
+        .filter(books::deletedat.eq(author.deletedat))
+        .load(connector)
+}
+
+/// Get a book in the trash
+///
+/// # Errors
+///
+/// If the book does not exist or is not in the trash, a `NotFound`
+/// error is returned. If an error is returned by diesel, forward it
+/// to the function calling `book`
+pub fn book(connector: &mut PgConnection, id: Uuid) -> ApiResult<Book> {
+    books::table
+        .find(id)
+        .filter(books::deletedat.is_not_null())
+        .first(connector)
+}
+
+/// Get a chapter in the trash
+///
+/// # Errors
+///
+/// If the chapter does not exist or is not in the trash, a `NotFound`
+/// error is returned. If an error is returned by diesel, forward it
+/// to the function calling `chapter`
+pub fn chapter(connector: &mut PgConnection, id: Uuid) -> ApiResult<Chapter> {
+    chapters::table
+        .find(id)
+        .filter(chapters::deletedat.is_not_null())
+        .first(connector)
+}
+
+/// Get a fragment in the trash
+///
+/// # Errors
+///
+/// If the fragment does not exist or is not in the trash, a
+/// `NotFound` error is returned. If an error is returned by diesel,
+/// forward it to the function calling `fragment`
+pub fn fragment(
+    connector: &mut PgConnection,
+    id: Uuid,
+) -> ApiResult<Bookfragment> {
+    bookfragments::table
+        .find(id)
+        .filter(bookfragments::deletedat.is_not_null())
+        .first(connector)
+}
+
+/// Seconds a token confirming a deletion stays valid, see
+/// [`Cascade::sign`]
+const CONFIRMATION_LIFETIME: i64 = 10 * 60;
+
+/// Secret key signing the tokens confirming deletions, see
+/// [`Cascade::sign`]
+pub struct ConfirmationKey(Vec<u8>);
+
+impl ConfirmationKey {
+    /// Read the key from `ALEXANDRIA_SECRET_KEY`
+    ///
+    /// When it is not set, a random key is generated, so that tokens
+    /// are only accepted by this instance until it is restarted.
+    #[must_use]
+    pub fn from_env() -> Self {
+        match env::var("ALEXANDRIA_SECRET_KEY") {
+            Ok(key) if !key.is_empty() => Self(key.into_bytes()),
+            _ => {
+                warn!("ALEXANDRIA_SECRET_KEY is not set, using a random key");
+                let mut key = vec![0; 32];
+                OsRng.fill_bytes(&mut key);
+                Self(key)
+            }
+        }
+    }
+
+    /// HMAC of the deletion described by `cascade`, made by the user
+    /// `user` and valid until the UNIX timestamp `expires`
+    fn mac(&self, cascade: &Cascade, user: Uuid, expires: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0)
+            .expect("HMAC accepts keys of any size");
+        mac.update(
+            format!(
+                "{}:{}:{}:{}:{}:{}",
+                cascade.kind,
+                cascade.id,
+                cascade.books,
+                cascade.fragments,
+                user,
+                expires
+            )
+            .as_bytes(),
+        );
+        mac
+    }
+}
+
+/// Items moved to the trash along with an author, a book or a
+/// chapter, see [`author_cascade`], [`book_cascade`] and
+/// [`chapter_cascade`]
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(crate = "rocket::serde")]
+pub struct Cascade {
+    #[serde(skip)]
+    kind: &'static str,
+    #[serde(skip)]
+    id: Uuid,
+    pub books: i64,
+    pub fragments: i64,
+    /// Token confirming the deletion, only set when other items than
+    /// the deleted one are moved to the trash, see [`Cascade::sign`]
+    pub token: Option<String>,
+}
+
+impl Cascade {
+    /// Describe the cascade of deleting the `kind` identified by `id`
+    fn new(kind: &'static str, id: Uuid, books: i64, fragments: i64) -> Self {
+        Self {
+            kind,
+            id,
+            books,
+            fragments,
+            token: None,
+        }
+    }
+
+    /// Set the token confirming the deletion of the cascade by the
+    /// user `user`
+    ///
+    /// The token is an HMAC of the item, of what its deletion cascades
+    /// to, of `user` and of its expiration date, signed with `key`. It
+    /// stops matching as soon as books or fragments are added to or
+    /// removed from the cascade, when another user sends it, and after
+    /// `CONFIRMATION_LIFETIME` seconds.
+    #[must_use]
+    pub fn sign(self, key: &ConfirmationKey, user: Uuid) -> Self {
+        if self.books == 0 && self.fragments == 0 {
+            return self;
+        }
+        let expires = chrono::Utc::now().timestamp() + CONFIRMATION_LIFETIME;
+        let mac = key.mac(&self, user, expires).finalize().into_bytes();
+        Self {
+            token: Some(format!("{}.{:x}", expires, mac)),
+            ..self
+        }
+    }
+
+    /// Whether `confirm` allows the user `user` to make the deletion
+    /// this cascade describes
+    ///
+    /// Deletions which do not cascade need no confirmation.
+    #[must_use]
+    pub fn confirmed(
+        &self,
+        key: &ConfirmationKey,
+        user: Uuid,
+        confirm: Option<&str>,
+    ) -> bool {
+        if self.books == 0 && self.fragments == 0 {
+            return true;
+        }
+        let (expires, mac) = match confirm.and_then(|c| c.split_once('.')) {
+            Some(token) => token,
+            None => return false,
+        };
+        let expires = match expires.parse::<i64>() {
+            Ok(expires) if expires >= chrono::Utc::now().timestamp() => expires,
+            _ => return false,
+        };
+        let mac: Option<Vec<u8>> = (0..mac.len())
+            .step_by(2)
+            .map(|i| {
+                mac.get(i..i + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            })
+            .collect();
+        match mac {
+            Some(mac) => {
+                key.mac(self, user, expires).verify_slice(&mac).is_ok()
+            }
+            None => false,
+        }
+    }
+}
+
+/// Count the books and fragments deleting an author would move to the
+/// trash along with them, see `author::delete`
+///
+/// # Errors
+///
+/// If the author does not exist or is in the trash, a `NotFound`
+/// error is returned. If an error is returned by diesel, forward it
+/// to the function calling `author_cascade`
+pub fn author_cascade(
+    connector: &mut PgConnection,
+    id: Uuid,
+) -> ApiResult<Cascade> {
+    authors::table
+        .find(id)
+        .filter(authors::deletedat.is_null())
+        .select(authors::id)
+        .first::<Uuid>(connector)?;
+    let published = published::by_author(connector, id)?;
+    let books: Vec<Uuid> = books::table
+        .filter(books::author.eq(id).or(books::id.eq_any(published)))
+        .filter(books::deletedat.is_null())
+        .select(books::id)
+        .load(connector)?;
+    let fragments = bookfragments::table
+        .filter(bookfragments::book.eq_any(&books))
+        .filter(bookfragments::deletedat.is_null())
+        .count()
+        .get_result(connector)?;
+    let books = i64::try_from(books.len()).unwrap_or(i64::MAX);
+    Ok(Cascade::new("author", id, books, fragments))
+}
+
+/// Count the fragments deleting a book would move to the trash along
+/// with it, see `book::delete`
+///
+/// # Errors
+///
+/// If the book does not exist or is in the trash, a `NotFound` error
+/// is returned. If an error is returned by diesel, forward it to the
+/// function calling `book_cascade`
+pub fn book_cascade(
+    connector: &mut PgConnection,
+    id: Uuid,
+) -> ApiResult<Cascade> {
+    books::table
+        .find(id)
+        .filter(books::deletedat.is_null())
+        .select(books::id)
+        .first::<Uuid>(connector)?;
+    let fragments = bookfragments::table
+        .filter(bookfragments::book.eq(id))
+        .filter(bookfragments::deletedat.is_null())
+        .count()
+        .get_result(connector)?;
+    Ok(Cascade::new("book", id, 0, fragments))
+}
+
+/// Count the fragments deleting a chapter would move to the trash
+/// along with it, see `chapter::delete`
+///
+/// # Errors
+///
+/// If the chapter does not exist or is in the trash, a `NotFound`
+/// error is returned. If an error is returned by diesel, forward it
+/// to the function calling `chapter_cascade`
+pub fn chapter_cascade(
+    connector: &mut PgConnection,
+    id: Uuid,
+) -> ApiResult<Cascade> {
+    chapters::table
+        .find(id)
+        .filter(chapters::deletedat.is_null())
+        .select(chapters::id)
+        .first::<Uuid>(connector)?;
+    let fragments = bookfragments::table
+        .filter(bookfragments::chapter.eq(id))
+        .filter(bookfragments::deletedat.is_null())
+        .count()
+        .get_result(connector)?;
+    Ok(Cascade::new("chapter", id, 0, fragments))
+}
+
+/// Bring the fragments of `books` deleted at `deleted` back from the
+/// trash
+///
+/// Fragments deleted on their own before their book are left in the
+/// trash.
+fn restore_fragments(
+    connector: &mut PgConnection,
+    books: Vec<Uuid>,
+    deleted: Option<chrono::NaiveDateTime>,
+) -> ApiResult<usize> {
+    diesel::update(bookfragments::table)
+        .filter(bookfragments::book.eq_any(books))
+        .filter(bookfragments::deletedat.eq(deleted))
+        .set(bookfragments::deletedat.eq(None::<chrono::NaiveDateTime>))
+        .execute(connector)
+}
+
+/// Bring an author back from the trash
+///
+/// Their books and fragments moved to the trash along with them are
+/// restored too, while the ones deleted earlier stay in the trash. The
+/// whole operation runs in a single serializable transaction.
+///
+/// # Errors
+///
+/// If the author is not in the trash, a `NotFound` error is returned.
+/// If an error is returned by diesel, forward it to the function
+/// calling `restore_author`
+pub fn restore_author(
+    connector: &mut PgConnection,
+    id: Uuid,
+) -> ApiResult<Author> {
+    serializable(connector, |connector| {
+        let trashed = author(connector, id)?;
+        let restored = diesel::update(authors::table.find(id))
+            .set(authors::deletedat.eq(None::<chrono::NaiveDateTime>))
+            .get_result(connector)?;
+        let published = published::by_author(connector, id)?;
+        let books = diesel::update(books::table)
+            .filter(books::author.eq(id).or(books::id.eq_any(published)))
+            .filter(books::deletedat.eq(trashed.deletedat))
+            .set(books::deletedat.eq(None::<chrono::NaiveDateTime>))
+            .returning(books::id)
+            .get_results(connector)?;
+        restore_fragments(connector, books, trashed.deletedat)?;
+        Ok(restored)
+    })
+}
+
+/// Bring a book back from the trash
+///
+/// Its fragments moved to the trash along with it are restored too,
+/// while the ones deleted earlier stay in the trash. The whole
+/// operation runs in a single serializable transaction.
+///
+/// # Errors
+///
+/// If the book is not in the trash, a `NotFound` error is returned. If
+/// an error is returned by diesel, forward it to the function calling
+/// `restore_book`
+pub fn restore_book(connector: &mut PgConnection, id: Uuid) -> ApiResult<Book> {
+    serializable(connector, |connector| {
+        let trashed = book(connector, id)?;
+        let restored = diesel::update(books::table.find(id))
+            .set(books::deletedat.eq(None::<chrono::NaiveDateTime>))
+            .get_result(connector)?;
+        restore_fragments(connector, vec![id], trashed.deletedat)?;
+        Ok(restored)
+    })
+}
+
+/// Bring a chapter back from the trash along with the fragments
+/// deleted with it, see `chapter::restore`
+///
+/// # Errors
+///
+/// If the chapter is not in the trash, a `NotFound` error is
+/// returned. If an error is returned by diesel, forward it to the
+/// function calling `restore_chapter`
+pub fn restore_chapter(
+    connector: &mut PgConnection,
+    id: Uuid,
+) -> ApiResult<Chapter> {
+    chapter::restore(connector, id)
+}
+
+/// Bring a fragment back from the trash, see `fragment::restore`
+///
+/// # Errors
+///
+/// If the fragment is not in the trash, a `NotFound` error is
+/// returned. If an error is returned by diesel, forward it to the
+/// function calling `restore_fragment`
+pub fn restore_fragment(
+    connector: &mut PgConnection,
+    id: Uuid,
+) -> ApiResult<Bookfragment> {
+    fragment::restore(connector, id)
+}
+
+/// Delete for good the items moved to the trash before `before`
+///
+/// Authors who still have books, whether they are live or were moved
+/// to the trash after `before`, are kept in the trash until their
+/// books are deleted, as deleting an author deletes their books.
+/// A chapter or a book is never moved to the trash before its
+/// fragments, so they are deleted for good along with it. Returns the
+/// amount of authors, books, chapters and fragments deleted.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `purge`
+pub fn purge(
+    connector: &mut PgConnection,
+    before: chrono::NaiveDateTime,
+) -> ApiResult<usize> {
+    use diesel::Connection;
+    connector.transaction(|connector| {
+        let fragments = diesel::delete(
+            bookfragments::table.filter(bookfragments::deletedat.lt(before)),
+        )
+        .execute(connector)?;
+        let chapters = diesel::delete(
+            chapters::table.filter(chapters::deletedat.lt(before)),
+        )
+        .execute(connector)?;
+        let books =
+            diesel::delete(books::table.filter(books::deletedat.lt(before)))
+                .execute(connector)?;
+        let authors = diesel::delete(
+            authors::table
+                .filter(authors::deletedat.lt(before))
+                .filter(sql::<Bool>(WITHOUT_BOOKS)),
+        )
+        .execute(connector)?;
+        Ok(fragments + chapters + books + authors)
+    })
+}
+
+/// Read how long items stay in the trash before being purged
+///
+/// The retention period is read in days from
+/// `ALEXANDRIA_TRASH_RETENTION_DAYS`, and defaults to 30 days when it
+/// is not set or invalid.
+#[must_use]
+pub fn retention_from_env() -> chrono::Duration {
+    let days = env::var("ALEXANDRIA_TRASH_RETENTION_DAYS")
+        .ok()
+        .and_then(|days| days.parse::<i64>().ok())
+        .filter(|days| *days >= 0)
+        .unwrap_or(DEFAULT_RETENTION_DAYS);
+    info!("Keeping items in the trash for {} days", days);
+    chrono::Duration::days(days)
+}
+
+/// Purge the trash every hour, deleting for good the items which have
+/// been in it for longer than `retention`
+///
+/// Errors are only logged so a failed purge is attempted again an
+/// hour later.
+pub async fn purge_periodically(
+    pool: Pool<ConnectionManager<PgConnection>>,
+    retention: chrono::Duration,
+) {
+    let mut interval =
+        rocket::tokio::time::interval(Duration::from_secs(PURGE_INTERVAL));
+    loop {
+        interval.tick().await;
+        let pool = pool.clone();
+        let task = move || {
+            let before = chrono::Utc::now().naive_utc() - retention;
+            match pool.get() {
+                Ok(mut connector) => {
+                    purge(&mut connector, before).map_err(|e| e.to_string())
+                }
+                Err(e) => Err(e.to_string()),
+            }
+        };
+        match rocket::tokio::task::spawn_blocking(task).await {
+            Ok(Ok(0)) => {}
+            Ok(Ok(purged)) => info!("Purged {} items from the trash", purged),
+            Ok(Err(e)) => warn!("Failed to purge the trash: {}", e),
+            Err(e) => warn!("Failed to purge the trash: {}", e),
+        }
+    }
+}
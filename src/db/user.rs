@@ -0,0 +1,348 @@
+use diesel::upsert::excluded;
+use diesel::{
+    AsChangeset, BoolExpressionMethods, ExpressionMethods, OptionalExtension,
+    PgConnection, QueryDsl, RunQueryDsl,
+};
+use rocket::serde::Serialize;
+use uuid::Uuid;
+
+use crate::auth::jwt::Claims;
+use crate::db::ApiResult;
+use crate::models::{ApiKey, Role, Session, User};
+use crate::schema::users::dsl;
+use crate::schema::{apikeys, sessions};
+
+/// A user as sent back by the API, without their password hash
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Account {
+    pub id: Uuid,
+    pub username: String,
+    pub role: Role,
+    pub created: chrono::NaiveDateTime,
+}
+
+impl From<User> for Account {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            role: user.role,
+            created: user.created,
+        }
+    }
+}
+
+/// An API key as sent back by the API, without its hash
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Key {
+    pub id: Uuid,
+    pub owner: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub created: chrono::NaiveDateTime,
+    pub lastused: Option<chrono::NaiveDateTime>,
+    pub revoked: Option<chrono::NaiveDateTime>,
+}
+
+impl From<ApiKey> for Key {
+    fn from(key: ApiKey) -> Self {
+        Self {
+            id: key.id,
+            owner: key.owner,
+            name: key.name,
+            prefix: key.prefix,
+            created: key.created,
+            lastused: key.lastused,
+            revoked: key.revoked,
+        }
+    }
+}
+
+/// Changes an administrator can make to a user
+///
+/// Fields left to `None` are not changed.
+#[derive(AsChangeset)]
+#[diesel(table_name = crate::schema::users)]
+pub struct Changes {
+    pub passwordhash: Option<String>,
+    pub role: Option<Role>,
+}
+
+/// List all users, sorted by username
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list`
+pub fn list(connector: &mut PgConnection) -> ApiResult<Vec<User>> {
+    dsl::users
+        .order(dsl::username.asc())
+        .load::<User>(connector)
+}
+
+/// Get a user by their identifier
+///
+/// # Errors
+///
+/// If the user does not exist, diesel returns a `NotFound` error. If
+/// an error is returned by diesel, forward it to the function calling
+/// `get`
+pub fn get(connector: &mut PgConnection, id: Uuid) -> ApiResult<User> {
+    dsl::users.find(id).first(connector)
+}
+
+/// Get a local user by their username
+///
+/// Users provisioned from an identity provider are never returned,
+/// even if their username matches.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `from_username`
+pub fn from_username(
+    connector: &mut PgConnection,
+    username: &str,
+) -> ApiResult<Option<User>> {
+    dsl::users
+        .filter(dsl::username.eq(username))
+        .filter(dsl::subject.is_null())
+        .first(connector)
+        .optional()
+}
+
+/// Add a new user in the database
+///
+/// # Errors
+///
+/// If a local user with the same username already exists, diesel
+/// returns a
+/// `UniqueViolation` error. If an error is returned by diesel, forward
+/// it to the function calling `new`
+pub fn new(connector: &mut PgConnection, user: &User) -> ApiResult<User> {
+    diesel::insert_into(dsl::users)
+        .values(user)
+        .get_result(connector)
+}
+
+/// Get the user asserted by the tokens of an identity provider,
+/// creating them if they do not exist yet
+///
+/// Users authenticated by an identity provider are provisioned this
+/// way on their first request, and identified by the `issuer` and the
+/// `subject` of their tokens. They are kept apart from local accounts,
+/// even those with the same username. Their username and their role
+/// are updated when they changed on the identity provider.
+/// Provisioned users have no password, so they cannot log in with
+/// one.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `provision`
+pub fn provision(
+    connector: &mut PgConnection,
+    claims: &Claims,
+) -> ApiResult<User> {
+    let found = dsl::users
+        .filter(dsl::issuer.eq(&claims.issuer))
+        .filter(dsl::subject.eq(&claims.subject))
+        .first::<User>(connector)
+        .optional()?;
+    match found {
+        Some(user)
+            if user.role == claims.role
+                && user.username == claims.username =>
+        {
+            Ok(user)
+        }
+        _ => {
+            let user = User {
+                id: Uuid::new_v4(),
+                username: claims.username.clone(),
+                passwordhash: String::new(),
+                role: claims.role,
+                created: chrono::Utc::now().naive_utc(),
+                issuer: Some(claims.issuer.clone()),
+                subject: Some(claims.subject.clone()),
+            };
+            diesel::insert_into(dsl::users)
+                .values(&user)
+                .on_conflict((dsl::issuer, dsl::subject))
+                .do_update()
+                .set((
+                    dsl::username.eq(excluded(dsl::username)),
+                    dsl::role.eq(excluded(dsl::role)),
+                ))
+                .get_result(connector)
+        }
+    }
+}
+
+/// Change the password hash and the role of a user
+///
+/// # Errors
+///
+/// If the user does not exist, diesel returns a `NotFound` error. If
+/// an error is returned by diesel, forward it to the function calling
+/// `update`
+pub fn update(
+    connector: &mut PgConnection,
+    id: Uuid,
+    changes: &Changes,
+) -> ApiResult<User> {
+    diesel::update(dsl::users.find(id))
+        .set(changes)
+        .get_result(connector)
+}
+
+/// Delete a user along with their sessions and API keys
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `delete`
+pub fn delete(connector: &mut PgConnection, id: Uuid) -> ApiResult<usize> {
+    diesel::delete(dsl::users.find(id)).execute(connector)
+}
+
+/// Whether any user is an administrator
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `has_admin`
+pub fn has_admin(connector: &mut PgConnection) -> ApiResult<bool> {
+    let highest = dsl::users
+        .select(dsl::role)
+        .order(dsl::role.desc())
+        .first::<Role>(connector)
+        .optional()?;
+    Ok(highest == Some(Role::Admin))
+}
+
+/// Open a new session, deleting the expired ones
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `new_session`
+pub fn new_session(
+    connector: &mut PgConnection,
+    session: &Session,
+) -> ApiResult<usize> {
+    diesel::delete(
+        sessions::table.filter(sessions::expires.lt(diesel::dsl::now)),
+    )
+    .execute(connector)?;
+    diesel::insert_into(sessions::table)
+        .values(session)
+        .execute(connector)
+}
+
+/// Get the user owning the unexpired session whose token hashes to
+/// `tokenhash`, along with the identifier of the session
+///
+/// # Errors
+///
+/// If there is no such session, diesel returns a `NotFound` error. If
+/// an error is returned by diesel, forward it to the function calling
+/// `from_session`
+pub fn from_session(
+    connector: &mut PgConnection,
+    tokenhash: &str,
+) -> ApiResult<(User, Uuid)> {
+    sessions::table
+        .inner_join(dsl::users)
+        .filter(sessions::tokenhash.eq(tokenhash))
+        .filter(sessions::expires.gt(diesel::dsl::now))
+        .select((crate::schema::users::all_columns, sessions::id))
+        .first(connector)
+}
+
+/// Close a session
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `delete_session`
+pub fn delete_session(
+    connector: &mut PgConnection,
+    id: Uuid,
+) -> ApiResult<usize> {
+    diesel::delete(sessions::table.find(id)).execute(connector)
+}
+
+/// List the API keys, or only those of `owner` if it is set, oldest
+/// first
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `list_keys`
+pub fn list_keys(
+    connector: &mut PgConnection,
+    owner: Option<Uuid>,
+) -> ApiResult<Vec<ApiKey>> {
+    let mut query = apikeys::table.order(apikeys::created.asc()).into_boxed();
+    if let Some(owner) = owner {
+        query = query.filter(apikeys::owner.eq(owner));
+    }
+    query.load::<ApiKey>(connector)
+}
+
+/// Add a new API key in the database
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `new_key`
+pub fn new_key(
+    connector: &mut PgConnection,
+    key: &ApiKey,
+) -> ApiResult<ApiKey> {
+    diesel::insert_into(apikeys::table)
+        .values(key)
+        .get_result(connector)
+}
+
+/// Revoke an API key which is still valid
+///
+/// # Errors
+///
+/// If the key does not exist or is already revoked, diesel returns a
+/// `NotFound` error. If an error is returned by diesel, forward it to
+/// the function calling `revoke_key`
+pub fn revoke_key(connector: &mut PgConnection, id: Uuid) -> ApiResult<ApiKey> {
+    diesel::update(
+        apikeys::table
+            .filter(apikeys::id.eq(id).and(apikeys::revoked.is_null())),
+    )
+    .set(apikeys::revoked.eq(chrono::Utc::now().naive_utc()))
+    .get_result(connector)
+}
+
+/// Get the user owning the valid API key hashing to `keyhash`, along
+/// with the identifier of the key
+///
+/// The key is marked as used.
+///
+/// # Errors
+///
+/// If there is no such key or if it is revoked, diesel returns a
+/// `NotFound` error. If an error is returned by diesel, forward it to
+/// the function calling `from_api_key`
+pub fn from_api_key(
+    connector: &mut PgConnection,
+    keyhash: &str,
+) -> ApiResult<(User, Uuid)> {
+    let key: ApiKey =
+        diesel::update(apikeys::table.filter(
+            apikeys::keyhash.eq(keyhash).and(apikeys::revoked.is_null()),
+        ))
+        .set(apikeys::lastused.eq(chrono::Utc::now().naive_utc()))
+        .get_result(connector)?;
+    Ok((get(connector, key.owner)?, key.id))
+}
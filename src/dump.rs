@@ -0,0 +1,457 @@
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+
+use diesel::{
+    Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl,
+};
+use diesel_migrations::MigrationHarness;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rocket::serde::json::serde_json;
+use rocket::serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::db::{schema_version, MIGRATIONS};
+use crate::models::{
+    ApiKey, AuditEntry, Author, Book, Bookfragment, Bookmark, Chapter,
+    Collaborator, FragmentRevision, MediaAsset, MediaVariant, Progress,
+    PublishedBook, PublishedChapter, PublishedFragment, User,
+};
+use crate::schema::{
+    apikeys, auditlog, authors, bookfragments, bookmarks, books, chapters,
+    collaborators, fragmentrevisions, mediaassets, mediavariants,
+    publishedbooks, publishedchapters, publishedfragments, readingprogress,
+    users,
+};
+
+/// Version of the dump format written by this version of alexandria
+const FORMAT: u32 = 1;
+
+/// Amount of rows read from or written to the database at once
+const BATCH_SIZE: usize = 1000;
+
+/// Error encountered while dumping or restoring an instance
+#[derive(Debug)]
+pub enum Error {
+    /// The dump could not be read or written
+    Io(std::io::Error),
+    /// A line of the dump is not a valid record
+    Json(serde_json::Error),
+    /// The dump does not match what this instance expects
+    Invalid(String),
+    /// The database could not be read, written or migrated
+    Database(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Json(e) => write!(f, "Invalid record: {}", e),
+            Self::Invalid(message) => write!(f, "Invalid dump: {}", message),
+            Self::Database(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(other: std::io::Error) -> Self {
+        Self::Io(other)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(other: serde_json::Error) -> Self {
+        Self::Json(other)
+    }
+}
+
+impl From<diesel::result::Error> for Error {
+    fn from(other: diesel::result::Error) -> Self {
+        Self::Database(other.to_string())
+    }
+}
+
+/// A line of a dump
+///
+/// A dump starts with a header, followed by all authors, users, API
+/// keys, media assets, variants of images, books, published books,
+/// collaborators, chapters, fragments, published chapters, published
+/// fragments, revisions of fragments, reading progress, bookmarks and
+/// audit log of the instance in this order, so they can
+/// be restored without breaking any foreign key.
+/// Only the metadata of media assets and variants are dumped, their
+/// content is left in the media storage.
+/// Sessions are not dumped, users log in again after a restore.
+#[derive(Serialize, Deserialize)]
+#[serde(
+    crate = "rocket::serde",
+    tag = "type",
+    content = "data",
+    rename_all = "lowercase"
+)]
+pub enum Record {
+    Header { format: u32, schema: String },
+    Author(Author),
+    User(User),
+    Key(ApiKey),
+    Media(MediaAsset),
+    Variant(MediaVariant),
+    Book(Book),
+    PublishedBook(PublishedBook),
+    Collaborator(Collaborator),
+    Chapter(Chapter),
+    Fragment(Bookfragment),
+    PublishedChapter(PublishedChapter),
+    Published(PublishedFragment),
+    Revision(FragmentRevision),
+    Progress(Progress),
+    Bookmark(Bookmark),
+    Audit(AuditEntry),
+}
+
+/// Write a record as a line of the dump
+fn write_record(output: &mut impl Write, record: &Record) -> Result<(), Error> {
+    serde_json::to_writer(&mut *output, record)?;
+    output.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Write all rows of a table in the dump, ordered by ID
+///
+/// Rows are read by batches of `BATCH_SIZE` so the whole table is
+/// never loaded in memory.
+macro_rules! dump_table {
+    (
+        $connector:expr,
+        $output:expr,
+        $table:ident,
+        $model:ty,
+        $variant:ident
+    ) => {{
+        use crate::schema::$table::dsl;
+        let mut last = None;
+        let mut count = 0;
+        loop {
+            let mut query = dsl::$table
+                .order(dsl::id.asc())
+                .limit(i64::try_from(BATCH_SIZE).unwrap_or(i64::MAX))
+                .into_boxed();
+            if let Some(last) = last {
+                query = query.filter(dsl::id.gt(last));
+            }
+            let rows: Vec<$model> = query.load($connector)?;
+            if rows.is_empty() {
+                break;
+            }
+            count += rows.len();
+            last = rows.last().map(|row| row.id);
+            for row in rows {
+                write_record($output, &Record::$variant(row))?;
+            }
+        }
+        info!("Dumped {} rows from {}", count, stringify!($table));
+    }};
+}
+
+/// Dump all authors, users, API keys, media assets, variants, books,
+/// published books, collaborators, chapters, fragments, published
+/// chapters, published fragments, revisions of fragments, reading
+/// progress, bookmarks and audit log of the instance
+///
+/// The dump is written to `output` as gzip-compressed JSON records,
+/// one per line, starting with a header holding the version of the
+/// database schema. All tables are read within a single transaction,
+/// so the dump is consistent even if the instance is in use.
+///
+/// # Errors
+///
+/// If the database has pending migrations or cannot be read, or if
+/// the dump cannot be written, an error is returned.
+pub fn dump(
+    connector: &mut PgConnection,
+    output: impl Write,
+) -> Result<(), Error> {
+    let pending = connector
+        .has_pending_migration(MIGRATIONS)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    if pending {
+        return Err(Error::Database(
+            "The database has pending migrations".to_owned(),
+        ));
+    }
+    let mut output = GzEncoder::new(output, Compression::default());
+    write_record(
+        &mut output,
+        &Record::Header {
+            format: FORMAT,
+            schema: schema_version(),
+        },
+    )?;
+    connector
+        .build_transaction()
+        .repeatable_read()
+        .read_only()
+        .run(|connector| {
+            dump_table!(connector, &mut output, authors, Author, Author);
+            dump_table!(connector, &mut output, users, User, User);
+            dump_table!(connector, &mut output, apikeys, ApiKey, Key);
+            dump_table!(connector, &mut output, mediaassets, MediaAsset, Media);
+            dump_table!(
+                connector,
+                &mut output,
+                mediavariants,
+                MediaVariant,
+                Variant
+            );
+            dump_table!(connector, &mut output, books, Book, Book);
+            dump_table!(
+                connector,
+                &mut output,
+                publishedbooks,
+                PublishedBook,
+                PublishedBook
+            );
+            dump_table!(
+                connector,
+                &mut output,
+                collaborators,
+                Collaborator,
+                Collaborator
+            );
+            dump_table!(connector, &mut output, chapters, Chapter, Chapter);
+            dump_table!(
+                connector,
+                &mut output,
+                bookfragments,
+                Bookfragment,
+                Fragment
+            );
+            dump_table!(
+                connector,
+                &mut output,
+                publishedchapters,
+                PublishedChapter,
+                PublishedChapter
+            );
+            dump_table!(
+                connector,
+                &mut output,
+                publishedfragments,
+                PublishedFragment,
+                Published
+            );
+            dump_table!(
+                connector,
+                &mut output,
+                fragmentrevisions,
+                FragmentRevision,
+                Revision
+            );
+            dump_table!(
+                connector,
+                &mut output,
+                readingprogress,
+                Progress,
+                Progress
+            );
+            dump_table!(connector, &mut output, bookmarks, Bookmark, Bookmark);
+            dump_table!(connector, &mut output, auditlog, AuditEntry, Audit);
+            Ok::<(), Error>(())
+        })?;
+    output.finish()?.flush()?;
+    Ok(())
+}
+
+/// Rows read from a dump and not yet inserted in the database
+#[derive(Default)]
+struct Pending {
+    authors: Vec<Author>,
+    users: Vec<User>,
+    keys: Vec<ApiKey>,
+    media: Vec<MediaAsset>,
+    variants: Vec<MediaVariant>,
+    books: Vec<Book>,
+    published_books: Vec<PublishedBook>,
+    collaborators: Vec<Collaborator>,
+    chapters: Vec<Chapter>,
+    fragments: Vec<Bookfragment>,
+    published_chapters: Vec<PublishedChapter>,
+    published: Vec<PublishedFragment>,
+    revisions: Vec<FragmentRevision>,
+    progress: Vec<Progress>,
+    bookmarks: Vec<Bookmark>,
+    audit: Vec<AuditEntry>,
+}
+
+impl Pending {
+    fn len(&self) -> usize {
+        self.authors.len()
+            + self.users.len()
+            + self.keys.len()
+            + self.media.len()
+            + self.variants.len()
+            + self.books.len()
+            + self.published_books.len()
+            + self.collaborators.len()
+            + self.chapters.len()
+            + self.fragments.len()
+            + self.published_chapters.len()
+            + self.published.len()
+            + self.revisions.len()
+            + self.progress.len()
+            + self.bookmarks.len()
+            + self.audit.len()
+    }
+
+    /// Insert all pending rows in the database
+    ///
+    /// Rows are inserted in the order of the foreign keys between
+    /// their tables.
+    fn flush(&mut self, connector: &mut PgConnection) -> Result<(), Error> {
+        diesel::insert_into(authors::table)
+            .values(&self.authors)
+            .execute(connector)?;
+        diesel::insert_into(users::table)
+            .values(&self.users)
+            .execute(connector)?;
+        diesel::insert_into(apikeys::table)
+            .values(&self.keys)
+            .execute(connector)?;
+        diesel::insert_into(mediaassets::table)
+            .values(&self.media)
+            .execute(connector)?;
+        diesel::insert_into(mediavariants::table)
+            .values(&self.variants)
+            .execute(connector)?;
+        diesel::insert_into(books::table)
+            .values(&self.books)
+            .execute(connector)?;
+        diesel::insert_into(publishedbooks::table)
+            .values(&self.published_books)
+            .execute(connector)?;
+        diesel::insert_into(collaborators::table)
+            .values(&self.collaborators)
+            .execute(connector)?;
+        diesel::insert_into(chapters::table)
+            .values(&self.chapters)
+            .execute(connector)?;
+        diesel::insert_into(bookfragments::table)
+            .values(&self.fragments)
+            .execute(connector)?;
+        diesel::insert_into(publishedchapters::table)
+            .values(&self.published_chapters)
+            .execute(connector)?;
+        diesel::insert_into(publishedfragments::table)
+            .values(&self.published)
+            .execute(connector)?;
+        diesel::insert_into(fragmentrevisions::table)
+            .values(&self.revisions)
+            .execute(connector)?;
+        diesel::insert_into(readingprogress::table)
+            .values(&self.progress)
+            .execute(connector)?;
+        diesel::insert_into(bookmarks::table)
+            .values(&self.bookmarks)
+            .execute(connector)?;
+        diesel::insert_into(auditlog::table)
+            .values(&self.audit)
+            .execute(connector)?;
+        *self = Self::default();
+        Ok(())
+    }
+}
+
+/// Restore a dump written by [`dump`]
+///
+/// Pending migrations are run first, then the schema version of the
+/// dump is checked against the one of this version of alexandria.
+/// All rows are restored within a single transaction: if any of them
+/// cannot be inserted, for instance because it already exists, the
+/// database is left untouched.
+///
+/// # Errors
+///
+/// If the dump cannot be read, if it was made with another schema
+/// version, or if the database cannot be migrated or written, an
+/// error is returned.
+pub fn restore(
+    connector: &mut PgConnection,
+    input: impl Read,
+) -> Result<(), Error> {
+    connector
+        .run_pending_migrations(MIGRATIONS)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let mut lines = BufReader::new(GzDecoder::new(input)).lines();
+    let header = match lines.next() {
+        Some(line) => line?,
+        None => String::new(),
+    };
+    match serde_json::from_str::<Record>(&header) {
+        Ok(Record::Header { format, schema }) => {
+            if format > FORMAT {
+                return Err(Error::Invalid(format!(
+                    "unsupported format version {}",
+                    format
+                )));
+            }
+            if schema != schema_version() {
+                return Err(Error::Invalid(format!(
+                    "dump made with schema {}, expected {}",
+                    schema,
+                    schema_version()
+                )));
+            }
+        }
+        _ => return Err(Error::Invalid("missing header".to_owned())),
+    }
+    connector.transaction(|connector| {
+        let mut pending = Pending::default();
+        let mut count = 0;
+        for line in lines {
+            match serde_json::from_str::<Record>(&line?)? {
+                Record::Header { .. } => {
+                    return Err(Error::Invalid("unexpected header".to_owned()))
+                }
+                Record::Author(author) => pending.authors.push(author),
+                Record::User(user) => pending.users.push(user),
+                Record::Key(key) => pending.keys.push(key),
+                Record::Media(asset) => pending.media.push(asset),
+                Record::Variant(variant) => pending.variants.push(variant),
+                Record::Book(book) => pending.books.push(book),
+                Record::PublishedBook(book) => {
+                    pending.published_books.push(book);
+                }
+                Record::Collaborator(collaborator) => {
+                    pending.collaborators.push(collaborator);
+                }
+                Record::Chapter(chapter) => pending.chapters.push(chapter),
+                Record::Fragment(fragment) => {
+                    pending.fragments.push(fragment);
+                }
+                Record::PublishedChapter(chapter) => {
+                    pending.published_chapters.push(chapter);
+                }
+                Record::Published(fragment) => {
+                    pending.published.push(fragment);
+                }
+                Record::Revision(revision) => {
+                    pending.revisions.push(revision);
+                }
+                Record::Progress(progress) => pending.progress.push(progress),
+                Record::Bookmark(bookmark) => pending.bookmarks.push(bookmark),
+                Record::Audit(entry) => pending.audit.push(entry),
+            }
+            count += 1;
+            if pending.len() >= BATCH_SIZE {
+                pending.flush(connector)?;
+            }
+        }
+        pending.flush(connector)?;
+        info!("Restored {} rows", count);
+        Ok(())
+    })
+}
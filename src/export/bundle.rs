@@ -0,0 +1,38 @@
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::models::{Author, Book, Bookfragment, Chapter};
+
+/// Version of the bundle format written by this version of Alexandria
+///
+/// It must be incremented whenever the format of a bundle changes in
+/// a way older versions of Alexandria cannot read. Version 2 added
+/// the media assets, the owner, the status and the language of books.
+pub const VERSION: u32 = 2;
+
+/// Version of a bundle, read before the rest of the bundle so that
+/// unsupported bundles are rejected without reading their content
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Header {
+    pub version: u32,
+}
+
+/// Whether bundles written with the format `version` can be read
+#[must_use]
+pub const fn is_supported(version: u32) -> bool {
+    version >= 1 && version <= VERSION
+}
+
+/// A complete book with its author, chapters and fragments
+///
+/// Bundles are meant to back up books or to move them between
+/// Alexandria instances, see `crate::import::bundle`.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct Bundle {
+    pub version: u32,
+    pub book: Book,
+    pub author: Author,
+    pub chapters: Vec<Chapter>,
+    pub fragments: Vec<Bookfragment>,
+}
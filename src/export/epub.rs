@@ -0,0 +1,581 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{Cursor, Write};
+
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::models::{
+    Author, Book, Bookfragment, Chapter, ImageType, SoundType,
+};
+
+/// A binary resource embedded in the EPUB package, such as an image
+/// or a sound
+#[derive(Debug, Clone)]
+pub struct Resource {
+    pub media_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Everything needed to package a book as an EPUB file
+///
+/// `images` maps the URLs of the images of the book (its cover and
+/// the `imgsource` of fragments whose image type is `Url`) to their
+/// downloaded content. Images missing from the map are left out of
+/// the package. `sounds` does the same for the background and oneshot
+/// sounds of fragments, except that sounds missing from the map are
+/// referenced as remote resources.
+pub struct Package<'a> {
+    pub book: &'a Book,
+    pub author: &'a Author,
+    pub chapters: &'a [Chapter],
+    pub fragments: &'a [Bookfragment],
+    pub images: &'a HashMap<String, Resource>,
+    pub sounds: &'a HashMap<String, Resource>,
+}
+
+/// Escape a string so it can be inserted in an XML document
+#[must_use]
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Guess the media type of an audio file from its URL
+///
+/// Defaults to `audio/mpeg`, the most common format for sounds.
+fn audio_media_type(url: &str) -> &'static str {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let extension = path.rsplit('.').next().unwrap_or_default();
+    match extension.to_lowercase().as_str() {
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "m4a" | "mp4" | "aac" => "audio/mp4",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        _ => "audio/mpeg",
+    }
+}
+
+/// File extension to use for an audio media type
+fn audio_extension(media_type: &str) -> &'static str {
+    match media_type {
+        "audio/ogg" | "audio/opus" => "ogg",
+        "audio/mp4" | "audio/aac" => "m4a",
+        "audio/wav" | "audio/x-wav" => "wav",
+        "audio/flac" => "flac",
+        "audio/webm" => "webm",
+        _ => "mp3",
+    }
+}
+
+/// File extension to use for an image media type
+fn image_extension(media_type: &str) -> &'static str {
+    match media_type {
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/svg+xml" => "svg",
+        "image/webp" => "webp",
+        _ => "jpg",
+    }
+}
+
+/// Name under which an author is displayed
+#[must_use]
+pub fn author_name(author: &Author) -> String {
+    author.penname.clone().unwrap_or_else(|| {
+        [&author.firstname, &author.lastname]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<String>>()
+            .join(" ")
+    })
+}
+
+/// Title under which a chapter is displayed
+fn chapter_title(chapter: &Chapter) -> String {
+    chapter
+        .title
+        .clone()
+        .unwrap_or_else(|| format!("Chapter {}", chapter.number))
+}
+
+/// Resources referenced by the content documents of the package
+struct Manifest {
+    /// Embedded images, indexed by their URL, with their path in the
+    /// package and their media type
+    images: Vec<(String, String, String)>,
+    /// Embedded audio files, indexed by their URL, with their path in
+    /// the package and their media type
+    sounds: Vec<(String, String, String)>,
+    /// Remote audio files with their media type
+    remote_sounds: Vec<(String, &'static str)>,
+    /// Numbers of the chapters referencing remote audio files
+    remote_chapters: Vec<i32>,
+}
+
+impl Manifest {
+    fn image_path(&self, url: &str) -> Option<&str> {
+        self.images
+            .iter()
+            .find(|(source, _, _)| source == url)
+            .map(|(_, path, _)| path.as_str())
+    }
+
+    /// Add the sound at `url` to the manifest, and return the address
+    /// the chapter numbered `chapter` references it with
+    ///
+    /// Sounds available in `sounds` are embedded in the package, while
+    /// the other ones are referenced as remote resources.
+    fn add_sound(
+        &mut self,
+        sounds: &HashMap<String, Resource>,
+        chapter: i32,
+        url: &str,
+    ) -> String {
+        if let Some(sound) = sounds.get(url) {
+            if let Some((_, path, _)) =
+                self.sounds.iter().find(|(source, _, _)| source == url)
+            {
+                return path.clone();
+            }
+            let path = format!(
+                "sounds/sound-{}.{}",
+                self.sounds.len(),
+                audio_extension(&sound.media_type)
+            );
+            self.sounds.push((
+                url.to_owned(),
+                path.clone(),
+                sound.media_type.clone(),
+            ));
+            return path;
+        }
+        if !self.remote_chapters.contains(&chapter) {
+            self.remote_chapters.push(chapter);
+        }
+        if !self.remote_sounds.iter().any(|(source, _)| source == url) {
+            self.remote_sounds.push((url.to_owned(), audio_media_type(url)));
+        }
+        url.to_owned()
+    }
+}
+
+/// Write the XHTML content document of a chapter
+fn chapter_document(
+    package: &Package,
+    chapter: &Chapter,
+    manifest: &mut Manifest,
+) -> String {
+    let title = escape(&chapter_title(chapter));
+    let mut body = String::new();
+    let _ = writeln!(
+        body,
+        "<section epub:type=\"chapter\" id=\"chapter-{}\">\n<h1>{}</h1>",
+        chapter.number, title
+    );
+    if let Some(epigraph) = &chapter.epigraph {
+        let _ = writeln!(
+            body,
+            "<blockquote class=\"epigraph\" epub:type=\"epigraph\">\
+             <p>{}</p></blockquote>",
+            escape(epigraph)
+        );
+    }
+    for fragment in package
+        .fragments
+        .iter()
+        .filter(|fragment| fragment.chapter == chapter.id)
+    {
+        let _ = writeln!(
+            body,
+            "<div class=\"fragment\" id=\"fragment-{}\">",
+            fragment.id
+        );
+        if let (ImageType::Url, Some(url)) =
+            (&fragment.imgtype, &fragment.imgsource)
+        {
+            if let Some(path) = manifest.image_path(url) {
+                let _ = writeln!(
+                    body,
+                    "<img class=\"background\" src=\"{}\" alt=\"\"/>",
+                    escape(path)
+                );
+            }
+        }
+        if let (SoundType::Url, Some(url)) =
+            (&fragment.bgsoundtype, &fragment.bgsoundsource)
+        {
+            let src = manifest.add_sound(package.sounds, chapter.number, url);
+            let _ = writeln!(
+                body,
+                "<audio class=\"background\" src=\"{}\" loop=\"loop\" \
+                 controls=\"controls\"></audio>",
+                escape(&src)
+            );
+        }
+        if let Some(url) = &fragment.oneshotsoundsource {
+            let src = manifest.add_sound(package.sounds, chapter.number, url);
+            let _ = writeln!(
+                body,
+                "<audio class=\"oneshot\" src=\"{}\" \
+                 controls=\"controls\"></audio>",
+                escape(&src)
+            );
+        }
+        for paragraph in fragment
+            .content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+        {
+            let _ = writeln!(body, "<p>{}</p>", escape(paragraph.trim()));
+        }
+        body.push_str("</div>\n");
+    }
+    body.push_str("</section>\n");
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+<title>{}</title>
+<link rel="stylesheet" type="text/css" href="style.css"/>
+</head>
+<body>
+{}</body>
+</html>
+"#,
+        title, body
+    )
+}
+
+/// Write the navigation document of the package
+fn navigation_document(package: &Package) -> String {
+    let mut items = String::new();
+    for chapter in package.chapters {
+        let _ = writeln!(
+            items,
+            "<li><a href=\"chapter-{}.xhtml\">{}</a></li>",
+            chapter.number,
+            escape(&chapter_title(chapter))
+        );
+    }
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+<title>{}</title>
+</head>
+<body>
+<nav epub:type="toc" id="toc">
+<h1>{}</h1>
+<ol>
+{}</ol>
+</nav>
+</body>
+</html>
+"#,
+        escape(&package.book.title),
+        escape(&package.book.title),
+        items
+    )
+}
+
+/// Text search configurations of the languages books can be written
+/// in, along with their language tag
+const LANGUAGES: &[(&str, &str)] = &[
+    ("arabic", "ar"),
+    ("armenian", "hy"),
+    ("basque", "eu"),
+    ("catalan", "ca"),
+    ("danish", "da"),
+    ("dutch", "nl"),
+    ("english", "en"),
+    ("finnish", "fi"),
+    ("french", "fr"),
+    ("german", "de"),
+    ("greek", "el"),
+    ("hindi", "hi"),
+    ("hungarian", "hu"),
+    ("indonesian", "id"),
+    ("irish", "ga"),
+    ("italian", "it"),
+    ("lithuanian", "lt"),
+    ("nepali", "ne"),
+    ("norwegian", "no"),
+    ("norwegian", "nb"),
+    ("norwegian", "nn"),
+    ("portuguese", "pt"),
+    ("romanian", "ro"),
+    ("russian", "ru"),
+    ("serbian", "sr"),
+    ("spanish", "es"),
+    ("swedish", "sv"),
+    ("tamil", "ta"),
+    ("turkish", "tr"),
+    ("yiddish", "yi"),
+];
+
+/// Language tag of the text search configuration `language` of a
+/// book, such as `en` for `english`
+///
+/// Configurations which are not a language, such as `simple`, are
+/// written as undetermined (`und`).
+fn language_tag(language: &str) -> &'static str {
+    LANGUAGES
+        .iter()
+        .find(|(config, _)| *config == language)
+        .map_or("und", |(_, tag)| tag)
+}
+
+/// Text search configuration of the language tag `tag`, such as
+/// `english` for `en` or `en-GB`, see `language_tag`
+///
+/// Returns `None` if the language has no text search configuration.
+#[must_use]
+pub fn language_config(tag: &str) -> Option<&'static str> {
+    let primary = tag.trim().split(['-', '_']).next().unwrap_or_default();
+    LANGUAGES
+        .iter()
+        .find(|(_, code)| code.eq_ignore_ascii_case(primary))
+        .map(|(config, _)| *config)
+}
+
+/// Write the metadata of the package document
+fn package_metadata(book: &Book, author: &Author) -> String {
+    let mut metadata = String::new();
+    let _ = writeln!(
+        metadata,
+        "<dc:identifier id=\"book-id\">urn:uuid:{}</dc:identifier>",
+        book.id
+    );
+    let _ = writeln!(metadata, "<dc:title>{}</dc:title>", escape(&book.title));
+    let _ = writeln!(
+        metadata,
+        "<dc:creator>{}</dc:creator>",
+        escape(&author_name(author))
+    );
+    let _ = writeln!(
+        metadata,
+        "<dc:language>{}</dc:language>",
+        language_tag(&book.language)
+    );
+    for isbn in book.isbn.iter().flatten().flatten() {
+        let _ = writeln!(
+            metadata,
+            "<dc:source>urn:isbn:{}</dc:source>",
+            escape(isbn)
+        );
+    }
+    if let Some(publisher) = &book.publisher {
+        let _ = writeln!(
+            metadata,
+            "<dc:publisher>{}</dc:publisher>",
+            escape(publisher)
+        );
+    }
+    if let Some(published) = book.published {
+        let _ = writeln!(metadata, "<dc:date>{}</dc:date>", published);
+    }
+    if let Some(synopsis) = &book.synopsis {
+        let _ = writeln!(
+            metadata,
+            "<dc:description>{}</dc:description>",
+            escape(synopsis)
+        );
+    }
+    for genre in book.genre.iter().flatten().flatten() {
+        let _ =
+            writeln!(metadata, "<dc:subject>{}</dc:subject>", escape(genre));
+    }
+    let _ = writeln!(
+        metadata,
+        "<meta property=\"dcterms:modified\">{}</meta>",
+        chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    );
+    metadata
+}
+
+/// Write the package document (OPF) of the package
+fn package_document(package: &Package, manifest: &Manifest) -> String {
+    let book = package.book;
+    let metadata = package_metadata(book, package.author);
+    let mut items = String::from(
+        "<item id=\"nav\" href=\"nav.xhtml\" \
+         media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+         <item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n",
+    );
+    let mut spine = String::new();
+    for chapter in package.chapters {
+        let remote = if manifest.remote_chapters.contains(&chapter.number) {
+            " properties=\"remote-resources\""
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            items,
+            "<item id=\"chapter-{0}\" href=\"chapter-{0}.xhtml\" \
+             media-type=\"application/xhtml+xml\"{1}/>",
+            chapter.number, remote
+        );
+        let _ =
+            writeln!(spine, "<itemref idref=\"chapter-{}\"/>", chapter.number);
+    }
+    for (index, (url, path, media_type)) in manifest.images.iter().enumerate() {
+        let cover = if book.cover.as_ref() == Some(url) {
+            " properties=\"cover-image\""
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            items,
+            "<item id=\"image-{}\" href=\"{}\" media-type=\"{}\"{}/>",
+            index,
+            escape(path),
+            media_type,
+            cover
+        );
+    }
+    for (index, (_, path, media_type)) in manifest.sounds.iter().enumerate() {
+        let _ = writeln!(
+            items,
+            "<item id=\"sound-{}\" href=\"{}\" media-type=\"{}\"/>",
+            index,
+            escape(path),
+            media_type
+        );
+    }
+    for (index, (url, media_type)) in
+        manifest.remote_sounds.iter().enumerate()
+    {
+        let _ = writeln!(
+            items,
+            "<item id=\"remote-sound-{}\" href=\"{}\" media-type=\"{}\"/>",
+            index,
+            escape(url),
+            media_type
+        );
+    }
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+{}</metadata>
+<manifest>
+{}</manifest>
+<spine>
+{}</spine>
+</package>
+"#,
+        metadata, items, spine
+    )
+}
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles>
+<rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+</rootfiles>
+</container>
+"#;
+
+const STYLESHEET: &str = "img.background { display: block; width: 100%; }
+audio { display: none; }
+blockquote.epigraph { font-style: italic; }
+";
+
+/// Build an EPUB 3 file from a book
+///
+/// Each chapter of the book is written as its own content document,
+/// holding its fragments in order. Background images of type `Url`
+/// are embedded in the package when they are available in
+/// `package.images`, and background and oneshot sounds when they are
+/// available in `package.sounds`. Other sounds are referenced as
+/// remote audio resources.
+///
+/// # Errors
+///
+/// Return an error if the archive could not be written.
+pub fn build(package: &Package) -> zip::result::ZipResult<Vec<u8>> {
+    let mut manifest = Manifest {
+        images: Vec::new(),
+        sounds: Vec::new(),
+        remote_sounds: Vec::new(),
+        remote_chapters: Vec::new(),
+    };
+    let image_urls = package.book.cover.iter().chain(
+        package
+            .fragments
+            .iter()
+            .filter(|fragment| matches!(fragment.imgtype, ImageType::Url))
+            .filter_map(|fragment| fragment.imgsource.as_ref()),
+    );
+    for url in image_urls {
+        if manifest.image_path(url).is_some() {
+            continue;
+        }
+        if let Some(image) = package.images.get(url) {
+            let path = format!(
+                "images/image-{}.{}",
+                manifest.images.len(),
+                image_extension(&image.media_type)
+            );
+            manifest
+                .images
+                .push((url.clone(), path, image.media_type.clone()));
+        }
+    }
+    let chapters = package
+        .chapters
+        .iter()
+        .map(|chapter| {
+            let document = chapter_document(package, chapter, &mut manifest);
+            (chapter.number, document)
+        })
+        .collect::<Vec<(i32, String)>>();
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let stored =
+        FileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated =
+        FileOptions::default().compression_method(CompressionMethod::Deflated);
+    // The mimetype must be the first, uncompressed file of the archive
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(b"application/epub+zip")?;
+    zip.start_file("META-INF/container.xml", deflated)?;
+    zip.write_all(CONTAINER.as_bytes())?;
+    zip.start_file("OEBPS/content.opf", deflated)?;
+    zip.write_all(package_document(package, &manifest).as_bytes())?;
+    zip.start_file("OEBPS/nav.xhtml", deflated)?;
+    zip.write_all(navigation_document(package).as_bytes())?;
+    zip.start_file("OEBPS/style.css", deflated)?;
+    zip.write_all(STYLESHEET.as_bytes())?;
+    for (number, document) in chapters {
+        zip.start_file(format!("OEBPS/chapter-{}.xhtml", number), deflated)?;
+        zip.write_all(document.as_bytes())?;
+    }
+    for (url, path, _) in &manifest.images {
+        if let Some(image) = package.images.get(url) {
+            zip.start_file(format!("OEBPS/{}", path), stored)?;
+            zip.write_all(&image.data)?;
+        }
+    }
+    for (url, path, _) in &manifest.sounds {
+        if let Some(sound) = package.sounds.get(url) {
+            zip.start_file(format!("OEBPS/{}", path), stored)?;
+            zip.write_all(&sound.data)?;
+        }
+    }
+    Ok(zip.finish()?.into_inner())
+}
@@ -0,0 +1,337 @@
+use std::fmt::Write as _;
+
+use uuid::Uuid;
+
+use crate::export::epub::author_name;
+use crate::models::{
+    Author, Book, BookType, Bookfragment, Chapter, ImageType, SoundType,
+};
+
+/// Escape a value so it fits on a single line
+///
+/// Backslashes are doubled and line breaks are written as `\n`, see
+/// `crate::import::markdown::unescape`.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Write a directive comment on its own line
+///
+/// `value` is escaped, as directives must fit on a single line.
+fn directive(output: &mut String, key: &str, value: &str) {
+    let _ = writeln!(output, "<!-- {}: {} -->", key, escape(value));
+}
+
+/// Directive value referencing the media asset `id`, followed by the
+/// URL of the resource if it also has one
+///
+/// The URL is kept so that instances where the asset does not exist
+/// can still use it, see `crate::import::markdown::parse`.
+fn asset(id: Uuid, source: Option<&String>) -> String {
+    match source {
+        Some(url) => format!("asset {} {}", id, url),
+        None => format!("asset {}", id),
+    }
+}
+
+/// Directive value describing a resource which may be a media asset
+fn resource(
+    source: Option<&String>,
+    asset_id: Option<Uuid>,
+) -> Option<String> {
+    match (asset_id, source) {
+        (Some(id), _) => Some(asset(id, source)),
+        (None, Some(url)) => Some(format!("url {}", url)),
+        (None, None) => None,
+    }
+}
+
+/// Directive value describing a background sound
+fn sound(
+    kind: &SoundType,
+    source: Option<&String>,
+    asset: Option<Uuid>,
+) -> Option<String> {
+    match kind {
+        SoundType::None => None,
+        SoundType::Same => Some("same".to_owned()),
+        SoundType::Url => {
+            Some(resource(source, asset).unwrap_or_else(|| "none".to_owned()))
+        }
+    }
+}
+
+/// Directive value describing an image
+fn image(
+    kind: &ImageType,
+    source: Option<&String>,
+    asset: Option<Uuid>,
+) -> Option<String> {
+    match (kind, source) {
+        (ImageType::None, _) => None,
+        (ImageType::Same, _) => Some("same".to_owned()),
+        (ImageType::Url, _) => {
+            Some(resource(source, asset).unwrap_or_else(|| "none".to_owned()))
+        }
+        (ImageType::Auto, Some(keywords)) => Some(format!("auto {}", keywords)),
+        (ImageType::Auto, None) => Some("auto".to_owned()),
+    }
+}
+
+/// Write the directives setting the metadata of the book
+fn book_header(output: &mut String, book: &Book, author: &Author) {
+    directive(output, "title", &book.title);
+    let name = author_name(author);
+    if !name.is_empty() {
+        directive(output, "author", &name);
+    }
+    for isbn in book.isbn.iter().flatten().flatten() {
+        directive(output, "isbn", isbn);
+    }
+    if let Some(publisher) = &book.publisher {
+        directive(output, "publisher", publisher);
+    }
+    if let Some(published) = &book.published {
+        directive(
+            output,
+            "published",
+            &published.format("%Y-%m-%d").to_string(),
+        );
+    }
+    for genre in book.genre.iter().flatten().flatten() {
+        directive(output, "genre", genre);
+    }
+    if let Some(synopsis) = &book.synopsis {
+        directive(output, "synopsis", synopsis);
+    }
+    match (book.coverasset, &book.cover) {
+        (Some(id), cover) => {
+            directive(output, "cover", &asset(id, cover.as_ref()));
+        }
+        (None, Some(cover)) => directive(output, "cover", cover),
+        (None, None) => (),
+    }
+    let kind = match book.booktype {
+        BookType::Novel => "novel",
+        BookType::ShortStory => "short-story",
+        BookType::Poem => "poem",
+    };
+    directive(output, "type", kind);
+    directive(output, "language", &book.language);
+}
+
+/// Write a fragment as a block of text preceded by its directives
+///
+/// Lines of text which could be mistaken for a heading, a directive or
+/// an escaped line are escaped with a backslash, and empty lines are
+/// written as a single backslash so the fragment is not split in
+/// several blocks.
+fn fragment_block(output: &mut String, fragment: &Bookfragment) {
+    if let Some(bg) = sound(
+        &fragment.bgsoundtype,
+        fragment.bgsoundsource.as_ref(),
+        fragment.bgsoundasset,
+    ) {
+        directive(output, "bg", &bg);
+    }
+    if let Some(img) = image(
+        &fragment.imgtype,
+        fragment.imgsource.as_ref(),
+        fragment.imgasset,
+    ) {
+        directive(output, "img", &img);
+    }
+    match (fragment.oneshotsoundasset, &fragment.oneshotsoundsource) {
+        (Some(id), sfx) => directive(output, "sfx", &asset(id, sfx.as_ref())),
+        (None, Some(sfx)) => directive(output, "sfx", sfx),
+        (None, None) => (),
+    }
+    if fragment.content.is_empty() {
+        output.push_str("\\\n");
+    }
+    for line in fragment.content.lines().map(str::trim_end) {
+        if line.is_empty()
+            || line.starts_with(['#', '\\'])
+            || line.trim_start().starts_with("<!--")
+        {
+            output.push('\\');
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    output.push('\n');
+}
+
+/// Write a book as a Markdown manuscript
+///
+/// The manuscript uses the format read by
+/// [`crate::import::markdown::parse`]: the metadata of the book is
+/// written as directives at the top of the file, each chapter starts
+/// with a level-one heading followed by its own directives, and each
+/// fragment is a block of text preceded by the directives describing
+/// its augmentations. `fragments` must be sorted by rank.
+#[must_use]
+pub fn build(
+    book: &Book,
+    author: &Author,
+    chapters: &[Chapter],
+    fragments: &[Bookfragment],
+) -> String {
+    let mut output = String::new();
+    book_header(&mut output, book, author);
+    output.push('\n');
+    for chapter in chapters {
+        match &chapter.title {
+            Some(title) => {
+                let _ = writeln!(output, "# {}", escape(title));
+            }
+            None => output.push_str("#\n"),
+        }
+        if let Some(bg) = &chapter.bgsoundsource {
+            directive(&mut output, "bg", &format!("url {}", bg));
+        }
+        if let Some(cover) = &chapter.cover {
+            directive(&mut output, "cover", cover);
+        }
+        if let Some(epigraph) = &chapter.epigraph {
+            directive(&mut output, "epigraph", epigraph);
+        }
+        output.push('\n');
+        for fragment in fragments
+            .iter()
+            .filter(|fragment| fragment.chapter == chapter.id)
+        {
+            fragment_block(&mut output, fragment);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use uuid::Uuid;
+
+    use super::build;
+    use crate::import::{author_from_name, markdown::parse, ImportedBook};
+    use crate::models::{BookType, ImageType, SoundType};
+
+    /// Build a book whose text and directives need escaping
+    fn book() -> ImportedBook {
+        let mut imported = ImportedBook::new(
+            author_from_name("Victor Hugo"),
+            "Title\\with <!-- odd --> text".to_owned(),
+        );
+        let book = &mut imported.book;
+        book.isbn = Some(vec![Some("978-2-07-040850-4".to_owned())]);
+        book.publisher = Some("Publisher".to_owned());
+        book.published = NaiveDate::from_ymd_opt(1862, 4, 3);
+        book.genre =
+            Some(vec![Some("novel".to_owned()), Some("epic".to_owned())]);
+        book.synopsis = Some("First line\nSecond \\n line\r\n".to_owned());
+        book.cover = Some("https://example.com/cover.png".to_owned());
+        book.coverasset = Some(Uuid::new_v4());
+        book.booktype = BookType::ShortStory;
+        book.language = "french".to_owned();
+        let chapter = imported.push_chapter(Some("# Chapter\\".to_owned()));
+        chapter.chapter.epigraph = Some("Epigraph\non two lines".to_owned());
+        chapter.chapter.cover = Some("https://example.com/ch.png".to_owned());
+        chapter.chapter.bgsoundsource =
+            Some("https://example.com/ch.mp3".to_owned());
+        let fragment = chapter.push_fragment(
+            "# Not a heading\n\\escaped\n\n<!-- bg: none -->\nText"
+                .to_owned(),
+        );
+        fragment.bgsoundtype = SoundType::Url;
+        fragment.bgsoundsource = Some("https://example.com/bg.mp3".to_owned());
+        fragment.bgsoundasset = Some(Uuid::new_v4());
+        fragment.imgtype = ImageType::Auto;
+        fragment.imgsource = Some("forest night".to_owned());
+        fragment.oneshotsoundasset = Some(Uuid::new_v4());
+        let fragment = chapter.push_fragment(String::new());
+        fragment.bgsoundtype = SoundType::Same;
+        fragment.imgtype = ImageType::Url;
+        fragment.imgsource = Some("https://example.com/img.png".to_owned());
+        fragment.oneshotsoundsource =
+            Some("https://example.com/sfx.mp3".to_owned());
+        let chapter = imported.push_chapter(None);
+        chapter.push_fragment("Last fragment".to_owned());
+        imported
+    }
+
+    #[test]
+    fn round_trip() {
+        let original = book();
+        let fragments: Vec<_> = original
+            .chapters
+            .iter()
+            .flat_map(|chapter| chapter.fragments.clone())
+            .collect();
+        let chapters: Vec<_> = original
+            .chapters
+            .iter()
+            .map(|chapter| chapter.chapter.clone())
+            .collect();
+        let author = original.author.clone().unwrap();
+        let text = build(&original.book, &author, &chapters, &fragments);
+        let parsed = parse(&text, None).unwrap();
+
+        let (book, expected) = (&parsed.book, &original.book);
+        assert_eq!(book.title, expected.title);
+        assert_eq!(book.isbn, expected.isbn);
+        assert_eq!(book.publisher, expected.publisher);
+        assert_eq!(book.published, expected.published);
+        assert_eq!(book.genre, expected.genre);
+        assert_eq!(book.synopsis, expected.synopsis);
+        assert_eq!(book.cover, expected.cover);
+        assert_eq!(book.coverasset, expected.coverasset);
+        assert_eq!(book.booktype, expected.booktype);
+        assert_eq!(book.language, expected.language);
+        let parsed_author = parsed.author.as_ref().unwrap();
+        assert_eq!(parsed_author.firstname, author.firstname);
+        assert_eq!(parsed_author.lastname, author.lastname);
+
+        assert_eq!(parsed.chapters.len(), original.chapters.len());
+        let chapters = parsed.chapters.iter().zip(&original.chapters);
+        for (chapter, expected) in chapters {
+            let (parsed_chapter, original_chapter) =
+                (&chapter.chapter, &expected.chapter);
+            assert_eq!(parsed_chapter.title, original_chapter.title);
+            assert_eq!(parsed_chapter.epigraph, original_chapter.epigraph);
+            assert_eq!(parsed_chapter.cover, original_chapter.cover);
+            assert_eq!(
+                parsed_chapter.bgsoundsource,
+                original_chapter.bgsoundsource
+            );
+            assert_eq!(chapter.fragments.len(), expected.fragments.len());
+            for (fragment, expected) in
+                chapter.fragments.iter().zip(&expected.fragments)
+            {
+                assert_eq!(fragment.content, expected.content);
+                assert_eq!(fragment.bgsoundtype, expected.bgsoundtype);
+                assert_eq!(fragment.bgsoundsource, expected.bgsoundsource);
+                assert_eq!(fragment.bgsoundasset, expected.bgsoundasset);
+                assert_eq!(fragment.imgtype, expected.imgtype);
+                assert_eq!(fragment.imgsource, expected.imgsource);
+                assert_eq!(fragment.imgasset, expected.imgasset);
+                assert_eq!(
+                    fragment.oneshotsoundsource,
+                    expected.oneshotsoundsource
+                );
+                assert_eq!(
+                    fragment.oneshotsoundasset,
+                    expected.oneshotsoundasset
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub mod bundle;
+pub mod epub;
+pub mod markdown;
@@ -0,0 +1,154 @@
+pub mod unsplash;
+
+use std::env;
+use std::fmt;
+
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, Pool, PoolError};
+use tracing::info;
+
+use crate::db::image_cache;
+use crate::models::CachedImage;
+
+/// Error encountered while looking for an image
+#[derive(Debug)]
+pub enum Error {
+    /// The provider could not be reached or returned an error
+    Http(reqwest::Error),
+    /// The response of the provider could not be understood
+    Invalid(String),
+    /// The cache could not be read or written
+    Database(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(e) => write!(f, "Image provider error: {}", e),
+            Self::Invalid(message) => {
+                write!(f, "Invalid image provider response: {}", message)
+            }
+            Self::Database(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(other: reqwest::Error) -> Self {
+        Self::Http(other)
+    }
+}
+
+impl From<diesel::result::Error> for Error {
+    fn from(other: diesel::result::Error) -> Self {
+        Self::Database(other.to_string())
+    }
+}
+
+impl From<PoolError> for Error {
+    fn from(other: PoolError) -> Self {
+        Self::Database(other.to_string())
+    }
+}
+
+/// An image found by a provider
+pub struct Found {
+    pub url: String,
+    pub author: Option<String>,
+    pub author_url: Option<String>,
+}
+
+/// Service able to find an image matching some keywords
+#[rocket::async_trait]
+pub trait ImageProvider: Send + Sync {
+    /// Name of the provider, stored along the images it found
+    fn name(&self) -> &'static str;
+
+    /// Search the image best matching `keywords`
+    ///
+    /// Returns `None` if the provider has no image for these keywords.
+    ///
+    /// # Errors
+    ///
+    /// If the provider cannot be reached or its response cannot be
+    /// read, an error is returned.
+    async fn search(&self, keywords: &str) -> Result<Option<Found>, Error>;
+}
+
+/// Build the image provider configured by the environment
+///
+/// Images are searched on Unsplash if `UNSPLASH_ACCESS_KEY` is set.
+/// `UNSPLASH_BASE_URL` may point to any service compatible with the
+/// Unsplash API instead of the official one, such as a local stub.
+#[must_use]
+pub fn from_env(client: &reqwest::Client) -> Option<Box<dyn ImageProvider>> {
+    let access_key = env::var("UNSPLASH_ACCESS_KEY")
+        .ok()
+        .filter(|key| !key.is_empty())?;
+    let base_url = env::var("UNSPLASH_BASE_URL")
+        .ok()
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| unsplash::DEFAULT_BASE_URL.to_owned());
+    info!("Resolving automatic images with Unsplash at {}", base_url);
+    Some(Box::new(unsplash::Unsplash::new(
+        client.clone(),
+        base_url,
+        access_key,
+    )))
+}
+
+/// Normalize the directions of an automatic image
+///
+/// Keywords are lowercased and separated by a single space, commas
+/// being read as separators, so that equivalent directions share the
+/// same cached image.
+#[must_use]
+pub fn normalize(keywords: &str) -> String {
+    keywords
+        .to_lowercase()
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|keyword| !keyword.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Turn the directions of an automatic image into a concrete image
+///
+/// The image is looked for in the cache first, and `provider` is only
+/// queried if no image was cached for these keywords yet. Images
+/// found by `provider` are then cached. Returns `None` if `keywords`
+/// is empty or if `provider` found no image.
+///
+/// # Errors
+///
+/// If the cache cannot be read or written, or if `provider` returns
+/// an error, forward it to the function calling `resolve`.
+pub async fn resolve(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    provider: &dyn ImageProvider,
+    keywords: &str,
+) -> Result<Option<CachedImage>, Error> {
+    let query = normalize(keywords);
+    if query.is_empty() {
+        return Ok(None);
+    }
+    let cached = image_cache::get(&mut *pool.get()?, &query)?;
+    if let Some(cached) = cached {
+        return Ok(Some(cached));
+    }
+    let found = match provider.search(&query).await? {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    let image = CachedImage {
+        query,
+        url: found.url,
+        author: found.author,
+        authorurl: found.author_url,
+        provider: provider.name().to_owned(),
+        fetchedat: chrono::Utc::now().naive_utc(),
+    };
+    Ok(Some(image_cache::insert(&mut *pool.get()?, &image)?))
+}
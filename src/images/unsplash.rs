@@ -0,0 +1,107 @@
+use rocket::serde::json::from_slice;
+use rocket::serde::Deserialize;
+
+use super::{Error, Found, ImageProvider};
+
+/// URL of the official Unsplash API
+pub const DEFAULT_BASE_URL: &str = "https://api.unsplash.com";
+
+/// Image provider searching photos through the Unsplash API
+pub struct Unsplash {
+    client: reqwest::Client,
+    base_url: String,
+    access_key: String,
+}
+
+impl Unsplash {
+    /// Create a provider querying the API found at `base_url`
+    ///
+    /// `access_key` is the access key of the Unsplash application
+    /// alexandria is registered as.
+    #[must_use]
+    pub fn new(
+        client: reqwest::Client,
+        base_url: String,
+        access_key: String,
+    ) -> Self {
+        Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            access_key,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct SearchResults {
+    results: Vec<Photo>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Photo {
+    urls: PhotoUrls,
+    user: Option<User>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct PhotoUrls {
+    regular: String,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct User {
+    name: Option<String>,
+    links: Option<UserLinks>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct UserLinks {
+    html: Option<String>,
+}
+
+#[rocket::async_trait]
+impl ImageProvider for Unsplash {
+    fn name(&self) -> &'static str {
+        "unsplash"
+    }
+
+    /// Search photos matching `keywords` and keep the most relevant
+    /// one, in landscape orientation so it fits as a background
+    async fn search(&self, keywords: &str) -> Result<Option<Found>, Error> {
+        let body = self
+            .client
+            .get(format!("{}/search/photos", self.base_url))
+            .query(&[
+                ("query", keywords),
+                ("per_page", "1"),
+                ("orientation", "landscape"),
+            ])
+            .header("Authorization", format!("Client-ID {}", self.access_key))
+            .header("Accept-Version", "v1")
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let results: SearchResults =
+            from_slice(&body).map_err(|e| Error::Invalid(e.to_string()))?;
+        Ok(results.results.into_iter().next().map(|photo| {
+            let (author, author_url) = match photo.user {
+                Some(user) => {
+                    (user.name, user.links.and_then(|links| links.html))
+                }
+                None => (None, None),
+            };
+            Found {
+                url: photo.urls.regular,
+                author,
+                author_url,
+            }
+        }))
+    }
+}
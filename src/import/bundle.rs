@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use super::{Error, ImportedBook, ImportedChapter};
+use crate::export::bundle::{is_supported, Bundle};
+
+/// Read a book from a bundle
+///
+/// If `regenerate` is true, the author, the book, its chapters and
+/// its fragments are given new identifiers, so the book can be
+/// imported in an instance already holding it. Otherwise, they keep
+/// their original identifiers. Chapters and fragments are renumbered
+/// so their numbering is continuous.
+///
+/// # Errors
+///
+/// If the bundle was written with an unsupported version of the
+/// format, or if its content is inconsistent, an `Error::Invalid` is returned.
+pub fn read(bundle: Bundle, regenerate: bool) -> Result<ImportedBook, Error> {
+    if !is_supported(bundle.version) {
+        return Err(Error::Invalid(format!(
+            "unsupported bundle version {}",
+            bundle.version
+        )));
+    }
+    let Bundle {
+        mut book,
+        mut author,
+        mut chapters,
+        mut fragments,
+        ..
+    } = bundle;
+    if book.author != author.id {
+        return Err(Error::Invalid(
+            "the book is not written by the bundled author".to_owned(),
+        ));
+    }
+    let original = book.id;
+    if regenerate {
+        author.id = Uuid::new_v4();
+        book.id = Uuid::new_v4();
+    }
+    book.author = author.id;
+    let mut imported = ImportedBook {
+        author: Some(author),
+        book,
+        chapters: Vec::new(),
+    };
+    chapters.sort_by_key(|chapter| chapter.number);
+    let mut indexes = HashMap::new();
+    for (index, mut chapter) in chapters.into_iter().enumerate() {
+        if chapter.book != original {
+            return Err(Error::Invalid(format!(
+                "chapter {} does not belong to the book",
+                chapter.id
+            )));
+        }
+        indexes.insert(chapter.id, index);
+        if regenerate {
+            chapter.id = Uuid::new_v4();
+        }
+        chapter.book = imported.book.id;
+        chapter.number = i32::try_from(index + 1).unwrap_or(i32::MAX);
+        chapter.deletedat = None;
+        imported.chapters.push(ImportedChapter {
+            chapter,
+            fragments: Vec::new(),
+        });
+    }
+    fragments.sort_by_key(|fragment| fragment.rank);
+    for mut fragment in fragments {
+        let chapter = match indexes.get(&fragment.chapter) {
+            Some(index) if fragment.book == original => {
+                &mut imported.chapters[*index]
+            }
+            _ => {
+                return Err(Error::Invalid(format!(
+                    "fragment {} does not belong to a chapter of the book",
+                    fragment.id
+                )))
+            }
+        };
+        if regenerate {
+            fragment.id = Uuid::new_v4();
+        }
+        fragment.book = chapter.chapter.book;
+        fragment.chapter = chapter.chapter.id;
+        fragment.rank =
+            i32::try_from(chapter.fragments.len() + 1).unwrap_or(i32::MAX);
+        chapter.fragments.push(fragment);
+    }
+    Ok(imported)
+}
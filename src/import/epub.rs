@@ -0,0 +1,579 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+use chrono::NaiveDate;
+use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::Reader;
+use zip::ZipArchive;
+
+use super::{author_from_name, Error, ImportedBook};
+use crate::export::epub::language_config;
+use crate::models::Author;
+
+type Archive<'a> = ZipArchive<Cursor<&'a [u8]>>;
+
+/// Path of the file pointing to the package document of an EPUB
+const CONTAINER: &str = "META-INF/container.xml";
+
+/// Maximum uncompressed size of a file read from an EPUB, in bytes
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Maximum uncompressed size of a whole EPUB, in bytes
+///
+/// Both the sizes declared by the archive and the amount of bytes
+/// actually read from it are checked against this limit, so that a
+/// small archive cannot expand into a huge book.
+const MAX_TOTAL_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Resolve the most common HTML entities, which are not predefined in
+/// XML but are often found in EPUB files
+fn entity(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "nbsp" => "\u{a0}",
+        "shy" => "\u{ad}",
+        "ndash" => "–",
+        "mdash" => "—",
+        "hellip" => "…",
+        "lsquo" => "‘",
+        "rsquo" => "’",
+        "sbquo" => "‚",
+        "ldquo" => "“",
+        "rdquo" => "”",
+        "bdquo" => "„",
+        "laquo" => "«",
+        "raquo" => "»",
+        "copy" => "©",
+        "reg" => "®",
+        "trade" => "™",
+        "deg" => "°",
+        "middot" => "·",
+        "bull" => "•",
+        "dagger" => "†",
+        "sect" => "§",
+        "para" => "¶",
+        "times" => "×",
+        "eacute" => "é",
+        "egrave" => "è",
+        "ecirc" => "ê",
+        "agrave" => "à",
+        "acirc" => "â",
+        "ccedil" => "ç",
+        "icirc" => "î",
+        "ocirc" => "ô",
+        "ugrave" => "ù",
+        "ucirc" => "û",
+        "oelig" => "œ",
+        "Eacute" => "É",
+        "Agrave" => "À",
+        "Ccedil" => "Ç",
+        _ => return None,
+    })
+}
+
+/// Get the unescaped content of a text event
+///
+/// Unknown entities are kept as is rather than failing the import.
+fn unescape(event: &BytesText) -> String {
+    event.unescape_with(entity).map_or_else(
+        |_| String::from_utf8_lossy(event).into_owned(),
+        Cow::into_owned,
+    )
+}
+
+/// Create a lenient XML reader for `content`
+///
+/// Mismatched closing tags are tolerated, as EPUB files in the wild
+/// are not always valid XHTML.
+fn reader(content: &str) -> Reader<&[u8]> {
+    let mut reader = Reader::from_str(content);
+    reader.check_end_names(false);
+    reader
+}
+
+/// Open an EPUB archive, rejecting it if its files add up to more
+/// than [`MAX_TOTAL_SIZE`] once uncompressed
+fn open(data: &[u8]) -> Result<Archive<'_>, Error> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let mut total: u64 = 0;
+    for index in 0..archive.len() {
+        total = total.saturating_add(archive.by_index(index)?.size());
+    }
+    if total > MAX_TOTAL_SIZE {
+        return Err(Error::Invalid(format!(
+            "the book exceeds {} MiB once uncompressed",
+            MAX_TOTAL_SIZE / 1024 / 1024
+        )));
+    }
+    Ok(archive)
+}
+
+/// Read a UTF-8 file from the archive
+///
+/// At most [`MAX_FILE_SIZE`] bytes are read from the file, whatever
+/// size the archive declares for it, and `read` counts the bytes read
+/// so far from the whole archive, which may not exceed
+/// [`MAX_TOTAL_SIZE`].
+fn read_file(
+    archive: &mut Archive,
+    path: &str,
+    read: &mut u64,
+) -> Result<String, Error> {
+    let limit = MAX_FILE_SIZE.min(MAX_TOTAL_SIZE.saturating_sub(*read));
+    let file = archive.by_name(path)?;
+    let mut content = String::new();
+    file.take(limit + 1)
+        .read_to_string(&mut content)
+        .map_err(|e| Error::Invalid(format!("{}: {}", path, e)))?;
+    let size = content.len() as u64;
+    if size > limit {
+        return Err(Error::Invalid(format!("{}: file too large", path)));
+    }
+    *read += size;
+    Ok(content)
+}
+
+/// Get the value of the attribute `name` of an element
+///
+/// The attribute is matched on its local name, ignoring its namespace.
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .attributes()
+        .filter_map(Result::ok)
+        .find(|attr| attr.key.local_name().as_ref() == name.as_bytes())
+        .and_then(|attr| {
+            attr.unescape_value_with(entity).ok().map(Cow::into_owned)
+        })
+}
+
+/// Collapse consecutive whitespaces of each line of `text`
+///
+/// Non-breaking spaces are kept, and empty lines are removed.
+fn collapse(text: &str) -> String {
+    text.lines()
+        .map(|line| line.split_ascii_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Remove HTML tags from `text`
+fn strip_tags(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                result.push(' ');
+            }
+            c if !in_tag => result.push(c),
+            _ => {}
+        }
+    }
+    collapse(&result)
+}
+
+/// Decode percent-encoded characters of a path
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let decoded = if bytes[i] == b'%' {
+            bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        if let Some(byte) = decoded {
+            result.push(byte);
+            i += 3;
+        } else {
+            result.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+/// Resolve `href` relatively to the directory `base` of the archive
+///
+/// Any URL fragment of `href` is dropped.
+fn resolve(base: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let mut parts: Vec<&str> =
+        base.split('/').filter(|s| !s.is_empty()).collect();
+    let href = percent_decode(href);
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Find the path of the package document in the archive
+fn package_path(container: &str) -> Result<String, Error> {
+    let mut reader = reader(container);
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e)
+                if e.local_name().as_ref() == b"rootfile" =>
+            {
+                if let Some(path) = attribute(&e, "full-path") {
+                    return Ok(path);
+                }
+            }
+            Event::Eof => {
+                return Err(Error::Invalid(
+                    "no package document declared".to_owned(),
+                ))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A creator of the book as declared in its metadata
+#[derive(Default)]
+struct Creator {
+    id: Option<String>,
+    name: String,
+    file_as: Option<String>,
+}
+
+/// Metadata and reading order read from the package document
+#[derive(Default)]
+struct Package {
+    title: Option<String>,
+    creators: Vec<Creator>,
+    isbn: Vec<String>,
+    publisher: Option<String>,
+    date: Option<String>,
+    description: Option<String>,
+    subjects: Vec<String>,
+    language: Option<String>,
+    /// Properties refining other metadata, by ID of the refined element
+    refinements: HashMap<String, HashMap<String, String>>,
+    /// Path and properties of each manifest item, by ID
+    manifest: HashMap<String, (String, String)>,
+    spine: Vec<String>,
+}
+
+impl Package {
+    /// Store the text of the metadata element `name`
+    fn set(&mut self, name: &[u8], element: &BytesStart, text: String) {
+        let text = collapse(&text);
+        if text.is_empty() {
+            return;
+        }
+        match name {
+            b"title" if self.title.is_none() => self.title = Some(text),
+            b"creator" => self.creators.push(Creator {
+                id: attribute(element, "id"),
+                name: text,
+                file_as: attribute(element, "file-as"),
+            }),
+            b"identifier" => {
+                let scheme = attribute(element, "scheme").unwrap_or_default();
+                let lower = text.to_lowercase();
+                if let Some(isbn) = lower.strip_prefix("urn:isbn:") {
+                    self.isbn.push(isbn.to_owned());
+                } else if scheme.eq_ignore_ascii_case("isbn") {
+                    self.isbn.push(text);
+                }
+            }
+            b"publisher" if self.publisher.is_none() => {
+                self.publisher = Some(text);
+            }
+            b"date" if self.date.is_none() => self.date = Some(text),
+            b"description" if self.description.is_none() => {
+                self.description = Some(strip_tags(&text));
+            }
+            b"subject" => self.subjects.push(text),
+            b"language" if self.language.is_none() => {
+                self.language = Some(text);
+            }
+            b"meta" => {
+                if let (Some(refines), Some(property)) = (
+                    attribute(element, "refines"),
+                    attribute(element, "property"),
+                ) {
+                    self.refinements
+                        .entry(refines.trim_start_matches('#').to_owned())
+                        .or_default()
+                        .insert(property, text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Read the package document of an EPUB
+    fn parse(content: &str) -> Result<Self, Error> {
+        let mut package = Self::default();
+        let mut reader = reader(content);
+        let mut in_metadata = false;
+        let mut current: Option<(BytesStart, String)> = None;
+        loop {
+            match reader.read_event()? {
+                Event::Start(e) if e.local_name().as_ref() == b"metadata" => {
+                    in_metadata = true;
+                }
+                Event::End(e) if e.local_name().as_ref() == b"metadata" => {
+                    in_metadata = false;
+                }
+                Event::Start(e) if in_metadata => {
+                    current = Some((e.into_owned(), String::new()));
+                }
+                Event::Text(e) => {
+                    if let Some((_, text)) = current.as_mut() {
+                        text.push_str(&unescape(&e));
+                    }
+                }
+                Event::CData(e) => {
+                    if let Some((_, text)) = current.as_mut() {
+                        text.push_str(&String::from_utf8_lossy(&e));
+                    }
+                }
+                Event::End(_) if in_metadata => {
+                    if let Some((element, text)) = current.take() {
+                        let name = element.local_name();
+                        package.set(name.as_ref(), &element, text);
+                    }
+                }
+                Event::Start(e) | Event::Empty(e)
+                    if e.local_name().as_ref() == b"item" =>
+                {
+                    if let (Some(id), Some(href)) =
+                        (attribute(&e, "id"), attribute(&e, "href"))
+                    {
+                        let properties =
+                            attribute(&e, "properties").unwrap_or_default();
+                        package.manifest.insert(id, (href, properties));
+                    }
+                }
+                Event::Start(e) | Event::Empty(e)
+                    if e.local_name().as_ref() == b"itemref" =>
+                {
+                    let linear = attribute(&e, "linear");
+                    if let Some(id) = attribute(&e, "idref") {
+                        if linear.as_deref() != Some("no") {
+                            package.spine.push(id);
+                        }
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Ok(package)
+    }
+
+    /// Create the author of the book from its first creator
+    ///
+    /// The name under which the creator is sorted is preferred to find
+    /// their first and last names. If the book has no creator, an
+    /// anonymous author is created.
+    fn author(&self) -> Author {
+        let creator = match self.creators.first() {
+            Some(creator) => creator,
+            None => return author_from_name(""),
+        };
+        let mut author = author_from_name(&creator.name);
+        let file_as = creator.file_as.clone().or_else(|| {
+            creator
+                .id
+                .as_ref()
+                .and_then(|id| self.refinements.get(id))
+                .and_then(|properties| properties.get("file-as"))
+                .cloned()
+        });
+        if let Some((last, first)) =
+            file_as.as_ref().and_then(|name| name.split_once(','))
+        {
+            author.firstname = Some(first.trim().to_owned());
+            author.lastname = Some(last.trim().to_owned());
+            author.penname = None;
+        }
+        author
+    }
+
+    /// Parse the publication date of the book
+    ///
+    /// Dates may be only a year or a year and a month, in which case
+    /// the first day of the period is used.
+    fn published(&self) -> Option<NaiveDate> {
+        let date = self.date.as_ref()?;
+        let date = date.get(..10).unwrap_or(date);
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .or_else(|_| {
+                NaiveDate::parse_from_str(&format!("{}-01", date), "%Y-%m-%d")
+            })
+            .or_else(|_| {
+                NaiveDate::parse_from_str(
+                    &format!("{}-01-01", date),
+                    "%Y-%m-%d",
+                )
+            })
+            .ok()
+    }
+}
+
+/// Text content of a chapter
+#[derive(Default)]
+struct Document {
+    title: Option<String>,
+    paragraphs: Vec<String>,
+}
+
+impl Document {
+    /// Read the title and paragraphs of an XHTML content document
+    ///
+    /// The title is the first heading of level 1 to 3 of the document.
+    /// Each paragraph of the document is kept with its line breaks.
+    fn parse(content: &str) -> Result<Self, Error> {
+        let mut document = Self::default();
+        let mut reader = reader(content);
+        let mut heading: Option<String> = None;
+        let mut paragraph: Option<String> = None;
+        loop {
+            match reader.read_event()? {
+                Event::Start(e) => match e.local_name().as_ref() {
+                    b"h1" | b"h2" | b"h3" if document.title.is_none() => {
+                        heading = Some(String::new());
+                    }
+                    b"p" => paragraph = Some(String::new()),
+                    _ => {}
+                },
+                Event::Empty(e) if e.local_name().as_ref() == b"br" => {
+                    if let Some(text) = paragraph.as_mut() {
+                        text.push('\n');
+                    } else if let Some(text) = heading.as_mut() {
+                        text.push(' ');
+                    }
+                }
+                Event::Text(e) => {
+                    // Only `<br/>` elements are line breaks in XHTML
+                    let content = unescape(&e).replace(['\r', '\n'], " ");
+                    if let Some(text) = paragraph.as_mut() {
+                        text.push_str(&content);
+                    } else if let Some(text) = heading.as_mut() {
+                        text.push_str(&content);
+                    }
+                }
+                Event::End(e) => match e.local_name().as_ref() {
+                    b"h1" | b"h2" | b"h3" => {
+                        if let Some(text) = heading.take() {
+                            let text = collapse(&text);
+                            if !text.is_empty() {
+                                document.title = Some(text);
+                            }
+                        }
+                    }
+                    b"p" => {
+                        if let Some(text) = paragraph.take() {
+                            let text = collapse(&text);
+                            if !text.is_empty() {
+                                document.paragraphs.push(text);
+                            }
+                        }
+                    }
+                    _ => {}
+                },
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Ok(document)
+    }
+}
+
+/// Read a book from an EPUB file
+///
+/// The author and the book are created from the metadata of the EPUB.
+/// Each document of its reading order becomes a chapter, and each of
+/// their paragraphs becomes a fragment. Documents without any
+/// paragraph, such as cover pages, are skipped. The language of the
+/// book is the text search configuration of its first `dc:language`,
+/// or the default one if this language has none, see
+/// `language_config`.
+///
+/// # Errors
+///
+/// If the file is not a valid EPUB or if it does not contain any
+/// text, or if it is too large once uncompressed, an `Error::Invalid`
+/// is returned.
+pub fn parse(data: &[u8]) -> Result<ImportedBook, Error> {
+    let mut archive = open(data)?;
+    let read = &mut 0;
+    let path = package_path(&read_file(&mut archive, CONTAINER, read)?)?;
+    let base = path.rsplit_once('/').map_or("", |(base, _)| base);
+    let package = Package::parse(&read_file(&mut archive, &path, read)?)?;
+    let title = package
+        .title
+        .clone()
+        .ok_or_else(|| Error::Invalid("the book has no title".to_owned()))?;
+    let mut imported = ImportedBook::new(package.author(), title);
+    if !package.isbn.is_empty() {
+        imported.book.isbn =
+            Some(package.isbn.iter().cloned().map(Some).collect());
+    }
+    if !package.subjects.is_empty() {
+        imported.book.genre =
+            Some(package.subjects.iter().cloned().map(Some).collect());
+    }
+    imported.book.publisher.clone_from(&package.publisher);
+    imported.book.published = package.published();
+    imported.book.synopsis.clone_from(&package.description);
+    if let Some(config) = package.language.as_deref().and_then(language_config)
+    {
+        config.clone_into(&mut imported.book.language);
+    }
+    for id in &package.spine {
+        let href = match package.manifest.get(id) {
+            Some((_, properties)) if properties.contains("nav") => continue,
+            Some((href, _)) => resolve(base, href),
+            None => {
+                return Err(Error::Invalid(format!(
+                    "spine item {} not found in manifest",
+                    id
+                )))
+            }
+        };
+        let document =
+            Document::parse(&read_file(&mut archive, &href, read)?)?;
+        if document.paragraphs.is_empty() {
+            continue;
+        }
+        let chapter = imported.push_chapter(document.title);
+        for paragraph in document.paragraphs {
+            chapter.push_fragment(paragraph);
+        }
+    }
+    if imported.chapters.is_empty() {
+        return Err(Error::Invalid("the book has no text".to_owned()));
+    }
+    Ok(imported)
+}
+
+/// Parse an EPUB file in a blocking task, see [`parse`]
+///
+/// # Errors
+///
+/// See [`parse`].
+pub async fn parse_blocking(data: Vec<u8>) -> Result<ImportedBook, Error> {
+    let task = move || parse(&data);
+    match rocket::tokio::task::spawn_blocking(task).await {
+        Ok(result) => result,
+        Err(e) => Err(Error::Invalid(e.to_string())),
+    }
+}
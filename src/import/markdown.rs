@@ -0,0 +1,382 @@
+use chrono::NaiveDate;
+use uuid::Uuid;
+
+use super::{author_from_name, Error, ImportedBook, ImportedChapter};
+use crate::models::{BookType, ImageType, SoundType};
+
+/// Read a value escaped to fit on a single line
+///
+/// `\n` and `\r` are read as line breaks and `\\` as a single
+/// backslash, see `crate::export::markdown::escape`. Any other
+/// backslash is kept as is.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('\\') | None => unescaped.push('\\'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+        }
+    }
+    unescaped
+}
+
+/// Read the content of a directive comment such as `<!-- bg: none -->`
+///
+/// Returns the key and the unescaped value of the directive, or
+/// `None` if the line is not a directive. HTML comments which do not
+/// start with a lowercase key followed by a colon are regular
+/// comments.
+fn directive(line: &str) -> Option<(&str, String)> {
+    let content = line.strip_prefix("<!--")?.strip_suffix("-->")?;
+    let (key, value) = content.trim().split_once(':')?;
+    if key.is_empty()
+        || !key.chars().all(|c| c.is_ascii_lowercase() || c == '-')
+    {
+        return None;
+    }
+    Some((key, unescape(value.trim())))
+}
+
+/// Read a heading line such as `## Chapter title`
+///
+/// Returns the unescaped title of the heading, which may be empty, or
+/// `None` if the line is not a heading.
+fn heading(line: &str) -> Option<String> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    let title = line.get(level..)?;
+    if (1..=6).contains(&level) && (title.is_empty() || title.starts_with(' '))
+    {
+        Some(unescape(title.trim()))
+    } else {
+        None
+    }
+}
+
+/// Error on the line `line` of the manuscript
+fn invalid(line: usize, message: &str) -> Error {
+    Error::Invalid(format!("line {}: {}", line, message))
+}
+
+/// Read the identifier of a media asset, optionally followed by the
+/// URL of the resource
+///
+/// Returns the URL, if any, and the identifier of the asset.
+fn asset(
+    line: usize,
+    value: &str,
+) -> Result<(Option<String>, Option<Uuid>), Error> {
+    let (id, url) = value.split_once(' ').unwrap_or((value, ""));
+    let id = Uuid::parse_str(id)
+        .map_err(|_| invalid(line, &format!("invalid asset `{}`", id)))?;
+    let url = url.trim();
+    Ok(((!url.is_empty()).then(|| url.to_owned()), Some(id)))
+}
+
+/// Read a background sound directive
+///
+/// Valid values are `none`, `same`, `url` followed by the URL of the
+/// sound, and `asset` followed by the identifier of a media asset.
+fn sound(
+    line: usize,
+    value: &str,
+) -> Result<(SoundType, Option<String>, Option<Uuid>), Error> {
+    let (kind, source) = value.split_once(' ').unwrap_or((value, ""));
+    let source = source.trim();
+    match (kind, source.is_empty()) {
+        ("none", true) => Ok((SoundType::None, None, None)),
+        ("same", true) => Ok((SoundType::Same, None, None)),
+        ("url", false) => Ok((SoundType::Url, Some(source.to_owned()), None)),
+        ("asset", false) => {
+            let (source, id) = asset(line, source)?;
+            Ok((SoundType::Url, source, id))
+        }
+        _ => Err(invalid(line, &format!("invalid sound `{}`", value))),
+    }
+}
+
+/// Read an image directive
+///
+/// Valid values are `none`, `same`, `url` followed by the URL of the
+/// image, `asset` followed by the identifier of a media asset, and
+/// `auto` optionally followed by keywords describing the image to
+/// find.
+fn image(
+    line: usize,
+    value: &str,
+) -> Result<(ImageType, Option<String>, Option<Uuid>), Error> {
+    let (kind, source) = value.split_once(' ').unwrap_or((value, ""));
+    let source = source.trim();
+    match (kind, source.is_empty()) {
+        ("none", true) => Ok((ImageType::None, None, None)),
+        ("same", true) => Ok((ImageType::Same, None, None)),
+        ("url", false) => Ok((ImageType::Url, Some(source.to_owned()), None)),
+        ("asset", false) => {
+            let (source, id) = asset(line, source)?;
+            Ok((ImageType::Url, source, id))
+        }
+        ("auto", true) => Ok((ImageType::Auto, None, None)),
+        ("auto", false) => Ok((ImageType::Auto, Some(source.to_owned()), None)),
+        _ => Err(invalid(line, &format!("invalid image `{}`", value))),
+    }
+}
+
+/// Read a oneshot sound or a cover directive
+///
+/// The value is either the URL of the resource, or `asset` followed
+/// by the identifier of a media asset.
+fn resource(
+    line: usize,
+    value: &str,
+) -> Result<(Option<String>, Option<Uuid>), Error> {
+    match value.strip_prefix("asset ") {
+        Some(id) => asset(line, id.trim()),
+        None => Ok((Some(value.to_owned()), None)),
+    }
+}
+
+/// Augmentations of a fragment read from its directives
+#[derive(Default)]
+struct Augmentation {
+    bg: Option<(SoundType, Option<String>, Option<Uuid>)>,
+    img: Option<(ImageType, Option<String>, Option<Uuid>)>,
+    sfx: Option<(Option<String>, Option<Uuid>)>,
+}
+
+impl Augmentation {
+    fn set(
+        &mut self,
+        line: usize,
+        key: &str,
+        value: &str,
+    ) -> Result<(), Error> {
+        match key {
+            "bg" => self.bg = Some(sound(line, value)?),
+            "img" => self.img = Some(image(line, value)?),
+            "sfx" => self.sfx = Some(resource(line, value)?),
+            _ => {
+                return Err(invalid(
+                    line,
+                    &format!("unknown fragment directive `{}`", key),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Set a metadata of the book from a directive
+fn set_book(
+    imported: &mut ImportedBook,
+    line: usize,
+    key: &str,
+    value: &str,
+) -> Result<(), Error> {
+    let book = &mut imported.book;
+    match key {
+        "title" => value.clone_into(&mut book.title),
+        "author" => {
+            let author = author_from_name(value);
+            book.author = author.id;
+            imported.author = Some(author);
+        }
+        "isbn" => book
+            .isbn
+            .get_or_insert_with(Vec::new)
+            .push(Some(value.to_owned())),
+        "publisher" => book.publisher = Some(value.to_owned()),
+        "published" => {
+            book.published =
+                Some(NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(
+                    |_| invalid(line, &format!("invalid date `{}`", value)),
+                )?);
+        }
+        "genre" => book
+            .genre
+            .get_or_insert_with(Vec::new)
+            .push(Some(value.to_owned())),
+        "synopsis" => book.synopsis = Some(value.to_owned()),
+        "language" => value.clone_into(&mut book.language),
+        "cover" => (book.cover, book.coverasset) = resource(line, value)?,
+        "type" => {
+            book.booktype = match value {
+                "novel" => BookType::Novel,
+                "short-story" => BookType::ShortStory,
+                "poem" => BookType::Poem,
+                _ => {
+                    return Err(invalid(
+                        line,
+                        &format!("invalid book type `{}`", value),
+                    ))
+                }
+            }
+        }
+        _ => {
+            return Err(invalid(
+                line,
+                &format!("unknown book directive `{}`", key),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Set a property of a chapter from a directive
+fn set_chapter(
+    chapter: &mut ImportedChapter,
+    line: usize,
+    key: &str,
+    value: &str,
+) -> Result<(), Error> {
+    let chapter = &mut chapter.chapter;
+    match key {
+        "bg" => match sound(line, value)? {
+            (_, _, Some(_)) => {
+                return Err(invalid(line, "chapters cannot use media assets"))
+            }
+            (SoundType::Url, source, None) => chapter.bgsoundsource = source,
+            (SoundType::None, _, None) => chapter.bgsoundsource = None,
+            (SoundType::Same, _, None) => {
+                return Err(invalid(line, "chapters cannot reuse a sound"))
+            }
+        },
+        "cover" => chapter.cover = Some(value.to_owned()),
+        "epigraph" => chapter.epigraph = Some(value.to_owned()),
+        _ => {
+            return Err(invalid(
+                line,
+                &format!("unknown chapter directive `{}`", key),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Fragment being read from the manuscript
+#[derive(Default)]
+struct Paragraph {
+    lines: Vec<String>,
+    augmentation: Augmentation,
+}
+
+impl Paragraph {
+    /// Add the paragraph to the last chapter of the book as a fragment
+    ///
+    /// If the book has no chapter yet, an untitled chapter is created.
+    /// If the paragraph has no text, its directives are kept for the
+    /// next paragraph.
+    fn flush(&mut self, imported: &mut ImportedBook) {
+        if self.lines.is_empty() {
+            return;
+        }
+        let chapter = match imported.chapters.last_mut() {
+            Some(chapter) => chapter,
+            None => imported.push_chapter(None),
+        };
+        let fragment = chapter.push_fragment(self.lines.join("\n"));
+        let augmentation = std::mem::take(&mut self.augmentation);
+        if let Some((kind, source, asset)) = augmentation.bg {
+            fragment.bgsoundtype = kind;
+            fragment.bgsoundsource = source;
+            fragment.bgsoundasset = asset;
+        }
+        if let Some((kind, source, asset)) = augmentation.img {
+            fragment.imgtype = kind;
+            fragment.imgsource = source;
+            fragment.imgasset = asset;
+        }
+        if let Some((source, asset)) = augmentation.sfx {
+            fragment.oneshotsoundsource = source;
+            fragment.oneshotsoundasset = asset;
+        }
+        self.lines.clear();
+    }
+}
+
+/// Read a book from a Markdown manuscript
+///
+/// Each heading of the manuscript starts a new chapter titled after
+/// the heading, and each block of text separated by blank lines
+/// becomes a fragment. Text preceding the first heading is put in an
+/// untitled chapter.
+///
+/// Augmentations are set with directives written as HTML comments on
+/// their own line, such as `<!-- img: auto forest night -->`:
+/// - Before any chapter or text, the directives `title`, `author`,
+///   `isbn`, `publisher`, `published`, `genre`, `synopsis`, `cover`,
+///   `type` and `language` set the metadata of the book. `language`
+///   is the text search configuration of the book, such as
+///   `english`.
+/// - Right below a heading, the directives `bg`, `cover` and
+///   `epigraph` set the properties of the chapter.
+/// - Anywhere else, the directives `bg`, `img` and `sfx` set the
+///   background sound, the image and the oneshot sound of the
+///   fragment they are in. If they are not followed by any text in
+///   their block, they apply to the next fragment.
+///
+/// Sounds, images and the cover of the book may reference a media
+/// asset instead of a URL with `asset` followed by the identifier of
+/// the asset, such as `<!-- img: asset <id> -->`. The identifier may
+/// be followed by the URL of the resource, which is kept if the asset
+/// does not exist on this instance.
+///
+/// Directive values and headings are unescaped, so that `\n` is a
+/// line break and `\\` a backslash. A line of text starting with a
+/// backslash is read without its backslash, so that headings,
+/// comments and empty lines can be written in a fragment as `\#`,
+/// `\<!--` and `\`. If the manuscript does not set the title of the
+/// book, `title` is used.
+///
+/// # Errors
+///
+/// If a directive is unknown or has an invalid value, or if the book
+/// has no title, an `Error::Invalid` is returned.
+pub fn parse(text: &str, title: Option<String>) -> Result<ImportedBook, Error> {
+    let mut imported =
+        ImportedBook::new(author_from_name(""), title.unwrap_or_default());
+    let mut paragraph = Paragraph::default();
+    let mut in_heading = false;
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim_end();
+        if line.trim_start().is_empty() {
+            paragraph.flush(&mut imported);
+            in_heading = false;
+        } else if let Some(title) = heading(line) {
+            paragraph.flush(&mut imported);
+            imported.push_chapter((!title.is_empty()).then_some(title));
+            in_heading = true;
+        } else if let Some((key, value)) = directive(line.trim_start()) {
+            if in_heading {
+                if let Some(chapter) = imported.chapters.last_mut() {
+                    set_chapter(chapter, number, key, &value)?;
+                }
+            } else if imported.chapters.is_empty()
+                && paragraph.lines.is_empty()
+                && !matches!(key, "bg" | "img" | "sfx")
+            {
+                set_book(&mut imported, number, key, &value)?;
+            } else {
+                paragraph.augmentation.set(number, key, &value)?;
+            }
+        } else if !line.trim_start().starts_with("<!--") {
+            in_heading = false;
+            let line = line.strip_prefix('\\').unwrap_or(line);
+            paragraph.lines.push(line.to_owned());
+        }
+    }
+    paragraph.flush(&mut imported);
+    if imported.book.title.trim().is_empty() {
+        return Err(Error::Invalid("the book has no title".to_owned()));
+    }
+    Ok(imported)
+}
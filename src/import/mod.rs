@@ -0,0 +1,241 @@
+pub mod bundle;
+pub mod epub;
+pub mod markdown;
+
+use std::fmt;
+
+use diesel::{Connection, PgConnection, RunQueryDsl};
+use uuid::Uuid;
+
+use crate::db::{author, book, media, ApiResult};
+use crate::models::{
+    default_language, Author, Book, BookStatus, BookType, Bookfragment,
+    Chapter, ImageType, SoundType,
+};
+use crate::schema::{bookfragments, chapters};
+
+/// Error encountered while importing a book
+#[derive(Debug)]
+pub enum Error {
+    /// The imported file could not be read or is not valid
+    Invalid(String),
+    /// The imported book could not be saved in the database
+    Database(diesel::result::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(message) => write!(f, "Invalid file: {}", message),
+            Self::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<diesel::result::Error> for Error {
+    fn from(other: diesel::result::Error) -> Self {
+        Self::Database(other)
+    }
+}
+
+impl From<zip::result::ZipError> for Error {
+    fn from(other: zip::result::ZipError) -> Self {
+        Self::Invalid(other.to_string())
+    }
+}
+
+impl From<quick_xml::Error> for Error {
+    fn from(other: quick_xml::Error) -> Self {
+        Self::Invalid(other.to_string())
+    }
+}
+
+/// Create an author from their full name
+///
+/// The last word of the name is considered to be the last name of the
+/// author, and the preceding ones their first name. A single name is
+/// considered to be a pen name. An empty name gives an anonymous
+/// author.
+#[must_use]
+pub fn author_from_name(name: &str) -> Author {
+    let name = name.trim();
+    let mut author = Author {
+        id: Uuid::new_v4(),
+        firstname: None,
+        lastname: None,
+        penname: None,
+        deletedat: None,
+    };
+    if let Some((first, last)) = name.rsplit_once(' ') {
+        author.firstname = Some(first.trim_end().to_owned());
+        author.lastname = Some(last.to_owned());
+    } else if !name.is_empty() {
+        author.penname = Some(name.to_owned());
+    }
+    author
+}
+
+/// A chapter read from an imported file, along with its fragments
+#[derive(Clone)]
+pub struct ImportedChapter {
+    pub chapter: Chapter,
+    pub fragments: Vec<Bookfragment>,
+}
+
+impl ImportedChapter {
+    /// Append a fragment without any sound or image to the chapter
+    ///
+    /// Returns the new fragment so its sounds and images can be set.
+    pub fn push_fragment(&mut self, content: String) -> &mut Bookfragment {
+        let rank = i32::try_from(self.fragments.len() + 1).unwrap_or(i32::MAX);
+        self.fragments.push(Bookfragment {
+            id: Uuid::new_v4(),
+            content,
+            oneshotsoundsource: None,
+            bgsoundtype: SoundType::None,
+            bgsoundsource: None,
+            imgtype: ImageType::None,
+            imgsource: None,
+            book: self.chapter.book,
+            rank,
+            chapter: self.chapter.id,
+            oneshotsoundasset: None,
+            bgsoundasset: None,
+            imgasset: None,
+            deletedat: None,
+        });
+        let last = self.fragments.len() - 1;
+        &mut self.fragments[last]
+    }
+}
+
+/// A book read from an imported file, ready to be saved
+///
+/// If `author` is set, it is created alongside the book. Otherwise,
+/// the book must reference an existing author.
+#[derive(Clone)]
+pub struct ImportedBook {
+    pub author: Option<Author>,
+    pub book: Book,
+    pub chapters: Vec<ImportedChapter>,
+}
+
+impl ImportedBook {
+    /// Create an empty book titled `title` written by `author`
+    #[must_use]
+    pub fn new(author: Author, title: String) -> Self {
+        let book = Book {
+            id: Uuid::new_v4(),
+            title,
+            author: author.id,
+            isbn: None,
+            cover: None,
+            publisher: None,
+            published: None,
+            genre: None,
+            synopsis: None,
+            booktype: BookType::Novel,
+            coverasset: None,
+            owner: None,
+            status: BookStatus::Draft,
+            deletedat: None,
+            language: default_language(),
+        };
+        Self {
+            author: Some(author),
+            book,
+            chapters: Vec::new(),
+        }
+    }
+
+    /// Append an empty chapter to the book
+    ///
+    /// Returns the new chapter so fragments can be added to it.
+    pub fn push_chapter(
+        &mut self,
+        title: Option<String>,
+    ) -> &mut ImportedChapter {
+        let number = i32::try_from(self.chapters.len() + 1).unwrap_or(i32::MAX);
+        self.chapters.push(ImportedChapter {
+            chapter: Chapter {
+                id: Uuid::new_v4(),
+                book: self.book.id,
+                number,
+                title,
+                epigraph: None,
+                cover: None,
+                bgsoundsource: None,
+                deletedat: None,
+            },
+            fragments: Vec::new(),
+        });
+        let last = self.chapters.len() - 1;
+        &mut self.chapters[last]
+    }
+
+    /// Remove the references to media assets which do not exist
+    ///
+    /// Imported books may come from another instance, whose assets
+    /// are not available here. Their URLs are kept, if any.
+    fn drop_missing_assets(
+        &mut self,
+        connector: &mut PgConnection,
+    ) -> ApiResult<()> {
+        let fragments = self
+            .chapters
+            .iter_mut()
+            .flat_map(|chapter| chapter.fragments.iter_mut());
+        let mut references: Vec<&mut Option<Uuid>> =
+            vec![&mut self.book.coverasset];
+        for fragment in fragments {
+            references.push(&mut fragment.oneshotsoundasset);
+            references.push(&mut fragment.bgsoundasset);
+            references.push(&mut fragment.imgasset);
+        }
+        let ids: Vec<Uuid> =
+            references.iter().filter_map(|id| **id).collect();
+        let existing = media::existing(connector, &ids)?;
+        for reference in references {
+            if matches!(reference, Some(id) if !existing.contains(id)) {
+                *reference = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Save an imported book in the database
+///
+/// The author (if any), the book, its chapters and its fragments are
+/// all created in a single transaction. References to media assets
+/// which do not exist are dropped. Returns the created rows.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `save`
+pub fn save(
+    connector: &mut PgConnection,
+    mut imported: ImportedBook,
+) -> ApiResult<ImportedBook> {
+    connector.transaction(|connector| {
+        imported.drop_missing_assets(connector)?;
+        if let Some(author) = &imported.author {
+            author::new(connector, author.clone())?;
+        }
+        book::new(connector, imported.book.clone())?;
+        for chapter in &imported.chapters {
+            diesel::insert_into(chapters::table)
+                .values(&chapter.chapter)
+                .execute(connector)?;
+            if !chapter.fragments.is_empty() {
+                diesel::insert_into(bookfragments::table)
+                    .values(&chapter.fragments)
+                    .execute(connector)?;
+            }
+        }
+        Ok(imported)
+    })
+}
@@ -8,63 +8,47 @@ use dotenvy::dotenv;
 
 #[macro_use]
 extern crate rocket;
-use rocket::http::{Method, Status};
-use rocket::request::{FromRequest, Outcome, Request};
+use rocket::data::{Limits, ToByteUnit};
+use rocket::http::Method;
 use rocket::response::status;
 use rocket::serde::json::Json;
 use rocket_cors::{AllowedHeaders, AllowedOrigins};
 
-use tracing::{info, Level};
+use tracing::{info, warn, Level};
 use tracing_subscriber::FmtSubscriber;
 
 use std::env;
 use std::error::Error;
+use std::fs::File;
+use std::io::BufRead;
+use std::time::Duration;
 
+pub mod auth;
 pub mod db;
+pub mod dump;
+pub mod export;
+pub mod images;
+pub mod import;
+pub mod media;
 pub mod models;
 pub mod schema;
 pub mod server;
 
-#[derive(Copy, Clone)]
-pub struct ApiKey<'r>(&'r str);
-
-#[derive(Debug)]
-pub enum ApiKeyError {
-    Missing,
-    Invalid,
-}
-
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for ApiKey<'r> {
-    type Error = ApiKeyError;
-
-    async fn from_request(
-        request: &'r Request<'_>,
-    ) -> Outcome<Self, Self::Error> {
-        fn is_valid(key: &str, server_key: &str) -> bool {
-            key == server_key
-        }
-        let server_key =
-            &request.rocket().state::<ServerState>().unwrap().api_key;
-        match request.headers().get_one("x-api-key") {
-            None => {
-                Outcome::Failure((Status::BadRequest, ApiKeyError::Missing))
-            }
-            Some(key) if is_valid(key, server_key.as_str()) => {
-                Outcome::Success(ApiKey(key))
-            }
-            Some(_) => {
-                Outcome::Failure((Status::BadRequest, ApiKeyError::Invalid))
-            }
-        }
-    }
-}
-
 pub struct ServerState {
     pool: Pool<ConnectionManager<PgConnection>>,
-    api_key: String,
+    images: Option<Box<dyn images::ImageProvider>>,
+    storage: Box<dyn media::Storage>,
+    auth: auth::Mode,
+    confirmation: db::trash::ConfirmationKey,
 }
 
+/// Seconds the server waits for a connection to a remote service,
+/// such as the image provider or the identity provider, to open
+const HTTP_CONNECT_TIMEOUT: u64 = 5;
+
+/// Seconds the server waits for a whole request to a remote service
+const HTTP_TIMEOUT: u64 = 30;
+
 type JsonResponse<T> = Result<Json<T>, status::Custom<String>>;
 
 fn make_cors(
@@ -78,6 +62,10 @@ fn make_cors(
             .collect(),
         allowed_headers: AllowedHeaders::some(&["Authorization", "Accept"]),
         allow_credentials: true,
+        expose_headers: ["Link", "X-Next-Cursor"]
+            .iter()
+            .map(ToString::to_string)
+            .collect(),
         ..Default::default()
     }
     .to_cors()
@@ -91,7 +79,103 @@ fn setup_logging() {
         .expect("Setting default subscriber failed");
 }
 
+/// Run the command given on the command line, if any
+///
+/// Four commands are available:
+/// - `alexandria dump <file>` dumps the whole instance to `file`
+/// - `alexandria restore <file>` restores the dump held by `file`
+/// - `alexandria probe-media` probes the sounds and generates the
+///   image variants of the assets uploaded before alexandria did so
+///   on upload
+/// - `alexandria add-user <username> [role]` creates a user whose
+///   password is read from the first line of the standard input,
+///   which is how the first administrator is created
+///
+/// Returns `false` if no command was given and the server should be
+/// launched.
+async fn run_command() -> Result<bool, Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match args.first() {
+        Some(command) => command.as_str(),
+        None => return Ok(false),
+    };
+    let path = match (command, args.get(1)) {
+        ("dump" | "restore", Some(path)) => path.as_str(),
+        ("dump" | "restore", None) => {
+            return Err(format!("Usage: alexandria {} <file>", command).into())
+        }
+        ("add-user", Some(username)) => username.as_str(),
+        ("add-user", None) => {
+            return Err("Usage: alexandria add-user <username> [role]".into())
+        }
+        ("probe-media", _) => "",
+        _ => return Err(format!("Unknown command {}", command).into()),
+    };
+    let pool = db::get_connection_pool();
+    let connector = &mut pool.get()?;
+    match command {
+        "dump" => {
+            info!("Dumping instance to {}", path);
+            dump::dump(connector, File::create(path)?)?;
+        }
+        "restore" => {
+            info!("Restoring instance from {}", path);
+            dump::restore(connector, File::open(path)?)?;
+        }
+        "add-user" => add_user(connector, path, args.get(2))?,
+        _ => {
+            let storage = media::from_env()?;
+            let probed =
+                media::probe_unprobed(connector, storage.as_ref()).await?;
+            info!("Processed {} media assets", probed);
+        }
+    }
+    Ok(true)
+}
+
+/// Create a user named `username` with the role `role`, reading their
+/// password from the standard input
+///
+/// If `role` is not set, the user is an administrator.
+fn add_user(
+    connector: &mut PgConnection,
+    username: &str,
+    role: Option<&String>,
+) -> Result<(), Box<dyn Error>> {
+    let role = match role.map(String::as_str) {
+        Some("reader") => models::Role::Reader,
+        Some("editor") => models::Role::Editor,
+        Some("admin") | None => models::Role::Admin,
+        Some(other) => return Err(format!("Unknown role {}", other).into()),
+    };
+    let mut password = String::new();
+    std::io::stdin().lock().read_line(&mut password)?;
+    let password = password.trim_end_matches(&['\r', '\n'][..]);
+    if password.chars().count() < auth::password::MIN_LENGTH {
+        return Err(format!(
+            "Passwords must be at least {} characters long",
+            auth::password::MIN_LENGTH
+        )
+        .into());
+    }
+    let user = db::user::new(
+        connector,
+        &models::User {
+            id: uuid::Uuid::new_v4(),
+            username: username.to_owned(),
+            passwordhash: auth::password::hash(password)?,
+            role,
+            created: chrono::Utc::now().naive_utc(),
+            issuer: None,
+            subject: None,
+        },
+    )?;
+    info!("Created user {} ({:?})", user.username, user.role);
+    Ok(())
+}
+
 #[rocket::main]
+#[allow(clippy::too_many_lines)]
 async fn main() -> Result<(), Box<dyn Error>> {
     color_eyre::install().unwrap();
     setup_logging();
@@ -99,6 +183,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
     info!("Reading environment variables");
     dotenv().ok();
 
+    if run_command().await? {
+        return Ok(());
+    }
+
     // NOTE: Maybe handle allowed origins through an env variable?
     let allowed_origins = AllowedOrigins::some_regex(&[".*"]);
     let cors = make_cors(allowed_origins)?;
@@ -106,32 +194,104 @@ async fn main() -> Result<(), Box<dyn Error>> {
     info!("Getting database connection manager pool");
     let pool = db::get_connection_pool();
     db::run_migrations(&mut pool.get()?)?;
+    if !db::user::has_admin(&mut *pool.get()?)? {
+        warn!("No administrator yet, create one with `alexandria add-user`");
+    }
+
+    let http = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(HTTP_CONNECT_TIMEOUT))
+        .timeout(Duration::from_secs(HTTP_TIMEOUT))
+        .build()?;
+    let images = images::from_env(&http);
+    let storage = media::from_env()?;
+    let auth = auth::from_env(&http).await?;
+    let limits = Limits::default()
+        .limit("file", media::MAX_SIZE.mebibytes())
+        .limit("data-form", media::MAX_SIZE.mebibytes());
+    let figment = rocket::Config::figment().merge(("limits", limits));
+
+    info!("Scheduling purges of the trash");
+    rocket::tokio::spawn(db::trash::purge_periodically(
+        pool.clone(),
+        db::trash::retention_from_env(),
+    ));
 
     info!("Launching server");
     #[allow(clippy::let_underscore_drop)]
-    let _ = rocket::build()
+    let _ = rocket::custom(figment)
         .attach(cors)
+        .mount(
+            "/auth",
+            routes![
+                server::auth::login,           // /login    POST
+                server::auth::logout,          // /logout   POST
+                server::auth::me,              // /me       GET
+                server::auth::change_password, // /password PUT
+            ],
+        )
+        .mount(
+            "/admin",
+            routes![
+                server::admin::list_users,  // /users     GET
+                server::admin::new_user,    // /users     POST
+                server::admin::update_user, // /users/:id PUT
+                server::admin::delete_user, // /users/:id DELETE
+                server::admin::list_keys,   // /keys      GET
+                server::admin::new_key,     // /keys      POST
+                server::admin::revoke_key,  // /keys/:id  DELETE
+                server::audit::list,        // /audit     GET
+            ],
+        )
         .mount(
             "/author",
             routes![
-                server::author::list,   // /     GET
-                server::author::new,    // /     POST
-                server::author::update, // /     PUT
-                server::author::find,   // /find GET
-                server::author::get,    // /:id  GET
-                server::author::delete, // /:id  DELETE
+                server::author::list,           // /                   GET
+                server::author::new,            // /                   POST
+                server::author::update,         // /                   PUT
+                server::author::find,           // /find               GET
+                server::author::get,            // /:id                GET
+                server::author::delete,         // /:id                DELETE
+                server::author::delete_preview, // /:id/delete-preview GET
             ],
         )
         .mount(
             "/book",
             routes![
-                server::book::list,   // /     GET
-                server::book::new,    // /     POST
-                server::book::find,   // /find GET
-                server::book::get,    // /:id  GET
-                server::book::delete, // /:id  DELETE
+                server::book::list,           // /                   GET
+                server::book::new,            // /                   POST
+                server::book::update,         // /                   PUT
+                server::book::find,           // /find               GET
+                server::book::get,            // /:id                GET
+                server::book::delete,         // /:id                DELETE
+                server::book::delete_preview, // /:id/delete-preview GET
+                server::book::set_owner,      // /:id/owner          PUT
+                server::book::set_status,     // /:id/status         PUT
+                // Import
+                server::import::bundle,   // /bundle          POST
+                server::import::epub,     // /import/epub     POST
+                server::import::markdown, // /import/markdown POST
+                // Collaborators
+                server::collaborator::list,   // /:id/collaborators     GET
+                server::collaborator::set,    // /:id/collaborators/:id PUT
+                server::collaborator::delete, // /:id/collaborators/:id DELETE
+                // Chapters
+                server::chapter::list,   // /:id/chapters     GET
+                server::chapter::new,    // /:id/chapters     POST
+                server::chapter::get,    // /:id/chapters/:id GET
+                server::chapter::update, // /:id/chapters/:id PUT
+                server::chapter::delete, // /:id/chapters/:id DELETE
+                // /:id/chapters/:id/delete-preview GET
+                server::chapter::delete_preview,
                 // Fragments
-                server::fragment::list // /:id/fragments GET
+                server::chapter::fragments, // /:id/chapters/:id/fragments GET
+                server::fragment::list,     // /:id/fragments              GET
+                server::fragment::renumber, // /:id/fragments/renumber     POST
+                server::fragment::batch,    // /:id/fragments/batch        POST
+                server::fragment::timeline, // /:id/ambience-timeline      GET
+                // Export
+                server::export::bundle,   // /:id/bundle      GET
+                server::export::epub,     // /:id/export.epub GET
+                server::export::markdown, // /:id/export.md   GET
             ],
         )
         .mount(
@@ -140,12 +300,53 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 server::fragment::get,     // /:id         GET
                 server::fragment::delete,  // /:id         DELETE
                 server::fragment::reorder, // /:id/reorder PUT
+                // Revisions
+                server::fragment::revisions, // /:id/revisions              GET
+                server::fragment::diff,      // /:id/revisions/diff         GET
+                server::fragment::restore,   // /:id/revisions/:rev/restore POST
+            ],
+        )
+        .mount(
+            "/media",
+            routes![
+                server::media::list,    // /             GET
+                server::media::upload,  // /             POST
+                server::media::get,     // /:id          GET
+                server::media::variant, // /:id/:variant GET
+                server::media::delete,  // /:id          DELETE
+            ],
+        )
+        .mount(
+            "/reader",
+            routes![
+                server::reader::list_progress,   // /me/progress      GET
+                server::reader::get_progress,    // /me/progress/:id  GET
+                server::reader::set_progress,    // /me/progress/:id  PUT
+                server::reader::list_bookmarks,  // /me/bookmarks     GET
+                server::reader::new_bookmark,    // /me/bookmarks     POST
+                server::reader::update_bookmark, // /me/bookmarks/:id PUT
+                server::reader::delete_bookmark, // /me/bookmarks/:id DELETE
+            ],
+        )
+        .mount(
+            "/trash",
+            routes![
+                server::trash::list,    // /                   GET
+                server::trash::restore, // /:kind/:id/restore POST
+            ],
+        )
+        .mount(
+            "/search",
+            routes![
+                server::search::find, // / GET
             ],
         )
         .manage(ServerState {
             pool,
-            api_key: env::var("ALEXANDRIA_ADMIN_KEY")
-                .expect("ALEXANDRIA_ADMIN_KEY must be set!"),
+            images,
+            storage,
+            auth,
+            confirmation: db::trash::ConfirmationKey::from_env(),
         })
         .launch()
         .await?;
@@ -0,0 +1,186 @@
+use std::fmt;
+
+use symphonia::core::audio::{Channels, SampleBuffer};
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::{
+    MediaSource, MediaSourceStream, MediaSourceStreamOptions,
+};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Share of the announced length of a sound which must be decoded for
+/// the sound not to be considered truncated
+const MIN_DECODED: f64 = 0.9;
+
+/// Error encountered while probing a sound
+#[derive(Debug)]
+pub enum Error {
+    /// The container or the codec of the sound is not supported
+    Unsupported(String),
+    /// The sound is broken and cannot be played entirely
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported(message) => {
+                write!(f, "Unsupported audio format: {}", message)
+            }
+            Self::Invalid(message) => {
+                write!(f, "Invalid audio file: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<SymphoniaError> for Error {
+    fn from(other: SymphoniaError) -> Self {
+        match other {
+            SymphoniaError::Unsupported(message) => {
+                Self::Unsupported(message.to_owned())
+            }
+            other => Self::Invalid(other.to_string()),
+        }
+    }
+}
+
+/// Properties of a sound found by probing it
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioInfo {
+    /// Duration of the sound in seconds
+    pub duration: f64,
+    /// Short name of the codec of the sound, such as `mp3`
+    pub codec: String,
+    /// Sample rate of the sound in Hz
+    pub samplerate: u32,
+    /// Amount of channels of the sound
+    pub channels: u32,
+    /// RMS level of the sound in dBFS, `None` if it is silent
+    pub loudness: Option<f64>,
+}
+
+/// Decode a whole sound to find its properties
+///
+/// `mime_type` helps finding the format of the sound. The sound is
+/// entirely decoded, so broken files are detected: a sound is
+/// rejected if none of it can be decoded, or if less than 90% of its
+/// announced length can be.
+///
+/// # Errors
+///
+/// If the format or the codec of the sound is not supported, an
+/// `Error::Unsupported` is returned. If the sound cannot be decoded,
+/// an `Error::Invalid` is returned.
+#[allow(clippy::cast_precision_loss)]
+pub fn probe(
+    source: Box<dyn MediaSource>,
+    mime_type: &str,
+) -> Result<AudioInfo, Error> {
+    let stream =
+        MediaSourceStream::new(source, MediaSourceStreamOptions::default());
+    let mut hint = Hint::new();
+    hint.mime_type(mime_type);
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?
+        .format;
+    let track = match format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+    {
+        Some(track) => track,
+        None => return Err(Error::Unsupported("no audio track".to_owned())),
+    };
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+    let codecs = symphonia::default::get_codecs();
+    let codec = codecs
+        .get_codec(params.codec)
+        .map(|descriptor| descriptor.short_name.to_owned())
+        .unwrap_or_default();
+    let mut decoder = codecs.make(&params, &DecoderOptions::default())?;
+    let mut samplerate = params.sample_rate.unwrap_or_default();
+    let mut channels = params.channels.map(Channels::count).unwrap_or_default();
+    let mut frames: u64 = 0;
+    let mut squares = 0.0_f64;
+    let mut samples: u64 = 0;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e))
+                if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                break
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let audio = match decoder.decode(&packet) {
+            Ok(audio) => audio,
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *audio.spec();
+        samplerate = spec.rate;
+        channels = spec.channels.count();
+        frames += audio.frames() as u64;
+        let capacity = audio.capacity() as u64;
+        let buffer = match &mut buffer {
+            Some(buffer) if buffer.capacity() as u64 >= capacity => buffer,
+            _ => buffer.insert(SampleBuffer::new(capacity, spec)),
+        };
+        buffer.copy_interleaved_ref(audio);
+        for sample in buffer.samples() {
+            squares += f64::from(*sample) * f64::from(*sample);
+        }
+        samples += buffer.samples().len() as u64;
+    }
+    if frames == 0 || samplerate == 0 {
+        return Err(Error::Invalid("no audio could be decoded".to_owned()));
+    }
+    if let Some(announced) = params.n_frames {
+        if (frames as f64) < announced as f64 * MIN_DECODED {
+            return Err(Error::Invalid(format!(
+                "only {} of {} frames could be decoded",
+                frames, announced
+            )));
+        }
+    }
+    let rms = (squares / samples as f64).sqrt();
+    Ok(AudioInfo {
+        duration: frames as f64 / f64::from(samplerate),
+        codec,
+        samplerate,
+        channels: u32::try_from(channels).unwrap_or(u32::MAX),
+        loudness: (rms > 0.0).then(|| 20.0 * rms.log10()),
+    })
+}
+
+/// Probe a sound in a blocking task, see [`probe`]
+///
+/// # Errors
+///
+/// See [`probe`].
+pub async fn probe_blocking(
+    source: Box<dyn MediaSource>,
+    mime_type: String,
+) -> Result<AudioInfo, Error> {
+    let task = move || probe(source, &mime_type);
+    match rocket::tokio::task::spawn_blocking(task).await {
+        Ok(result) => result,
+        Err(e) => Err(Error::Invalid(e.to_string())),
+    }
+}
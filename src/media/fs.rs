@@ -0,0 +1,71 @@
+use std::io::{self, SeekFrom};
+use std::path::PathBuf;
+
+use rocket::tokio::fs::{self, File};
+use rocket::tokio::io::{self as tokio_io, AsyncReadExt, AsyncSeekExt};
+use uuid::Uuid;
+
+use super::{Storage, Stream};
+
+/// Storage keeping each asset in a file named after its identifier
+pub struct FsStorage {
+    root: PathBuf,
+}
+
+impl FsStorage {
+    /// Create a storage in the directory `root`
+    ///
+    /// # Errors
+    ///
+    /// If `root` does not exist and cannot be created, an error is
+    /// returned.
+    pub fn new(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    fn path(&self, id: Uuid) -> PathBuf {
+        self.root.join(id.to_string())
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for FsStorage {
+    /// Write the content to a temporary file first, then move it in
+    /// place, so an asset is never left partially written
+    async fn store(&self, id: Uuid, mut data: Stream<'_>) -> io::Result<u64> {
+        let path = self.path(id);
+        let partial = path.with_extension("part");
+        let mut file = File::create(&partial).await?;
+        let written = match tokio_io::copy(&mut data, &mut file).await {
+            Ok(written) => written,
+            Err(e) => {
+                drop(file);
+                let _ = fs::remove_file(&partial).await;
+                return Err(e);
+            }
+        };
+        file.sync_all().await?;
+        fs::rename(&partial, &path).await?;
+        Ok(written)
+    }
+
+    async fn read(
+        &self,
+        id: Uuid,
+        start: u64,
+        length: u64,
+    ) -> io::Result<Stream<'static>> {
+        let mut file = File::open(self.path(id)).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        Ok(Box::new(file.take(length)))
+    }
+
+    async fn remove(&self, id: Uuid) -> io::Result<()> {
+        match fs::remove_file(self.path(id)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,296 @@
+pub mod audio;
+pub mod fs;
+pub mod variants;
+
+use std::env;
+use std::io::{self, Cursor};
+
+use diesel::PgConnection;
+use rocket::tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::db;
+use crate::models::MediaVariant;
+
+/// Maximum size of an uploaded asset in MiB
+pub const MAX_SIZE: u64 = 64;
+
+/// Content of an asset being read from or written to a storage
+pub type Stream<'a> = Box<dyn AsyncRead + Send + Unpin + 'a>;
+
+/// Backend holding the content of media assets
+///
+/// The metadata of assets are stored in the database, see
+/// `crate::models::MediaAsset`, while their content is handed to a
+/// storage under the identifier of the asset.
+#[rocket::async_trait]
+pub trait Storage: Send + Sync {
+    /// Store the content read from `data` as the asset `id`
+    ///
+    /// Returns the amount of bytes stored. If the asset already
+    /// exists, its content is replaced.
+    ///
+    /// # Errors
+    ///
+    /// If `data` cannot be read or the content cannot be written, an
+    /// error is returned and nothing is stored.
+    async fn store(&self, id: Uuid, data: Stream<'_>) -> io::Result<u64>;
+
+    /// Read `length` bytes of the asset `id`, starting at `start`
+    ///
+    /// # Errors
+    ///
+    /// If the asset does not exist or cannot be read, an error is
+    /// returned.
+    async fn read(
+        &self,
+        id: Uuid,
+        start: u64,
+        length: u64,
+    ) -> io::Result<Stream<'static>>;
+
+    /// Remove the content of the asset `id`
+    ///
+    /// Removing an asset which does not exist is not an error.
+    ///
+    /// # Errors
+    ///
+    /// If the content of the asset cannot be removed, an error is
+    /// returned.
+    async fn remove(&self, id: Uuid) -> io::Result<()>;
+}
+
+/// Build the storage configured by the environment
+///
+/// Assets are stored in the directory `ALEXANDRIA_MEDIA_DIR`, or in
+/// `media` if it is not set, which is created if needed.
+///
+/// # Errors
+///
+/// If the directory cannot be created, an error is returned.
+pub fn from_env() -> io::Result<Box<dyn Storage>> {
+    let root = env::var("ALEXANDRIA_MEDIA_DIR")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .unwrap_or_else(|| "media".to_owned());
+    info!("Storing media assets in {}", root);
+    Ok(Box::new(fs::FsStorage::new(root)?))
+}
+
+/// URL at which the content of the asset `id` is served
+///
+/// The URL is relative to the root of the API.
+#[must_use]
+pub fn url(id: Uuid) -> String {
+    format!("/media/{}", id)
+}
+
+/// URL at which a variant of the image `asset` is served
+///
+/// The URL is relative to the root of the API, such as
+/// `/media/<asset>/640.webp`.
+#[must_use]
+pub fn variant_url(asset: Uuid, width: i32, content_type: &str) -> String {
+    let extension = variants::extension(content_type).unwrap_or("bin");
+    format!("/media/{}/{}.{}", asset, width, extension)
+}
+
+/// Identifier of the asset served at `url`, if any, see [`url`]
+#[must_use]
+pub fn parse_url(url: &str) -> Option<Uuid> {
+    url.strip_prefix("/media/")
+        .and_then(|id| Uuid::parse_str(id).ok())
+}
+
+/// Source of a sound or an image which may be a media asset
+///
+/// If `asset` is set, the URL of the asset is returned, otherwise
+/// `source` is.
+#[must_use]
+pub fn source(asset: Option<Uuid>, source: Option<String>) -> Option<String> {
+    asset.map(url).or(source)
+}
+
+/// MIME types of the assets which can be uploaded, see [`sniff`]
+pub const ACCEPTED: [&str; 9] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "audio/mpeg",
+    "audio/ogg",
+    "audio/flac",
+    "audio/wav",
+    "audio/mp4",
+];
+
+/// Amount of bytes read from the start of an asset to find its type,
+/// see [`sniff`]
+pub const SNIFF_SIZE: u64 = 16;
+
+/// Whether assets of the MIME type `content_type` can be uploaded
+///
+/// Only raster images and sounds can be used by books. Other images,
+/// such as SVG images which may hold scripts, are refused.
+#[must_use]
+pub fn is_accepted(content_type: &str) -> bool {
+    ACCEPTED.contains(&content_type)
+}
+
+/// Find the MIME type of an asset from its first bytes
+///
+/// The type declared by the client uploading an asset is not trusted,
+/// the signature of the file is read instead. Returns `None` if the
+/// asset is not one of the [`ACCEPTED`] types.
+#[must_use]
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    let riff = |kind: &[u8]| {
+        data.starts_with(b"RIFF") && data.get(8..12) == Some(kind)
+    };
+    let brand = data.get(8..12);
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if riff(b"WEBP") {
+        Some("image/webp")
+    } else if riff(b"WAVE") {
+        Some("audio/wav")
+    } else if data.starts_with(b"OggS") {
+        Some("audio/ogg")
+    } else if data.starts_with(b"fLaC") {
+        Some("audio/flac")
+    } else if data.get(4..8) == Some(b"ftyp")
+        && (brand == Some(b"M4A ") || brand == Some(b"M4B "))
+    {
+        Some("audio/mp4")
+    } else if data.starts_with(b"ID3")
+        || matches!(data, [0xff, second, ..] if second & 0xe0 == 0xe0)
+    {
+        Some("audio/mpeg")
+    } else {
+        None
+    }
+}
+
+/// Store the variants of the image `asset` in `storage`
+///
+/// Each variant is stored under its own identifier. Returns the
+/// variants to record in the database.
+///
+/// # Errors
+///
+/// If a variant cannot be stored, the variants already stored are
+/// removed and an error is returned.
+pub async fn store_variants(
+    storage: &dyn Storage,
+    asset: Uuid,
+    variants: Vec<variants::Variant>,
+) -> io::Result<Vec<MediaVariant>> {
+    let mut stored = Vec::with_capacity(variants.len());
+    for variant in variants {
+        let id = Uuid::new_v4();
+        let data = Box::new(Cursor::new(variant.data));
+        match storage.store(id, data).await {
+            Ok(size) => stored.push(MediaVariant {
+                id,
+                asset,
+                width: i32::try_from(variant.width).unwrap_or(i32::MAX),
+                height: i32::try_from(variant.height).unwrap_or(i32::MAX),
+                contenttype: variant.content_type.to_owned(),
+                size: i64::try_from(size).unwrap_or(i64::MAX),
+            }),
+            Err(e) => {
+                remove_variants(storage, &stored).await;
+                return Err(e);
+            }
+        }
+    }
+    Ok(stored)
+}
+
+/// Remove the content of `variants` from `storage`
+///
+/// Variants which cannot be removed are logged.
+pub async fn remove_variants(storage: &dyn Storage, variants: &[MediaVariant]) {
+    for variant in variants {
+        if let Err(e) = storage.remove(variant.id).await {
+            warn!("Could not remove media variant {}: {}", variant.id, e);
+        }
+    }
+}
+
+/// Read the whole content of `asset` from `storage`
+async fn read_all(
+    storage: &dyn Storage,
+    asset: &crate::models::MediaAsset,
+) -> io::Result<Vec<u8>> {
+    let length = u64::try_from(asset.size).unwrap_or_default();
+    let mut data = Vec::new();
+    storage
+        .read(asset.id, 0, length)
+        .await?
+        .read_to_end(&mut data)
+        .await?;
+    Ok(data)
+}
+
+/// Process the assets uploaded before alexandria inspected them
+///
+/// Sounds whose properties are unknown are probed, see
+/// `audio::probe`, and images without variants get their variants
+/// and blurhash generated, see `variants::generate`. Assets which
+/// cannot be processed are left untouched and logged. Returns the
+/// amount of assets processed.
+///
+/// # Errors
+///
+/// If the database or the storage cannot be read or written, an
+/// error is returned.
+pub async fn probe_unprobed(
+    connector: &mut PgConnection,
+    storage: &dyn Storage,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut probed = 0;
+    for asset in db::media::unprocessed(connector)? {
+        let data = read_all(storage, &asset).await?;
+        let generated =
+            variants::generate_blocking(data, asset.contenttype.clone()).await;
+        let derivatives = match generated {
+            Ok(derivatives) => derivatives,
+            Err(e) => {
+                warn!("Could not process media asset {}: {}", asset.id, e);
+                continue;
+            }
+        };
+        let stored =
+            store_variants(storage, asset.id, derivatives.variants).await?;
+        let image = db::media::set_image(
+            connector,
+            asset.id,
+            (derivatives.width, derivatives.height),
+            &derivatives.blurhash,
+            &stored,
+        );
+        if let Err(e) = image {
+            remove_variants(storage, &stored).await;
+            return Err(e.into());
+        }
+        probed += 1;
+    }
+    for asset in db::media::unprobed(connector)? {
+        let data = read_all(storage, &asset).await?;
+        let source = Box::new(Cursor::new(data));
+        match audio::probe_blocking(source, asset.contenttype).await {
+            Ok(info) => {
+                db::media::set_audio(connector, asset.id, &info)?;
+                probed += 1;
+            }
+            Err(e) => warn!("Could not probe media asset {}: {}", asset.id, e),
+        }
+    }
+    Ok(probed)
+}
@@ -0,0 +1,205 @@
+use std::fmt;
+use std::io::Cursor;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::io::{Limits, Reader};
+use image::{
+    ColorType, DynamicImage, GenericImageView, ImageError, ImageFormat,
+};
+
+/// Widths in pixels of the variants generated for each image
+///
+/// Only the widths smaller than the one of the image are generated.
+pub const WIDTHS: [u32; 4] = [320, 640, 1280, 1920];
+
+/// Formats of the variants generated for each width, as MIME types
+pub const FORMATS: [&str; 2] = ["image/webp", "image/jpeg"];
+
+/// Quality of the JPEG variants, from 1 to 100
+const JPEG_QUALITY: u8 = 80;
+
+/// Quality of the lossy WebP variants, from 0 to 100
+const WEBP_QUALITY: f32 = 80.0;
+
+/// Maximum width and height in pixels of an image to decode
+const MAX_DIMENSION: u32 = 8192;
+
+/// Maximum amount of memory in bytes allocated to decode an image
+const MAX_ALLOC: u64 = 256 * 1024 * 1024;
+
+/// Size of the thumbnail used to compute the blurhash of an image
+const THUMBNAIL_SIZE: u32 = 32;
+
+/// Error encountered while generating the variants of an image
+#[derive(Debug)]
+pub enum Error {
+    /// The format of the image is not supported
+    Unsupported(String),
+    /// The image is broken and cannot be decoded or encoded
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported(message) => {
+                write!(f, "Unsupported image format: {}", message)
+            }
+            Self::Invalid(message) => write!(f, "Invalid image: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ImageError> for Error {
+    fn from(other: ImageError) -> Self {
+        match other {
+            ImageError::Unsupported(e) => Self::Unsupported(e.to_string()),
+            other => Self::Invalid(other.to_string()),
+        }
+    }
+}
+
+/// A resized copy of an image, encoded in one of [`FORMATS`]
+pub struct Variant {
+    pub width: u32,
+    pub height: u32,
+    pub content_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// Properties and variants of an image
+pub struct Derivatives {
+    /// Width of the original image in pixels
+    pub width: u32,
+    /// Height of the original image in pixels
+    pub height: u32,
+    /// Blurred placeholder of the image, see <https://blurha.sh>
+    pub blurhash: String,
+    pub variants: Vec<Variant>,
+}
+
+/// Extension of the URL of variants of the MIME type `content_type`
+#[must_use]
+pub fn extension(content_type: &str) -> Option<&'static str> {
+    match content_type {
+        "image/webp" => Some("webp"),
+        "image/jpeg" => Some("jpg"),
+        _ => None,
+    }
+}
+
+/// MIME type of variants whose URL ends with `extension`, see
+/// [`extension`]
+#[must_use]
+pub fn content_type(extension: &str) -> Option<&'static str> {
+    FORMATS
+        .into_iter()
+        .find(|format| self::extension(format) == Some(extension))
+}
+
+/// Encode `image` in the format `content_type`, one of [`FORMATS`]
+fn encode(image: &DynamicImage, content_type: &str) -> Result<Vec<u8>, Error> {
+    let (width, height) = image.dimensions();
+    if content_type == "image/webp" {
+        let pixels = image.to_rgba8();
+        return webp::Encoder::from_rgba(pixels.as_raw(), width, height)
+            .encode_simple(false, WEBP_QUALITY)
+            .map(|data| data.to_vec())
+            .map_err(|e| Error::Invalid(format!("{:?}", e)));
+    }
+    let mut data = Cursor::new(Vec::new());
+    JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY).encode(
+        image.to_rgb8().as_raw(),
+        width,
+        height,
+        ColorType::Rgb8,
+    )?;
+    Ok(data.into_inner())
+}
+
+/// Generate the variants and the blurhash of an image
+///
+/// The image is decoded according to its MIME type `mime_type`, and
+/// refused if it is larger than [`MAX_DIMENSION`] pixels in either
+/// direction or needs more than [`MAX_ALLOC`] bytes to decode. Each
+/// variant is generated in all [`FORMATS`], for all [`WIDTHS`]
+/// smaller than the width of the image. An image narrower than all
+/// of them gets variants of its own width instead. The aspect ratio
+/// of the image is kept.
+///
+/// # Errors
+///
+/// If the format of the image is not supported, an
+/// `Error::Unsupported` is returned. If the image cannot be decoded,
+/// an `Error::Invalid` is returned.
+pub fn generate(data: &[u8], mime_type: &str) -> Result<Derivatives, Error> {
+    let format = ImageFormat::from_mime_type(mime_type).ok_or_else(|| {
+        Error::Unsupported(format!("unknown MIME type `{}`", mime_type))
+    })?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_ALLOC);
+    let mut reader = Reader::with_format(Cursor::new(data), format);
+    reader.limits(limits);
+    let image = reader.decode()?;
+    let (width, height) = image.dimensions();
+    let mut widths: Vec<u32> = WIDTHS
+        .into_iter()
+        .filter(|target| *target < width)
+        .collect();
+    if widths.is_empty() {
+        widths.push(width);
+    }
+    let mut variants = Vec::with_capacity(widths.len() * FORMATS.len());
+    for target in widths {
+        let resized = if target == width {
+            image.clone()
+        } else {
+            image.resize(target, u32::MAX, FilterType::CatmullRom)
+        };
+        for content_type in FORMATS {
+            variants.push(Variant {
+                width: resized.width(),
+                height: resized.height(),
+                content_type,
+                data: encode(&resized, content_type)?,
+            });
+        }
+    }
+    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgba8();
+    let blurhash = blurhash::encode(
+        4,
+        3,
+        thumbnail.width(),
+        thumbnail.height(),
+        thumbnail.as_raw(),
+    )
+    .map_err(|e| Error::Invalid(e.to_string()))?;
+    Ok(Derivatives {
+        width,
+        height,
+        blurhash,
+        variants,
+    })
+}
+
+/// Generate the variants of an image in a blocking task, see
+/// [`generate`]
+///
+/// # Errors
+///
+/// See [`generate`].
+pub async fn generate_blocking(
+    data: Vec<u8>,
+    mime_type: String,
+) -> Result<Derivatives, Error> {
+    let task = move || generate(&data, &mime_type);
+    match rocket::tokio::task::spawn_blocking(task).await {
+        Ok(result) => result,
+        Err(e) => Err(Error::Invalid(e.to_string())),
+    }
+}
@@ -3,15 +3,21 @@ use rocket::serde::{Deserialize, Serialize};
 
 use uuid::Uuid;
 
-use crate::schema::{authors, bookfragments, books};
+use crate::schema::{
+    apikeys, auditlog, authors, bookfragments, bookmarks, books, chapters,
+    collaborators, fragmentrevisions, imagecache, mediaassets,
+    mediavariants, publishedbooks, publishedchapters, publishedfragments,
+    readingprogress, sessions, users,
+};
 
 /// Rust representation of the `Autors` table in the database
 ///
-/// It contains four elements:
+/// It contains five elements:
 /// - The identifier of the author
 /// - Their first name (can include their middle name)
 /// - Their last name
 /// - Their pen name
+/// - When they were moved to the trash (null unless they are trashed)
 /// All of them except the identifier can be null. However, the pen
 /// name must be set if the first and last names aren’t, and vice
 /// versa.
@@ -22,6 +28,8 @@ pub struct Author {
     pub firstname: Option<String>,
     pub lastname: Option<String>,
     pub penname: Option<String>,
+    #[serde(default)]
+    pub deletedat: Option<chrono::NaiveDateTime>,
 }
 
 /// Different types of books.
@@ -38,9 +46,33 @@ pub enum BookType {
     Poem,
 }
 
+/// Stage of the publishing workflow a book is in
+///
+/// - **Draft**: The book is being written
+/// - **`InReview`**: The book is ready to be reviewed before being
+///   published
+/// - **Published**: The book can be read by everyone, as it was when
+///   it was last published
+/// - **Archived**: The book is no longer available to readers
+///
+/// Only published books are visible to readers, other books are only
+/// visible to the users who can view their drafts. See [`Book`]
+///
+/// [`Book`]: ./struct.Book.html
+#[derive(Debug, Serialize, Deserialize, diesel_derive_enum::DbEnum, Clone, Copy, PartialEq, Eq, Default)]
+#[DieselTypePath = "crate::schema::sql_types::Bookstatus"]
+#[serde(crate = "rocket::serde", rename_all = "kebab-case")]
+pub enum BookStatus {
+    #[default]
+    Draft,
+    InReview,
+    Published,
+    Archived,
+}
+
 /// Rust representation of the `Books` table in the database.
 ///
-/// The table consists of ten elements:
+/// The table consists of fifteen elements:
 /// - The unique identifier of the book
 /// - The title of the book, including its subtitle
 /// - The unique identifier of the author of the book
@@ -49,8 +81,19 @@ pub enum BookType {
 /// - The date the book was published (can be null)
 /// - The synopsis of the book (can be null)
 /// - The type of book it is (see [`BookType`])
+/// - The media asset used as the cover of the book, which takes
+///   precedence over `cover` (can be null, see [`MediaAsset`])
+/// - The user owning the book (can be null, see [`User`])
+/// - Its stage in the publishing workflow (see [`BookStatus`]),
+///   drafts when missing from Json data
+/// - When it was moved to the trash (null unless it is trashed)
+/// - The text search configuration its content is indexed with, see
+///   [`DEFAULT_LANGUAGE`]
 ///
 /// [`BookType`]: ./enum.BookType.html
+/// [`BookStatus`]: ./enum.BookStatus.html
+/// [`MediaAsset`]: ./struct.MediaAsset.html
+/// [`User`]: ./struct.User.html
 #[derive(Queryable, Deserialize, Serialize, Insertable, Clone, AsChangeset)]
 #[serde(crate = "rocket::serde")]
 pub struct Book {
@@ -64,6 +107,55 @@ pub struct Book {
     pub genre: Option<Vec<Option<String>>>,
     pub synopsis: Option<String>,
     pub booktype: BookType,
+    pub coverasset: Option<Uuid>,
+    pub owner: Option<Uuid>,
+    #[serde(default)]
+    pub status: BookStatus,
+    #[serde(default)]
+    pub deletedat: Option<chrono::NaiveDateTime>,
+    #[serde(default = "default_language")]
+    pub language: String,
+}
+
+/// Text search configuration of books created without a language,
+/// which indexes words as they are
+pub const DEFAULT_LANGUAGE: &str = "simple";
+
+/// Language of books read from Json data without one, see
+/// [`DEFAULT_LANGUAGE`]
+#[must_use]
+pub fn default_language() -> String {
+    DEFAULT_LANGUAGE.to_owned()
+}
+
+/// Rust representation of the `Chapters` table in the database.
+///
+/// The table consists of eight elements:
+/// - The unique identifier of the chapter
+/// - Which book this chapter belongs to (references its unique id,
+///   see [`Book`])
+/// - Its number within the book (1 is the first chapter)
+/// - Its title (can be null)
+/// - Its epigraph (can be null)
+/// - A link to the default background image of its fragments (can be
+///   null)
+/// - A link to the default background sound of its fragments (can be
+///   null)
+/// - When it was moved to the trash (null unless it is trashed)
+///
+/// [`Book`]: ./struct.Book.html
+#[derive(Queryable, Deserialize, Serialize, Insertable, Clone, AsChangeset)]
+#[serde(crate = "rocket::serde")]
+pub struct Chapter {
+    pub id: Uuid,
+    pub book: Uuid,
+    pub number: i32,
+    pub title: Option<String>,
+    pub epigraph: Option<String>,
+    pub cover: Option<String>,
+    pub bgsoundsource: Option<String>,
+    #[serde(default)]
+    pub deletedat: Option<chrono::NaiveDateTime>,
 }
 
 /// The type of image used as the background for a fragment.
@@ -77,7 +169,7 @@ pub struct Book {
 /// - **Same**: Keep using the previous image
 ///
 /// [`BookFragment`]: ./struct.Bookfragment.html
-#[derive(Debug, Serialize, Deserialize, diesel_derive_enum::DbEnum, Clone)]
+#[derive(Debug, Serialize, Deserialize, diesel_derive_enum::DbEnum, Clone, PartialEq, Eq)]
 #[DieselTypePath = "crate::schema::sql_types::Imagetype"]
 #[serde(crate = "rocket::serde")]
 pub enum ImageType {
@@ -98,7 +190,7 @@ pub enum ImageType {
 ///   fragment
 ///
 /// [`BookFragment`]: ./struct.Bookfragment.html
-#[derive(Debug, Serialize, Deserialize, diesel_derive_enum::DbEnum, Clone)]
+#[derive(Debug, Serialize, Deserialize, diesel_derive_enum::DbEnum, Clone, PartialEq, Eq)]
 #[DieselTypePath = "crate::schema::sql_types::Soundtype"]
 #[serde(crate = "rocket::serde")]
 pub enum SoundType {
@@ -109,7 +201,7 @@ pub enum SoundType {
 
 /// Rust representation of the `BookFragments` table in the database.
 ///
-/// The table consists of ten elements:
+/// The table consists of fourteen elements:
 /// - Its unique identifier
 /// - The text content of the fragment
 /// - The source of the oneshot sound (can be null if none)
@@ -124,13 +216,22 @@ pub enum SoundType {
 ///   automatically from Unsplash.
 /// - Which book this fragment belongs to (references is unique id,
 ///   see [`Book`])
-/// - The chapter it is in (1 is the first chapter)
 /// - Its ranking within the chapter (1 is the first fragment of the
 ///   chapter)
+/// - The chapter it is in (references its unique id, see
+///   [`Chapter`])
+/// - The media assets used as its oneshot sound, its background sound
+///   and its background image (can be null, see [`MediaAsset`]). When
+///   set, an asset takes precedence over the URL held by the matching
+///   source, and the background ones are only used if `bgsoundtype`
+///   or `imgtype` is `Url`.
+/// - When it was moved to the trash (null unless it is trashed)
 ///
 /// [`ImageType`]: ./enum.ImageType.html
 /// [`SoundType`]: ./enum.SoundType.html
 /// [`Book`]: ./struct.Book.html
+/// [`Chapter`]: ./struct.Chapter.html
+/// [`MediaAsset`]: ./struct.MediaAsset.html
 #[derive(Queryable, Deserialize, Serialize, Insertable, Clone, AsChangeset)]
 #[serde(crate = "rocket::serde")]
 pub struct Bookfragment {
@@ -142,6 +243,469 @@ pub struct Bookfragment {
     pub imgtype: ImageType,
     pub imgsource: Option<String>,
     pub book: Uuid,
-    pub chapter: i32,
     pub rank: i32,
+    pub chapter: Uuid,
+    pub oneshotsoundasset: Option<Uuid>,
+    pub bgsoundasset: Option<Uuid>,
+    pub imgasset: Option<Uuid>,
+    #[serde(default)]
+    pub deletedat: Option<chrono::NaiveDateTime>,
+}
+
+/// Rust representation of the `PublishedFragments` table in the
+/// database.
+///
+/// Each row is a copy of a [`Bookfragment`] of a published book, as
+/// it was when the book was last published. Rows have the same
+/// elements as [`Bookfragment`], in the same order, except for when
+/// it was moved to the trash since trashed fragments are never
+/// published.
+///
+/// [`Bookfragment`]: ./struct.Bookfragment.html
+#[derive(Queryable, Deserialize, Serialize, Insertable, Clone)]
+#[diesel(table_name = publishedfragments)]
+#[serde(crate = "rocket::serde")]
+pub struct PublishedFragment {
+    pub id: Uuid,
+    pub content: String,
+    pub oneshotsoundsource: Option<String>,
+    pub bgsoundtype: SoundType,
+    pub bgsoundsource: Option<String>,
+    pub imgtype: ImageType,
+    pub imgsource: Option<String>,
+    pub book: Uuid,
+    pub rank: i32,
+    pub chapter: Uuid,
+    pub oneshotsoundasset: Option<Uuid>,
+    pub bgsoundasset: Option<Uuid>,
+    pub imgasset: Option<Uuid>,
+}
+
+impl From<PublishedFragment> for Bookfragment {
+    fn from(other: PublishedFragment) -> Self {
+        Self {
+            id: other.id,
+            content: other.content,
+            oneshotsoundsource: other.oneshotsoundsource,
+            bgsoundtype: other.bgsoundtype,
+            bgsoundsource: other.bgsoundsource,
+            imgtype: other.imgtype,
+            imgsource: other.imgsource,
+            book: other.book,
+            rank: other.rank,
+            chapter: other.chapter,
+            oneshotsoundasset: other.oneshotsoundasset,
+            bgsoundasset: other.bgsoundasset,
+            imgasset: other.imgasset,
+            deletedat: None,
+        }
+    }
+}
+
+/// Rust representation of the `PublishedBooks` table in the
+/// database.
+///
+/// Each row holds the metadata of a published [`Book`], as they were
+/// when the book was last published. Rows have the same elements as
+/// [`Book`], in the same order, up to its cover asset: the owner, the
+/// status and the language of a book are never part of its snapshot.
+///
+/// [`Book`]: ./struct.Book.html
+#[derive(Queryable, Deserialize, Serialize, Insertable, Clone)]
+#[diesel(table_name = publishedbooks)]
+#[serde(crate = "rocket::serde")]
+pub struct PublishedBook {
+    pub id: Uuid,
+    pub title: String,
+    pub author: Uuid,
+    pub isbn: Option<Vec<Option<String>>>,
+    pub cover: Option<String>,
+    pub publisher: Option<String>,
+    pub published: Option<chrono::NaiveDate>,
+    pub genre: Option<Vec<Option<String>>>,
+    pub synopsis: Option<String>,
+    pub booktype: BookType,
+    pub coverasset: Option<Uuid>,
+}
+
+/// Rust representation of the `PublishedChapters` table in the
+/// database.
+///
+/// Each row is a copy of a [`Chapter`] of a published book, as it was
+/// when the book was last published. Rows have the same elements as
+/// [`Chapter`], in the same order, except for when it was moved to
+/// the trash since trashed chapters are never published.
+///
+/// [`Chapter`]: ./struct.Chapter.html
+#[derive(Queryable, Deserialize, Serialize, Insertable, Clone)]
+#[diesel(table_name = publishedchapters)]
+#[serde(crate = "rocket::serde")]
+pub struct PublishedChapter {
+    pub id: Uuid,
+    pub book: Uuid,
+    pub number: i32,
+    pub title: Option<String>,
+    pub epigraph: Option<String>,
+    pub cover: Option<String>,
+    pub bgsoundsource: Option<String>,
+}
+
+impl From<PublishedChapter> for Chapter {
+    fn from(other: PublishedChapter) -> Self {
+        Self {
+            id: other.id,
+            book: other.book,
+            number: other.number,
+            title: other.title,
+            epigraph: other.epigraph,
+            cover: other.cover,
+            bgsoundsource: other.bgsoundsource,
+            deletedat: None,
+        }
+    }
+}
+
+/// Rust representation of the `FragmentRevisions` table in the
+/// database.
+///
+/// Each row is a state a [`Bookfragment`] went through, written when
+/// it was created or updated:
+/// - Its unique identifier
+/// - The fragment it belongs to (references its unique id, see
+///   [`Bookfragment`])
+/// - Its number, starting from 1 for each fragment
+/// - The content, sounds and image of the fragment, as in
+///   [`Bookfragment`]
+/// - The chapter and the rank of the fragment
+/// - The user who made the change (null if they were deleted, or for
+///   fragments created before revisions were recorded)
+/// - When the change was made
+///
+/// [`Bookfragment`]: ./struct.Bookfragment.html
+#[derive(Queryable, Deserialize, Serialize, Insertable, Clone)]
+#[diesel(table_name = fragmentrevisions)]
+#[serde(crate = "rocket::serde")]
+pub struct FragmentRevision {
+    pub id: Uuid,
+    pub fragment: Uuid,
+    pub revision: i32,
+    pub content: String,
+    pub oneshotsoundsource: Option<String>,
+    pub bgsoundtype: SoundType,
+    pub bgsoundsource: Option<String>,
+    pub imgtype: ImageType,
+    pub imgsource: Option<String>,
+    pub chapter: Uuid,
+    pub rank: i32,
+    pub oneshotsoundasset: Option<Uuid>,
+    pub bgsoundasset: Option<Uuid>,
+    pub imgasset: Option<Uuid>,
+    pub editor: Option<Uuid>,
+    pub created: chrono::NaiveDateTime,
+}
+
+/// Rust representation of the `ImageCache` table in the database.
+///
+/// Each row holds an image found by an image provider for keywords
+/// of an automatic image, see [`ImageType`]:
+/// - The normalized keywords the image was searched for
+/// - The URL of the image
+/// - The name of the author of the image and the URL of their
+///   profile, if the provider gives them, for attribution
+/// - The name of the provider the image was found with
+/// - When the image was found
+///
+/// [`ImageType`]: ./enum.ImageType.html
+#[derive(Queryable, Deserialize, Serialize, Insertable, Clone, PartialEq, Eq)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = imagecache)]
+pub struct CachedImage {
+    pub query: String,
+    pub url: String,
+    pub author: Option<String>,
+    pub authorurl: Option<String>,
+    pub provider: String,
+    pub fetchedat: chrono::NaiveDateTime,
+}
+
+/// Rust representation of the `MediaAssets` table in the database.
+///
+/// Each row describes a sound or an image uploaded to alexandria,
+/// whose content is held by the media storage (see
+/// `crate::media::Storage`):
+/// - Its unique identifier
+/// - The name of the uploaded file
+/// - Its MIME type
+/// - Its size in bytes
+/// - When it was uploaded
+/// - For sounds, their duration in seconds, the short name of their
+///   codec, their sample rate in Hz, their amount of channels and
+///   their loudness as their RMS level in dBFS (all null for images,
+///   and loudness null for silent sounds)
+/// - For images, their width and height in pixels and their blurhash
+///   placeholder (all null for sounds), see [`MediaVariant`] for
+///   their resized variants
+///
+/// [`MediaVariant`]: ./struct.MediaVariant.html
+#[derive(Queryable, Deserialize, Serialize, Insertable, Clone)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = mediaassets)]
+pub struct MediaAsset {
+    pub id: Uuid,
+    pub filename: String,
+    pub contenttype: String,
+    pub size: i64,
+    pub uploaded: chrono::NaiveDateTime,
+    pub duration: Option<f64>,
+    pub codec: Option<String>,
+    pub samplerate: Option<i32>,
+    pub channels: Option<i32>,
+    pub loudness: Option<f64>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub blurhash: Option<String>,
+}
+
+/// Rust representation of the `MediaVariants` table in the database.
+///
+/// Each row describes a resized copy of an image uploaded as a media
+/// asset, whose content is held by the media storage under the
+/// identifier of the variant:
+/// - Its unique identifier
+/// - The asset it is a variant of (references its unique id, see
+///   [`MediaAsset`])
+/// - Its width and height in pixels
+/// - Its MIME type
+/// - Its size in bytes
+///
+/// [`MediaAsset`]: ./struct.MediaAsset.html
+#[derive(Queryable, Deserialize, Serialize, Insertable, Clone)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = mediavariants)]
+pub struct MediaVariant {
+    pub id: Uuid,
+    pub asset: Uuid,
+    pub width: i32,
+    pub height: i32,
+    pub contenttype: String,
+    pub size: i64,
+}
+
+/// Rust representation of the `ReadingProgress` table in the
+/// database.
+///
+/// Each row holds where a reader stopped reading a book:
+/// - Its unique identifier
+/// - The user reading (references their unique id, see [`User`])
+/// - The book (references its unique id, see [`Book`])
+/// - The last fragment read (references its unique id, see
+///   [`Bookfragment`]), null if it has been deleted since
+/// - The number of the chapter of this fragment and its rank within
+///   the chapter, kept even if the fragment is deleted
+/// - When the progress was last updated
+///
+/// A user has at most one progress per book.
+///
+/// [`User`]: ./struct.User.html
+/// [`Book`]: ./struct.Book.html
+/// [`Bookfragment`]: ./struct.Bookfragment.html
+#[derive(Queryable, Deserialize, Serialize, Insertable, Clone)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = readingprogress)]
+pub struct Progress {
+    pub id: Uuid,
+    pub reader: Uuid,
+    pub book: Uuid,
+    pub fragment: Option<Uuid>,
+    pub chapternumber: i32,
+    pub rank: i32,
+    pub updated: chrono::NaiveDateTime,
+}
+
+/// Rust representation of the `Bookmarks` table in the database.
+///
+/// Each row is a fragment bookmarked by a user:
+/// - Its unique identifier
+/// - The user reading (references their unique id, see [`User`])
+/// - The bookmarked fragment (references its unique id, see
+///   [`Bookfragment`])
+/// - A note written by the user (can be null)
+/// - When the bookmark was created
+///
+/// [`User`]: ./struct.User.html
+/// [`Bookfragment`]: ./struct.Bookfragment.html
+#[derive(Queryable, Deserialize, Serialize, Insertable, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct Bookmark {
+    pub id: Uuid,
+    pub reader: Uuid,
+    pub fragment: Uuid,
+    pub note: Option<String>,
+    pub created: chrono::NaiveDateTime,
+}
+
+/// Role of a user, granting them more permissions than the previous
+/// role
+///
+/// - Readers can only read the library
+/// - Editors can also add, edit and delete authors, books, chapters,
+///   fragments and media assets
+/// - Administrators can also manage users and their API keys
+///
+/// See [`User`]
+///
+/// [`User`]: ./struct.User.html
+#[derive(Debug, Serialize, Deserialize, diesel_derive_enum::DbEnum, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[DieselTypePath = "crate::schema::sql_types::Userrole"]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Role {
+    Reader,
+    Editor,
+    Admin,
+}
+
+/// Rust representation of the `Users` table in the database.
+///
+/// Each row describes an account able to use the API:
+/// - Its unique identifier
+/// - Its username, unique among local accounts
+/// - The argon2 hash of its password, in the PHC string format
+/// - Its role (see [`Role`])
+/// - When it was created
+/// - The issuer and the subject of the tokens of the identity
+///   provider it was provisioned from, both null for local accounts
+///
+/// [`Role`]: ./enum.Role.html
+#[derive(Queryable, Deserialize, Serialize, Insertable, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct User {
+    pub id: Uuid,
+    pub username: String,
+    pub passwordhash: String,
+    pub role: Role,
+    pub created: chrono::NaiveDateTime,
+    #[serde(default)]
+    pub issuer: Option<String>,
+    #[serde(default)]
+    pub subject: Option<String>,
+}
+
+/// Rust representation of the `Sessions` table in the database.
+///
+/// Each row is a session opened by a user logging in:
+/// - Its unique identifier
+/// - The user who opened it (references their unique id, see
+///   [`User`])
+/// - The SHA-256 hash of the token of the session, in hexadecimal
+/// - When it was opened
+/// - When it expires
+///
+/// [`User`]: ./struct.User.html
+#[derive(Queryable, Insertable, Clone)]
+pub struct Session {
+    pub id: Uuid,
+    pub owner: Uuid,
+    pub tokenhash: String,
+    pub created: chrono::NaiveDateTime,
+    pub expires: chrono::NaiveDateTime,
+}
+
+/// Rust representation of the `ApiKeys` table in the database.
+///
+/// Each row is an API key issued to a user, granting the same
+/// permissions as the user:
+/// - Its unique identifier
+/// - The user it belongs to (references their unique id, see
+///   [`User`])
+/// - A name describing what the key is used for
+/// - The first characters of the key, to recognize it
+/// - The SHA-256 hash of the key, in hexadecimal
+/// - When it was issued
+/// - When it was last used (can be null)
+/// - When it was revoked (null while the key is valid)
+///
+/// [`User`]: ./struct.User.html
+#[derive(Queryable, Deserialize, Serialize, Insertable, Clone)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = apikeys)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub owner: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub keyhash: String,
+    pub created: chrono::NaiveDateTime,
+    pub lastused: Option<chrono::NaiveDateTime>,
+    pub revoked: Option<chrono::NaiveDateTime>,
+}
+
+/// Rust representation of the `Collaborators` table in the database.
+///
+/// Each row grants a user permissions on a book they do not own:
+/// - Its unique identifier
+/// - The book (references its unique id, see [`Book`])
+/// - The user (references their unique id, see [`User`])
+/// - Whether they can view the book while it is a draft
+/// - Whether they can edit its chapters and fragments
+/// - Whether they can publish it
+/// - Whether they can delete it
+///
+/// [`Book`]: ./struct.Book.html
+/// [`User`]: ./struct.User.html
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Queryable, Deserialize, Serialize, Insertable, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct Collaborator {
+    pub id: Uuid,
+    pub book: Uuid,
+    pub member: Uuid,
+    pub viewdraft: bool,
+    pub editfragments: bool,
+    pub publish: bool,
+    pub deletebook: bool,
+}
+
+/// Kind of change recorded in the audit log, see [`AuditEntry`]
+///
+/// [`AuditEntry`]: ./struct.AuditEntry.html
+#[derive(Debug, Serialize, Deserialize, diesel_derive_enum::DbEnum, rocket::FromFormField, Clone, Copy, PartialEq, Eq)]
+#[DieselTypePath = "crate::schema::sql_types::Auditaction"]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+}
+
+/// Rust representation of the `AuditLog` table in the database.
+///
+/// Each row records a change made through the API:
+/// - Its unique identifier
+/// - The user who made the change (references their unique id, see
+///   [`User`], null if they were deleted since)
+/// - Their username at the time of the change
+/// - The route they called, such as `DELETE /book/<id>`
+/// - The kind of change (see [`AuditAction`])
+/// - The type of the changed entity, such as `book`
+/// - The identifier of the changed entity
+/// - The entity before the change (null for creations)
+/// - The entity after the change (null for deletions)
+/// - When the change was made
+///
+/// [`User`]: ./struct.User.html
+/// [`AuditAction`]: ./enum.AuditAction.html
+#[derive(Queryable, Deserialize, Serialize, Insertable, Clone)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = auditlog)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub actor: Option<Uuid>,
+    pub actorname: String,
+    pub route: String,
+    pub action: AuditAction,
+    pub entitytype: String,
+    pub entityid: Uuid,
+    pub before: Option<rocket::serde::json::Value>,
+    pub after: Option<rocket::serde::json::Value>,
+    pub created: chrono::NaiveDateTime,
 }
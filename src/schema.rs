@@ -1,6 +1,14 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "auditaction"))]
+    pub struct Auditaction;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "bookstatus"))]
+    pub struct Bookstatus;
+
     #[derive(diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "booktype"))]
     pub struct Booktype;
@@ -12,6 +20,41 @@ pub mod sql_types {
     #[derive(diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "soundtype"))]
     pub struct Soundtype;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "userrole"))]
+    pub struct Userrole;
+}
+
+diesel::table! {
+    apikeys (id) {
+        id -> Uuid,
+        owner -> Uuid,
+        name -> Varchar,
+        prefix -> Varchar,
+        keyhash -> Varchar,
+        created -> Timestamp,
+        lastused -> Nullable<Timestamp>,
+        revoked -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Auditaction;
+
+    auditlog (id) {
+        id -> Uuid,
+        actor -> Nullable<Uuid>,
+        actorname -> Varchar,
+        route -> Varchar,
+        action -> Auditaction,
+        entitytype -> Varchar,
+        entityid -> Uuid,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+        created -> Timestamp,
+    }
 }
 
 diesel::table! {
@@ -20,6 +63,7 @@ diesel::table! {
         firstname -> Nullable<Varchar>,
         lastname -> Nullable<Varchar>,
         penname -> Nullable<Varchar>,
+        deletedat -> Nullable<Timestamp>,
     }
 }
 
@@ -37,14 +81,29 @@ diesel::table! {
         imgtype -> Imagetype,
         imgsource -> Nullable<Varchar>,
         book -> Uuid,
-        chapter -> Int4,
         rank -> Int4,
+        chapter -> Uuid,
+        oneshotsoundasset -> Nullable<Uuid>,
+        bgsoundasset -> Nullable<Uuid>,
+        imgasset -> Nullable<Uuid>,
+        deletedat -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    bookmarks (id) {
+        id -> Uuid,
+        reader -> Uuid,
+        fragment -> Uuid,
+        note -> Nullable<Text>,
+        created -> Timestamp,
     }
 }
 
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::Booktype;
+    use super::sql_types::Bookstatus;
 
     books (id) {
         id -> Uuid,
@@ -57,14 +116,235 @@ diesel::table! {
         genre -> Nullable<Array<Nullable<Text>>>,
         synopsis -> Nullable<Text>,
         booktype -> Booktype,
+        coverasset -> Nullable<Uuid>,
+        owner -> Nullable<Uuid>,
+        status -> Bookstatus,
+        deletedat -> Nullable<Timestamp>,
+        language -> Varchar,
+    }
+}
+
+diesel::table! {
+    chapters (id) {
+        id -> Uuid,
+        book -> Uuid,
+        number -> Int4,
+        title -> Nullable<Varchar>,
+        epigraph -> Nullable<Text>,
+        cover -> Nullable<Varchar>,
+        bgsoundsource -> Nullable<Varchar>,
+        deletedat -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    collaborators (id) {
+        id -> Uuid,
+        book -> Uuid,
+        member -> Uuid,
+        viewdraft -> Bool,
+        editfragments -> Bool,
+        publish -> Bool,
+        deletebook -> Bool,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Soundtype;
+    use super::sql_types::Imagetype;
+
+    fragmentrevisions (id) {
+        id -> Uuid,
+        fragment -> Uuid,
+        revision -> Int4,
+        content -> Text,
+        oneshotsoundsource -> Nullable<Varchar>,
+        bgsoundtype -> Soundtype,
+        bgsoundsource -> Nullable<Varchar>,
+        imgtype -> Imagetype,
+        imgsource -> Nullable<Varchar>,
+        chapter -> Uuid,
+        rank -> Int4,
+        oneshotsoundasset -> Nullable<Uuid>,
+        bgsoundasset -> Nullable<Uuid>,
+        imgasset -> Nullable<Uuid>,
+        editor -> Nullable<Uuid>,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    imagecache (query) {
+        query -> Varchar,
+        url -> Text,
+        author -> Nullable<Varchar>,
+        authorurl -> Nullable<Text>,
+        provider -> Varchar,
+        fetchedat -> Timestamp,
+    }
+}
+
+diesel::table! {
+    mediaassets (id) {
+        id -> Uuid,
+        filename -> Varchar,
+        contenttype -> Varchar,
+        size -> Int8,
+        uploaded -> Timestamp,
+        duration -> Nullable<Float8>,
+        codec -> Nullable<Varchar>,
+        samplerate -> Nullable<Int4>,
+        channels -> Nullable<Int4>,
+        loudness -> Nullable<Float8>,
+        width -> Nullable<Int4>,
+        height -> Nullable<Int4>,
+        blurhash -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    mediavariants (id) {
+        id -> Uuid,
+        asset -> Uuid,
+        width -> Int4,
+        height -> Int4,
+        contenttype -> Varchar,
+        size -> Int8,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Soundtype;
+    use super::sql_types::Imagetype;
+
+    publishedfragments (id) {
+        id -> Uuid,
+        content -> Text,
+        oneshotsoundsource -> Nullable<Varchar>,
+        bgsoundtype -> Soundtype,
+        bgsoundsource -> Nullable<Varchar>,
+        imgtype -> Imagetype,
+        imgsource -> Nullable<Varchar>,
+        book -> Uuid,
+        rank -> Int4,
+        chapter -> Uuid,
+        oneshotsoundasset -> Nullable<Uuid>,
+        bgsoundasset -> Nullable<Uuid>,
+        imgasset -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Booktype;
+
+    publishedbooks (id) {
+        id -> Uuid,
+        title -> Varchar,
+        author -> Uuid,
+        isbn -> Nullable<Array<Nullable<Text>>>,
+        cover -> Nullable<Varchar>,
+        publisher -> Nullable<Varchar>,
+        published -> Nullable<Date>,
+        genre -> Nullable<Array<Nullable<Text>>>,
+        synopsis -> Nullable<Text>,
+        booktype -> Booktype,
+        coverasset -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    publishedchapters (id) {
+        id -> Uuid,
+        book -> Uuid,
+        number -> Int4,
+        title -> Nullable<Varchar>,
+        epigraph -> Nullable<Text>,
+        cover -> Nullable<Varchar>,
+        bgsoundsource -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    readingprogress (id) {
+        id -> Uuid,
+        reader -> Uuid,
+        book -> Uuid,
+        fragment -> Nullable<Uuid>,
+        chapternumber -> Int4,
+        rank -> Int4,
+        updated -> Timestamp,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Uuid,
+        owner -> Uuid,
+        tokenhash -> Varchar,
+        created -> Timestamp,
+        expires -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Userrole;
+
+    users (id) {
+        id -> Uuid,
+        username -> Varchar,
+        passwordhash -> Varchar,
+        role -> Userrole,
+        created -> Timestamp,
+        issuer -> Nullable<Varchar>,
+        subject -> Nullable<Varchar>,
     }
 }
 
+diesel::joinable!(apikeys -> users (owner));
+diesel::joinable!(auditlog -> users (actor));
 diesel::joinable!(bookfragments -> books (book));
+diesel::joinable!(bookfragments -> chapters (chapter));
+diesel::joinable!(bookmarks -> bookfragments (fragment));
+diesel::joinable!(bookmarks -> users (reader));
 diesel::joinable!(books -> authors (author));
+diesel::joinable!(books -> users (owner));
+diesel::joinable!(chapters -> books (book));
+diesel::joinable!(collaborators -> books (book));
+diesel::joinable!(collaborators -> users (member));
+diesel::joinable!(fragmentrevisions -> bookfragments (fragment));
+diesel::joinable!(fragmentrevisions -> users (editor));
+diesel::joinable!(mediavariants -> mediaassets (asset));
+diesel::joinable!(publishedbooks -> authors (author));
+diesel::joinable!(publishedbooks -> books (id));
+diesel::joinable!(publishedchapters -> books (book));
+diesel::joinable!(publishedfragments -> books (book));
+diesel::joinable!(publishedfragments -> publishedchapters (chapter));
+diesel::joinable!(readingprogress -> bookfragments (fragment));
+diesel::joinable!(readingprogress -> books (book));
+diesel::joinable!(readingprogress -> users (reader));
+diesel::joinable!(sessions -> users (owner));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    apikeys,
+    auditlog,
     authors,
     bookfragments,
+    bookmarks,
     books,
+    chapters,
+    collaborators,
+    fragmentrevisions,
+    imagecache,
+    mediaassets,
+    mediavariants,
+    publishedbooks,
+    publishedchapters,
+    publishedfragments,
+    readingprogress,
+    sessions,
+    users,
 );
@@ -0,0 +1,301 @@
+use crate::auth::{self, password, Admin};
+use crate::db::get_connector;
+use crate::db::user::{self, Account, Changes, Key};
+use crate::models::{ApiKey, Role, User};
+use crate::server::auth::hash;
+use crate::server::{json_val_or_error, make_error};
+use crate::{Json, JsonResponse, ServerState};
+
+use diesel::result::Error::NotFound;
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::uuid::Uuid;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use tracing::info;
+
+/// Data an administrator sends to create a user
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewUser {
+    pub username: String,
+    pub password: String,
+    pub role: Role,
+}
+
+/// Data an administrator sends to change the password or the role of
+/// a user
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UserChanges {
+    pub password: Option<String>,
+    pub role: Option<Role>,
+}
+
+/// Data an administrator sends to issue an API key to a user
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewKey {
+    pub user: Uuid,
+    pub name: String,
+}
+
+/// API key freshly issued to a user
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Issued {
+    /// The key itself, to send in the `X-Api-Key` header
+    pub key: String,
+    #[serde(flatten)]
+    pub info: Key,
+}
+
+/// Number of characters of an API key kept to recognize it
+const PREFIX_LENGTH: usize = 8;
+
+/// Check an administrator does not lose their own role
+///
+/// # Errors
+///
+/// If `admin` is `id` and `role` is not `Role::Admin`, a 409 error is
+/// returned so the instance always keeps an administrator.
+fn check_self(
+    admin: &Admin,
+    id: Uuid,
+    role: Option<Role>,
+) -> Result<(), status::Custom<String>> {
+    if admin.0.user.id == id && role != Some(Role::Admin) {
+        return make_error!(
+            Status::Conflict,
+            "Administrators cannot remove their own role".to_owned()
+        );
+    }
+    Ok(())
+}
+
+/// Convert the result of a query on a user, returning a 404 error if
+/// they do not exist
+fn user_or_error<T>(
+    result: Result<T, diesel::result::Error>,
+    id: Uuid,
+) -> JsonResponse<T> {
+    match result {
+        Ok(val) => Ok(Json(val)),
+        Err(NotFound) => {
+            make_error!(Status::NotFound, format!("User ID {} not found", id))
+        }
+        Err(other) => {
+            make_error!(Status::InternalServerError, other.to_string())
+        }
+    }
+}
+
+/// List all users, sorted by username
+///
+/// # Errors
+///
+/// Any error from the server will be returned to the user as a 500
+/// HTTP error.
+#[get("/users")]
+pub fn list_users(
+    db: &State<ServerState>,
+    _admin: Admin,
+) -> JsonResponse<Vec<Account>> {
+    let connector = &mut get_connector!(db);
+    json_val_or_error!(user::list(connector)
+        .map(|users| users.into_iter().map(Into::into).collect()))
+}
+
+/// Create a new user
+///
+/// # Errors
+///
+/// If the username is empty or the password is too short, a 400 error
+/// is returned to the user. If the username is already taken, a 409
+/// error is returned. Any other error will be returned as a 500 HTTP
+/// error.
+#[post("/users", format = "json", data = "<input>")]
+pub fn new_user(
+    db: &State<ServerState>,
+    _admin: Admin,
+    input: Json<NewUser>,
+) -> JsonResponse<Account> {
+    let input = input.into_inner();
+    let username = input.username.trim().to_owned();
+    if username.is_empty() {
+        return make_error!(Status::BadRequest, "Missing username".to_owned());
+    }
+    let created = User {
+        id: Uuid::new_v4(),
+        username,
+        passwordhash: hash(&input.password)?,
+        role: input.role,
+        created: chrono::Utc::now().naive_utc(),
+        issuer: None,
+        subject: None,
+    };
+    let connector = &mut get_connector!(db);
+    info!("Creating user {}", created.username);
+    match user::new(connector, &created) {
+        Ok(val) => Ok(Json(val.into())),
+        Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            make_error!(
+                Status::Conflict,
+                format!("Username {} is already taken", created.username)
+            )
+        }
+        Err(other) => {
+            make_error!(Status::InternalServerError, other.to_string())
+        }
+    }
+}
+
+/// Change the password or the role of a user
+///
+/// # Errors
+///
+/// If nothing is changed or if the password is too short, a 400 error
+/// is returned to the user. If the user does not exist, a 404 error is
+/// returned. If administrators try to remove their own role, a 409
+/// error is returned. Any other error will be returned as a 500 HTTP
+/// error.
+#[put("/users/<id>", format = "json", data = "<input>")]
+pub fn update_user(
+    db: &State<ServerState>,
+    admin: Admin,
+    id: Uuid,
+    input: Json<UserChanges>,
+) -> JsonResponse<Account> {
+    let input = input.into_inner();
+    if input.password.is_none() && input.role.is_none() {
+        return make_error!(Status::BadRequest, "Nothing to change".to_owned());
+    }
+    if input.role.is_some() {
+        check_self(&admin, id, input.role)?;
+    }
+    let changes = Changes {
+        passwordhash: input.password.as_deref().map(hash).transpose()?,
+        role: input.role,
+    };
+    let connector = &mut get_connector!(db);
+    info!("Updating user {}", id);
+    user_or_error(user::update(connector, id, &changes).map(Into::into), id)
+}
+
+/// Delete a user along with their sessions and API keys
+///
+/// # Errors
+///
+/// If the user does not exist, a 404 error is returned to the user.
+/// If administrators try to delete themselves, a 409 error is
+/// returned. Any other error will be returned as a 500 HTTP error.
+#[delete("/users/<id>")]
+pub fn delete_user(
+    db: &State<ServerState>,
+    admin: Admin,
+    id: Uuid,
+) -> JsonResponse<()> {
+    check_self(&admin, id, None)?;
+    let connector = &mut get_connector!(db);
+    info!("Deleting user {}", id);
+    match user::delete(connector, id) {
+        Ok(0) => {
+            make_error!(Status::NotFound, format!("User ID {} not found", id))
+        }
+        Ok(_) => Ok(Json(())),
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// List the API keys, or only those of `user` if it is set, oldest
+/// first
+///
+/// The keys themselves are never sent back, only their first
+/// characters.
+///
+/// # Errors
+///
+/// Any error from the server will be returned to the user as a 500
+/// HTTP error.
+#[get("/keys?<user>")]
+pub fn list_keys(
+    db: &State<ServerState>,
+    _admin: Admin,
+    user: Option<Uuid>,
+) -> JsonResponse<Vec<Key>> {
+    let connector = &mut get_connector!(db);
+    json_val_or_error!(user::list_keys(connector, user)
+        .map(|keys| keys.into_iter().map(Into::into).collect()))
+}
+
+/// Issue a new API key to a user
+///
+/// The key grants the same permissions as its user. It is returned
+/// once and cannot be retrieved afterwards.
+///
+/// # Errors
+///
+/// If the name of the key is empty, a 400 error is returned to the
+/// user. If the user does not exist, a 404 error is returned. Any
+/// other error will be returned as a 500 HTTP error.
+#[post("/keys", format = "json", data = "<input>")]
+pub fn new_key(
+    db: &State<ServerState>,
+    _admin: Admin,
+    input: Json<NewKey>,
+) -> JsonResponse<Issued> {
+    let input = input.into_inner();
+    let name = input.name.trim().to_owned();
+    if name.is_empty() {
+        return make_error!(Status::BadRequest, "Missing name".to_owned());
+    }
+    let connector = &mut get_connector!(db);
+    let owner = user_or_error(user::get(connector, input.user), input.user)?;
+    let key = password::new_token(auth::KEY_PREFIX);
+    let created = ApiKey {
+        id: Uuid::new_v4(),
+        owner: owner.id,
+        name,
+        prefix: key.chars().take(PREFIX_LENGTH).collect(),
+        keyhash: password::hash_token(&key),
+        created: chrono::Utc::now().naive_utc(),
+        lastused: None,
+        revoked: None,
+    };
+    info!("Issuing API key {} to user {}", created.id, owner.id);
+    json_val_or_error!(user::new_key(connector, &created).map(|val| Issued {
+        key,
+        info: val.into(),
+    }))
+}
+
+/// Revoke an API key
+///
+/// Revoked keys are kept so their use can still be audited, but they
+/// no longer authenticate requests.
+///
+/// # Errors
+///
+/// If the key does not exist or is already revoked, a 404 error is
+/// returned to the user. Any other error will be returned as a 500
+/// HTTP error.
+#[delete("/keys/<id>")]
+pub fn revoke_key(
+    db: &State<ServerState>,
+    _admin: Admin,
+    id: Uuid,
+) -> JsonResponse<Key> {
+    let connector = &mut get_connector!(db);
+    info!("Revoking API key {}", id);
+    match user::revoke_key(connector, id) {
+        Ok(val) => Ok(Json(val.into())),
+        Err(NotFound) => make_error!(
+            Status::NotFound,
+            format!("API key ID {} not found or already revoked", id)
+        ),
+        Err(other) => {
+            make_error!(Status::InternalServerError, other.to_string())
+        }
+    }
+}
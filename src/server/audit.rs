@@ -0,0 +1,237 @@
+use crate::auth::{Admin, Identity};
+use crate::db::audit::{self, Filter};
+use crate::db::{get_connector, ApiResult};
+use crate::import::ImportedBook;
+use crate::models::{AuditAction, AuditEntry, Book, Bookfragment};
+use crate::server::{json_val_or_error, make_error};
+use crate::{Json, JsonResponse, ServerState};
+
+use diesel::PgConnection;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::serde_json;
+use rocket::serde::uuid::Uuid;
+use rocket::serde::Serialize;
+use rocket::{Route, State};
+
+/// Amount of entries returned by `list` when no limit is given
+const DEFAULT_LIMIT: i64 = 100;
+
+/// Maximum amount of entries returned by `list` at once
+const MAX_LIMIT: i64 = 1000;
+
+/// Criteria an administrator can send to filter the audit log
+///
+/// `since` and `until` are RFC 3339 dates, such as
+/// `2023-01-09T14:00:00Z`.
+#[derive(FromForm)]
+pub struct Query {
+    pub actor: Option<Uuid>,
+    pub action: Option<AuditAction>,
+    pub entity: Option<String>,
+    pub id: Option<Uuid>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Entry of the audit log describing a change made by the user
+/// sending a request, see [`record`]
+fn entry<T: Serialize>(
+    identity: &Identity,
+    route: &Route,
+    action: AuditAction,
+    entity: &str,
+    id: Uuid,
+    before: Option<&T>,
+    after: Option<&T>,
+) -> AuditEntry {
+    let snapshot = |value: Option<&T>| {
+        value.and_then(|value| serde_json::to_value(value).ok())
+    };
+    AuditEntry {
+        id: Uuid::new_v4(),
+        actor: Some(identity.user.id),
+        actorname: identity.user.username.clone(),
+        route: format!("{} {}", route.method, route.uri.path()),
+        action,
+        entitytype: entity.to_owned(),
+        entityid: id,
+        before: snapshot(before),
+        after: snapshot(after),
+        created: chrono::Utc::now().naive_utc(),
+    }
+}
+
+/// Record a change made by the user sending a request in the audit
+/// log
+///
+/// `entity` is the type of the changed entity and `id` its
+/// identifier, while `before` and `after` are its states before and
+/// after the change. The entry must be recorded in the same
+/// transaction as the change, so that a change cannot be made
+/// without being recorded.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `record`
+#[allow(clippy::too_many_arguments)]
+pub fn record<T: Serialize>(
+    connector: &mut PgConnection,
+    identity: &Identity,
+    route: &Route,
+    action: AuditAction,
+    entity: &str,
+    id: Uuid,
+    before: Option<&T>,
+    after: Option<&T>,
+) -> ApiResult<()> {
+    let entry = entry(identity, route, action, entity, id, before, after);
+    audit::record(connector, &[entry]).map(|_| ())
+}
+
+/// Record the deletion of the books and fragments moved to the trash
+/// along with an author, a book or a chapter
+///
+/// `books` and `fragments` are their states before the deletion. Like
+/// [`record`], it must run in the same transaction as the deletion.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `record_cascade`
+pub fn record_cascade(
+    connector: &mut PgConnection,
+    identity: &Identity,
+    route: &Route,
+    books: &[Book],
+    fragments: &[Bookfragment],
+) -> ApiResult<()> {
+    let action = AuditAction::Delete;
+    let entries: Vec<AuditEntry> = books
+        .iter()
+        .map(|book| {
+            entry(identity, route, action, "book", book.id, Some(book), None)
+        })
+        .chain(fragments.iter().map(|fragment| {
+            let id = fragment.id;
+            entry(identity, route, action, "fragment", id, Some(fragment), None)
+        }))
+        .collect();
+    audit::record(connector, &entries).map(|_| ())
+}
+
+/// Record the creation of an imported book, of its chapters and of
+/// its fragments, along with its author when they were created by the
+/// import
+///
+/// `imported` holds the rows returned by `import::save`. Like
+/// [`record`], it must run in the same transaction as the import.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `record_import`
+pub fn record_import(
+    connector: &mut PgConnection,
+    identity: &Identity,
+    route: &Route,
+    imported: &ImportedBook,
+) -> ApiResult<()> {
+    let action = AuditAction::Create;
+    let mut entries = Vec::new();
+    if let Some(author) = &imported.author {
+        entries.push(entry(
+            identity,
+            route,
+            action,
+            "author",
+            author.id,
+            None,
+            Some(author),
+        ));
+    }
+    let book = &imported.book;
+    entries.push(entry(
+        identity,
+        route,
+        action,
+        "book",
+        book.id,
+        None,
+        Some(book),
+    ));
+    for imported in &imported.chapters {
+        let chapter = &imported.chapter;
+        entries.push(entry(
+            identity,
+            route,
+            action,
+            "chapter",
+            chapter.id,
+            None,
+            Some(chapter),
+        ));
+        entries.extend(imported.fragments.iter().map(|fragment| {
+            let id = fragment.id;
+            entry(identity, route, action, "fragment", id, None, Some(fragment))
+        }));
+    }
+    audit::record(connector, &entries).map(|_| ())
+}
+
+/// Read an RFC 3339 date sent as the query parameter `name`
+///
+/// # Errors
+///
+/// If the date is invalid, a 400 error is returned.
+fn parse_date(
+    name: &str,
+    date: Option<String>,
+) -> Result<Option<chrono::NaiveDateTime>, status::Custom<String>> {
+    match date.map(|date| chrono::DateTime::parse_from_rfc3339(&date)) {
+        None => Ok(None),
+        Some(Ok(date)) => Ok(Some(date.naive_utc())),
+        Some(Err(e)) => make_error!(
+            Status::BadRequest,
+            format!("Invalid date for {}: {}", name, e)
+        ),
+    }
+}
+
+/// List the changes recorded in the audit log, from the newest to the
+/// oldest
+///
+/// Entries can be filtered by the user who made the change (`actor`),
+/// the kind of change (`action`, either `create`, `update` or
+/// `delete`), the type of the changed entity (`entity`, either
+/// `author`, `book` or `fragment`), its identifier (`id`), and the
+/// period when they were made (`since` included, `until` excluded).
+/// At most `limit` entries are returned, 100 by default and 1000 at
+/// most, after skipping the first `offset` ones.
+///
+/// # Errors
+///
+/// If a date is invalid, a 400 error is returned to the user. Any
+/// other error will be returned as a 500 HTTP error.
+#[get("/audit?<query..>")]
+pub fn list(
+    db: &State<ServerState>,
+    query: Query,
+    _admin: Admin,
+) -> JsonResponse<Vec<AuditEntry>> {
+    let filter = Filter {
+        actor: query.actor,
+        action: query.action,
+        entity: query.entity,
+        id: query.id,
+        since: parse_date("since", query.since)?,
+        until: parse_date("until", query.until)?,
+        limit: query.limit.unwrap_or(DEFAULT_LIMIT).clamp(0, MAX_LIMIT),
+        offset: query.offset.unwrap_or(0).max(0),
+    };
+    let connector = &mut get_connector!(db);
+    json_val_or_error!(audit::list(connector, &filter))
+}
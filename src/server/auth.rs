@@ -0,0 +1,180 @@
+use crate::auth::{self, password, Credential, Reader};
+use crate::db::get_connector;
+use crate::db::user::{self, Account, Changes};
+use crate::models::Session;
+use crate::server::{json_val_or_error, make_error};
+use crate::{Json, JsonResponse, ServerState};
+
+use chrono::Duration;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::uuid::Uuid;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use tracing::info;
+
+/// Credentials a user sends to log in
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Login {
+    pub username: String,
+    pub password: String,
+}
+
+/// Session opened by logging in
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Opened {
+    /// Token to send in the `Authorization` header as a bearer token
+    pub token: String,
+    pub expires: chrono::NaiveDateTime,
+    pub user: Account,
+}
+
+/// Data a user sends to change their own password
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewPassword {
+    pub current: String,
+    pub new: String,
+}
+
+/// Log in with a username and a password
+///
+/// A new session is opened, its token is returned once along with the
+/// account of the user. The session expires after
+/// [`auth::SESSION_DAYS`] days.
+///
+/// # Errors
+///
+/// If users are authenticated by an identity provider, see
+/// [`auth::Mode::Jwt`], a 400 error is returned to the user. If the
+/// username or the password is wrong, a 401 error is returned. Any
+/// other error will be returned as a 500 HTTP error.
+#[post("/login", format = "json", data = "<login>")]
+pub fn login(
+    db: &State<ServerState>,
+    login: Json<Login>,
+) -> JsonResponse<Opened> {
+    if let auth::Mode::Jwt(_) = db.auth {
+        return make_error!(
+            Status::BadRequest,
+            "Logging in is disabled, use a token of the identity provider"
+                .to_owned()
+        );
+    }
+    let connector = &mut get_connector!(db);
+    let found = match user::from_username(connector, &login.username) {
+        Ok(val) => val,
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    let user = match found {
+        Some(user) if password::verify(&login.password, &user.passwordhash) => {
+            user
+        }
+        _ => {
+            return make_error!(
+                Status::Unauthorized,
+                "Invalid username or password".to_owned()
+            )
+        }
+    };
+    let token = password::new_token(auth::SESSION_PREFIX);
+    let now = chrono::Utc::now().naive_utc();
+    let session = Session {
+        id: Uuid::new_v4(),
+        owner: user.id,
+        tokenhash: password::hash_token(&token),
+        created: now,
+        expires: now + Duration::days(auth::SESSION_DAYS),
+    };
+    info!("Opening session {} for user {}", session.id, user.id);
+    json_val_or_error!(user::new_session(connector, &session).map(|_| Opened {
+        token,
+        expires: session.expires,
+        user: user.into(),
+    }))
+}
+
+/// Close the session the request was sent with
+///
+/// # Errors
+///
+/// If the request was authenticated with an API key or a JWT instead
+/// of a session, a 400 error is returned to the user. Any other error
+/// will be returned as a 500 HTTP error.
+#[post("/logout")]
+pub fn logout(db: &State<ServerState>, me: Reader) -> JsonResponse<()> {
+    let id = match me.0.credential {
+        Credential::Session(id) => id,
+        Credential::ApiKey(_) | Credential::Jwt => {
+            return make_error!(
+                Status::BadRequest,
+                "Not authenticated with a session".to_owned()
+            )
+        }
+    };
+    let connector = &mut get_connector!(db);
+    json_val_or_error!(user::delete_session(connector, id).map(|_| ()))
+}
+
+/// Get the account of the user sending the request
+#[get("/me")]
+#[must_use]
+pub fn me(me: Reader) -> Json<Account> {
+    Json(me.0.user.into())
+}
+
+/// Change the password of the user sending the request
+///
+/// # Errors
+///
+/// If the current password is wrong, a 401 error is returned to the
+/// user. If the new password is shorter than
+/// [`password::MIN_LENGTH`] characters, a 400 error is returned. Any
+/// other error will be returned as a 500 HTTP error.
+#[put("/password", format = "json", data = "<input>")]
+pub fn change_password(
+    db: &State<ServerState>,
+    me: Reader,
+    input: Json<NewPassword>,
+) -> JsonResponse<Account> {
+    let user = me.0.user;
+    if !password::verify(&input.current, &user.passwordhash) {
+        return make_error!(
+            Status::Unauthorized,
+            "Invalid password".to_owned()
+        );
+    }
+    let changes = Changes {
+        passwordhash: Some(hash(&input.new)?),
+        role: None,
+    };
+    let connector = &mut get_connector!(db);
+    json_val_or_error!(
+        user::update(connector, user.id, &changes).map(Into::into)
+    )
+}
+
+/// Hash a new password, checking it is long enough
+///
+/// # Errors
+///
+/// If the password is shorter than [`password::MIN_LENGTH`]
+/// characters, a 400 error is returned. If it cannot be hashed, a 500
+/// error is returned.
+pub fn hash(new: &str) -> Result<String, status::Custom<String>> {
+    if new.chars().count() < password::MIN_LENGTH {
+        return make_error!(
+            Status::BadRequest,
+            format!(
+                "Passwords must be at least {} characters long",
+                password::MIN_LENGTH
+            )
+        );
+    }
+    password::hash(new)
+        .map_err(|e| status::Custom(Status::InternalServerError, e.to_string()))
+}
@@ -1,13 +1,19 @@
-use crate::db::author;
-use crate::db::get_connector;
-use crate::models::Author;
-use crate::server::{json_val_or_error, make_error};
-use crate::{ApiKey, Json, JsonResponse, ServerState};
+use crate::auth::{Editor, Identity, Permission};
+use crate::db::author::{self, SortKey};
+use crate::db::{book, get_connector, serializable};
+use crate::db::page::Item;
+use crate::db::search::Scored;
+use crate::db::trash::{self, Cascade};
+use crate::models::{AuditAction, Author};
+use crate::server::page::{self, PagedResponse};
+use crate::server::trash::{check_books, check_confirmation};
+use crate::server::{audit, json_val_or_error, make_error};
+use crate::{Json, JsonResponse, ServerState};
 
 use rocket::http::Status;
 use rocket::response::status;
 use rocket::serde::Deserialize;
-use rocket::State;
+use rocket::{Route, State};
 use uuid::Uuid;
 
 #[derive(Deserialize, Debug, Clone)]
@@ -33,20 +39,51 @@ impl From<UserInput> for Author {
             firstname: other.firstname,
             lastname: other.lastname,
             penname: other.penname,
+            deletedat: None,
         }
     }
 }
 
-/// List all authors in the database
+/// Check the user sending the request has `permission` on all the
+/// books of the author `id`, see `check_books`
+///
+/// # Errors
+///
+/// If the user does not have the permission on one of the books, a
+/// 403 error is returned. Any other error is returned as a 500 HTTP
+/// error.
+fn check_author_books(
+    connector: &mut diesel::PgConnection,
+    identity: &Identity,
+    id: Uuid,
+    permission: Permission,
+) -> Result<(), status::Custom<String>> {
+    match book::by_author(connector, id) {
+        Ok(books) => check_books(connector, identity, &books, permission),
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// List a page of the authors in the database
+///
+/// Authors are sorted by `lastname` by default, or by `firstname` or
+/// `penname`, and only the `fields` asked for are returned if any.
+/// See `page::Query` for the pagination parameters.
 ///
 /// # Errors
 ///
 /// In case of an error, return to the user a Json file containing the
 /// error message as well as the appropriate HTTP response.
-#[get("/")]
-pub fn list(db: &State<ServerState>) -> JsonResponse<Vec<Author>> {
+#[get("/?<query..>")]
+pub fn list(
+    db: &State<ServerState>,
+    query: page::Query,
+) -> PagedResponse<Item<Author>> {
+    let page = query.page()?;
+    let sort = query.sort(SortKey::from_name, SortKey::Lastname)?;
+    let fields = query.fields(author::COLUMNS)?;
     let connector = &mut get_connector!(db);
-    json_val_or_error!(author::list(connector))
+    page::paged(author::list(connector, &page, sort, fields.as_ref()))
 }
 
 /// Create a new author
@@ -65,7 +102,8 @@ pub fn list(db: &State<ServerState>) -> JsonResponse<Vec<Author>> {
 pub fn new(
     author: Json<UserInput>,
     db: &State<ServerState>,
-    _key: ApiKey<'_>,
+    editor: Editor,
+    route: &Route,
 ) -> JsonResponse<()> {
     let connector = &mut get_connector!(db);
     let author = author.into_inner();
@@ -75,8 +113,22 @@ pub fn new(
             format!("At least one field must be full. Received {:?}", author),
         ));
     }
-    match author::new(connector, author.into()) {
-        Ok(_) => Ok(Json(())),
+    let author: Author = author.into();
+    let result = serializable(connector, |connector| {
+        author::new(connector, author.clone())?;
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Create,
+            "author",
+            author.id,
+            None,
+            Some(&author),
+        )
+    });
+    match result {
+        Ok(()) => Ok(Json(())),
         Err(e) => {
             Err(status::Custom(Status::InternalServerError, e.to_string()))
         }
@@ -85,18 +137,48 @@ pub fn new(
 
 /// Update an existing author
 ///
+/// The user must be able to edit all the books of the author, see
+/// `Permission::EditFragments`.
+///
 /// # Errors
 ///
-/// Any error from the server will be returned to the user as a 500
-/// HTTP error.
+/// If the user cannot edit one of the books of the author, a 403
+/// error is returned. Any other error from the server will be
+/// returned to the user as a 500 HTTP error.
 #[put("/", format = "json", data = "<author>")]
 pub fn update(
     author: Json<Author>,
     db: &State<ServerState>,
+    editor: Editor,
+    route: &Route,
 ) -> JsonResponse<()> {
     let connector = &mut get_connector!(db);
-    match author::update(connector, author.into_inner()) {
-        Ok(_) => Ok(Json(())),
+    let author = author.into_inner();
+    check_author_books(
+        connector,
+        &editor.0,
+        author.id,
+        Permission::EditFragments,
+    )?;
+    let before = author::get(connector, author.id).ok();
+    let result = serializable(connector, |connector| {
+        author::update(connector, author.clone())?;
+        match &before {
+            Some(before) => audit::record(
+                connector,
+                &editor.0,
+                route,
+                AuditAction::Update,
+                "author",
+                author.id,
+                Some(before),
+                Some(&author),
+            ),
+            None => Ok(()),
+        }
+    });
+    match result {
+        Ok(()) => Ok(Json(())),
         Err(e) => {
             Err(status::Custom(Status::InternalServerError, e.to_string()))
         }
@@ -105,7 +187,9 @@ pub fn update(
 
 /// Find an author by name
 ///
-/// May not work with full names and typos.
+/// Full names and typos are tolerated, and authors are sent along
+/// with their `score`, from the most to the least similar to `name`,
+/// see `author::find`.
 ///
 /// # Errors
 ///
@@ -115,7 +199,7 @@ pub fn update(
 pub fn find(
     db: &State<ServerState>,
     name: String,
-) -> JsonResponse<Vec<Author>> {
+) -> JsonResponse<Vec<Scored<Author>>> {
     let connector = &mut get_connector!(db);
     json_val_or_error!(author::find(connector, &name))
 }
@@ -146,19 +230,102 @@ pub fn get(db: &State<ServerState>, id: Uuid) -> JsonResponse<Author> {
     }
 }
 
-/// Delete an author
+/// Count the books and fragments deleting an author would move to the
+/// trash along with them
+///
+/// When the author has books, the returned token must be sent back to
+/// `delete` by the same user within ten minutes to confirm the
+/// deletion, see `Cascade::sign`. The user must be able to delete all
+/// the books of the author, see `Permission::Delete`.
 ///
 /// # Errors
 ///
-/// Any error from the server will be returned to the user as a 500
-/// HTTP error.
-// TODO: Handle author not found
-#[delete("/<id>")]
+/// If the author does not exist, a 404 error is returned to the user.
+/// If the user cannot delete one of the books of the author, a 403
+/// error is returned. Any other error from the server will be
+/// returned to the user as a 500 HTTP error.
+#[get("/<id>/delete-preview")]
+pub fn delete_preview(
+    db: &State<ServerState>,
+    id: Uuid,
+    editor: Editor,
+) -> JsonResponse<Cascade> {
+    let connector = &mut get_connector!(db);
+    check_author_books(connector, &editor.0, id, Permission::Delete)?;
+    match trash::author_cascade(connector, id) {
+        Ok(val) => Ok(Json(val.sign(&db.confirmation, editor.0.user.id))),
+        Err(diesel::result::Error::NotFound) => {
+            make_error!(Status::NotFound, format!("Author ID {} not found", id))
+        }
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// Move an author to the trash, along with their books
+///
+/// If the author has books, the deletion must be confirmed by sending
+/// the token returned by `delete_preview` as `confirm`. The user must
+/// be able to delete all the books of the author, see
+/// `Permission::Delete`. The deletion of the author, of their books
+/// and of the fragments of these books are all recorded in the audit
+/// log.
+///
+/// # Errors
+///
+/// If the author does not exist, a 404 error is returned to the user.
+/// If the user cannot delete one of the books of the author, a 403
+/// error is returned. If the deletion is not confirmed, a 409 error
+/// is returned. Any other error from the server will be returned to
+/// the user as a 500 HTTP error.
+#[delete("/<id>?<confirm>")]
 pub fn delete(
     db: &State<ServerState>,
     id: Uuid,
-    _key: ApiKey<'_>,
+    confirm: Option<&str>,
+    editor: Editor,
+    route: &Route,
 ) -> JsonResponse<()> {
     let connector = &mut get_connector!(db);
-    json_val_or_error!(author::delete(connector, id))
+    let before = match author::get(connector, id) {
+        Ok(val) => val,
+        Err(diesel::result::Error::NotFound) => {
+            return make_error!(
+                Status::NotFound,
+                format!("Author ID {} not found", id)
+            )
+        }
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    check_author_books(connector, &editor.0, id, Permission::Delete)?;
+    let cascade = match trash::author_cascade(connector, id) {
+        Ok(val) => val,
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    check_confirmation(
+        &db.confirmation,
+        &editor.0,
+        "author",
+        id,
+        &format!("/author/{}/delete-preview", id),
+        &cascade,
+        confirm,
+    )?;
+    json_val_or_error!(serializable(connector, |connector| {
+        let (books, fragments) = author::delete(connector, id)?;
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Delete,
+            "author",
+            id,
+            Some(&before),
+            None,
+        )?;
+        audit::record_cascade(connector, &editor.0, route, &books, &fragments)
+    }))
 }
@@ -1,13 +1,23 @@
-use crate::db::book::{self, SearchQuery};
-use crate::db::get_connector;
-use crate::models::{Book, BookType};
-use crate::server::{json_val_or_error, make_error};
-use crate::{ApiKey, Json, JsonResponse, ServerState};
+use crate::auth::{self, Editor, Identity, Permission, Reader};
+use crate::db::book::{self, SearchQuery, SortKey, WithCover};
+use crate::db::{get_connector, serializable};
+use crate::db::page::Item;
+use crate::db::published::{self, Version};
+use crate::db::search::{self, Scored};
+use crate::db::trash::{self, Cascade};
+use crate::models::{
+    default_language, AuditAction, Book, BookStatus, BookType,
+};
+use crate::server::page::{self, PagedResponse};
+use crate::server::trash::check_confirmation;
+use crate::server::{audit, json_val_or_error, make_error};
+use crate::{Json, JsonResponse, ServerState};
 
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
 use rocket::http::Status;
 use rocket::response::status;
 use rocket::serde::Deserialize;
-use rocket::State;
+use rocket::{Route, State};
 use tracing::info;
 use uuid::Uuid;
 
@@ -24,6 +34,23 @@ pub struct UserInput {
     pub genre: Option<Vec<Option<String>>>,
     pub synopsis: Option<String>,
     pub booktype: BookType,
+    pub coverasset: Option<Uuid>,
+    #[serde(default = "default_language")]
+    pub language: String,
+}
+
+/// Data the user can send to give a book to another user
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewOwner {
+    pub user: Uuid,
+}
+
+/// Data the user can send to move a book in the publishing workflow
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewStatus {
+    pub status: BookStatus,
 }
 
 impl From<UserInput> for Book {
@@ -39,39 +66,229 @@ impl From<UserInput> for Book {
             genre: other.genre,
             synopsis: other.synopsis,
             booktype: other.booktype,
+            coverasset: other.coverasset,
+            owner: None,
+            status: BookStatus::Draft,
+            deletedat: None,
+            language: other.language,
+        }
+    }
+}
+
+/// Check the language of `book` is a text search configuration known
+/// to the database, see `search::language_exists`
+///
+/// # Errors
+///
+/// If the language is unknown, a 422 error is returned. Any other
+/// error is returned as a 500 HTTP error.
+pub fn check_language(
+    connector: &mut diesel::PgConnection,
+    book: &Book,
+) -> Result<(), status::Custom<String>> {
+    match search::language_exists(connector, &book.language) {
+        Ok(true) => Ok(()),
+        Ok(false) => make_error!(
+            Status::UnprocessableEntity,
+            format!("Unknown language {}", book.language)
+        ),
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// Retrieve a book, checking the user sending the request has
+/// `permission` on it, see `auth::can`
+///
+/// # Errors
+///
+/// If the book does not exist, a 404 error is returned. If the user
+/// does not have the permission, a 403 error is returned. Any other
+/// error is returned as a 500 HTTP error.
+pub fn authorize(
+    connector: &mut diesel::PgConnection,
+    identity: &Identity,
+    id: Uuid,
+    permission: Permission,
+) -> Result<Book, status::Custom<String>> {
+    use diesel::result::Error::NotFound;
+    let found = match book::get(connector, id) {
+        Ok(val) => val,
+        Err(NotFound) => {
+            return make_error!(
+                Status::NotFound,
+                format!("Book ID {} not found", id)
+            )
+        }
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
         }
+    };
+    match auth::can(connector, &identity.user, &found, permission) {
+        Ok(true) => Ok(found),
+        Ok(false) => make_error!(
+            Status::Forbidden,
+            format!("Missing permission {:?} on book {}", permission, id)
+        ),
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
     }
 }
 
-/// List all books in the database
+/// Retrieve a book the user sending the request can read, along
+/// with the version of the book they read
+///
+/// Everyone reads the published version of published books, whose
+/// metadata are then the ones of their last publication, see
+/// `published::book`. Users who can view the drafts of a book, see
+/// `Permission::ViewDraft`, read its draft if the book is not
+/// published or if they ask for its `draft`.
 ///
 /// # Errors
 ///
-/// If an internal error happens, return a 500 error to the user.
-/// Otherwise, send an array of books in Json format.
-#[get("/")]
-pub fn list(db: &State<ServerState>) -> JsonResponse<Vec<Book>> {
+/// If the book does not exist or cannot be read by the user, a 404
+/// error is returned. If the user asks for the draft of a published
+/// book they cannot view the drafts of, a 403 error is returned. Any
+/// other error is returned as a 500 HTTP error.
+pub fn readable(
+    connector: &mut diesel::PgConnection,
+    identity: Option<&Identity>,
+    id: Uuid,
+    draft: bool,
+) -> Result<(Book, Version), status::Custom<String>> {
+    use diesel::result::Error::NotFound;
+    let found = match book::get(connector, id) {
+        Ok(val) => val,
+        Err(NotFound) => {
+            return make_error!(
+                Status::NotFound,
+                format!("Book ID {} not found", id)
+            )
+        }
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    let published = found.status == BookStatus::Published;
+    if published && !draft {
+        return match published::book(connector, found) {
+            Ok(val) => Ok((val, Version::Published)),
+            Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+        };
+    }
+    let allowed = match identity {
+        Some(identity) => {
+            match auth::can(
+                connector,
+                &identity.user,
+                &found,
+                Permission::ViewDraft,
+            ) {
+                Ok(allowed) => allowed,
+                Err(e) => {
+                    return make_error!(
+                        Status::InternalServerError,
+                        e.to_string()
+                    )
+                }
+            }
+        }
+        None => false,
+    };
+    match (allowed, published) {
+        (true, _) => Ok((found, Version::Draft)),
+        (false, true) => make_error!(
+            Status::Forbidden,
+            format!("Missing permission ViewDraft on book {}", id)
+        ),
+        (false, false) => {
+            make_error!(Status::NotFound, format!("Book ID {} not found", id))
+        }
+    }
+}
+
+/// List a page of the books the user can read, see
+/// `auth::readable_by`
+///
+/// Books are sorted by `title` by default, or by `published`, and
+/// only the `fields` asked for are returned if any. Otherwise, books
+/// are sent along with their cover if it is a media asset. See
+/// `page::Query` for the pagination parameters.
+///
+/// # Errors
+///
+/// If a parameter is invalid, a 400 error is returned. If an internal
+/// error happens, return a 500 error to the user.
+#[get("/?<query..>")]
+pub fn list(
+    db: &State<ServerState>,
+    query: page::Query,
+    reader: Option<Reader>,
+) -> PagedResponse<Item<WithCover>> {
     info!("Listing books");
+    let page = query.page()?;
+    let sort = query.sort(SortKey::from_name, SortKey::Title)?;
+    let fields = query.fields(book::COLUMNS)?;
     let connector = &mut get_connector!(db);
-    json_val_or_error!(book::list(connector))
+    let user = reader.as_ref().map(|reader| &reader.0.user);
+    let readable = match auth::readable_by(connector, user) {
+        Ok(readable) => readable,
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    page::paged(book::list(
+        connector,
+        &page,
+        sort,
+        &readable,
+        fields.as_ref(),
+    ))
 }
 
 /// Create a new book.
 ///
-/// Create a new book based on `book` received as Json data.
+/// Create a new book based on `book` received as Json data. The user
+/// creating the book owns it, and the book starts as a draft. Its
+/// `language` is the text search configuration its content is
+/// indexed with, `simple` by default.
 ///
 /// # Errors
 ///
-/// Any server error is returned to the user as a 500 HTTP error.
+/// If the author of the book does not exist or is in the trash, a 404
+/// error is returned. If the language of the book is unknown, a 422
+/// error is returned. Any other server error is returned to the user
+/// as a 500 HTTP error.
 #[post("/", format = "json", data = "<book>")]
 pub fn new(
     book: Json<UserInput>,
     db: &State<ServerState>,
-    _key: ApiKey<'_>,
+    editor: Editor,
+    route: &Route,
 ) -> JsonResponse<()> {
     let connector = &mut get_connector!(db);
-    match book::new(connector, book.into_inner().into()) {
-        Ok(_) => Ok(Json(())),
+    let book = Book {
+        owner: Some(editor.0.user.id),
+        ..book.into_inner().into()
+    };
+    check_language(connector, &book)?;
+    let result = serializable(connector, |connector| {
+        book::new(connector, book.clone())?;
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Create,
+            "book",
+            book.id,
+            None,
+            Some(&book),
+        )
+    });
+    match result {
+        Ok(()) => Ok(Json(())),
+        Err(diesel::result::Error::NotFound) => make_error!(
+            Status::NotFound,
+            format!("Author ID {} not found", book.author)
+        ),
         Err(e) => {
             Err(status::Custom(Status::InternalServerError, e.to_string()))
         }
@@ -80,16 +297,53 @@ pub fn new(
 
 /// Update a book
 ///
+/// The owner and the status of the book are left untouched, see
+/// `set_owner` and `set_status`. The metadata of a published book
+/// are updated in its draft, which readers see once it is published
+/// again.
+///
 /// # Errors
 ///
-/// Any error from the server will be returned to the user as a 500
-/// HTTP error.
+/// If the book or its author does not exist, or if the author is in
+/// the trash, a 404 error is returned to the user. If the user cannot
+/// edit the book, a 403 error is returned, and if its language is
+/// unknown, a 422 error. Any other
+/// error from the server will be returned as a 500 HTTP error.
 #[put("/", format = "json", data = "<book>")]
-pub fn update(book: Json<Book>, db: &State<ServerState>) -> JsonResponse<()> {
+pub fn update(
+    book: Json<Book>,
+    db: &State<ServerState>,
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<()> {
     let connector = &mut get_connector!(db);
     let book = book.into_inner();
     let id = book.id;
-    match book::update(connector, book) {
+    let current =
+        authorize(connector, &editor.0, id, Permission::EditFragments)?;
+    let book = Book {
+        owner: current.owner,
+        status: current.status,
+        ..book
+    };
+    check_language(connector, &book)?;
+    let result = serializable(connector, |connector| {
+        let updated = book::update(connector, book.clone())?;
+        if updated == 1 {
+            audit::record(
+                connector,
+                &editor.0,
+                route,
+                AuditAction::Update,
+                "book",
+                id,
+                Some(&current),
+                Some(&book),
+            )?;
+        }
+        Ok(updated)
+    });
+    match result {
         Ok(val) => {
             if val == 1 {
                 Ok(Json(()))
@@ -105,7 +359,10 @@ pub fn update(book: Json<Book>, db: &State<ServerState>) -> JsonResponse<()> {
             match e {
                 NotFound => make_error!(
                     Status::NotFound,
-                    format!("Book ID {} not found", id)
+                    format!(
+                        "Book ID {} or author ID {} not found",
+                        id, book.author
+                    )
                 ),
                 other => {
                     make_error!(Status::InternalServerError, other.to_string())
@@ -117,72 +374,258 @@ pub fn update(book: Json<Book>, db: &State<ServerState>) -> JsonResponse<()> {
 
 /// Find books matching the title `name`
 ///
-/// Return in a vector all books whose title contain `name`. Typos are
-/// not implemented as for now.
+/// Return in a vector all books whose title contain `name` or are
+/// similar enough to it, despite typos, and which the user can read.
+/// Books are sent along with their `score`, from the most to the
+/// least similar to `name`, see `book::find`.
 ///
 /// # Errors
 ///
 /// Any error from the server will be returned to the user as a 500
 /// HTTP error.
 #[get("/find?<name>")]
-pub fn find(db: &State<ServerState>, name: String) -> JsonResponse<Vec<Book>> {
+pub fn find(
+    db: &State<ServerState>,
+    name: String,
+    reader: Option<Reader>,
+) -> JsonResponse<Vec<Scored<WithCover>>> {
     let connector = &mut get_connector!(db);
-    json_val_or_error!(book::find(connector, &name))
+    let user = reader.as_ref().map(|reader| &reader.0.user);
+    let found = auth::readable_by(connector, user).and_then(|readable| {
+        let (books, scores): (Vec<Book>, Vec<f32>) =
+            book::find(connector, &name)?
+                .into_iter()
+                .filter(|found| readable.allows(&found.item))
+                .map(|found| (found.item, found.score))
+                .unzip();
+        Ok(book::with_covers(connector, books)?
+            .into_iter()
+            .zip(scores)
+            .map(|(item, score)| Scored { item, score })
+            .collect())
+    });
+    json_val_or_error!(found)
 }
 
 /// Perform an advanced search query for books.
 ///
 /// Search for books matching a certain title, type, or genres. See
-/// `SearchQuery` for more details.
+/// `SearchQuery` for more details. Only the books the user can read
+/// are returned.
 #[get("/find", format = "json", data = "<search>")]
 pub fn advanced_find(
     db: &State<ServerState>,
     search: Json<SearchQuery>,
-) -> JsonResponse<Vec<Book>> {
+    reader: Option<Reader>,
+) -> JsonResponse<Vec<WithCover>> {
     let connector = &mut get_connector!(db);
-    json_val_or_error!(book::advanced_find(connector, search.into_inner()))
+    let user = reader.as_ref().map(|reader| &reader.0.user);
+    json_val_or_error!(book::advanced_find(connector, search.into_inner())
+        .and_then(|books| auth::readable(connector, user, books))
+        .and_then(|books| book::with_covers(connector, books)))
 }
 
-/// Get a book by its ID
+/// Get a book by its ID, along with its cover if it is a media asset
+///
+/// Books which are not published can only be read by the users who
+/// can view their drafts. Published books are returned as they were
+/// last published, unless `draft` is true, see `readable`.
 ///
 /// # Errors
 ///
-/// Any error from the server will be returned to the user as a 500
-/// HTTP error.
-#[get("/<id>")]
-pub fn get(db: &State<ServerState>, id: Uuid) -> JsonResponse<Book> {
+/// If the book does not exist or cannot be read by the user, a 404
+/// error is returned. If they ask for a draft they cannot view, a 403
+/// error is returned. Any other error from the server will be
+/// returned to the user as a 500 HTTP error.
+#[get("/<id>?<draft>")]
+pub fn get(
+    db: &State<ServerState>,
+    id: Uuid,
+    draft: Option<bool>,
+    reader: Option<Reader>,
+) -> JsonResponse<WithCover> {
     info!("Retrieving book {}", id);
     let connector = &mut get_connector!(db);
-    match book::get(connector, id) {
-        Ok(val) => Ok(Json(val)),
+    let identity = reader.as_ref().map(|reader| &reader.0);
+    let (found, _) =
+        readable(connector, identity, id, draft.unwrap_or(false))?;
+    json_val_or_error!(book::with_cover(connector, found))
+}
+
+/// Count the fragments deleting a book would move to the trash along
+/// with it
+///
+/// When the book has fragments, the returned token must be sent back
+/// to `delete` by the same user within ten minutes to confirm the
+/// deletion, see `Cascade::sign`.
+///
+/// # Errors
+///
+/// If the book does not exist, a 404 error is returned to the user.
+/// If the user cannot delete the book, a 403 error is returned. Any
+/// other error from the server will be returned as a 500 HTTP error.
+#[get("/<id>/delete-preview")]
+pub fn delete_preview(
+    db: &State<ServerState>,
+    id: Uuid,
+    editor: Editor,
+) -> JsonResponse<Cascade> {
+    let connector = &mut get_connector!(db);
+    authorize(connector, &editor.0, id, Permission::Delete)?;
+    json_val_or_error!(trash::book_cascade(connector, id)
+        .map(|cascade| cascade.sign(&db.confirmation, editor.0.user.id)))
+}
+
+/// Move the book with a set ID to the trash
+///
+/// If the book has fragments, the deletion must be confirmed by
+/// sending the token returned by `delete_preview` as `confirm`. The
+/// deletion of the book and of its fragments are all recorded in the
+/// audit log.
+///
+/// # Errors
+///
+/// If the book does not exist, a 404 error is returned to the user.
+/// If the user cannot delete the book, a 403 error is returned. If
+/// the deletion is not confirmed, a 409 error is returned. Any other
+/// error from the server will be returned as a 500 HTTP error.
+#[delete("/<id>?<confirm>")]
+pub fn delete(
+    db: &State<ServerState>,
+    id: Uuid,
+    confirm: Option<&str>,
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<()> {
+    let connector = &mut get_connector!(db);
+    let before = authorize(connector, &editor.0, id, Permission::Delete)?;
+    let cascade = match trash::book_cascade(connector, id) {
+        Ok(val) => val,
         Err(e) => {
-            use diesel::result::Error::NotFound;
-            match e {
-                NotFound => make_error!(
-                    Status::NotFound,
-                    format!("Book ID {} not found", id)
-                ),
-                other => {
-                    make_error!(Status::InternalServerError, other.to_string())
-                }
-            }
+            return make_error!(Status::InternalServerError, e.to_string())
         }
+    };
+    check_confirmation(
+        &db.confirmation,
+        &editor.0,
+        "book",
+        id,
+        &format!("/book/{}/delete-preview", id),
+        &cascade,
+        confirm,
+    )?;
+    json_val_or_error!(serializable(connector, |connector| {
+        let fragments = book::delete(connector, id)?;
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Delete,
+            "book",
+            id,
+            Some(&before),
+            None,
+        )?;
+        audit::record_cascade(connector, &editor.0, route, &[], &fragments)
+    }))
+}
+
+/// Give a book to another user
+///
+/// Only the owner of the book and administrators can do so. The
+/// updated book is returned to the user.
+///
+/// # Errors
+///
+/// If the book or the new owner does not exist, a 404 error is
+/// returned to the user. If the user does not own the book, a 403
+/// error is returned. Any other error from the server will be
+/// returned as a 500 HTTP error.
+#[put("/<id>/owner", format = "json", data = "<owner>")]
+pub fn set_owner(
+    db: &State<ServerState>,
+    id: Uuid,
+    owner: Json<NewOwner>,
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<Book> {
+    let connector = &mut get_connector!(db);
+    let before = authorize(connector, &editor.0, id, Permission::Manage)?;
+    info!("Giving book {} to user {}", id, owner.user);
+    let result = serializable(connector, |connector| {
+        let after = book::set_owner(connector, id, owner.user)?;
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Update,
+            "book",
+            id,
+            Some(&before),
+            Some(&after),
+        )?;
+        Ok(after)
+    });
+    match result {
+        Ok(val) => Ok(Json(val)),
+        Err(DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => {
+            make_error!(
+                Status::NotFound,
+                format!("User ID {} not found", owner.user)
+            )
+        }
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
     }
 }
 
-/// Delete the book with a set ID
+/// Move a book to another stage of the publishing workflow
+///
+/// Publishing a book, even if it is already published, replaces the
+/// version of its metadata, chapters and fragments readers see by
+/// their current draft. Until then, edits of the book are only
+/// visible to the users who can view its drafts. Publishing or
+/// archiving a book requires the `Publish` permission, while moving it
+/// back to a draft or in review requires the `EditFragments`
+/// permission. The updated book is returned to the user.
 ///
 /// # Errors
 ///
-/// Any error from the server will be returned to the user as a 500
-/// HTTP error.
-// TODO: Handle book not found
-#[delete("/<id>")]
-pub fn delete(
+/// If the book does not exist, a 404 error is returned to the user.
+/// If the user does not have the required permission, a 403 error is
+/// returned. Any other error from the server will be returned as a
+/// 500 HTTP error.
+#[put("/<id>/status", format = "json", data = "<status>")]
+pub fn set_status(
     db: &State<ServerState>,
     id: Uuid,
-    _key: ApiKey<'_>,
-) -> JsonResponse<()> {
+    status: Json<NewStatus>,
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<Book> {
     let connector = &mut get_connector!(db);
-    json_val_or_error!(book::delete(connector, id))
+    let status = status.status;
+    let permission = match status {
+        BookStatus::Published | BookStatus::Archived => Permission::Publish,
+        BookStatus::Draft | BookStatus::InReview => Permission::EditFragments,
+    };
+    let before = authorize(connector, &editor.0, id, permission)?;
+    info!("Moving book {} to {:?}", id, status);
+    json_val_or_error!(serializable(connector, |connector| {
+        let after = match status {
+            BookStatus::Published => published::publish(connector, id)
+                .and_then(|_| book::get(connector, id))?,
+            other => book::set_status(connector, id, other)?,
+        };
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Update,
+            "book",
+            id,
+            Some(&before),
+            Some(&after),
+        )?;
+        Ok(after)
+    }))
 }
@@ -0,0 +1,329 @@
+use crate::auth::{Editor, Permission, Reader};
+use crate::db::ambience::{self, Resolved};
+use crate::db::chapter;
+use crate::db::fragment::Simple;
+use crate::db::published::{self, Version};
+use crate::db::trash::{self, Cascade};
+use crate::db::{get_connector, serializable};
+use crate::models::{AuditAction, Chapter};
+use crate::server::book::{authorize, readable};
+use crate::server::trash::check_confirmation;
+use crate::server::{audit, json_val_or_error, make_error};
+use crate::{Json, JsonResponse, ServerState};
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::uuid::Uuid;
+use rocket::serde::Deserialize;
+use rocket::{Route, State};
+
+/// Data the user can send to create or update a chapter
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UserInput {
+    pub number: i32,
+    pub title: Option<String>,
+    pub epigraph: Option<String>,
+    pub cover: Option<String>,
+    pub bgsoundsource: Option<String>,
+}
+
+impl UserInput {
+    fn into_chapter(self, id: Uuid, book: Uuid) -> Chapter {
+        Chapter {
+            id,
+            book,
+            number: self.number,
+            title: self.title,
+            epigraph: self.epigraph,
+            cover: self.cover,
+            bgsoundsource: self.bgsoundsource,
+            deletedat: None,
+        }
+    }
+}
+
+/// Retrieve the `version` of a chapter, making sure it belongs to the
+/// book `book_id`
+fn get_chapter(
+    connector: &mut diesel::PgConnection,
+    book_id: Uuid,
+    id: Uuid,
+    version: Version,
+) -> Result<Chapter, status::Custom<String>> {
+    use diesel::result::Error::NotFound;
+    let found = match version {
+        Version::Draft => chapter::get(connector, id),
+        Version::Published => published::chapter(connector, id),
+    };
+    match found {
+        Ok(val) if val.book == book_id => Ok(val),
+        Ok(_) | Err(NotFound) => make_error!(
+            Status::NotFound,
+            format!("Chapter ID {} not found in book {}", id, book_id)
+        ),
+        Err(other) => {
+            make_error!(Status::InternalServerError, other.to_string())
+        }
+    }
+}
+
+/// List all chapters of a book
+///
+/// The chapters of published books are the ones of their last
+/// publication, unless `draft` is true, see `readable`.
+///
+/// # Errors
+///
+/// If the book does not exist or cannot be read by the user, see
+/// `readable`, a 404 error is returned to the user. If they ask for a
+/// draft they cannot view, a 403 error is returned. Any other error
+/// from the server will be returned as a 500 HTTP error.
+#[get("/<book_id>/chapters?<draft>")]
+pub fn list(
+    db: &State<ServerState>,
+    book_id: Uuid,
+    draft: Option<bool>,
+    reader: Option<Reader>,
+) -> JsonResponse<Vec<Chapter>> {
+    let connector = &mut get_connector!(db);
+    let identity = reader.as_ref().map(|reader| &reader.0);
+    let (_, version) =
+        readable(connector, identity, book_id, draft.unwrap_or(false))?;
+    json_val_or_error!(match version {
+        Version::Draft => chapter::list(connector, book_id),
+        Version::Published => published::list_chapters(connector, book_id),
+    })
+}
+
+/// Create a new chapter in a book
+///
+/// If a chapter already exists with the same number, shift it and all
+/// subsequent chapters by one to insert the new chapter. If the
+/// number exceeds the amount of chapters, the chapter is appended at
+/// the end of the book. The created chapter is returned to the user.
+/// Chapters are created in the draft of the book, see
+/// `published::publish`.
+///
+/// # Errors
+///
+/// If the book does not exist, a 404 error is returned to the user.
+/// If the user cannot edit the book, a 403 error is returned. Any
+/// other error from the server will be returned as a 500 HTTP error.
+#[post("/<book_id>/chapters", format = "json", data = "<chapter>")]
+pub fn new(
+    db: &State<ServerState>,
+    book_id: Uuid,
+    chapter: Json<UserInput>,
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<Chapter> {
+    let connector = &mut get_connector!(db);
+    authorize(connector, &editor.0, book_id, Permission::EditFragments)?;
+    let chapter = chapter.into_inner().into_chapter(Uuid::new_v4(), book_id);
+    json_val_or_error!(serializable(connector, |connector| {
+        let created = chapter::new(connector, chapter.clone())?;
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Create,
+            "chapter",
+            created.id,
+            None,
+            Some(&created),
+        )?;
+        Ok(created)
+    }))
+}
+
+/// Get a chapter by ID
+///
+/// The chapters of published books are the ones of their last
+/// publication, unless `draft` is true, see `readable`.
+///
+/// # Errors
+///
+/// If the chapter does not exist in this version or does not belong
+/// to the book, or if the book cannot be read by the user, a 404
+/// error is returned to the user. If they ask for a draft they cannot
+/// view, a 403 error is returned. Any other error will be returned as
+/// a 500 HTTP error.
+#[get("/<book_id>/chapters/<id>?<draft>")]
+pub fn get(
+    db: &State<ServerState>,
+    book_id: Uuid,
+    id: Uuid,
+    draft: Option<bool>,
+    reader: Option<Reader>,
+) -> JsonResponse<Chapter> {
+    let connector = &mut get_connector!(db);
+    let identity = reader.as_ref().map(|reader| &reader.0);
+    let (_, version) =
+        readable(connector, identity, book_id, draft.unwrap_or(false))?;
+    get_chapter(connector, book_id, id, version).map(Json)
+}
+
+/// Update a chapter
+///
+/// If the chapter’s number changes, shift the other chapters of the
+/// book to keep its numbering continuous. Only the draft of the
+/// chapter is updated, see `published::publish`.
+///
+/// # Errors
+///
+/// If the chapter does not exist or does not belong to the book, a
+/// 404 error is returned to the user. If the user cannot edit the
+/// book, a 403 error is returned. Any other error will be returned as
+/// a 500 HTTP error.
+#[put("/<book_id>/chapters/<id>", format = "json", data = "<chapter>")]
+pub fn update(
+    db: &State<ServerState>,
+    book_id: Uuid,
+    id: Uuid,
+    chapter: Json<UserInput>,
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<Chapter> {
+    let connector = &mut get_connector!(db);
+    authorize(connector, &editor.0, book_id, Permission::EditFragments)?;
+    let before = get_chapter(connector, book_id, id, Version::Draft)?;
+    let chapter = chapter.into_inner().into_chapter(id, book_id);
+    json_val_or_error!(serializable(connector, |connector| {
+        let updated = chapter::update(connector, chapter.clone())?;
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Update,
+            "chapter",
+            id,
+            Some(&before),
+            Some(&updated),
+        )?;
+        Ok(updated)
+    }))
+}
+
+/// Count the fragments deleting a chapter would move to the trash
+/// along with it
+///
+/// When the chapter has fragments, the returned token must be sent
+/// back to `delete` by the same user within ten minutes to confirm
+/// the deletion, see `Cascade::sign`.
+///
+/// # Errors
+///
+/// If the chapter does not exist or does not belong to the book, a
+/// 404 error is returned to the user. If the user cannot edit the
+/// book, a 403 error is returned. Any other error from the server
+/// will be returned as a 500 HTTP error.
+#[get("/<book_id>/chapters/<id>/delete-preview")]
+pub fn delete_preview(
+    db: &State<ServerState>,
+    book_id: Uuid,
+    id: Uuid,
+    editor: Editor,
+) -> JsonResponse<Cascade> {
+    let connector = &mut get_connector!(db);
+    authorize(connector, &editor.0, book_id, Permission::EditFragments)?;
+    get_chapter(connector, book_id, id, Version::Draft)?;
+    json_val_or_error!(trash::chapter_cascade(connector, id)
+        .map(|cascade| cascade.sign(&db.confirmation, editor.0.user.id)))
+}
+
+/// Move a chapter and all of its fragments to the trash
+///
+/// They can be brought back together from the trash, see
+/// `trash::restore`. If the chapter has fragments, the deletion must
+/// be confirmed by sending the token returned by `delete_preview` as
+/// `confirm`. The deletion of the chapter and of its fragments are
+/// all recorded in the audit log. Only the draft of the book loses
+/// the chapter, which readers of a published book keep reading until
+/// it is published again, see `published::publish`.
+///
+/// # Errors
+///
+/// If the chapter does not exist or does not belong to the book, a
+/// 404 error is returned to the user. If the user cannot edit the
+/// book, a 403 error is returned, and if the deletion is not
+/// confirmed, a 409 error. Any other error will be returned as a 500
+/// HTTP error.
+#[delete("/<book_id>/chapters/<id>?<confirm>")]
+pub fn delete(
+    db: &State<ServerState>,
+    book_id: Uuid,
+    id: Uuid,
+    confirm: Option<&str>,
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<()> {
+    let connector = &mut get_connector!(db);
+    authorize(connector, &editor.0, book_id, Permission::EditFragments)?;
+    let before = get_chapter(connector, book_id, id, Version::Draft)?;
+    let cascade = match trash::chapter_cascade(connector, id) {
+        Ok(val) => val,
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    check_confirmation(
+        &db.confirmation,
+        &editor.0,
+        "chapter",
+        id,
+        &format!("/book/{}/chapters/{}/delete-preview", book_id, id),
+        &cascade,
+        confirm,
+    )?;
+    json_val_or_error!(serializable(connector, |connector| {
+        let fragments = chapter::delete(connector, id)?;
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Delete,
+            "chapter",
+            id,
+            Some(&before),
+            None,
+        )?;
+        audit::record_cascade(connector, &editor.0, route, &[], &fragments)
+    }))
+}
+
+/// Get all fragments of a chapter
+///
+/// Returns an array of simple fragments sorted by rank, see `Simple`.
+/// If `resolve` is true, the effective image and sound of each
+/// fragment, resolved from the preceding fragments, are returned as
+/// well. The fragments of the draft of the book are returned if
+/// `draft` is true, see `fragment::list`.
+///
+/// # Errors
+///
+/// If the chapter does not exist or does not belong to the book, or
+/// if the book cannot be read by the user, a 404 error is returned to
+/// the user. If they ask for a draft they cannot view, a 403 error is
+/// returned. Any other error will be returned as a 500 HTTP error.
+#[get("/<book_id>/chapters/<id>/fragments?<resolve>&<draft>")]
+pub fn fragments(
+    db: &State<ServerState>,
+    book_id: Uuid,
+    id: Uuid,
+    resolve: Option<bool>,
+    draft: Option<bool>,
+    reader: Option<Reader>,
+) -> JsonResponse<Vec<Resolved<Simple>>> {
+    let connector = &mut get_connector!(db);
+    let identity = reader.as_ref().map(|reader| &reader.0);
+    let (_, version) =
+        readable(connector, identity, book_id, draft.unwrap_or(false))?;
+    let chapter = get_chapter(connector, book_id, id, version)?;
+    json_val_or_error!(ambience::list_chapter(
+        connector,
+        &chapter,
+        version,
+        resolve.unwrap_or(false)
+    ))
+}
@@ -0,0 +1,132 @@
+use crate::auth::{Editor, Permission};
+use crate::db::{collaborator, get_connector};
+use crate::models::Collaborator;
+use crate::server::book::authorize;
+use crate::server::{json_val_or_error, make_error};
+use crate::{Json, JsonResponse, ServerState};
+
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::uuid::Uuid;
+use rocket::serde::Deserialize;
+use rocket::State;
+use tracing::info;
+
+/// Permissions the user can grant to a collaborator of a book
+///
+/// Permissions left out are not granted.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct Permissions {
+    pub viewdraft: bool,
+    pub editfragments: bool,
+    pub publish: bool,
+    pub deletebook: bool,
+}
+
+/// List the collaborators of a book along with their permissions
+///
+/// Only the owner of the book and administrators can do so.
+///
+/// # Errors
+///
+/// If the book does not exist, a 404 error is returned to the user.
+/// If the user does not own the book, a 403 error is returned. Any
+/// other error from the server will be returned as a 500 HTTP error.
+#[get("/<book_id>/collaborators")]
+pub fn list(
+    db: &State<ServerState>,
+    book_id: Uuid,
+    editor: Editor,
+) -> JsonResponse<Vec<Collaborator>> {
+    let connector = &mut get_connector!(db);
+    authorize(connector, &editor.0, book_id, Permission::Manage)?;
+    json_val_or_error!(collaborator::list(connector, book_id))
+}
+
+/// Grant permissions on a book to the user `user_id`
+///
+/// If the user already collaborates on the book, their permissions
+/// are replaced. Only the owner of the book and administrators can do
+/// so. The collaborator is returned to the user.
+///
+/// # Errors
+///
+/// If the book or the collaborating user does not exist, a 404 error
+/// is returned to the user. If the user does not own the book, a 403
+/// error is returned. Any other error from the server will be
+/// returned as a 500 HTTP error.
+#[put(
+    "/<book_id>/collaborators/<user_id>",
+    format = "json",
+    data = "<permissions>"
+)]
+pub fn set(
+    db: &State<ServerState>,
+    book_id: Uuid,
+    user_id: Uuid,
+    permissions: Json<Permissions>,
+    editor: Editor,
+) -> JsonResponse<Collaborator> {
+    let connector = &mut get_connector!(db);
+    authorize(connector, &editor.0, book_id, Permission::Manage)?;
+    let permissions = permissions.into_inner();
+    let collaborator = Collaborator {
+        id: Uuid::new_v4(),
+        book: book_id,
+        member: user_id,
+        viewdraft: permissions.viewdraft,
+        editfragments: permissions.editfragments,
+        publish: permissions.publish,
+        deletebook: permissions.deletebook,
+    };
+    info!(
+        "Setting permissions of user {} on book {}",
+        user_id, book_id
+    );
+    match collaborator::set(connector, &collaborator) {
+        Ok(val) => Ok(Json(val)),
+        Err(DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => {
+            make_error!(
+                Status::NotFound,
+                format!("User ID {} not found", user_id)
+            )
+        }
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// Remove the user `user_id` from the collaborators of a book
+///
+/// Only the owner of the book and administrators can do so.
+///
+/// # Errors
+///
+/// If the book does not exist or the user does not collaborate on
+/// it, a 404 error is returned to the user. If the user does not own
+/// the book, a 403 error is returned. Any other error from the server
+/// will be returned as a 500 HTTP error.
+#[delete("/<book_id>/collaborators/<user_id>")]
+pub fn delete(
+    db: &State<ServerState>,
+    book_id: Uuid,
+    user_id: Uuid,
+    editor: Editor,
+) -> JsonResponse<()> {
+    let connector = &mut get_connector!(db);
+    authorize(connector, &editor.0, book_id, Permission::Manage)?;
+    info!(
+        "Removing user {} from the collaborators of {}",
+        user_id, book_id
+    );
+    match collaborator::delete(connector, book_id, user_id) {
+        Ok(0) => make_error!(
+            Status::NotFound,
+            format!("User ID {} does not collaborate on {}", user_id, book_id)
+        ),
+        Ok(_) => Ok(Json(())),
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use crate::auth::{Identity, Reader};
+use crate::db::get_connector;
+use crate::db::published::{self, Version};
+use crate::db::{author, chapter, fragment, media};
+use crate::export;
+use crate::export::bundle::{Bundle, VERSION};
+use crate::export::epub::{Package, Resource};
+use crate::models::{
+    Author, Book, Bookfragment, Chapter, ImageType, SoundType,
+};
+use crate::server::book::readable;
+use crate::server::make_error;
+use crate::{Json, JsonResponse, ServerState};
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::status;
+use rocket::serde::uuid::Uuid;
+use rocket::tokio::io::AsyncReadExt;
+use rocket::State;
+use tracing::{info, warn};
+
+/// A file sent to the user as an attachment
+#[derive(Responder)]
+pub struct Download {
+    data: Vec<u8>,
+    content_type: ContentType,
+    disposition: Header<'static>,
+}
+
+impl Download {
+    /// Create an attachment named `filename`
+    #[must_use]
+    pub fn new(
+        data: Vec<u8>,
+        content_type: ContentType,
+        filename: &str,
+    ) -> Self {
+        let filename = filename.replace(['"', '\\', '/'], "_");
+        Self {
+            data,
+            content_type,
+            disposition: Header::new(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        }
+    }
+}
+
+pub type DownloadResponse = Result<Download, status::Custom<String>>;
+
+/// Read a media asset so it can be embedded in an exported book
+///
+/// Return `None` if the asset could not be read or if its content
+/// type is not of the `kind` expected, such as `image` or `audio`.
+async fn read_asset(
+    db: &ServerState,
+    id: Uuid,
+    kind: &str,
+) -> Option<Resource> {
+    let asset = match db.pool.get() {
+        Ok(mut connector) => media::get(&mut connector, id).ok()?,
+        Err(e) => {
+            warn!("Could not read asset {}: {}", id, e);
+            return None;
+        }
+    };
+    if !asset.contenttype.starts_with(&format!("{}/", kind)) {
+        warn!(
+            "Ignoring asset {}: not {} content ({})",
+            id, kind, asset.contenttype
+        );
+        return None;
+    }
+    let length = u64::try_from(asset.size).unwrap_or_default();
+    let mut data = Vec::new();
+    let read = match db.storage.read(id, 0, length).await {
+        Ok(mut stream) => stream.read_to_end(&mut data).await,
+        Err(e) => Err(e),
+    };
+    match read {
+        Ok(_) => Some(Resource {
+            media_type: asset.contenttype,
+            data,
+        }),
+        Err(e) => {
+            warn!("Could not read asset {}: {}", id, e);
+            None
+        }
+    }
+}
+
+/// Read the media assets among `urls` so they can be embedded in an
+/// exported book, see `read_asset`
+///
+/// URLs which are not media assets are left out, so exporting a book
+/// never makes the server download arbitrary URLs.
+async fn read_assets(
+    db: &ServerState,
+    urls: Vec<&String>,
+    kind: &str,
+) -> HashMap<String, Resource> {
+    let mut resources = HashMap::new();
+    for url in urls {
+        let asset = match crate::media::parse_url(url) {
+            Some(asset) if !resources.contains_key(url) => asset,
+            _ => continue,
+        };
+        if let Some(resource) = read_asset(db, asset, kind).await {
+            resources.insert(url.clone(), resource);
+        }
+    }
+    resources
+}
+
+/// A book along with its author, its chapters and its fragments
+type Content = (Book, Author, Vec<Chapter>, Vec<Bookfragment>);
+
+/// Load a book along with its author, its chapters and its fragments
+///
+/// The metadata, chapters and fragments of published books are the
+/// ones of their last publication, unless `draft` is true, see
+/// `readable`.
+///
+/// # Errors
+///
+/// If the book does not exist or cannot be read by the user, a 404
+/// error is returned. If they ask for a draft they cannot view, a 403
+/// error is returned. Any other error from the database is returned
+/// as a 500 HTTP error.
+fn load(
+    db: &State<ServerState>,
+    identity: Option<&Identity>,
+    id: Uuid,
+    draft: bool,
+) -> Result<Content, status::Custom<String>> {
+    let connector = &mut get_connector!(db);
+    let (book, version) = readable(connector, identity, id, draft)?;
+    let content = author::get(connector, book.author).and_then(|author| {
+        Ok((
+            author,
+            match version {
+                Version::Draft => chapter::list(connector, id)?,
+                Version::Published => published::list_chapters(connector, id)?,
+            },
+            match version {
+                Version::Draft => fragment::list_full(connector, id)?,
+                Version::Published => published::list_full(connector, id)?,
+            },
+        ))
+    });
+    match content {
+        Ok((author, chapters, fragments)) => {
+            Ok((book, author, chapters, fragments))
+        }
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// Export a book as an EPUB 3 file
+///
+/// The book is packaged with its metadata, its author, and all of its
+/// chapters and fragments in order. Its cover and the background
+/// images and sounds of its fragments are embedded in the file when
+/// they are media assets, read from the media storage. Images hosted
+/// elsewhere are left out, so exporting a book never makes the server
+/// download arbitrary URLs, while sounds hosted elsewhere are
+/// referenced as remote audio resources.
+/// The draft of the book is exported if `draft` is true, see `load`.
+///
+/// # Errors
+///
+/// If the book does not exist or cannot be read by the user, a 404
+/// error is returned to the user. If they ask for a draft they cannot
+/// view, a 403 error is returned. Any other error from the server
+/// will be returned to the user as a 500 HTTP error.
+#[get("/<id>/export.epub?<draft>")]
+pub async fn epub(
+    db: &State<ServerState>,
+    id: Uuid,
+    draft: Option<bool>,
+    reader: Option<Reader>,
+) -> DownloadResponse {
+    info!("Exporting book {} as EPUB", id);
+    let (mut book, author, chapters, mut fragments) = load(
+        db,
+        reader.as_ref().map(|reader| &reader.0),
+        id,
+        draft.unwrap_or(false),
+    )?;
+    if let Some(asset) = book.coverasset {
+        book.cover = Some(crate::media::url(asset));
+    }
+    for fragment in &mut fragments {
+        if let Some(asset) = fragment.imgasset {
+            fragment.imgsource = Some(crate::media::url(asset));
+        }
+        if let Some(asset) = fragment.bgsoundasset {
+            fragment.bgsoundsource = Some(crate::media::url(asset));
+        }
+        if let Some(asset) = fragment.oneshotsoundasset {
+            fragment.oneshotsoundsource = Some(crate::media::url(asset));
+        }
+    }
+    let image_urls = book
+        .cover
+        .iter()
+        .chain(
+            fragments
+                .iter()
+                .filter(|fragment| matches!(fragment.imgtype, ImageType::Url))
+                .filter_map(|fragment| fragment.imgsource.as_ref()),
+        )
+        .collect();
+    let images = read_assets(db, image_urls, "image").await;
+    let sound_urls = fragments
+        .iter()
+        .filter(|fragment| matches!(fragment.bgsoundtype, SoundType::Url))
+        .filter_map(|fragment| fragment.bgsoundsource.as_ref())
+        .chain(
+            fragments
+                .iter()
+                .filter_map(|fragment| fragment.oneshotsoundsource.as_ref()),
+        )
+        .collect();
+    let sounds = read_assets(db, sound_urls, "audio").await;
+    let package = Package {
+        book: &book,
+        author: &author,
+        chapters: &chapters,
+        fragments: &fragments,
+        images: &images,
+        sounds: &sounds,
+    };
+    match export::epub::build(&package) {
+        Ok(data) => Ok(Download::new(
+            data,
+            ContentType::new("application", "epub+zip"),
+            &format!("{}.epub", book.title),
+        )),
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// Export a book as a Markdown manuscript
+///
+/// The manuscript can be imported back with `/book/import/markdown`,
+/// see `crate::export::markdown::build` for its format. The draft of
+/// the book is exported if `draft` is true, see `load`.
+///
+/// # Errors
+///
+/// If the book does not exist or cannot be read by the user, a 404
+/// error is returned to the user. If they ask for a draft they cannot
+/// view, a 403 error is returned. Any other error from the server
+/// will be returned to the user as a 500 HTTP error.
+#[get("/<id>/export.md?<draft>")]
+pub fn markdown(
+    db: &State<ServerState>,
+    id: Uuid,
+    draft: Option<bool>,
+    reader: Option<Reader>,
+) -> DownloadResponse {
+    info!("Exporting book {} as Markdown", id);
+    let (book, author, chapters, fragments) = load(
+        db,
+        reader.as_ref().map(|reader| &reader.0),
+        id,
+        draft.unwrap_or(false),
+    )?;
+    Ok(Download::new(
+        export::markdown::build(&book, &author, &chapters, &fragments)
+            .into_bytes(),
+        ContentType::Markdown,
+        &format!("{}.md", book.title),
+    ))
+}
+
+/// Export a book as a JSON bundle
+///
+/// The bundle holds the book, its author, its chapters and its
+/// fragments. It can be imported back with `/book/bundle`, including
+/// in another instance of Alexandria. The draft of the book is
+/// exported if `draft` is true, see `load`.
+///
+/// # Errors
+///
+/// If the book does not exist or cannot be read by the user, a 404
+/// error is returned to the user. If they ask for a draft they cannot
+/// view, a 403 error is returned. Any other error from the server
+/// will be returned to the user as a 500 HTTP error.
+#[get("/<id>/bundle?<draft>")]
+pub fn bundle(
+    db: &State<ServerState>,
+    id: Uuid,
+    draft: Option<bool>,
+    reader: Option<Reader>,
+) -> JsonResponse<Bundle> {
+    info!("Exporting book {} as a bundle", id);
+    let (book, author, chapters, fragments) = load(
+        db,
+        reader.as_ref().map(|reader| &reader.0),
+        id,
+        draft.unwrap_or(false),
+    )?;
+    Ok(Json(Bundle {
+        version: VERSION,
+        book,
+        author,
+        chapters,
+        fragments,
+    }))
+}
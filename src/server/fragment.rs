@@ -1,14 +1,27 @@
-use crate::db::fragment;
-use crate::db::get_connector;
-use crate::models::{Bookfragment, ImageType, SoundType};
-use crate::server::{json_val_or_error, make_error};
-use crate::{ApiKey, Json, JsonResponse, ServerState};
+use std::collections::HashMap;
+
+use crate::auth::{Editor, Identity, Permission, Reader};
+use crate::db::ambience::{self, Image, Resolved};
+use crate::db::page::Item;
+use crate::db::published::Version;
+use crate::db::{fragment, published, revision};
+use crate::db::{get_connector, media, serializable, ApiResult};
+use crate::images;
+use crate::models::{
+    AuditAction, Bookfragment, CachedImage, FragmentRevision, ImageType,
+    SoundType,
+};
+use crate::server::book::{authorize, readable};
+use crate::server::page::{self, PagedResponse};
+use crate::server::{audit, json_val_or_error, make_error};
+use crate::{Json, JsonResponse, ServerState};
 
 use rocket::http::Status;
 use rocket::response::status;
+use rocket::serde::json::json;
 use rocket::serde::uuid::Uuid;
 use rocket::serde::Deserialize;
-use rocket::State;
+use rocket::{Route, State};
 
 #[derive(Deserialize)]
 #[serde(crate = "rocket::serde")]
@@ -16,6 +29,41 @@ pub struct ToRank {
     pub to: i32,
 }
 
+/// Operation the user can send in a batch, see `batch`
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", tag = "op", rename_all = "lowercase")]
+pub enum BatchOperation {
+    Create {
+        fragment: UserInput,
+    },
+    Update {
+        fragment: Bookfragment,
+    },
+    Move {
+        id: Uuid,
+        chapter: Option<Uuid>,
+        to: i32,
+    },
+    Delete {
+        id: Uuid,
+    },
+}
+
+impl From<BatchOperation> for fragment::Operation {
+    fn from(other: BatchOperation) -> Self {
+        match other {
+            BatchOperation::Create { fragment } => {
+                Self::Create(fragment.into())
+            }
+            BatchOperation::Update { fragment } => Self::Update(fragment),
+            BatchOperation::Move { id, chapter, to } => {
+                Self::Move { id, chapter, to }
+            }
+            BatchOperation::Delete { id } => Self::Delete(id),
+        }
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(crate = "rocket::serde")]
 pub struct UserInput {
@@ -26,8 +74,11 @@ pub struct UserInput {
     pub imgtype: ImageType,
     pub imgsource: Option<String>,
     pub book: Uuid,
-    pub chapter: i32,
+    pub chapter: Uuid,
     pub rank: i32,
+    pub oneshotsoundasset: Option<Uuid>,
+    pub bgsoundasset: Option<Uuid>,
+    pub imgasset: Option<Uuid>,
 }
 
 impl From<UserInput> for Bookfragment {
@@ -41,41 +92,287 @@ impl From<UserInput> for Bookfragment {
             imgtype: other.imgtype,
             imgsource: other.imgsource,
             book: other.book,
-            chapter: other.chapter,
             rank: other.rank,
+            chapter: other.chapter,
+            oneshotsoundasset: other.oneshotsoundasset,
+            bgsoundasset: other.bgsoundasset,
+            imgasset: other.imgasset,
+            deletedat: None,
+        }
+    }
+}
+
+/// Check the sounds `fragment` uses as media assets
+///
+/// # Errors
+///
+/// If a sound asset does not exist, is not a sound, or has not been
+/// probed, a 422 error is returned, see `media::check_sounds`. Any
+/// other error is returned as a 500 HTTP error.
+fn check_sounds(
+    connector: &mut diesel::PgConnection,
+    fragment: &Bookfragment,
+) -> Result<(), status::Custom<String>> {
+    match media::check_sounds(connector, fragment) {
+        Ok(None) => Ok(()),
+        Ok(Some(message)) => make_error!(Status::UnprocessableEntity, message),
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// Retrieve a fragment, checking the user sending the request can
+/// edit its book
+///
+/// # Errors
+///
+/// See `permitted`.
+fn editable(
+    connector: &mut diesel::PgConnection,
+    identity: &Identity,
+    id: Uuid,
+) -> Result<Bookfragment, status::Custom<String>> {
+    permitted(connector, identity, id, Permission::EditFragments)
+}
+
+/// Retrieve a fragment, checking the user sending the request has
+/// `permission` on its book
+///
+/// # Errors
+///
+/// If the fragment or its book does not exist, a 404 error is
+/// returned. If the user does not have the permission, a 403 error is
+/// returned. Any other error is returned as a 500 HTTP error.
+fn permitted(
+    connector: &mut diesel::PgConnection,
+    identity: &Identity,
+    id: Uuid,
+    permission: Permission,
+) -> Result<Bookfragment, status::Custom<String>> {
+    use diesel::result::Error::NotFound;
+    let found = match fragment::get(connector, id) {
+        Ok(val) => val,
+        Err(NotFound) => {
+            return make_error!(
+                Status::NotFound,
+                format!("Fragment ID {} not found", id)
+            )
+        }
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    authorize(connector, identity, found.book, permission)?;
+    Ok(found)
+}
+
+/// Find the book of a fragment, whether the fragment is only a draft
+/// or was deleted since its book was last published
+///
+/// # Errors
+///
+/// If the fragment does not exist in any version, a 404 error is
+/// returned. Any other error is returned as a 500 HTTP error.
+fn book_of(
+    connector: &mut diesel::PgConnection,
+    id: Uuid,
+) -> Result<Uuid, status::Custom<String>> {
+    use diesel::result::Error::NotFound;
+    let found = match fragment::get(connector, id) {
+        Err(NotFound) => published::get(connector, id),
+        other => other,
+    };
+    match found {
+        Ok(val) => Ok(val.book),
+        Err(NotFound) => make_error!(
+            Status::NotFound,
+            format!("Fragment with ID {} not found", id)
+        ),
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// Turn the directions of automatic images into concrete images
+///
+/// Nothing is done if no image provider is configured. An image which
+/// cannot be resolved is left untouched, and the error is logged.
+async fn resolve_images<'a>(
+    db: &ServerState,
+    images: impl Iterator<Item = &'a mut Image> + Send,
+) {
+    let provider = match &db.images {
+        Some(provider) => provider.as_ref(),
+        None => return,
+    };
+    let mut found: HashMap<String, Option<CachedImage>> = HashMap::new();
+    for image in images.filter(|image| image.imgtype == ImageType::Auto) {
+        let keywords = match &image.imgsource {
+            Some(keywords) => keywords.clone(),
+            None => continue,
+        };
+        if let Some(resolved) = found.get(&keywords) {
+            image.resolved.clone_from(resolved);
+            continue;
         }
+        let resolved =
+            match images::resolve(&db.pool, provider, &keywords).await {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    warn!("Could not resolve image `{}`: {}", keywords, e);
+                    None
+                }
+            };
+        found.insert(keywords, resolved.clone());
+        image.resolved = resolved;
     }
 }
 
-/// Get all fragments of a book
+/// Get a page of the fragments of a book
+///
+/// Returns an array of simple fragments, see `Simple`, or of the
+/// `fields` asked for if any. Fragments are always sorted in reading
+/// order, see `page::Query` for the pagination parameters. If `resolve`
+/// is true, the effective image and sound of each fragment, resolved
+/// from the preceding fragments, are returned as well. Automatic
+/// images are then turned into concrete images with their
+/// attribution, if an image provider is configured.
 ///
-/// Returns an array of simple fragments, see `Simple`.
+/// The fragments of published books are returned as they were when
+/// the book was last published, unless `draft` is true. See
+/// `readable` for who can read which version of a book.
 ///
 /// # Errors
 ///
-/// Any error from the server will be returned to the user as a 500
-/// HTTP error. If the book pointed at by `book_id` does not exist, a
-/// simple empty list is returned.
-#[get("/<book_id>/fragments")]
-pub fn list(
+/// If the book does not exist or cannot be read by the user, a 404
+/// error is returned to the user. If they ask for a draft they cannot
+/// view, a 403 error is returned. If a pagination parameter is
+/// invalid, or if they ask for a sort, a 400 error is returned. Any
+/// other error from the server will be returned as a 500 HTTP error.
+#[get("/<book_id>/fragments?<resolve>&<draft>&<query..>")]
+pub async fn list(
     db: &State<ServerState>,
     book_id: Uuid,
-) -> JsonResponse<Vec<fragment::Simple>> {
-    let connector = &mut get_connector!(db);
-    json_val_or_error!(fragment::list(connector, book_id))
+    resolve: Option<bool>,
+    draft: Option<bool>,
+    query: page::Query,
+    reader: Option<Reader>,
+) -> PagedResponse<Resolved<Item<fragment::Simple>>> {
+    if query.sort.is_some() {
+        return make_error!(
+            Status::BadRequest,
+            "Fragments are always sorted in reading order".to_owned()
+        );
+    }
+    let page = query.page()?;
+    let result = {
+        let connector = &mut get_connector!(db);
+        let identity = reader.as_ref().map(|reader| &reader.0);
+        let (_, version) =
+            readable(connector, identity, book_id, draft.unwrap_or(false))?;
+        let fields = query.fields(&fragment::columns(match version {
+            Version::Draft => "BookFragments",
+            Version::Published => "PublishedFragments",
+        }))?;
+        ambience::list(
+            connector,
+            book_id,
+            version,
+            resolve.unwrap_or(false),
+            &page,
+            fields.as_ref(),
+        )
+    };
+    let mut fragments = page::paged(result)?;
+    resolve_images(
+        db,
+        fragments.0.items.iter_mut().filter_map(|fragment| {
+            fragment.effective.as_mut()?.effective_image.as_mut()
+        }),
+    )
+    .await;
+    Ok(fragments)
+}
+
+/// Get the background images and sounds active over a book
+///
+/// Returns the ranges of fragments over which each background image
+/// and sound is active, see `Timeline`. Automatic images are turned
+/// into concrete images if an image provider is configured. The
+/// timeline of the draft of the book is returned if `draft` is true,
+/// see `list`.
+///
+/// # Errors
+///
+/// If the book does not exist or cannot be read by the user, a 404
+/// error is returned to the user. If they ask for a draft they cannot
+/// view, a 403 error is returned. Any other error from the server
+/// will be returned as a 500 HTTP error.
+#[get("/<book_id>/ambience-timeline?<draft>")]
+pub async fn timeline(
+    db: &State<ServerState>,
+    book_id: Uuid,
+    draft: Option<bool>,
+    reader: Option<Reader>,
+) -> JsonResponse<ambience::Timeline> {
+    let result = {
+        let connector = &mut get_connector!(db);
+        let identity = reader.as_ref().map(|reader| &reader.0);
+        let (_, version) =
+            readable(connector, identity, book_id, draft.unwrap_or(false))?;
+        ambience::effective(connector, book_id, version).map(ambience::timeline)
+    };
+    let mut timeline = match result {
+        Ok(timeline) => timeline,
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    resolve_images(db, timeline.images.iter_mut().map(|span| &mut span.asset))
+        .await;
+    Ok(Json(timeline))
 }
 
 /// Get a fragment by ID
 ///
+/// If `resolve` is true, the effective image and sound of the
+/// fragment, resolved from the preceding fragments, are returned as
+/// well. An automatic image is then turned into a concrete image with
+/// its attribution, if an image provider is configured. The draft of
+/// the fragment is returned if `draft` is true, see `list`.
+///
 /// # Errors
 ///
-/// If an internal error happens, return a 500 error to the user.
-/// Otherwise, send an array of books in Json format.
-#[get("/<id>")]
-pub fn get(db: &State<ServerState>, id: Uuid) -> JsonResponse<Bookfragment> {
-    let connector = &mut get_connector!(db);
-    match fragment::get(connector, id) {
-        Ok(val) => Ok(Json(val)),
+/// If the fragment does not exist in the version read by the user, or
+/// if its book cannot be read by the user, a 404 error is returned.
+/// If they ask for a draft they cannot view, a 403 error is returned.
+/// Any other error will be returned as a 500 HTTP error.
+#[get("/<id>?<resolve>&<draft>")]
+pub async fn get(
+    db: &State<ServerState>,
+    id: Uuid,
+    resolve: Option<bool>,
+    draft: Option<bool>,
+    reader: Option<Reader>,
+) -> JsonResponse<Resolved<Bookfragment>> {
+    let result = {
+        let connector = &mut get_connector!(db);
+        let identity = reader.as_ref().map(|reader| &reader.0);
+        let book = book_of(connector, id)?;
+        let (_, version) =
+            readable(connector, identity, book, draft.unwrap_or(false))?;
+        ambience::get(connector, id, version, resolve.unwrap_or(false))
+    };
+    match result {
+        Ok(mut val) => {
+            resolve_images(
+                db,
+                val.effective
+                    .as_mut()
+                    .and_then(|effective| effective.effective_image.as_mut())
+                    .into_iter(),
+            )
+            .await;
+            Ok(Json(val))
+        }
         Err(e) => {
             use diesel::result::Error::NotFound;
             match e {
@@ -98,45 +395,115 @@ pub fn get(db: &State<ServerState>, id: Uuid) -> JsonResponse<Bookfragment> {
 /// the new fragment. If the fragment’s rank exceeds the amount of
 /// fragments already existing, set it to the last logical rank ---
 /// i.e. if a fragment at rank 999 is inserted but the last fragment
-/// is at rank 41, insert the new fragment at rank 42.
+/// is at rank 41, insert the new fragment at rank 42. The created
+/// fragment is returned with its actual rank.
 ///
 /// # Errors
 ///
-/// If an internal error happens, return a 500 error to the user.
-/// Otherwise, send an array of books in Json format.
+/// If the fragment’s book or chapter does not exist, return a 404
+/// error to the user. If the user cannot edit the book, return a 403
+/// error. If one of its sounds is an invalid media asset, return a
+/// 422 error. If an internal error happens, return a 500 error.
 #[post("/", format = "json", data = "<fragment>")]
 pub fn new(
     db: &State<ServerState>,
     fragment: Json<UserInput>,
-) -> JsonResponse<()> {
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<Bookfragment> {
     let connector = &mut get_connector!(db);
-    match fragment::new(connector, fragment.into_inner().into()) {
-        Ok(_) => Ok(Json(())),
+    let fragment: Bookfragment = fragment.into_inner().into();
+    authorize(
+        connector,
+        &editor.0,
+        fragment.book,
+        Permission::EditFragments,
+    )?;
+    let chapter = fragment.chapter;
+    check_sounds(connector, &fragment)?;
+    let result = serializable(connector, |connector| {
+        let created =
+            fragment::new(connector, fragment.clone(), editor.0.user.id)?;
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Create,
+            "fragment",
+            created.id,
+            None,
+            Some(&created),
+        )?;
+        Ok(created)
+    });
+    match result {
+        Ok(val) => Ok(Json(val)),
         Err(e) => {
-            Err(status::Custom(Status::InternalServerError, e.to_string()))
+            use diesel::result::Error::NotFound;
+            match e {
+                NotFound => make_error!(
+                    Status::NotFound,
+                    format!("Chapter ID {} not found", chapter)
+                ),
+                other => {
+                    make_error!(Status::InternalServerError, other.to_string())
+                }
+            }
         }
     }
 }
 
 /// Update an existing fragment
 ///
-/// In case the fragment’s rank changes, shift all the necessary
-/// fragments to keep continuity in the book, see `new`.
+/// In case the fragment’s chapter or rank changes, shift all the
+/// necessary fragments to keep continuity in the book, see `new`. The
+/// updated fragment is returned with its actual rank.
 ///
 /// # Errors
 ///
-/// If an internal error happens, return a 500 error to the user.
-/// Otherwise, send an array of books in Json format.
+/// If the fragment does not exist, return a 404 error to the user. If
+/// the user cannot edit its book, or the book it is moved to, return
+/// a 403 error. If one of the sounds of the fragment is an invalid
+/// media asset, return a 422 error. If an internal error happens,
+/// return a 500 error. Otherwise, send the updated fragment in Json
+/// format.
 #[put("/", format = "json", data = "<fragment>")]
 pub fn update(
     db: &State<ServerState>,
     fragment: Json<Bookfragment>,
-) -> JsonResponse<()> {
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<Bookfragment> {
     let connector = &mut get_connector!(db);
     let fragment = fragment.into_inner();
     let id = fragment.id;
-    match fragment::update(connector, fragment) {
-        Ok(_) => Ok(Json(())),
+    let before = editable(connector, &editor.0, id)?;
+    if before.book != fragment.book {
+        authorize(
+            connector,
+            &editor.0,
+            fragment.book,
+            Permission::EditFragments,
+        )?;
+    }
+    check_sounds(connector, &fragment)?;
+    let result = serializable(connector, |connector| {
+        let after =
+            fragment::update(connector, fragment.clone(), editor.0.user.id)?;
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Update,
+            "fragment",
+            id,
+            Some(&before),
+            Some(&after),
+        )?;
+        Ok(after)
+    });
+    match result {
+        Ok(val) => Ok(Json(val)),
         Err(e) => {
             use diesel::result::Error::NotFound;
             match e {
@@ -152,43 +519,96 @@ pub fn update(
     }
 }
 
-/// Delete a fragment by ID
+/// Move a fragment to the trash by ID
+///
+/// The following fragments of its chapter are shifted back by one
+/// rank.
 ///
 /// # Errors
 ///
-/// Any error from the server will be returned to the user as a 500
-/// HTTP error.
-// TODO: Handle fragment not found
+/// If the fragment does not exist, a 404 error is returned to the
+/// user. If the user cannot edit its book, a 403 error is returned.
+/// Any other error from the server will be returned to the user as a
+/// 500 HTTP error.
 #[delete("/<id>")]
 pub fn delete(
     db: &State<ServerState>,
     id: Uuid,
-    _key: ApiKey<'_>,
+    editor: Editor,
+    route: &Route,
 ) -> JsonResponse<()> {
     let connector = &mut get_connector!(db);
-    json_val_or_error!(fragment::delete(connector, id))
+    let before = editable(connector, &editor.0, id)?;
+    let result = serializable(connector, |connector| {
+        fragment::delete(connector, id)?;
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Delete,
+            "fragment",
+            id,
+            Some(&before),
+            None,
+        )
+    });
+    match result {
+        Ok(()) => Ok(Json(())),
+        Err(e) => {
+            use diesel::result::Error::NotFound;
+            match e {
+                NotFound => make_error!(
+                    Status::NotFound,
+                    format!("Fragment ID {} not found", id)
+                ),
+                other => {
+                    make_error!(Status::InternalServerError, other.to_string())
+                }
+            }
+        }
+    }
 }
 
 /// Reorder a book fragment
 ///
 /// Move a fragment to a new rank. If needed, shift other fragments.
-/// If the user tries to move the fragment further than the end of the
-/// book, set the fragment as its last fragment and renumber it.
+/// If the user tries to move the fragment further than the end of its
+/// chapter, set the fragment as its last fragment. The moved fragment
+/// is returned with its actual rank.
 ///
 /// # Errors
 ///
-/// Any error from the server will be returned to the user as a 500
-/// HTTP error.
+/// If the fragment does not exist, a 404 error is returned to the
+/// user. If the user cannot edit its book, a 403 error is returned.
+/// Any other error from the server will be returned to the user as a
+/// 500 HTTP error.
 #[put("/<id>/reorder", format = "json", data = "<to>")]
 pub fn reorder(
     db: &State<ServerState>,
     id: Uuid,
     to: Json<ToRank>,
-    _key: ApiKey<'_>,
-) -> JsonResponse<()> {
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<Bookfragment> {
     let connector = &mut get_connector!(db);
-    match fragment::move_frag_id(connector, id, to.to) {
-        Ok(_) => Ok(Json(())),
+    let before = editable(connector, &editor.0, id)?;
+    let result = serializable(connector, |connector| {
+        let after =
+            fragment::move_frag_id(connector, id, to.to, editor.0.user.id)?;
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Update,
+            "fragment",
+            id,
+            Some(&before),
+            Some(&after),
+        )?;
+        Ok(after)
+    });
+    match result {
+        Ok(val) => Ok(Json(val)),
         Err(e) => {
             use diesel::result::Error::NotFound;
             match e {
@@ -203,3 +623,303 @@ pub fn reorder(
         }
     }
 }
+
+/// List the revisions of a fragment
+///
+/// Each creation or update of the fragment is recorded as a revision
+/// holding its content, sounds, image and position along with the
+/// user who made the change and when, from the oldest to the newest.
+///
+/// # Errors
+///
+/// If the fragment does not exist, a 404 error is returned to the
+/// user. If the user cannot view the drafts of its book, a 403 error
+/// is returned. Any other error will be returned as a 500 HTTP error.
+#[get("/<id>/revisions")]
+pub fn revisions(
+    db: &State<ServerState>,
+    id: Uuid,
+    reader: Reader,
+) -> JsonResponse<Vec<FragmentRevision>> {
+    let connector = &mut get_connector!(db);
+    permitted(connector, &reader.0, id, Permission::ViewDraft)?;
+    json_val_or_error!(revision::list(connector, id))
+}
+
+/// Compare two revisions of a fragment
+///
+/// The content of the revision `from` is compared line by line with
+/// the one of the revision `to`, which is the latest revision if it
+/// is not set. The other elements of the fragment which changed
+/// between the two revisions are listed by name.
+///
+/// # Errors
+///
+/// If the fragment or one of the revisions does not exist, a 404
+/// error is returned to the user. If the user cannot view the drafts
+/// of its book, a 403 error is returned. Any other error will be
+/// returned as a 500 HTTP error.
+#[get("/<id>/revisions/diff?<from>&<to>")]
+pub fn diff(
+    db: &State<ServerState>,
+    id: Uuid,
+    from: i32,
+    to: Option<i32>,
+    reader: Reader,
+) -> JsonResponse<revision::Diff> {
+    use diesel::result::Error::NotFound;
+    let connector = &mut get_connector!(db);
+    permitted(connector, &reader.0, id, Permission::ViewDraft)?;
+    let result = revision::get(connector, id, from).and_then(|old| {
+        let new = match to {
+            Some(to) => revision::get(connector, id, to)?,
+            None => revision::list(connector, id)?.pop().ok_or(NotFound)?,
+        };
+        Ok(revision::diff(&old, &new))
+    });
+    match result {
+        Ok(val) => Ok(Json(val)),
+        Err(NotFound) => make_error!(
+            Status::NotFound,
+            format!("Revision not found for fragment ID {}", id)
+        ),
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// Restore a fragment to one of its revisions
+///
+/// The content, sounds and image of the fragment are brought back to
+/// the ones of the revision `rev`, while the fragment stays at its
+/// current position. The restoration is recorded as a new revision,
+/// and the restored fragment is returned.
+///
+/// # Errors
+///
+/// If the fragment or the revision does not exist, a 404 error is
+/// returned to the user. If the user cannot edit its book, a 403
+/// error is returned. If one of the sounds of the revision is no
+/// longer a valid media asset, a 422 error is returned. Any other
+/// error will be returned as a 500 HTTP error.
+#[post("/<id>/revisions/<rev>/restore")]
+pub fn restore(
+    db: &State<ServerState>,
+    id: Uuid,
+    rev: i32,
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<Bookfragment> {
+    use diesel::result::Error::NotFound;
+    let connector = &mut get_connector!(db);
+    let before = editable(connector, &editor.0, id)?;
+    let mut restored = before.clone();
+    match revision::get(connector, id, rev) {
+        Ok(old) => {
+            restored.oneshotsoundasset = old.oneshotsoundasset;
+            restored.bgsoundasset = old.bgsoundasset;
+        }
+        Err(NotFound) => {
+            return make_error!(
+                Status::NotFound,
+                format!("Revision {} of fragment ID {} not found", rev, id)
+            )
+        }
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    }
+    check_sounds(connector, &restored)?;
+    let result = serializable(connector, |connector| {
+        let after = revision::restore(connector, id, rev, editor.0.user.id)?;
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Update,
+            "fragment",
+            id,
+            Some(&before),
+            Some(&after),
+        )?;
+        Ok(after)
+    });
+    match result {
+        Ok(val) => Ok(Json(val)),
+        Err(NotFound) => make_error!(
+            Status::NotFound,
+            format!("Revision {} of fragment ID {} not found", rev, id)
+        ),
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// Renumber the fragments of a book
+///
+/// Repair the ranks of all chapters of the book so that they start at
+/// 1 and have no gap, without changing the order of the fragments.
+/// Returns how many fragments were renumbered.
+///
+/// # Errors
+///
+/// If the book does not exist, a 404 error is returned to the user.
+/// If the user cannot edit the book, a 403 error is returned. Any
+/// other error from the server will be returned as a 500 HTTP error.
+#[post("/<book_id>/fragments/renumber")]
+pub fn renumber(
+    db: &State<ServerState>,
+    book_id: Uuid,
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<usize> {
+    let connector = &mut get_connector!(db);
+    authorize(connector, &editor.0, book_id, Permission::EditFragments)?;
+    json_val_or_error!(serializable(connector, |connector| {
+        let renumbered = fragment::renumber(connector, book_id)?;
+        audit::record(
+            connector,
+            &editor.0,
+            route,
+            AuditAction::Update,
+            "book",
+            book_id,
+            None,
+            Some(&json!({ "renumbered": renumbered })),
+        )?;
+        Ok(renumbered)
+    }))
+}
+
+/// Record the operations of a batch in the audit log
+///
+/// `before` holds the fragments targeted by the operations as they
+/// were before the batch, while their state after the batch is read
+/// again from the database, in the same transaction as the batch.
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `audit_batch`
+fn audit_batch(
+    connector: &mut diesel::PgConnection,
+    identity: &Identity,
+    route: &Route,
+    before: &HashMap<Uuid, Bookfragment>,
+    reports: &[fragment::OperationReport],
+) -> ApiResult<()> {
+    for report in reports {
+        let action = match report.op {
+            "create" => AuditAction::Create,
+            "delete" => AuditAction::Delete,
+            _ => AuditAction::Update,
+        };
+        let after = match report.chapter {
+            Some(_) => Some(fragment::get(connector, report.id)?),
+            None => None,
+        };
+        audit::record(
+            connector,
+            identity,
+            route,
+            action,
+            "fragment",
+            report.id,
+            before.get(&report.id),
+            after.as_ref(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Apply several operations on the fragments of a book at once
+///
+/// Receive a list of create, update, move and delete operations and
+/// apply them in order in a single transaction. Either all operations
+/// succeed, or none of them is applied. On success, a report of each
+/// operation is returned with the final position of the fragments.
+///
+/// # Errors
+///
+/// If the book does not exist, or if an operation targets a fragment
+/// or a chapter which does not exist or which belongs to another
+/// book, a 404 error is returned to the user. If the user cannot edit
+/// the book, a 403 error is returned. If a created or updated
+/// fragment uses an invalid media asset as a sound, a 422 error is
+/// returned. Any other error will be returned as a 500 HTTP error.
+/// In all cases, the message indicates which operation failed.
+#[post("/<book_id>/fragments/batch", format = "json", data = "<operations>")]
+pub fn batch(
+    db: &State<ServerState>,
+    book_id: Uuid,
+    operations: Json<Vec<BatchOperation>>,
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<Vec<fragment::OperationReport>> {
+    let connector = &mut get_connector!(db);
+    authorize(connector, &editor.0, book_id, Permission::EditFragments)?;
+    let operations: Vec<fragment::Operation> = operations
+        .into_inner()
+        .into_iter()
+        .map(From::from)
+        .collect();
+    for (index, operation) in operations.iter().enumerate() {
+        if let fragment::Operation::Create(fragment)
+        | fragment::Operation::Update(fragment) = operation
+        {
+            if let Err(status::Custom(status, message)) =
+                check_sounds(connector, fragment)
+            {
+                return make_error!(
+                    status,
+                    format!("Operation {}: {}", index, message)
+                );
+            }
+        }
+    }
+    let before: HashMap<Uuid, Bookfragment> = operations
+        .iter()
+        .filter_map(|operation| match operation {
+            fragment::Operation::Create(_) => None,
+            fragment::Operation::Update(fragment) => Some(fragment.id),
+            fragment::Operation::Move { id, .. }
+            | fragment::Operation::Delete(id) => Some(*id),
+        })
+        .filter_map(|id| Some((id, fragment::get(connector, id).ok()?)))
+        .collect();
+    let mut failed = None;
+    let result = serializable(connector, |connector| {
+        failed = None;
+        let reports = fragment::batch(
+            connector,
+            book_id,
+            operations.clone(),
+            editor.0.user.id,
+        )
+        .map_err(|fragment::BatchError { index, error }| {
+            failed = index;
+            error
+        })?;
+        audit_batch(connector, &editor.0, route, &before, &reports)?;
+        Ok(reports)
+    });
+    match result.map_err(|error| (failed, error)) {
+        Ok(val) => Ok(Json(val)),
+        Err((index, error)) => {
+            use diesel::result::Error::NotFound;
+            let operation = index
+                .map_or_else(String::new, |i| format!("Operation {}: ", i));
+            match error {
+                NotFound => make_error!(
+                    Status::NotFound,
+                    format!(
+                        "{}fragment or chapter not found in book {}",
+                        operation, book_id
+                    )
+                ),
+                other => make_error!(
+                    Status::InternalServerError,
+                    format!("{}{}", operation, other)
+                ),
+            }
+        }
+    }
+}
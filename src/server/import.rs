@@ -0,0 +1,224 @@
+use crate::auth::{Editor, Identity};
+use crate::db::{author, get_connector, serializable};
+use crate::export::bundle::{is_supported, Bundle, Header, VERSION};
+use crate::import::{self, ImportedBook};
+use crate::models::{Book, BookStatus};
+use crate::server::{audit, book::check_language, make_error};
+use crate::{Json, JsonResponse, ServerState};
+
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::from_slice;
+use rocket::serde::uuid::Uuid;
+use rocket::{Route, State};
+use tracing::info;
+
+/// Maximum size of an imported file, in mebibytes
+const MAX_SIZE: u64 = 64;
+
+/// Read the imported file sent as the body of the request
+///
+/// # Errors
+///
+/// If the file is too big, a 413 error is returned. If the body
+/// cannot be read, a 400 error is returned.
+async fn read(data: Data<'_>) -> Result<Vec<u8>, status::Custom<String>> {
+    match data.open(MAX_SIZE.mebibytes()).into_bytes().await {
+        Ok(data) if data.is_complete() => Ok(data.into_inner()),
+        Ok(_) => make_error!(
+            Status::PayloadTooLarge,
+            format!("File exceeds {} MiB", MAX_SIZE)
+        ),
+        Err(e) => make_error!(Status::BadRequest, e.to_string()),
+    }
+}
+
+/// Save an imported book owned by the user sending the request and
+/// return it to the user
+///
+/// The book is saved as a draft, whatever its status in a bundle. The
+/// creation of the book, of its chapters, of its fragments and of its
+/// author, if any, are recorded in the audit log, see
+/// `audit::record_import`.
+///
+/// # Errors
+///
+/// If the language of the book is unknown, a 422 error is returned.
+/// If the book is imported without its author and its author does
+/// not exist, a 404 error is returned. If an imported row already
+/// exists, a 409 error is returned. Any other error from the database
+/// is returned as a 500 HTTP error.
+fn save(
+    db: &State<ServerState>,
+    identity: &Identity,
+    route: &Route,
+    mut imported: ImportedBook,
+) -> JsonResponse<Book> {
+    info!("Importing book {}", imported.book.title);
+    imported.book.owner = Some(identity.user.id);
+    imported.book.status = BookStatus::Draft;
+    let connector = &mut get_connector!(db);
+    check_language(connector, &imported.book)?;
+    if imported.author.is_none() {
+        let id = imported.book.author;
+        match author::get(connector, id) {
+            Ok(_) => {}
+            Err(diesel::result::Error::NotFound) => {
+                return make_error!(
+                    Status::NotFound,
+                    format!("Author ID {} not found", id)
+                );
+            }
+            Err(e) => {
+                return make_error!(Status::InternalServerError, e.to_string())
+            }
+        }
+    }
+    let result = serializable(connector, |connector| {
+        let saved = import::save(connector, imported.clone())?;
+        audit::record_import(connector, identity, route, &saved)?;
+        Ok(saved.book)
+    });
+    match result {
+        Ok(book) => Ok(Json(book)),
+        Err(DatabaseError(DatabaseErrorKind::UniqueViolation, info)) => {
+            make_error!(Status::Conflict, info.message().to_owned())
+        }
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// Import a book from an EPUB file
+///
+/// The EPUB file is sent as the raw body of the request. Its author,
+/// the book, its chapters and its fragments are created in a single
+/// transaction, and the created book is returned to the user.
+///
+/// # Errors
+///
+/// If the file is too big, a 413 error is returned to the user. If
+/// the file is not a valid EPUB or if its language is unknown to the
+/// database, a 422 error is returned. Any other error from the server
+/// will be returned as a 500 HTTP error.
+#[post("/import/epub", data = "<data>")]
+pub async fn epub(
+    db: &State<ServerState>,
+    data: Data<'_>,
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<Book> {
+    let data = read(data).await?;
+    match import::epub::parse_blocking(data).await {
+        Ok(imported) => save(db, &editor.0, route, imported),
+        Err(e) => make_error!(Status::UnprocessableEntity, e.to_string()),
+    }
+}
+
+/// Import a book from a Markdown or plain text manuscript
+///
+/// The manuscript is sent as the raw body of the request, see
+/// `crate::import::markdown::parse` for its format. If the manuscript
+/// does not set the title of the book, `title` is used. If `author`
+/// is set, the book is attached to this existing author instead of
+/// the author named in the manuscript. The created book is returned
+/// to the user.
+///
+/// # Errors
+///
+/// If the file is too big, a 413 error is returned to the user. If
+/// the manuscript is invalid or if its language is unknown, a 422
+/// error is returned. If `author` does not exist, a 404 error is
+/// returned. Any other error from the server will be returned as a
+/// 500 HTTP error.
+#[post("/import/markdown?<title>&<author>", data = "<data>")]
+pub async fn markdown(
+    db: &State<ServerState>,
+    data: Data<'_>,
+    title: Option<String>,
+    author: Option<Uuid>,
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<Book> {
+    let text = match String::from_utf8(read(data).await?) {
+        Ok(text) => text,
+        Err(e) => {
+            return make_error!(Status::UnprocessableEntity, e.to_string())
+        }
+    };
+    match import::markdown::parse(&text, title) {
+        Ok(mut imported) => {
+            if let Some(author) = author {
+                imported.author = None;
+                imported.book.author = author;
+            }
+            save(db, &editor.0, route, imported)
+        }
+        Err(e) => make_error!(Status::UnprocessableEntity, e.to_string()),
+    }
+}
+
+/// Import a book from a JSON bundle
+///
+/// The bundle is sent as the body of the request, in the format
+/// returned by `/book/<id>/bundle`. If `regenerate` is true, the
+/// imported rows are given new identifiers instead of keeping the
+/// ones of the bundle. If `author` is set, the book is attached to
+/// this existing author instead of the bundled one. The created book
+/// is returned to the user.
+///
+/// # Errors
+///
+/// If the file is too big, a 413 error is returned to the user. If
+/// the version of the bundle is missing or unsupported, a 400 error
+/// is returned. If the bundle is invalid or its language is unknown,
+/// a 422 error is returned. If `author` does not exist, a 404 error
+/// is returned. If the bundled identifiers are kept and already
+/// exist, a 409 error is returned. Any other error from the server
+/// will be returned as a 500 HTTP error.
+#[post("/bundle?<regenerate>&<author>", data = "<data>")]
+pub async fn bundle(
+    db: &State<ServerState>,
+    data: Data<'_>,
+    regenerate: Option<bool>,
+    author: Option<Uuid>,
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<Book> {
+    let data = read(data).await?;
+    match from_slice::<Header>(&data) {
+        Ok(header) if is_supported(header.version) => {}
+        Ok(header) => {
+            return make_error!(
+                Status::BadRequest,
+                format!(
+                    "Unsupported bundle version {}, expected {} at most",
+                    header.version, VERSION
+                )
+            )
+        }
+        Err(e) => {
+            return make_error!(
+                Status::BadRequest,
+                format!("Invalid bundle version: {}", e)
+            )
+        }
+    }
+    let bundle: Bundle = match from_slice(&data) {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            return make_error!(Status::UnprocessableEntity, e.to_string())
+        }
+    };
+    match import::bundle::read(bundle, regenerate.unwrap_or(false)) {
+        Ok(mut imported) => {
+            if let Some(author) = author {
+                imported.author = None;
+                imported.book.author = author;
+            }
+            save(db, &editor.0, route, imported)
+        }
+        Err(e) => make_error!(Status::UnprocessableEntity, e.to_string()),
+    }
+}
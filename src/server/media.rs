@@ -0,0 +1,556 @@
+use crate::auth::Editor;
+use crate::db::{get_connector, media};
+use crate::media::audio::{self, AudioInfo};
+use crate::media::variants::{self, Derivatives};
+use crate::media::{
+    is_accepted, remove_variants, sniff, store_variants, SNIFF_SIZE,
+};
+use crate::models::MediaAsset;
+use crate::server::{json_val_or_error, make_error};
+use crate::{Json, JsonResponse, ServerState};
+
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use diesel::Connection;
+use rocket::form::Form;
+use rocket::fs::TempFile;
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{self, FromRequest, Outcome, Request};
+use rocket::response::{self, status, Responder, Response};
+use rocket::serde::uuid::Uuid;
+use rocket::tokio::fs::File;
+use rocket::tokio::io::AsyncReadExt;
+use rocket::State;
+use tracing::{info, warn};
+
+/// Form sent to upload a media asset
+#[derive(FromForm)]
+pub struct Upload<'r> {
+    pub file: TempFile<'r>,
+}
+
+/// Value of the `Range` header of a request, if any
+pub struct RangeHeader<'r>(Option<&'r str>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RangeHeader<'r> {
+    type Error = ();
+
+    async fn from_request(
+        request: &'r Request<'_>,
+    ) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(Self(request.headers().get_one("Range")))
+    }
+}
+
+/// Read a byte range such as `bytes=0-1023` for an asset of `size`
+/// bytes
+///
+/// Returns `None` if the whole asset should be sent, which is the
+/// case if the range is invalid or if several ranges are requested,
+/// and `Some(Err(()))` if the range cannot be satisfied. Otherwise,
+/// the first and last bytes of the range are returned.
+fn parse_range(header: &str, size: u64) -> Option<Result<(u64, u64), ()>> {
+    let range = header.trim().strip_prefix("bytes=")?;
+    if range.contains(',') {
+        return None;
+    }
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 || size == 0 {
+            return Some(Err(()));
+        }
+        return Some(Ok((size.saturating_sub(suffix), size - 1)));
+    }
+    let start: u64 = start.parse().ok()?;
+    let end = if end.is_empty() {
+        u64::MAX
+    } else {
+        end.parse().ok()?
+    };
+    if end < start {
+        None
+    } else if start >= size {
+        Some(Err(()))
+    } else {
+        Some(Ok((start, end.min(size - 1))))
+    }
+}
+
+/// Policy sent along with the content of media assets, so that an
+/// asset opened in a browser can neither run scripts nor load
+/// anything
+const CONTENT_POLICY: &str = "default-src 'none'; sandbox";
+
+/// Content of a media asset or of one of its variants, or part of
+/// it, sent to the user
+///
+/// Only assets of an accepted type are displayed by browsers, see
+/// `is_accepted`. Others, such as SVG images uploaded before types
+/// were checked, are sent as attachments to download.
+pub struct Content {
+    contenttype: String,
+    filename: String,
+    size: u64,
+    range: Option<(u64, u64)>,
+    body: crate::media::Stream<'static>,
+}
+
+impl<'r> Responder<'r, 'static> for Content {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let content_type = ContentType::parse_flexible(&self.contenttype)
+            .unwrap_or(ContentType::Binary);
+        let filename = self.filename.replace(['"', '\\'], "_");
+        let disposition = if is_accepted(&self.contenttype) {
+            "inline"
+        } else {
+            "attachment"
+        };
+        let mut response = Response::build();
+        response
+            .header(content_type)
+            .header(Header::new("Accept-Ranges", "bytes"))
+            .header(Header::new(
+                "Content-Disposition",
+                format!("{}; filename=\"{}\"", disposition, filename),
+            ))
+            .header(Header::new("Content-Security-Policy", CONTENT_POLICY))
+            .header(Header::new("X-Content-Type-Options", "nosniff"))
+            .streamed_body(self.body);
+        if let Some((start, end)) = self.range {
+            response.status(Status::PartialContent).header(Header::new(
+                "Content-Range",
+                format!("bytes {}-{}/{}", start, end, self.size),
+            ));
+        }
+        response.ok()
+    }
+}
+
+/// Read the content stored under `id` in the media storage
+///
+/// Only the part requested by `range` is read, see `parse_range`.
+///
+/// # Errors
+///
+/// If the requested range cannot be satisfied, a 416 error is
+/// returned. If the content cannot be read, a 500 error is returned.
+async fn read(
+    db: &State<ServerState>,
+    id: Uuid,
+    contenttype: String,
+    filename: String,
+    size: i64,
+    range: RangeHeader<'_>,
+) -> Result<Content, status::Custom<String>> {
+    let size = u64::try_from(size).unwrap_or_default();
+    let range = match range.0.and_then(|header| parse_range(header, size)) {
+        None => None,
+        Some(Ok(range)) => Some(range),
+        Some(Err(())) => {
+            return make_error!(
+                Status::RangeNotSatisfiable,
+                format!("Range not satisfiable, asset has {} bytes", size)
+            );
+        }
+    };
+    let (start, length) = match range {
+        Some((start, end)) => (start, end - start + 1),
+        None => (0, size),
+    };
+    match db.storage.read(id, start, length).await {
+        Ok(body) => Ok(Content {
+            contenttype,
+            filename,
+            size,
+            range,
+            body,
+        }),
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// List all media assets
+///
+/// Only editors can list the assets, readers only get the assets used
+/// by the books they read.
+///
+/// # Errors
+///
+/// If an internal error happens, return a 500 error to the user.
+/// Otherwise, send an array of assets in Json format.
+#[get("/")]
+pub fn list(
+    db: &State<ServerState>,
+    _editor: Editor,
+) -> JsonResponse<Vec<MediaAsset>> {
+    info!("Listing media assets");
+    let connector = &mut get_connector!(db);
+    json_val_or_error!(media::list(connector))
+}
+
+/// Probe an uploaded sound, see `audio::probe`
+///
+/// # Errors
+///
+/// If the format of the sound is not supported, a 415 error is
+/// returned. If the sound is broken, a 422 error is returned.
+async fn probe(
+    path: &std::path::Path,
+    content_type: &str,
+) -> Result<AudioInfo, status::Custom<String>> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    match audio::probe_blocking(Box::new(file), content_type.to_owned()).await {
+        Ok(info) => Ok(info),
+        Err(e @ audio::Error::Unsupported(_)) => {
+            make_error!(Status::UnsupportedMediaType, e.to_string())
+        }
+        Err(e @ audio::Error::Invalid(_)) => {
+            make_error!(Status::UnprocessableEntity, e.to_string())
+        }
+    }
+}
+
+/// Name of an uploaded file, without its directories
+///
+/// Files sent without a name are named `upload`.
+fn filename(file: &TempFile<'_>) -> String {
+    file.raw_name()
+        .map(|name| name.dangerous_unsafe_unsanitized_raw().as_str())
+        .and_then(|name| name.rsplit(['/', '\\']).next())
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or("upload")
+        .chars()
+        .take(255)
+        .collect()
+}
+
+/// Find the type of an uploaded file from its content, see `sniff`
+///
+/// # Errors
+///
+/// If the file is not an accepted image or sound, a 415 error is
+/// returned.
+async fn content_type(
+    path: &std::path::Path,
+) -> Result<&'static str, status::Custom<String>> {
+    let mut head = Vec::new();
+    let read = match File::open(path).await {
+        Ok(file) => file.take(SNIFF_SIZE).read_to_end(&mut head).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = read {
+        return make_error!(Status::InternalServerError, e.to_string());
+    }
+    match sniff(&head) {
+        Some(content_type) => Ok(content_type),
+        None => make_error!(
+            Status::UnsupportedMediaType,
+            "Unsupported media type, only PNG, JPEG, GIF and WebP images \
+             and MP3, Ogg, FLAC, WAV and MP4 sounds are accepted"
+                .to_owned()
+        ),
+    }
+}
+
+/// Generate the variants of an uploaded image, see
+/// `variants::generate`
+///
+/// Returns `None` if the format of the image is not supported, in
+/// which case the image is stored without any variant.
+///
+/// # Errors
+///
+/// If the image is broken, a 422 error is returned.
+async fn derive(
+    path: &std::path::Path,
+    content_type: &str,
+) -> Result<Option<Derivatives>, status::Custom<String>> {
+    let data = match rocket::tokio::fs::read(path).await {
+        Ok(data) => data,
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    match variants::generate_blocking(data, content_type.to_owned()).await {
+        Ok(derivatives) => Ok(Some(derivatives)),
+        Err(e @ variants::Error::Unsupported(_)) => {
+            warn!("Storing image without variants: {}", e);
+            Ok(None)
+        }
+        Err(e @ variants::Error::Invalid(_)) => {
+            make_error!(Status::UnprocessableEntity, e.to_string())
+        }
+    }
+}
+
+/// Upload a new media asset
+///
+/// The asset is sent as the `file` field of a `multipart/form-data`
+/// form, and must be a raster image or a sound, whose type is found
+/// from its content rather than trusted from the client. Sounds are
+/// probed to find
+/// their duration, codec, sample rate, channels and loudness. Images
+/// are measured and resized in several variants along with a
+/// blurhash placeholder, unless their format is not supported. The
+/// content of the asset is written to the media storage and the
+/// created asset is returned to the user. Its identifier can then be
+/// used by books and fragments instead of a URL.
+///
+/// # Errors
+///
+/// If the asset is neither an image nor a sound, or if the format of
+/// a sound is not supported, a 415 error is returned to the user. If
+/// a sound or an image is broken, a 422 error is returned. If the
+/// form does not hold a file, a 400 error is returned. If the asset
+/// cannot be stored, a 500 error is returned.
+#[post("/", data = "<upload>")]
+pub async fn upload(
+    db: &State<ServerState>,
+    _editor: Editor,
+    upload: Form<Upload<'_>>,
+) -> JsonResponse<MediaAsset> {
+    let file = &upload.file;
+    let path = match file.path() {
+        Some(path) => path,
+        None => {
+            return make_error!(Status::BadRequest, "Missing file".to_owned())
+        }
+    };
+    let content_type = content_type(path).await?.to_owned();
+    let audio = if content_type.starts_with("audio/") {
+        Some(probe(path, &content_type).await?)
+    } else {
+        None
+    };
+    let derived = if content_type.starts_with("image/") {
+        derive(path, &content_type).await?
+    } else {
+        None
+    };
+    let (image, generated) = match derived {
+        Some(image) => (
+            Some((image.width, image.height, image.blurhash)),
+            image.variants,
+        ),
+        None => (None, Vec::new()),
+    };
+    let id = Uuid::new_v4();
+    info!("Uploading media asset {} ({})", id, content_type);
+    let stored = match File::open(path).await {
+        Ok(data) => db.storage.store(id, Box::new(data)).await,
+        Err(e) => Err(e),
+    };
+    let size = match stored {
+        Ok(size) => i64::try_from(size).unwrap_or(i64::MAX),
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    let stored_variants =
+        match store_variants(db.storage.as_ref(), id, generated).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                if let Err(e) = db.storage.remove(id).await {
+                    warn!("Could not remove media asset {}: {}", id, e);
+                }
+                return make_error!(Status::InternalServerError, e.to_string());
+            }
+        };
+    let asset = MediaAsset {
+        id,
+        filename: filename(file),
+        contenttype: content_type,
+        size,
+        uploaded: chrono::Utc::now().naive_utc(),
+        duration: audio.as_ref().map(|audio| audio.duration),
+        codec: audio.as_ref().map(|audio| audio.codec.clone()),
+        samplerate: audio
+            .as_ref()
+            .and_then(|audio| i32::try_from(audio.samplerate).ok()),
+        channels: audio
+            .as_ref()
+            .and_then(|audio| i32::try_from(audio.channels).ok()),
+        loudness: audio.and_then(|audio| audio.loudness),
+        width: image
+            .as_ref()
+            .and_then(|(width, _, _)| i32::try_from(*width).ok()),
+        height: image
+            .as_ref()
+            .and_then(|(_, height, _)| i32::try_from(*height).ok()),
+        blurhash: image.map(|(_, _, blurhash)| blurhash),
+    };
+    let created = {
+        let connector = &mut get_connector!(db);
+        connector.transaction::<_, diesel::result::Error, _>(|connector| {
+            let created = media::new(connector, &asset)?;
+            media::new_variants(connector, &stored_variants)?;
+            Ok(created)
+        })
+    };
+    match created {
+        Ok(asset) => Ok(Json(asset)),
+        Err(e) => {
+            if let Err(e) = db.storage.remove(id).await {
+                warn!("Could not remove media asset {}: {}", id, e);
+            }
+            remove_variants(db.storage.as_ref(), &stored_variants).await;
+            make_error!(Status::InternalServerError, e.to_string())
+        }
+    }
+}
+
+/// Get the content of a media asset
+///
+/// A single byte range can be requested with the `Range` header, in
+/// which case only this part of the asset is sent with a 206 status,
+/// so audio players can seek within long sounds.
+///
+/// # Errors
+///
+/// If the asset does not exist, a 404 error is returned to the user.
+/// If the requested range cannot be satisfied, a 416 error is
+/// returned. Any other error will be returned as a 500 HTTP error.
+#[get("/<id>")]
+pub async fn get(
+    db: &State<ServerState>,
+    id: Uuid,
+    range: RangeHeader<'_>,
+) -> Result<Content, status::Custom<String>> {
+    let asset = {
+        let connector = &mut get_connector!(db);
+        media::get(connector, id)
+    };
+    let asset = match asset {
+        Ok(asset) => asset,
+        Err(diesel::result::Error::NotFound) => {
+            return make_error!(
+                Status::NotFound,
+                format!("Media asset ID {} not found", id)
+            );
+        }
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    read(db, id, asset.contenttype, asset.filename, asset.size, range).await
+}
+
+/// Get the content of a variant of an image, see `variants::generate`
+///
+/// `variant` is the width of the variant followed by the extension of
+/// its format, such as `640.webp` or `640.jpg`. Range requests are
+/// supported as for the asset itself.
+///
+/// # Errors
+///
+/// If the asset or the variant does not exist, a 404 error is
+/// returned to the user. If the requested range cannot be satisfied,
+/// a 416 error is returned. Any other error will be returned as a 500
+/// HTTP error.
+#[get("/<id>/<variant>")]
+pub async fn variant(
+    db: &State<ServerState>,
+    id: Uuid,
+    variant: &str,
+    range: RangeHeader<'_>,
+) -> Result<Content, status::Custom<String>> {
+    let wanted = variant.split_once('.').and_then(|(width, extension)| {
+        Some((
+            width.parse::<i32>().ok()?,
+            variants::content_type(extension)?,
+        ))
+    });
+    let found = match wanted {
+        Some((width, content_type)) => {
+            let connector = &mut get_connector!(db);
+            media::get(connector, id).and_then(|asset| {
+                media::variant(connector, id, width, content_type)
+                    .map(|variant| (asset, variant))
+            })
+        }
+        None => Err(diesel::result::Error::NotFound),
+    };
+    let (asset, variant) = match found {
+        Ok(found) => found,
+        Err(diesel::result::Error::NotFound) => {
+            return make_error!(
+                Status::NotFound,
+                format!(
+                    "Variant {} of media asset ID {} not found",
+                    variant, id
+                )
+            );
+        }
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    let stem = asset
+        .filename
+        .rsplit_once('.')
+        .map_or(asset.filename.as_str(), |(stem, _)| stem);
+    let extension = variants::extension(&variant.contenttype).unwrap_or("bin");
+    let filename = format!("{}-{}.{}", stem, variant.width, extension);
+    read(
+        db,
+        variant.id,
+        variant.contenttype,
+        filename,
+        variant.size,
+        range,
+    )
+    .await
+}
+
+/// Delete a media asset
+///
+/// Its content and the content of its variants are removed from the
+/// media storage as well.
+///
+/// # Errors
+///
+/// If the asset does not exist, a 404 error is returned to the user.
+/// If it is still used by a book or a fragment, a 409 error is
+/// returned. Any other error will be returned as a 500 HTTP error.
+#[delete("/<id>")]
+pub async fn delete(
+    db: &State<ServerState>,
+    id: Uuid,
+    _editor: Editor,
+) -> JsonResponse<()> {
+    let deleted = {
+        let connector = &mut get_connector!(db);
+        media::variants(connector, id).and_then(|variants| {
+            media::delete(connector, id).map(|deleted| (deleted, variants))
+        })
+    };
+    match deleted {
+        Ok((0, _)) => make_error!(
+            Status::NotFound,
+            format!("Media asset ID {} not found", id)
+        ),
+        Ok((_, variants)) => {
+            info!("Deleted media asset {}", id);
+            if let Err(e) = db.storage.remove(id).await {
+                warn!("Could not remove media asset {}: {}", id, e);
+            }
+            remove_variants(db.storage.as_ref(), &variants).await;
+            Ok(Json(()))
+        }
+        Err(DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => {
+            make_error!(
+                Status::Conflict,
+                format!("Media asset ID {} is still in use", id)
+            )
+        }
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
@@ -1,6 +1,18 @@
+pub mod admin;
+pub mod audit;
+pub mod auth;
 pub mod author;
 pub mod book;
+pub mod chapter;
+pub mod collaborator;
+pub mod export;
 pub mod fragment;
+pub mod import;
+pub mod media;
+pub mod page;
+pub mod reader;
+pub mod search;
+pub mod trash;
 
 #[macro_export]
 macro_rules! make_error {
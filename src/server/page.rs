@@ -0,0 +1,173 @@
+use crate::db::page::{Fields, Page, Paginated, Sort};
+use crate::db::ApiResult;
+use crate::server::make_error;
+use crate::Json;
+
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, status, Responder};
+use rocket::serde::uuid::Uuid;
+use rocket::serde::Serialize;
+
+/// Amount of items in a page when no limit is given
+const DEFAULT_LIMIT: i64 = 100;
+
+/// Maximum amount of items in a page
+const MAX_LIMIT: i64 = 1000;
+
+/// Pagination, sorting and fields the user can ask for on a list
+///
+/// `cursor` is the `X-Next-Cursor` header of the previous page,
+/// `sort` the name of a sort key, prefixed with `-` to sort in
+/// descending order, and `fields` a comma separated list of the
+/// fields to return.
+#[derive(FromForm)]
+pub struct Query {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub sort: Option<String>,
+    pub fields: Option<String>,
+}
+
+impl Query {
+    /// Read the page asked for
+    ///
+    /// At most `limit` items are returned, 100 by default and 1000 at
+    /// most.
+    ///
+    /// # Errors
+    ///
+    /// If the cursor is invalid, a 400 error is returned.
+    pub fn page(&self) -> Result<Page, status::Custom<String>> {
+        let cursor = match self.cursor.as_deref().map(Uuid::parse_str) {
+            None => None,
+            Some(Ok(cursor)) => Some(cursor),
+            Some(Err(e)) => {
+                return make_error!(
+                    Status::BadRequest,
+                    format!("Invalid cursor: {}", e)
+                )
+            }
+        };
+        Ok(Page {
+            limit: self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+            cursor,
+        })
+    }
+
+    /// Read the sort asked for, with `from_name` finding a sort key by
+    /// its name and `default` being used when none is given
+    ///
+    /// # Errors
+    ///
+    /// If the sort key is unknown, a 400 error is returned.
+    pub fn sort<K>(
+        &self,
+        from_name: fn(&str) -> Option<K>,
+        default: K,
+    ) -> Result<Sort<K>, status::Custom<String>> {
+        let sort = match self.sort.as_deref() {
+            None => {
+                return Ok(Sort {
+                    key: default,
+                    descending: false,
+                })
+            }
+            Some(sort) => sort,
+        };
+        let (name, descending) = match sort.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (sort, false),
+        };
+        match from_name(name) {
+            Some(key) => Ok(Sort { key, descending }),
+            None => make_error!(
+                Status::BadRequest,
+                format!("Unknown sort key {}", name)
+            ),
+        }
+    }
+
+    /// Read the fields asked for among `columns`, see `Fields::new`
+    ///
+    /// # Errors
+    ///
+    /// If a field is unknown, a 400 error is returned.
+    pub fn fields<S: AsRef<str>>(
+        &self,
+        columns: &[(&str, S)],
+    ) -> Result<Option<Fields>, status::Custom<String>> {
+        let fields = match &self.fields {
+            None => return Ok(None),
+            Some(fields) => fields,
+        };
+        let requested: Vec<&str> = fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .collect();
+        match Fields::new(&requested, columns) {
+            Ok(fields) => Ok(Some(fields)),
+            Err(e) => make_error!(Status::BadRequest, e),
+        }
+    }
+}
+
+/// A page of a list sent to the user
+///
+/// The items are sent as a JSON array. When another page follows, its
+/// cursor is sent in the `X-Next-Cursor` header, and its URL in the
+/// `Link` header with the relation `next`.
+pub struct Paged<T>(pub Paginated<T>);
+
+/// Response holding a page of a list, or an error
+pub type PagedResponse<T> = Result<Paged<T>, status::Custom<String>>;
+
+/// Send a page of a list to the user
+///
+/// # Errors
+///
+/// If the cursor of the page does not exist, a 400 error is returned.
+/// Any other error is returned as a 500 HTTP error.
+pub fn paged<T>(result: ApiResult<Paginated<T>>) -> PagedResponse<T> {
+    match result {
+        Ok(page) => Ok(Paged(page)),
+        Err(diesel::result::Error::NotFound) => {
+            make_error!(Status::BadRequest, "Unknown cursor".to_owned())
+        }
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// URL of the page following the one requested by `request`, whose
+/// cursor is `cursor`
+fn next_url(request: &Request<'_>, cursor: Uuid) -> String {
+    let uri = request.uri();
+    let mut query: Vec<String> = uri
+        .query()
+        .map(|query| {
+            query
+                .raw_segments()
+                .filter(|segment| !segment.as_str().starts_with("cursor="))
+                .map(ToString::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    query.push(format!("cursor={}", cursor));
+    format!("{}?{}", uri.path(), query.join("&"))
+}
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Paged<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let next = self.0.next;
+        let mut response = Json(self.0.items).respond_to(request)?;
+        if let Some(next) = next {
+            response.set_raw_header(
+                "Link",
+                format!("<{}>; rel=\"next\"", next_url(request, next)),
+            );
+            response.set_raw_header("X-Next-Cursor", next.to_string());
+        }
+        Ok(response)
+    }
+}
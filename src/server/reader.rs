@@ -0,0 +1,233 @@
+use crate::auth::{self, Reader};
+use crate::db::{book, get_connector, published, reader};
+use crate::models::{Bookfragment, Bookmark, Progress};
+use crate::server::{json_val_or_error, make_error};
+use crate::{Json, JsonResponse, ServerState};
+
+use diesel::result::Error::NotFound;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::uuid::Uuid;
+use rocket::serde::Deserialize;
+use rocket::State;
+
+/// Data the user can send to save where they stopped reading a book
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewProgress {
+    pub fragment: Uuid,
+}
+
+/// Data the user can send to bookmark a fragment
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewBookmark {
+    pub fragment: Uuid,
+    pub note: Option<String>,
+}
+
+/// Data the user can send to change the note of a bookmark
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BookmarkNote {
+    pub note: Option<String>,
+}
+
+/// Retrieve a fragment readers can read, returning a 404 error if
+/// there is none
+///
+/// Readers only read published books, so the fragment is looked up
+/// in the published version of the books, and its book must still be
+/// readable by anyone, see `auth::readable_by`.
+fn get_fragment(
+    connector: &mut diesel::PgConnection,
+    id: Uuid,
+) -> Result<Bookfragment, status::Custom<String>> {
+    let found = published::get(connector, id).and_then(|fragment| {
+        let book = book::get(connector, fragment.book)?;
+        let readable = auth::readable_by(connector, None)?.allows(&book);
+        Ok((fragment, readable))
+    });
+    match found {
+        Ok((fragment, true)) => Ok(fragment),
+        Ok((_, false)) | Err(NotFound) => make_error!(
+            Status::NotFound,
+            format!("Fragment ID {} not found", id)
+        ),
+        Err(other) => {
+            make_error!(Status::InternalServerError, other.to_string())
+        }
+    }
+}
+
+/// List the progress of the reader in all the books they read, most
+/// recently read first
+///
+/// # Errors
+///
+/// Any error from the server will be returned to the user as a 500
+/// HTTP error.
+#[get("/me/progress")]
+pub fn list_progress(
+    db: &State<ServerState>,
+    me: Reader,
+) -> JsonResponse<Vec<Progress>> {
+    let connector = &mut get_connector!(db);
+    json_val_or_error!(reader::list_progress(connector, me.0.user.id))
+}
+
+/// Get where the reader stopped reading a book, so they can resume
+/// reading it
+///
+/// # Errors
+///
+/// If the reader never read the book, a 404 error is returned to the
+/// user. Any other error will be returned as a 500 HTTP error.
+#[get("/me/progress/<book_id>")]
+pub fn get_progress(
+    db: &State<ServerState>,
+    me: Reader,
+    book_id: Uuid,
+) -> JsonResponse<Progress> {
+    let connector = &mut get_connector!(db);
+    match reader::get_progress(connector, me.0.user.id, book_id) {
+        Ok(val) => Ok(Json(val)),
+        Err(NotFound) => make_error!(
+            Status::NotFound,
+            format!("No progress in book {}", book_id)
+        ),
+        Err(other) => {
+            make_error!(Status::InternalServerError, other.to_string())
+        }
+    }
+}
+
+/// Save the last fragment the reader read in a book
+///
+/// The identifier of the fragment is sent as Json data, its rank and
+/// the number of its chapter are saved along with it.
+///
+/// # Errors
+///
+/// If the fragment is not part of a published book, a 404 error is
+/// returned to the user. If it does not belong to the book, a 422
+/// error is returned.
+/// Any other error will be returned as a 500 HTTP error.
+#[put("/me/progress/<book_id>", format = "json", data = "<progress>")]
+pub fn set_progress(
+    db: &State<ServerState>,
+    me: Reader,
+    book_id: Uuid,
+    progress: Json<NewProgress>,
+) -> JsonResponse<Progress> {
+    let connector = &mut get_connector!(db);
+    let fragment = get_fragment(connector, progress.fragment)?;
+    if fragment.book != book_id {
+        return make_error!(
+            Status::UnprocessableEntity,
+            format!(
+                "Fragment ID {} does not belong to book {}",
+                fragment.id, book_id
+            )
+        );
+    }
+    json_val_or_error!(reader::set_progress(connector, me.0.user.id, &fragment))
+}
+
+/// List the bookmarks of the reader, oldest first
+///
+/// If `book` is set, only the bookmarks in this book are listed.
+///
+/// # Errors
+///
+/// Any error from the server will be returned to the user as a 500
+/// HTTP error.
+#[get("/me/bookmarks?<book>")]
+pub fn list_bookmarks(
+    db: &State<ServerState>,
+    me: Reader,
+    book: Option<Uuid>,
+) -> JsonResponse<Vec<Bookmark>> {
+    let connector = &mut get_connector!(db);
+    json_val_or_error!(reader::list_bookmarks(connector, me.0.user.id, book))
+}
+
+/// Bookmark a fragment, optionally with a note
+///
+/// # Errors
+///
+/// If the fragment is not part of a published book, a 404 error is
+/// returned to the user. Any other error will be returned as a 500
+/// HTTP error.
+#[post("/me/bookmarks", format = "json", data = "<bookmark>")]
+pub fn new_bookmark(
+    db: &State<ServerState>,
+    me: Reader,
+    bookmark: Json<NewBookmark>,
+) -> JsonResponse<Bookmark> {
+    let connector = &mut get_connector!(db);
+    let bookmark = bookmark.into_inner();
+    let fragment = get_fragment(connector, bookmark.fragment)?;
+    let created = Bookmark {
+        id: Uuid::new_v4(),
+        reader: me.0.user.id,
+        fragment: fragment.id,
+        note: bookmark.note,
+        created: chrono::Utc::now().naive_utc(),
+    };
+    json_val_or_error!(reader::new_bookmark(connector, &created))
+}
+
+/// Change the note of a bookmark of the reader
+///
+/// # Errors
+///
+/// If the reader has no such bookmark, a 404 error is returned to
+/// the user. Any other error will be returned as a 500 HTTP error.
+#[put("/me/bookmarks/<id>", format = "json", data = "<note>")]
+pub fn update_bookmark(
+    db: &State<ServerState>,
+    me: Reader,
+    id: Uuid,
+    note: Json<BookmarkNote>,
+) -> JsonResponse<Bookmark> {
+    let connector = &mut get_connector!(db);
+    match reader::update_bookmark(
+        connector,
+        me.0.user.id,
+        id,
+        note.into_inner().note,
+    ) {
+        Ok(val) => Ok(Json(val)),
+        Err(NotFound) => make_error!(
+            Status::NotFound,
+            format!("Bookmark ID {} not found", id)
+        ),
+        Err(other) => {
+            make_error!(Status::InternalServerError, other.to_string())
+        }
+    }
+}
+
+/// Delete a bookmark of the reader
+///
+/// # Errors
+///
+/// If the reader has no such bookmark, a 404 error is returned to
+/// the user. Any other error will be returned as a 500 HTTP error.
+#[delete("/me/bookmarks/<id>")]
+pub fn delete_bookmark(
+    db: &State<ServerState>,
+    me: Reader,
+    id: Uuid,
+) -> JsonResponse<()> {
+    let connector = &mut get_connector!(db);
+    match reader::delete_bookmark(connector, me.0.user.id, id) {
+        Ok(0) => make_error!(
+            Status::NotFound,
+            format!("Bookmark ID {} not found", id)
+        ),
+        Ok(_) => Ok(Json(())),
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
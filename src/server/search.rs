@@ -0,0 +1,46 @@
+use crate::auth::{self, Reader};
+use crate::db::get_connector;
+use crate::db::search::{self, Hits};
+use crate::server::{json_val_or_error, make_error};
+use crate::{Json, JsonResponse, ServerState};
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::State;
+use tracing::info;
+
+/// Amount of books and of fragments returned when no limit is given
+const DEFAULT_LIMIT: i64 = 20;
+
+/// Maximum amount of books and of fragments returned
+const MAX_LIMIT: i64 = 100;
+
+/// Search the books and fragments the user can read for `q`
+///
+/// Returns the books whose title or synopsis matches, and the
+/// fragments whose content matches along with their book, chapter and
+/// rank, each ranked by relevance with a highlighted snippet. `q` is
+/// read as a web search, see `search::search`. At most `limit` books
+/// and `limit` fragments are returned, 20 by default and 100 at most.
+///
+/// # Errors
+///
+/// If `q` is empty, a 400 error is returned. Any other error from the
+/// server will be returned as a 500 HTTP error.
+#[get("/?<q>&<limit>")]
+pub fn find(
+    db: &State<ServerState>,
+    q: &str,
+    limit: Option<i64>,
+    reader: Option<Reader>,
+) -> JsonResponse<Hits> {
+    info!("Searching for {}", q);
+    if q.trim().is_empty() {
+        return make_error!(Status::BadRequest, "Empty search".to_owned());
+    }
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let connector = &mut get_connector!(db);
+    let user = reader.as_ref().map(|reader| &reader.0.user);
+    json_val_or_error!(auth::readable_by(connector, user)
+        .and_then(|readable| search::search(connector, q, &readable, limit)))
+}
@@ -0,0 +1,446 @@
+use std::collections::hash_map::{Entry, HashMap};
+
+use crate::auth::{self, Editor, Identity, Permission};
+use crate::db::{author, book, chapter, get_connector, serializable, trash};
+use crate::models::{AuditAction, Author, Book, Bookfragment, Chapter};
+use crate::server::{audit, json_val_or_error, make_error};
+use crate::{Json, JsonResponse, ServerState};
+
+use diesel::result::Error::NotFound;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::uuid::Uuid;
+use rocket::serde::Serialize;
+use rocket::{Route, State};
+use tracing::info;
+
+/// Item brought back from the trash, see `restore`
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde", untagged)]
+pub enum Restored {
+    Author(Author),
+    Book(Book),
+    Chapter(Chapter),
+    Fragment(Bookfragment),
+}
+
+/// Find a book, whether it is in the trash or not
+fn any_book(
+    connector: &mut diesel::PgConnection,
+    id: Uuid,
+) -> diesel::QueryResult<Book> {
+    match book::get(connector, id) {
+        Err(NotFound) => trash::book(connector, id),
+        other => other,
+    }
+}
+
+/// Whether `user` has `permission` on all of `books`, see `auth::can`
+///
+/// # Errors
+///
+/// If an error is returned by diesel, forward it to the function
+/// calling `can_all`
+fn can_all(
+    connector: &mut diesel::PgConnection,
+    identity: &Identity,
+    books: &[Book],
+    permission: Permission,
+) -> diesel::QueryResult<bool> {
+    for found in books {
+        if !auth::can(connector, &identity.user, found, permission)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Keep the items of the trash the user sending the request could
+/// restore
+///
+/// Editors can restore the authors whose books deleted with them they
+/// can all delete, the books they can delete, and the chapters and
+/// fragments of the books they can edit.
+fn restorable(
+    connector: &mut diesel::PgConnection,
+    identity: &Identity,
+    content: trash::Trash,
+) -> diesel::QueryResult<trash::Trash> {
+    let mut authors = Vec::with_capacity(content.authors.len());
+    for found in content.authors {
+        let cascaded = trash::author_books(connector, &found)?;
+        if can_all(connector, identity, &cascaded, Permission::Delete)? {
+            authors.push(found);
+        }
+    }
+    let mut books = Vec::with_capacity(content.books.len());
+    for found in content.books {
+        if auth::can(connector, &identity.user, &found, Permission::Delete)? {
+            books.push(found);
+        }
+    }
+    let mut editable: HashMap<Uuid, bool> = HashMap::new();
+    let chapters = content.chapters.iter().map(|found| found.book);
+    let fragments = content.fragments.iter().map(|found| found.book);
+    for parent in chapters.chain(fragments) {
+        if let Entry::Vacant(entry) = editable.entry(parent) {
+            let parent = any_book(connector, parent)?;
+            entry.insert(auth::can(
+                connector,
+                &identity.user,
+                &parent,
+                Permission::EditFragments,
+            )?);
+        }
+    }
+    let chapters = content
+        .chapters
+        .into_iter()
+        .filter(|found| editable[&found.book])
+        .collect();
+    let fragments = content
+        .fragments
+        .into_iter()
+        .filter(|found| editable[&found.book])
+        .collect();
+    Ok(trash::Trash {
+        authors,
+        books,
+        chapters,
+        fragments,
+    })
+}
+
+/// List the authors, books, chapters and fragments in the trash
+///
+/// Only the items the user sending the request could restore are
+/// listed, see `restorable`.
+///
+/// # Errors
+///
+/// Any error from the server will be returned to the user as a 500
+/// HTTP error.
+#[get("/")]
+pub fn list(
+    db: &State<ServerState>,
+    editor: Editor,
+) -> JsonResponse<trash::Trash> {
+    info!("Listing the trash");
+    let connector = &mut get_connector!(db);
+    json_val_or_error!(trash::list(connector)
+        .and_then(|content| restorable(connector, &editor.0, content)))
+}
+
+/// Check the user sending the request confirmed the deletion of the
+/// `kind` identified by `id`
+///
+/// Deletions moving other items to the trash, as described by
+/// `cascade`, must be confirmed with the token the `preview` endpoint
+/// of the item returned to the same user, signed with `key`, as
+/// `confirm`.
+///
+/// # Errors
+///
+/// If the deletion needs a confirmation and `confirm` is missing,
+/// expired or does not match its token, a 409 error is returned.
+pub fn check_confirmation(
+    key: &trash::ConfirmationKey,
+    identity: &Identity,
+    kind: &str,
+    id: Uuid,
+    preview: &str,
+    cascade: &trash::Cascade,
+    confirm: Option<&str>,
+) -> Result<(), status::Custom<String>> {
+    if cascade.confirmed(key, identity.user.id, confirm) {
+        return Ok(());
+    }
+    make_error!(
+        Status::Conflict,
+        format!(
+            "Deleting {} ID {} also moves {} books and {} fragments to the \
+             trash, confirm it with the token of {}",
+            kind, id, cascade.books, cascade.fragments, preview
+        )
+    )
+}
+
+/// Turn an error raised while looking for an item of the trash into
+/// an HTTP error
+fn trash_error<T>(
+    kind: &str,
+    id: Uuid,
+    error: &diesel::result::Error,
+) -> Result<T, status::Custom<String>> {
+    match error {
+        NotFound => make_error!(
+            Status::NotFound,
+            format!("No {} with ID {} in the trash", kind, id)
+        ),
+        other => make_error!(Status::InternalServerError, other.to_string()),
+    }
+}
+
+/// Check the user sending the request has `permission` on all of
+/// `books`
+///
+/// Changes to an author affect all of their books, including the
+/// ones owned by other users, so they are only allowed to users who
+/// could make the same change to each book.
+///
+/// # Errors
+///
+/// If the user does not have the permission on one of the books, a
+/// 403 error is returned. Any other error is returned as a 500 HTTP
+/// error.
+pub fn check_books(
+    connector: &mut diesel::PgConnection,
+    identity: &Identity,
+    books: &[Book],
+    permission: Permission,
+) -> Result<(), status::Custom<String>> {
+    for found in books {
+        check_permission(connector, identity, found, permission)?;
+    }
+    Ok(())
+}
+
+/// Check the user sending the request has `permission` on `book`
+///
+/// # Errors
+///
+/// If the user does not have the permission, a 403 error is returned.
+/// Any other error is returned as a 500 HTTP error.
+fn check_permission(
+    connector: &mut diesel::PgConnection,
+    identity: &Identity,
+    book: &Book,
+    permission: Permission,
+) -> Result<(), status::Custom<String>> {
+    match auth::can(connector, &identity.user, book, permission) {
+        Ok(true) => Ok(()),
+        Ok(false) => make_error!(
+            Status::Forbidden,
+            format!("Missing permission {:?} on book {}", permission, book.id)
+        ),
+        Err(e) => make_error!(Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// Bring an author back from the trash, along with the books and
+/// fragments deleted with them
+fn restore_author(
+    connector: &mut diesel::PgConnection,
+    identity: &Identity,
+    route: &Route,
+    id: Uuid,
+) -> Result<Restored, status::Custom<String>> {
+    let before = trash::author(connector, id)
+        .or_else(|e| trash_error("author", id, &e))?;
+    let cascaded = trash::author_books(connector, &before)
+        .or_else(|e| trash_error("author", id, &e))?;
+    check_books(connector, identity, &cascaded, Permission::Delete)?;
+    let restored = serializable(connector, |connector| {
+        let restored = trash::restore_author(connector, id)?;
+        audit::record(
+            connector,
+            identity,
+            route,
+            AuditAction::Update,
+            "author",
+            id,
+            Some(&before),
+            Some(&restored),
+        )?;
+        Ok(restored)
+    })
+    .or_else(|e| trash_error("author", id, &e))?;
+    Ok(Restored::Author(restored))
+}
+
+/// Bring a book back from the trash, along with the fragments deleted
+/// with it
+fn restore_book(
+    connector: &mut diesel::PgConnection,
+    identity: &Identity,
+    route: &Route,
+    id: Uuid,
+) -> Result<Restored, status::Custom<String>> {
+    let before =
+        trash::book(connector, id).or_else(|e| trash_error("book", id, &e))?;
+    if let Err(e) = author::get(connector, before.author) {
+        return match e {
+            NotFound => make_error!(
+                Status::Conflict,
+                format!(
+                    "Author ID {} of book ID {} is in the trash",
+                    before.author, id
+                )
+            ),
+            other => {
+                make_error!(Status::InternalServerError, other.to_string())
+            }
+        };
+    }
+    check_permission(connector, identity, &before, Permission::Delete)?;
+    let restored = serializable(connector, |connector| {
+        let restored = trash::restore_book(connector, id)?;
+        audit::record(
+            connector,
+            identity,
+            route,
+            AuditAction::Update,
+            "book",
+            id,
+            Some(&before),
+            Some(&restored),
+        )?;
+        Ok(restored)
+    })
+    .or_else(|e| trash_error("book", id, &e))?;
+    Ok(Restored::Book(restored))
+}
+
+/// Bring a chapter back from the trash, at the number it had in its
+/// book, along with the fragments deleted with it
+fn restore_chapter(
+    connector: &mut diesel::PgConnection,
+    identity: &Identity,
+    route: &Route,
+    id: Uuid,
+) -> Result<Restored, status::Custom<String>> {
+    let before = trash::chapter(connector, id)
+        .or_else(|e| trash_error("chapter", id, &e))?;
+    let parent = match book::get(connector, before.book) {
+        Ok(val) => val,
+        Err(NotFound) => {
+            return make_error!(
+                Status::Conflict,
+                format!(
+                    "Book ID {} of chapter ID {} is in the trash",
+                    before.book, id
+                )
+            )
+        }
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    check_permission(connector, identity, &parent, Permission::EditFragments)?;
+    let restored = serializable(connector, |connector| {
+        let restored = trash::restore_chapter(connector, id)?;
+        audit::record(
+            connector,
+            identity,
+            route,
+            AuditAction::Update,
+            "chapter",
+            id,
+            Some(&before),
+            Some(&restored),
+        )?;
+        Ok(restored)
+    })
+    .or_else(|e| trash_error("chapter", id, &e))?;
+    Ok(Restored::Chapter(restored))
+}
+
+/// Bring a fragment back from the trash, at the rank it had in its
+/// chapter
+fn restore_fragment(
+    connector: &mut diesel::PgConnection,
+    identity: &Identity,
+    route: &Route,
+    id: Uuid,
+) -> Result<Restored, status::Custom<String>> {
+    let before = trash::fragment(connector, id)
+        .or_else(|e| trash_error("fragment", id, &e))?;
+    let parent = match book::get(connector, before.book) {
+        Ok(val) => val,
+        Err(NotFound) => {
+            return make_error!(
+                Status::Conflict,
+                format!(
+                    "Book ID {} of fragment ID {} is in the trash",
+                    before.book, id
+                )
+            )
+        }
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    };
+    match chapter::get(connector, before.chapter) {
+        Ok(_) => {}
+        Err(NotFound) => {
+            return make_error!(
+                Status::Conflict,
+                format!(
+                    "Chapter ID {} of fragment ID {} is in the trash",
+                    before.chapter, id
+                )
+            )
+        }
+        Err(e) => {
+            return make_error!(Status::InternalServerError, e.to_string())
+        }
+    }
+    check_permission(connector, identity, &parent, Permission::EditFragments)?;
+    let restored = serializable(connector, |connector| {
+        let restored = trash::restore_fragment(connector, id)?;
+        audit::record(
+            connector,
+            identity,
+            route,
+            AuditAction::Update,
+            "fragment",
+            id,
+            Some(&before),
+            Some(&restored),
+        )?;
+        Ok(restored)
+    })
+    .or_else(|e| trash_error("fragment", id, &e))?;
+    Ok(Restored::Fragment(restored))
+}
+
+/// Bring an item back from the trash
+///
+/// `kind` is either `author`, `book`, `chapter` or `fragment`.
+/// Restoring an author also restores the books and fragments deleted
+/// along with them, and restoring a book or a chapter the fragments
+/// deleted along with it. A chapter is put back at the number it had
+/// in its book, and a fragment at the rank it had in its chapter. The
+/// restored item is returned.
+///
+/// # Errors
+///
+/// If `kind` is unknown or if the item is not in the trash, a 404
+/// error is returned to the user. If the author of a book, the book
+/// of a chapter, or the book or the chapter of a fragment is still in
+/// the trash, a 409 error is returned. If the user cannot delete the
+/// book, the books of the author, or edit the book of the chapter or
+/// of the fragment, a 403 error is returned. Any other error will be
+/// returned as a 500 HTTP error.
+#[post("/<kind>/<id>/restore")]
+pub fn restore(
+    db: &State<ServerState>,
+    kind: &str,
+    id: Uuid,
+    editor: Editor,
+    route: &Route,
+) -> JsonResponse<Restored> {
+    info!("Restoring {} {} from the trash", kind, id);
+    let connector = &mut get_connector!(db);
+    let restored = match kind {
+        "author" => restore_author(connector, &editor.0, route, id),
+        "book" => restore_book(connector, &editor.0, route, id),
+        "chapter" => restore_chapter(connector, &editor.0, route, id),
+        "fragment" => restore_fragment(connector, &editor.0, route, id),
+        _ => make_error!(
+            Status::NotFound,
+            format!("Unknown kind of item {}", kind)
+        ),
+    };
+    restored.map(Json)
+}